@@ -0,0 +1,371 @@
+use crate::{
+    Allocator, CongeeCompactSet,
+    error::{ArtError, CompactSetError},
+    nodes::{BaseNode, MAX_PREFIX_LEN, Node, Node4, Node16, Node48, Node256, NodePtr},
+    range_remove::drain_subtree,
+    utils::KeyTracker,
+};
+use std::ptr::NonNull;
+
+/// Builds a tree bottom-up from entries sorted by key.
+///
+/// Every node is allocated once with the type that fits its children,
+/// prefixes longer than `MAX_PREFIX_LEN` are split into a chain of nodes the same way `make_path` does.
+pub(crate) struct BulkLoader<'a, const K_LEN: usize, A: Allocator> {
+    allocator: &'a A,
+}
+
+/// Why [BulkLoader::build_sorted] stopped.
+pub(crate) enum SortedBuildError {
+    Oom,
+    /// The key of the entry at this index is not greater than the key before it.
+    Unsorted(usize),
+}
+
+/// A node of a sorted build that may still get children.
+struct Frame<const K_LEN: usize> {
+    /// The level of the edges to the children.
+    level: usize,
+    /// The first key below the node, its bytes before `level` are shared by every key below the node.
+    first: [u8; K_LEN],
+    children: Vec<(u8, NodePtr)>,
+}
+
+impl<'a, const K_LEN: usize, A: Allocator + Clone> BulkLoader<'a, K_LEN, A> {
+    pub(crate) fn new(allocator: &'a A) -> Self {
+        Self { allocator }
+    }
+
+    /// Builds the root node while consuming `entries`, and returns it with the number of entries.
+    ///
+    /// Only the nodes on the path of the last key are kept open, every other node is allocated as soon as
+    /// the keys move past it, so the entries are never buffered. On error, the nodes built so far are freed and
+    /// the payloads consumed so far, including the one that failed, are passed to `drain`.
+    pub(crate) fn build_sorted(
+        &self,
+        entries: impl Iterator<Item = ([u8; K_LEN], usize)>,
+        drain: &dyn Fn([u8; K_LEN], usize),
+    ) -> Result<(NonNull<BaseNode>, usize), SortedBuildError> {
+        let mut stack = vec![Frame {
+            level: 0,
+            first: [0; K_LEN],
+            children: Vec::new(),
+        }];
+        let mut prev: Option<[u8; K_LEN]> = None;
+        let mut count = 0;
+        for (key, payload) in entries {
+            // The level of the first byte where the key differs from the one before it.
+            let level = match prev {
+                None => 0,
+                Some(prev) if prev < key => {
+                    prev.iter().zip(&key).take_while(|(a, b)| a == b).count()
+                }
+                Some(_) => {
+                    drain(key, payload);
+                    self.drain_frames(stack, drain);
+                    return Err(SortedBuildError::Unsorted(count));
+                }
+            };
+            if self.close_frames(&mut stack, level).is_err() {
+                drain(key, payload);
+                self.drain_frames(stack, drain);
+                return Err(SortedBuildError::Oom);
+            }
+
+            let payload = (key[K_LEN - 1], NodePtr::from_payload(payload));
+            if level == K_LEN - 1 {
+                stack.last_mut().unwrap().children.push(payload);
+            } else {
+                stack.push(Frame {
+                    level: K_LEN - 1,
+                    first: key,
+                    children: vec![payload],
+                });
+            }
+            prev = Some(key);
+            count += 1;
+        }
+
+        if self.close_frames(&mut stack, 0).is_err() {
+            self.drain_frames(stack, drain);
+            return Err(SortedBuildError::Oom);
+        }
+        match self.alloc_node(&[], &stack[0].children) {
+            Ok(root) => Ok((unsafe { root.as_sub_node_unchecked() }, count)),
+            Err(_) => {
+                self.drain_frames(stack, drain);
+                Err(SortedBuildError::Oom)
+            }
+        }
+    }
+
+    /// Allocates the frames whose edges are below `level`, the next key branches off at `level`.
+    /// Afterwards the last frame has its edges at `level`. On error, every built node is still in a frame.
+    fn close_frames(&self, stack: &mut Vec<Frame<K_LEN>>, level: usize) -> Result<(), ArtError> {
+        while stack.last().unwrap().level > level {
+            let mut frame = stack.pop().unwrap();
+            let below_level = stack.last().unwrap().level;
+            let parent_level = below_level.max(level);
+            let node = match self.alloc_frame(&mut frame, parent_level) {
+                Ok(node) => node,
+                Err(e) => {
+                    stack.push(frame);
+                    return Err(e);
+                }
+            };
+            if below_level < level {
+                // The keys so far and the next one branch off below the parent, at `level`.
+                stack.push(Frame {
+                    level,
+                    first: frame.first,
+                    children: Vec::new(),
+                });
+            }
+            let edge = (frame.first[parent_level], node);
+            stack.last_mut().unwrap().children.push(edge);
+        }
+        Ok(())
+    }
+
+    /// Allocates the node of a frame whose parent has its edges at `parent_level`, below a chain of single child
+    /// nodes if its prefix is longer than `MAX_PREFIX_LEN`. The children are moved to the node,
+    /// on error nothing is allocated and the frame is left untouched.
+    fn alloc_frame(
+        &self,
+        frame: &mut Frame<K_LEN>,
+        parent_level: usize,
+    ) -> Result<NodePtr, ArtError> {
+        let chain_start = parent_level + 1;
+        let mut start = chain_start;
+        while frame.level - start > MAX_PREFIX_LEN {
+            start += MAX_PREFIX_LEN + 1;
+        }
+        let mut node = self.alloc_node(&frame.first[start..frame.level], &frame.children)?;
+        let mut chain_len = 0;
+        while start > chain_start {
+            start -= MAX_PREFIX_LEN + 1;
+            let edge_level = start + MAX_PREFIX_LEN;
+            let edge = (frame.first[edge_level], node);
+            match self.alloc_node(&frame.first[start..edge_level], &[edge]) {
+                Ok(n) => node = n,
+                Err(e) => {
+                    // Free the chain and the node, but not the children that are still in the frame.
+                    let mut n = unsafe { node.as_sub_node_unchecked() };
+                    for _ in 0..chain_len {
+                        let (_k, child) = unsafe { n.as_ref() }
+                            .get_children(0, u8::MAX)
+                            .next()
+                            .unwrap();
+                        unsafe { BaseNode::drop_node(n, self.allocator.clone()) };
+                        n = unsafe { child.as_sub_node_unchecked() };
+                    }
+                    unsafe { BaseNode::drop_node(n, self.allocator.clone()) };
+                    return Err(e);
+                }
+            }
+            chain_len += 1;
+        }
+        frame.children.clear();
+        Ok(node)
+    }
+
+    /// Frees the nodes of a failed sorted build and passes the payloads below them to `drain`.
+    fn drain_frames(&self, stack: Vec<Frame<K_LEN>>, drain: &dyn Fn([u8; K_LEN], usize)) {
+        for frame in stack {
+            let mut key = KeyTracker::<K_LEN>::empty();
+            for b in &frame.first[..frame.level] {
+                key.push(*b);
+            }
+            for (k, child) in frame.children {
+                key.push(k);
+                if frame.level == K_LEN - 1 {
+                    drain(
+                        *unsafe { key.as_last_level_unchecked() }.key(),
+                        child.as_payload(),
+                    );
+                } else {
+                    let node = unsafe { child.as_sub_node_unchecked() };
+                    let prefix = unsafe { node.as_ref() }.prefix();
+                    for b in prefix {
+                        key.push(*b);
+                    }
+                    let prefix_len = prefix.len();
+                    unsafe { drain_subtree(node, &mut key, drain, self.allocator) };
+                    for _ in 0..prefix_len {
+                        key.pop();
+                    }
+                }
+                key.pop();
+            }
+        }
+    }
+
+    /// Builds a node whose prefix starts at `level`, all `entries` share the key bytes before `level`.
+    pub(crate) fn build_sub_node(
+        &self,
+        entries: &[([u8; K_LEN], usize)],
+        level: usize,
+    ) -> Result<NodePtr, ArtError> {
+        // The entries are sorted, so the first and the last key share the prefix of all of them.
+        let first = &entries[0].0;
+        let last = &entries[entries.len() - 1].0;
+        let common = (level..K_LEN - 1)
+            .take_while(|&i| first[i] == last[i])
+            .count();
+        let edge_level = level + common.min(MAX_PREFIX_LEN);
+
+        let children = self.build_children(entries, edge_level)?;
+        self.make_node(&first[level..edge_level], children, edge_level)
+    }
+
+    /// Builds the root node from the nodes of a compact set, one node per compact node,
+    /// and returns it with the number of keys. The payload of every key is `1`.
+    /// On error, all nodes allocated so far are freed.
+    pub(crate) fn build_compact<K: Copy + From<usize>>(
+        &self,
+        compact: &CongeeCompactSet<K>,
+    ) -> Result<(NonNull<BaseNode>, usize), CompactSetError>
+    where
+        usize: From<K>,
+    {
+        let (root, count) = if compact.is_empty() {
+            let root = self
+                .make_node(&[], Vec::new(), 0)
+                .map_err(|_| CompactSetError::Oom)?;
+            (root, 0)
+        } else {
+            self.build_compact_node(compact, 0, 0)?
+        };
+        Ok((unsafe { root.as_sub_node_unchecked() }, count))
+    }
+
+    /// Builds the compact node at `offset` whose prefix starts at `level`, and its subtree.
+    /// The node is checked to fit there, so that malformed data can't build a broken tree.
+    fn build_compact_node<K: Copy + From<usize>>(
+        &self,
+        compact: &CongeeCompactSet<K>,
+        offset: usize,
+        level: usize,
+    ) -> Result<(NodePtr, usize), CompactSetError>
+    where
+        usize: From<K>,
+    {
+        let malformed = CompactSetError::Malformed { offset };
+        let node = compact.try_read_node(offset).ok_or(malformed)?;
+        let edge_level = level + node.prefix.len();
+        // The root has no prefix, every other node has a child, and the keys are in ascending order.
+        let valid = node.prefix.len() <= MAX_PREFIX_LEN
+            && (level > 0 || node.prefix.is_empty())
+            && edge_level < K_LEN
+            && node.is_leaf == (edge_level == K_LEN - 1)
+            && (level == 0 || !node.children.is_empty())
+            && node.children.windows(2).all(|w| w[0].0 < w[1].0);
+        if !valid {
+            return Err(malformed);
+        }
+
+        let mut children = Vec::with_capacity(node.children.len());
+        let mut count = 0;
+        for (k, child_offset) in node.children {
+            let child = if node.is_leaf {
+                count += 1;
+                NodePtr::from_payload(1)
+            } else {
+                match self.build_compact_node(compact, child_offset, edge_level + 1) {
+                    Ok((child, n)) => {
+                        count += n;
+                        child
+                    }
+                    Err(e) => {
+                        self.drop_children(children, edge_level);
+                        return Err(e);
+                    }
+                }
+            };
+            children.push((k, child));
+        }
+        let node = self
+            .make_node(node.prefix, children, edge_level)
+            .map_err(|_| CompactSetError::Oom)?;
+        Ok((node, count))
+    }
+
+    /// Builds the children of a node, one per distinct key byte at `level`.
+    fn build_children(
+        &self,
+        entries: &[([u8; K_LEN], usize)],
+        level: usize,
+    ) -> Result<Vec<(u8, NodePtr)>, ArtError> {
+        let mut children = Vec::new();
+        let mut rest = entries;
+        while let Some((first, _)) = rest.first() {
+            let key = first[level];
+            let (group, tail) = rest.split_at(rest.partition_point(|(k, _)| k[level] == key));
+
+            let child = if level == K_LEN - 1 {
+                NodePtr::from_payload(group[0].1)
+            } else {
+                match self.build_sub_node(group, level + 1) {
+                    Ok(child) => child,
+                    Err(e) => {
+                        self.drop_children(children, level);
+                        return Err(e);
+                    }
+                }
+            };
+            children.push((key, child));
+            rest = tail;
+        }
+        Ok(children)
+    }
+
+    /// Allocates the smallest node that holds all `children`, they are freed on error.
+    fn make_node(
+        &self,
+        prefix: &[u8],
+        children: Vec<(u8, NodePtr)>,
+        level: usize,
+    ) -> Result<NodePtr, ArtError> {
+        let node = self.alloc_node(prefix, &children);
+        if node.is_err() {
+            self.drop_children(children, level);
+        }
+        node
+    }
+
+    /// Allocates the smallest node that holds all `children`, they are left untouched on error.
+    fn alloc_node(&self, prefix: &[u8], children: &[(u8, NodePtr)]) -> Result<NodePtr, ArtError> {
+        match children.len() {
+            0..=4 => self.fill::<Node4>(prefix, children),
+            5..=16 => self.fill::<Node16>(prefix, children),
+            17..=48 => self.fill::<Node48>(prefix, children),
+            _ => self.fill::<Node256>(prefix, children),
+        }
+    }
+
+    fn fill<N: Node>(
+        &self,
+        prefix: &[u8],
+        children: &[(u8, NodePtr)],
+    ) -> Result<NodePtr, ArtError> {
+        let mut node = BaseNode::make_node::<N, A>(prefix, self.allocator)?;
+        for (k, child) in children {
+            node.as_mut().insert(*k, *child);
+        }
+        Ok(node.into_note_ptr())
+    }
+
+    /// Frees the sub nodes below edges at `level`.
+    pub(crate) fn drop_children(&self, children: Vec<(u8, NodePtr)>, level: usize) {
+        if level == K_LEN - 1 {
+            return;
+        }
+        for (_k, child) in children {
+            let node = unsafe { child.as_sub_node_unchecked() };
+            let node_ref = unsafe { node.as_ref() };
+            let edge_level = level + 1 + node_ref.prefix_len();
+            self.drop_children(node_ref.get_children(0, u8::MAX).collect(), edge_level);
+            unsafe { BaseNode::drop_node(node, self.allocator.clone()) };
+        }
+    }
+}
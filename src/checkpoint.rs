@@ -0,0 +1,274 @@
+//! The binary checkpoint format of [CongeeRaw](crate::CongeeRaw) and [CongeeSet](crate::CongeeSet).
+//!
+//! All integers are little endian.
+//! - The header: the magic bytes `CONGEECK`, the format version (u32), the kind of tree (u8, 0 for maps and 1 for sets),
+//!   the key length (u32), and the CRC-32 of the header bytes before it (u32).
+//! - One block per group of keys sharing their first `K_LEN - 1` bytes, in ascending key order:
+//!   the number of entries (u16, 1 to 256), the shared `K_LEN - 1` key bytes, the last key byte of each entry
+//!   followed by its value (u64, maps only), and the CRC-32 of the block bytes before it (u32).
+//! - The trailer: an entry count of zero (u16), the number of entries in all blocks (u64),
+//!   and the CRC-32 of the trailer bytes before it (u32).
+//!
+//! The blocks are written while the tree is traversed, so the total only comes at the end.
+
+use std::io::{Read, Write};
+
+use crate::{error::CheckpointError, utils::crc32};
+
+const MAGIC: &[u8; 8] = b"CONGEECK";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 4 + 1 + 4;
+const TRAILER_LEN: usize = 2 + 8;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Map,
+    Set,
+}
+
+impl Kind {
+    fn tag(self) -> u8 {
+        match self {
+            Kind::Map => 0,
+            Kind::Set => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Kind::Map),
+            1 => Some(Kind::Set),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Map => "map",
+            Kind::Set => "set",
+        }
+    }
+
+    fn entry_len(self) -> usize {
+        match self {
+            Kind::Map => 1 + 8,
+            Kind::Set => 1,
+        }
+    }
+}
+
+/// Writes the entries, in strictly ascending key order, as a checkpoint.
+pub(crate) fn write<W: Write + ?Sized, const K_LEN: usize>(
+    w: &mut W,
+    kind: Kind,
+    entries: impl Iterator<Item = ([u8; K_LEN], usize)>,
+) -> Result<(), CheckpointError> {
+    let mut header = Vec::with_capacity(HEADER_LEN + 4);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.push(kind.tag());
+    header.extend_from_slice(&(K_LEN as u32).to_le_bytes());
+    header.extend_from_slice(&crc32(&header).to_le_bytes());
+    w.write_all(&header)?;
+
+    let mut block = Vec::new();
+    let mut count = 0u16;
+    let mut written = 0u64;
+    for (k, v) in entries {
+        if count > 0 && block[2..K_LEN + 1] != k[..K_LEN - 1] {
+            write_block(w, &mut block, count)?;
+            count = 0;
+        }
+        if count == 0 {
+            block.extend_from_slice(&0u16.to_le_bytes());
+            block.extend_from_slice(&k[..K_LEN - 1]);
+        }
+        block.push(k[K_LEN - 1]);
+        if kind == Kind::Map {
+            block.extend_from_slice(&(v as u64).to_le_bytes());
+        }
+        count += 1;
+        written += 1;
+    }
+    if count > 0 {
+        write_block(w, &mut block, count)?;
+    }
+
+    let mut trailer = Vec::with_capacity(TRAILER_LEN + 4);
+    trailer.extend_from_slice(&0u16.to_le_bytes());
+    trailer.extend_from_slice(&written.to_le_bytes());
+    trailer.extend_from_slice(&crc32(&trailer).to_le_bytes());
+    w.write_all(&trailer)?;
+    Ok(())
+}
+
+/// Fills in the entry count of the block, writes it with its checksum and clears it.
+fn write_block<W: Write + ?Sized>(
+    w: &mut W,
+    block: &mut Vec<u8>,
+    count: u16,
+) -> Result<(), CheckpointError> {
+    block[..2].copy_from_slice(&count.to_le_bytes());
+    let crc = crc32(block);
+    block.extend_from_slice(&crc.to_le_bytes());
+    w.write_all(block)?;
+    block.clear();
+    Ok(())
+}
+
+/// Reads the entries of a checkpoint in ascending key order, checking each block before any of its entries is returned.
+///
+/// Iteration stops at the trailer or at the first error, which [finish](Self::finish) returns.
+pub(crate) struct Reader<R: Read, const K_LEN: usize> {
+    reader: R,
+    kind: Kind,
+    read: u64,
+    block_index: u64,
+    block: std::vec::IntoIter<([u8; K_LEN], usize)>,
+    last: Option<[u8; K_LEN]>,
+    done: bool,
+    error: Option<CheckpointError>,
+}
+
+impl<R: Read, const K_LEN: usize> Reader<R, K_LEN> {
+    /// Reads and checks the header.
+    pub(crate) fn new(mut reader: R, kind: Kind) -> Result<Self, CheckpointError> {
+        let mut header = [0u8; HEADER_LEN + 4];
+        reader.read_exact(&mut header)?;
+        let (header, crc) = header.split_at(HEADER_LEN);
+
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(CheckpointError::BadMagic);
+        }
+        let mut at = MAGIC.len();
+        let mut take = |n: usize| {
+            let bytes = &header[at..at + n];
+            at += n;
+            bytes
+        };
+        let version = u32::from_le_bytes(take(4).try_into().unwrap());
+        if version != VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+        let tag = take(1)[0];
+        let key_len = u32::from_le_bytes(take(4).try_into().unwrap()) as usize;
+        if crc32(header) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return Err(CheckpointError::CorruptedHeader);
+        }
+        let found =
+            Kind::from_tag(tag).ok_or(CheckpointError::Malformed("unknown kind of tree"))?;
+        if found != kind {
+            return Err(CheckpointError::KindMismatch {
+                expected: kind.name(),
+                found: found.name(),
+            });
+        }
+        if key_len != K_LEN {
+            return Err(CheckpointError::KeyLengthMismatch {
+                expected: K_LEN,
+                found: key_len,
+            });
+        }
+
+        Ok(Self {
+            reader,
+            kind,
+            read: 0,
+            block_index: 0,
+            block: Vec::new().into_iter(),
+            last: None,
+            done: false,
+            error: None,
+        })
+    }
+
+    /// Reads the next block, or the trailer after the last one.
+    fn read_block(&mut self) -> Result<(), CheckpointError> {
+        let corrupted = CheckpointError::CorruptedBlock {
+            block: self.block_index,
+        };
+        let mut block = vec![0u8; 2];
+        self.reader.read_exact(&mut block)?;
+        let count = u16::from_le_bytes([block[0], block[1]]) as usize;
+        if count == 0 {
+            return self.read_trailer(block);
+        }
+        if count > 256 {
+            // The count is not covered by a checksum yet, an invalid one can only come from corruption.
+            return Err(corrupted);
+        }
+        block.resize(2 + K_LEN - 1 + count * self.kind.entry_len() + 4, 0);
+        self.reader.read_exact(&mut block[2..])?;
+        let (block, crc) = block.split_at(block.len() - 4);
+        if crc32(block) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return Err(corrupted);
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        let mut key = [0u8; K_LEN];
+        key[..K_LEN - 1].copy_from_slice(&block[2..K_LEN + 1]);
+        for entry in block[K_LEN + 1..].chunks_exact(self.kind.entry_len()) {
+            key[K_LEN - 1] = entry[0];
+            if self.last.is_some_and(|last| last >= key) {
+                return Err(CheckpointError::Malformed(
+                    "keys are not in ascending order",
+                ));
+            }
+            self.last = Some(key);
+            let v = match self.kind {
+                Kind::Map => usize::try_from(u64::from_le_bytes(entry[1..].try_into().unwrap()))
+                    .map_err(|_| CheckpointError::Malformed("value does not fit in usize"))?,
+                Kind::Set => 0,
+            };
+            entries.push((key, v));
+        }
+        self.read += count as u64;
+        self.block_index += 1;
+        self.block = entries.into_iter();
+        Ok(())
+    }
+
+    /// Reads the rest of the trailer, whose zero entry count is already in `trailer`.
+    fn read_trailer(&mut self, mut trailer: Vec<u8>) -> Result<(), CheckpointError> {
+        trailer.resize(TRAILER_LEN + 4, 0);
+        self.reader.read_exact(&mut trailer[2..])?;
+        let (trailer, crc) = trailer.split_at(TRAILER_LEN);
+        if crc32(trailer) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return Err(CheckpointError::CorruptedTrailer);
+        }
+        if u64::from_le_bytes(trailer[2..].try_into().unwrap()) != self.read {
+            return Err(CheckpointError::Malformed(
+                "the number of entries does not match the trailer",
+            ));
+        }
+        self.done = true;
+        Ok(())
+    }
+
+    /// Returns the error that stopped the iteration, if any.
+    pub(crate) fn finish(self) -> Result<(), CheckpointError> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: Read, const K_LEN: usize> Iterator for Reader<R, K_LEN> {
+    type Item = ([u8; K_LEN], usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.block.next() {
+                return Some(entry);
+            }
+            if self.done || self.error.is_some() {
+                return None;
+            }
+            if let Err(e) = self.read_block() {
+                self.error = Some(e);
+                return None;
+            }
+        }
+    }
+}
@@ -1,40 +1,70 @@
-use std::{marker::PhantomData, ptr::with_exposed_provenance, sync::Arc};
-
-use crate::{CongeeInner, DefaultAllocator, epoch, error::OOMError};
+use std::{
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    ptr::with_exposed_provenance,
+    sync::Arc,
+};
+
+use crate::{
+    CongeeInner, CongeeIter, CongeeSnapshot, DefaultAllocator, KeyEncoding, epoch,
+    error::OOMError,
+    iter::{RawIter, encode_bound, prefix_bounds},
+    range_scan::Converted,
+};
 
 /// A concurrent map-like data structure that uses Arc for reference counting of values.
 ///
 /// CongeeArc provides a way to store Arc-wrapped values in a concurrent tree structure.
 /// It automatically manages reference counting when inserting, retrieving, and removing values.
-pub struct Congee<K: From<usize> + Copy, V: Sync + Send + 'static>
-where
-    usize: From<K>,
-{
-    inner: Arc<CongeeInner<8, DefaultAllocator>>,
+///
+/// `K_LEN` is the length of the key in bytes, keys are converted to bytes by [KeyEncoding].
+pub struct Congee<K: KeyEncoding<K_LEN>, V: Sync + Send + 'static, const K_LEN: usize = 8> {
+    inner: Arc<CongeeInner<K_LEN, DefaultAllocator>>,
     pt_val: PhantomData<V>,
     pt_key: PhantomData<K>,
 }
 
-unsafe fn arc_from_usize<V>(v: usize) -> Arc<V> {
+pub(crate) unsafe fn arc_from_usize<V>(v: usize) -> Arc<V> {
     // # Safety
     // The pointer was previously inserted with expose_provenance
     let ptr: *const V = with_exposed_provenance(v);
     unsafe { Arc::from_raw(ptr) }
 }
 
-impl<K: From<usize> + Copy, V: Sync + Send + 'static> Default for Congee<K, V>
-where
-    usize: From<K>,
+/// Drain callback of the tree, releases the reference the tree holds on a value.
+fn release_value<V, const K_LEN: usize>(_k: [u8; K_LEN], v: usize) {
+    // Safety
+    // The pointer was previously inserted with expose_provenance
+    let owned = unsafe { arc_from_usize::<V>(v) };
+    drop(owned);
+}
+
+/// Clone a value stored in the tree, the tree keeps its own reference.
+pub(crate) fn clone_value<V>(v: usize) -> Arc<V> {
+    // Safety: The pointer was previously inserted with expose_provenance,
+    // and removed values are only released after the guard is dropped.
+    let owned = unsafe { arc_from_usize::<V>(v) };
+    let value = owned.clone();
+    _ = Arc::into_raw(owned); // Leak to maintain reference in tree
+    value
+}
+
+/// Clone the value of an entry, the tree keeps its own reference.
+pub(crate) fn clone_entry<K: KeyEncoding<K_LEN>, V, const K_LEN: usize>(
+    (k, v): ([u8; K_LEN], usize),
+) -> (K, Arc<V>) {
+    (K::from_key_bytes(k), clone_value(v))
+}
+
+impl<K: KeyEncoding<K_LEN>, V: Sync + Send + 'static, const K_LEN: usize> Default
+    for Congee<K, V, K_LEN>
 {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<K: From<usize> + Copy, V: Sync + Send + 'static> Congee<K, V>
-where
-    usize: From<K>,
-{
+impl<K: KeyEncoding<K_LEN>, V: Sync + Send + 'static, const K_LEN: usize> Congee<K, V, K_LEN> {
     /// Creates a new empty CongeeArc instance.
     ///
     /// # Examples
@@ -46,19 +76,51 @@ where
     /// let tree: Congee<usize, String> = Congee::new();
     /// ```
     pub fn new() -> Self {
-        let drainer = |_k: [u8; 8], v: usize| {
-            // Safety
-            // The pointer was previously inserted with expose_provenance
-            let owned = unsafe { arc_from_usize::<V>(v) };
-            drop(owned);
-        };
         Self {
-            inner: Arc::new(CongeeInner::new(DefaultAllocator {}, Arc::new(drainer))),
+            inner: Arc::new(CongeeInner::new(
+                DefaultAllocator {},
+                Arc::new(release_value::<V, K_LEN>),
+            )),
             pt_val: PhantomData,
             pt_key: PhantomData,
         }
     }
 
+    /// Builds a tree from key-value pairs in strictly ascending key order.
+    ///
+    /// The nodes are built bottom-up with their final size,
+    /// which is much faster than inserting the keys one by one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not in strictly ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> =
+    ///     Congee::from_sorted_iter((0..100).map(|k| (k, Arc::new(k.to_string())))).unwrap();
+    /// let guard = tree.pin();
+    /// assert_eq!(tree.get(42, &guard).unwrap().as_str(), "42");
+    /// ```
+    pub fn from_sorted_iter(iter: impl IntoIterator<Item = (K, Arc<V>)>) -> Result<Self, OOMError> {
+        let entries = iter
+            .into_iter()
+            .map(|(k, v)| (k.to_key_bytes(), Arc::into_raw(v).expose_provenance()));
+        Ok(Self {
+            inner: Arc::new(CongeeInner::from_sorted_iter(
+                DefaultAllocator {},
+                Arc::new(release_value::<V, K_LEN>),
+                entries,
+            )?),
+            pt_val: PhantomData,
+            pt_key: PhantomData,
+        })
+    }
+
     /// Enters an epoch.
     ///
     /// This is necessary before performing operations on the tree.
@@ -98,6 +160,26 @@ where
         self.inner.is_empty(guard)
     }
 
+    /// Returns the number of keys in the tree, in O(1).
+    ///
+    /// The count is exact when no insert or remove runs concurrently, otherwise those may or may not be counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    /// assert_eq!(tree.len(&guard), 0);
+    /// tree.insert(1, Arc::new(String::from("value")), &guard).unwrap();
+    /// assert_eq!(tree.len(&guard), 1);
+    /// ```
+    pub fn len(&self, guard: &epoch::Guard) -> usize {
+        self.inner.len(guard)
+    }
+
     /// Removes a key-value pair from the tree and returns the removed value (if present).
     ///
     /// Note: Congee holds a reference to the removed value until the guard is flushed.
@@ -119,8 +201,7 @@ where
     /// assert!(tree.is_empty(&guard));
     /// ```
     pub fn remove(&self, key: K, guard: &epoch::Guard) -> Option<Arc<V>> {
-        let usize_key: usize = usize::from(key);
-        let key: [u8; 8] = usize_key.to_be_bytes();
+        let key = key.to_key_bytes();
         let (old, new) = self.inner.compute_if_present(&key, &mut |_v| None, guard)?;
         debug_assert!(new.is_none());
 
@@ -134,6 +215,67 @@ where
         Some(rt)
     }
 
+    /// Removes all keys within `range` and returns the number of removed entries.
+    ///
+    /// The removal is atomic: concurrent operations see either all keys of the range or none of them.
+    /// The tree's references to the removed values are released once no reader can see them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    /// for i in 0..10 {
+    ///     tree.insert(i, Arc::new(i.to_string()), &guard).unwrap();
+    /// }
+    ///
+    /// assert_eq!(tree.remove_range(..5, &guard), 5);
+    /// assert!(tree.get(4, &guard).is_none());
+    /// assert_eq!(tree.get(5, &guard).unwrap().as_str(), "5");
+    /// ```
+    pub fn remove_range<R: RangeBounds<K>>(&self, range: R, guard: &epoch::Guard) -> usize {
+        self.inner.remove_range(
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            guard,
+        )
+    }
+
+    /// Keeps only the entries for which `f` returns true, returns the number of removed entries.
+    ///
+    /// Each entry is checked and removed atomically, entries inserted concurrently may or may not be visited.
+    /// Note that `f` must be safe to execute multiple times on the same entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1, Arc::new(String::from("keep")), &guard).unwrap();
+    /// tree.insert(2, Arc::new(String::from("drop")), &guard).unwrap();
+    ///
+    /// assert_eq!(tree.retain(|_k, v| v == "keep", &guard), 1);
+    /// assert!(tree.get(1, &guard).is_some());
+    /// assert!(tree.get(2, &guard).is_none());
+    /// ```
+    pub fn retain<F: FnMut(K, &V) -> bool>(&self, mut f: F, guard: &epoch::Guard) -> usize {
+        self.inner.retain(
+            |k, v| {
+                // Safety: The pointer was previously inserted with expose_provenance,
+                // and removed values are only released after the guard is dropped.
+                let value = unsafe { &*with_exposed_provenance::<V>(v) };
+                f(K::from_key_bytes(*k), value)
+            },
+            guard,
+        )
+    }
+
     /// Retrieves a value from the tree without removing it.
     ///
     /// # Examples
@@ -152,8 +294,7 @@ where
     /// assert_eq!(retrieved.as_ref(), "hello");
     /// ```
     pub fn get(&self, key: K, guard: &epoch::Guard) -> Option<Arc<V>> {
-        let usize_key: usize = usize::from(key);
-        let key: [u8; 8] = usize_key.to_be_bytes();
+        let key = key.to_key_bytes();
         let v = self.inner.get(&key, guard)?;
 
         // Get
@@ -169,6 +310,40 @@ where
         Some(rt)
     }
 
+    /// Looks up all `keys` at once, writing the value of `keys[i]` to `values[i]`.
+    ///
+    /// Consecutive lookups share the part of the traversal their keys have in common,
+    /// sorting the keys makes this much faster than calling [get](Self::get) for each key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` and `values` have different lengths.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1, Arc::new(String::from("one")), &guard).unwrap();
+    ///
+    /// let mut values = vec![None, None];
+    /// tree.get_many(&[1, 2], &mut values, &guard);
+    /// assert_eq!(values[0].as_deref().unwrap(), "one");
+    /// assert!(values[1].is_none());
+    /// ```
+    pub fn get_many(&self, keys: &[K], values: &mut [Option<Arc<V>>], guard: &epoch::Guard) {
+        assert_eq!(keys.len(), values.len());
+        let mut values = values.iter_mut();
+        self.inner.get_many(
+            keys.iter().map(|k| k.to_key_bytes()),
+            |raw| *values.next().unwrap() = raw.map(clone_value),
+            guard,
+        );
+    }
+
     /// Inserts a key-value pair into the tree.
     ///
     /// If the key already exists, the old value is replaced and returned.
@@ -197,8 +372,7 @@ where
         val: Arc<V>,
         guard: &epoch::Guard,
     ) -> Result<Option<Arc<V>>, OOMError> {
-        let usize_key: usize = usize::from(key);
-        let key: [u8; 8] = usize_key.to_be_bytes();
+        let key = key.to_key_bytes();
 
         // Insertion
         // 1. Get the pointer of the value, consume the Arc
@@ -222,6 +396,146 @@ where
         }
     }
 
+    /// Inserts a key-value pair only if the key is absent.
+    ///
+    /// If the key already exists, the existing value is left in place and returned as `Err`,
+    /// and `val` is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    ///
+    /// let value1 = Arc::new(String::from("hello"));
+    /// assert!(tree.insert_if_absent(1, value1, &guard).unwrap().is_ok());
+    ///
+    /// let value2 = Arc::new(String::from("world"));
+    /// let existing = tree.insert_if_absent(1, value2, &guard).unwrap().unwrap_err();
+    /// assert_eq!(existing.as_ref(), "hello");
+    /// assert_eq!(tree.get(1, &guard).unwrap().as_ref(), "hello");
+    /// ```
+    pub fn insert_if_absent(
+        &self,
+        key: K,
+        val: Arc<V>,
+        guard: &epoch::Guard,
+    ) -> Result<Result<(), Arc<V>>, OOMError> {
+        let key = key.to_key_bytes();
+        let ptr_usize = Arc::into_raw(val).expose_provenance();
+        let existing = self.inner.insert_if_absent(&key, ptr_usize, guard);
+        match existing {
+            Ok(None) => Ok(Ok(())),
+            Ok(Some(v)) => {
+                // Safety: the value was not inserted, so we still own it.
+                drop(unsafe { arc_from_usize::<V>(ptr_usize) });
+                Ok(Err(clone_value(v)))
+            }
+            Err(e) => {
+                // Safety: the value was not inserted, so we still own it.
+                drop(unsafe { arc_from_usize::<V>(ptr_usize) });
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the value of the key, inserting the value made by `f` if the key is absent.
+    ///
+    /// `f` runs at most once. If another thread inserts the key first, its value is returned and the one made by `f` is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    ///
+    /// let v = tree.get_or_insert_with(1, || String::from("hello"), &guard).unwrap();
+    /// assert_eq!(v.as_ref(), "hello");
+    ///
+    /// let v = tree.get_or_insert_with(1, || unreachable!(), &guard).unwrap();
+    /// assert_eq!(v.as_ref(), "hello");
+    /// ```
+    pub fn get_or_insert_with<F>(
+        &self,
+        key: K,
+        f: F,
+        guard: &epoch::Guard,
+    ) -> Result<Arc<V>, OOMError>
+    where
+        F: FnOnce() -> V,
+    {
+        if let Some(v) = self.get(key, guard) {
+            return Ok(v);
+        }
+        let val = Arc::new(f());
+        match self.insert_if_absent(key, val.clone(), guard)? {
+            Ok(()) => Ok(val),
+            Err(existing) => Ok(existing),
+        }
+    }
+
+    /// Replaces the value of the key with `new` if the current value is `current`, compared with [Arc::ptr_eq].
+    /// A `new` of `None` removes the key.
+    ///
+    /// Returns `Ok` with the new value on success, or `Err` with the actual value otherwise, in which case `new` is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    ///
+    /// let v1 = Arc::new(String::from("hello"));
+    /// tree.insert(1, v1.clone(), &guard).unwrap();
+    ///
+    /// // An equal string in another allocation does not match.
+    /// let other = Arc::new(String::from("hello"));
+    /// let actual = tree.compare_exchange(1, &other, None, &guard).unwrap_err();
+    /// assert!(Arc::ptr_eq(&actual.unwrap(), &v1));
+    ///
+    /// let v2 = Arc::new(String::from("world"));
+    /// tree.compare_exchange(1, &v1, Some(v2), &guard).unwrap();
+    /// assert_eq!(tree.get(1, &guard).unwrap().as_ref(), "world");
+    /// ```
+    pub fn compare_exchange(
+        &self,
+        key: K,
+        current: &Arc<V>,
+        new: Option<Arc<V>>,
+        guard: &epoch::Guard,
+    ) -> Result<Option<Arc<V>>, Option<Arc<V>>> {
+        let key = key.to_key_bytes();
+        let current = Arc::as_ptr(current).expose_provenance();
+        let new_v = new.map(|v| Arc::into_raw(v).expose_provenance());
+        let mut fc = |v: usize| -> Option<usize> { if v == current { new_v } else { Some(v) } };
+        let v = self.inner.compute_if_present(&key, &mut fc, guard);
+        match v {
+            Some((actual_old, actual_new)) if actual_old == current && actual_new == new_v => {
+                // The tree's reference to the old value is released once no reader can see it.
+                let old = unsafe { arc_from_usize::<V>(actual_old) };
+                guard.defer(move || {
+                    drop(old);
+                });
+                Ok(new_v.map(clone_value))
+            }
+            v => {
+                if let Some(ptr) = new_v {
+                    // Safety: the value was never inserted, so we still own it.
+                    drop(unsafe { arc_from_usize::<V>(ptr) });
+                }
+                Err(v.and_then(|(_old, actual)| actual).map(clone_value))
+            }
+        }
+    }
+
     /// Computes a new value for a key if it exists in the tree.
     ///
     /// The function `f` is called with the current value and should return an optional new value.
@@ -261,9 +575,14 @@ where
     where
         F: FnMut(Arc<V>) -> Option<Arc<V>>,
     {
-        let usize_key: usize = usize::from(key);
-        let key: [u8; 8] = usize_key.to_be_bytes();
+        let key = key.to_key_bytes();
+        let mut pending = None;
         let mut inner_f = |v: usize| {
+            // The closure only runs again when the previous attempt failed before writing its value.
+            if let Some(ptr) = pending.take() {
+                // Safety: the value was never inserted, so we still own it.
+                drop(unsafe { arc_from_usize::<V>(ptr) });
+            }
             // Safety
             // The pointer was previously inserted with expose_provenance
             let owned = unsafe { arc_from_usize::<V>(v) };
@@ -271,13 +590,21 @@ where
             let rt = f(owned_clone);
             _ = Arc::into_raw(owned);
             if let Some(new) = rt {
-                let new_v = Arc::into_raw(new);
-                Some(new_v.expose_provenance())
+                let new_v = Arc::into_raw(new).expose_provenance();
+                pending = Some(new_v);
+                Some(new_v)
             } else {
                 None
             }
         };
-        let (old, _new) = self.inner.compute_if_present(&key, &mut inner_f, guard)?;
+        let Some((old, _new)) = self.inner.compute_if_present(&key, &mut inner_f, guard) else {
+            // The key was removed after a failed attempt.
+            if let Some(ptr) = pending {
+                // Safety: the value was never inserted, so we still own it.
+                drop(unsafe { arc_from_usize::<V>(ptr) });
+            }
+            return None;
+        };
         let old_owned = unsafe { arc_from_usize::<V>(old) };
         let delayed_v = old_owned.clone();
         guard.defer(move || {
@@ -286,6 +613,118 @@ where
         Some(old_owned)
     }
 
+    /// Returns an iterator over all entries in key order.
+    ///
+    /// The entries are fetched lazily, so the iterator never needs a pre-sized buffer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(2, Arc::new(String::from("b")), &guard).unwrap();
+    /// tree.insert(1, Arc::new(String::from("a")), &guard).unwrap();
+    ///
+    /// let entries: Vec<_> = tree.iter(&guard).map(|(k, v)| (k, v.to_string())).collect();
+    /// assert_eq!(entries, vec![(1, "a".to_string()), (2, "b".to_string())]);
+    /// ```
+    pub fn iter<'a>(&'a self, guard: &'a epoch::Guard) -> CongeeIter<'a, K, V, K_LEN> {
+        self.range_iter(.., guard)
+    }
+
+    /// Takes a read-only view of the tree as of a single point in time.
+    ///
+    /// Reads on the snapshot see one consistent state, however long they take.
+    /// Writers don't wait for the snapshot, the values they replace are recorded until the last snapshot is dropped.
+    /// The guard stays pinned as long as the snapshot is alive, which keeps the replaced values alive as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1, Arc::new("old".to_string()), &guard).unwrap();
+    ///
+    /// let snapshot = tree.snapshot(&guard);
+    /// tree.insert(1, Arc::new("new".to_string()), &guard).unwrap();
+    ///
+    /// assert_eq!(snapshot.get(1).unwrap().as_str(), "old");
+    /// assert_eq!(tree.get(1, &guard).unwrap().as_str(), "new");
+    /// ```
+    pub fn snapshot<'a>(&'a self, guard: &'a epoch::Guard) -> CongeeSnapshot<'a, K, V, K_LEN> {
+        CongeeSnapshot::new(&self.inner, guard)
+    }
+
+    /// Returns an iterator over the entries within `range` in key order.
+    /// Call `rev` on the iterator to walk the entries from the largest key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// for i in 0..10 {
+    ///     tree.insert(i, Arc::new(i * 10), &guard).unwrap();
+    /// }
+    ///
+    /// let values: Vec<_> = tree.range_iter(7.., &guard).map(|(_k, v)| *v).collect();
+    /// assert_eq!(values, vec![70, 80, 90]);
+    ///
+    /// let latest: Vec<_> = tree.range_iter(..5, &guard).rev().map(|(k, _v)| k).take(2).collect();
+    /// assert_eq!(latest, vec![4, 3]);
+    /// ```
+    pub fn range_iter<'a, R: RangeBounds<K>>(
+        &'a self,
+        range: R,
+        guard: &'a epoch::Guard,
+    ) -> CongeeIter<'a, K, V, K_LEN> {
+        CongeeIter::new(RawIter::new(
+            &self.inner,
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            guard,
+        ))
+    }
+
+    /// Iterates over the keys whose first `prefix_len` encoded bytes equal those of `prefix`, in ascending order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<u64, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// for k in [0x0100, 0x01ff, 0x0200] {
+    ///     tree.insert(k, Arc::new(k as usize), &guard).unwrap();
+    /// }
+    /// let keys: Vec<u64> = tree.iter_prefix(&0x0100, 7, &guard).map(|(k, _)| k).collect();
+    /// assert_eq!(keys, vec![0x0100, 0x01ff]);
+    /// ```
+    pub fn iter_prefix<'a>(
+        &'a self,
+        prefix: &K,
+        prefix_len: usize,
+        guard: &'a epoch::Guard,
+    ) -> CongeeIter<'a, K, V, K_LEN> {
+        let (start, end) = prefix_bounds(prefix, prefix_len);
+        CongeeIter::new(RawIter::new(&self.inner, start, end, guard))
+    }
+
     /// Retrieves all keys from the tree.
     ///
     /// Isolation level: read committed.
@@ -313,14 +752,16 @@ where
         self.inner
             .keys()
             .into_iter()
-            .map(|k| K::from(usize::from_be_bytes(k)))
+            .map(K::from_key_bytes)
             .collect()
     }
 
-    /// Scan the tree with the range of [start, end], write the result to the
+    /// Scan the tree with the range of [start, end), write the result to the
     /// `result` buffer.
     /// It scans the length of `result` or the number of the keys within the range, whichever is smaller;
     /// returns the number of the keys scanned.
+    /// The entries are written straight to `result`,
+    /// entries past the returned count may have been overwritten by a scan that restarted on a concurrent write.
     ///
     /// # Examples
     ///
@@ -351,41 +792,346 @@ where
         result: &mut [(K, Option<Arc<V>>)],
         guard: &epoch::Guard,
     ) -> usize {
-        let start_usize = usize::from(*start);
-        let end_usize = usize::from(*end);
-        let start_bytes: [u8; 8] = start_usize.to_be_bytes();
-        let end_bytes: [u8; 8] = end_usize.to_be_bytes();
+        let start_bytes = start.to_key_bytes();
+        let end_bytes = end.to_key_bytes();
+
+        let mut result =
+            Converted::new(result, |k, v| (K::from_key_bytes(k), Some(clone_value(v))));
+        self.inner
+            .range(&start_bytes, &end_bytes, &mut result, guard)
+    }
+
+    /// Same as [range](Self::range), but takes any [RangeBounds], so each end can be included, excluded or unbounded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1, Arc::new(10), &guard).unwrap();
+    /// tree.insert(usize::MAX, Arc::new(20), &guard).unwrap();
+    ///
+    /// let mut result = vec![(0usize, None::<Arc<usize>>); 2];
+    /// assert_eq!(tree.range_bounds(1..=1, &mut result, &guard), 1);
+    /// assert_eq!(result[0].0, 1);
+    ///
+    /// assert_eq!(tree.range_bounds(2.., &mut result, &guard), 1);
+    /// assert_eq!(result[0].0, usize::MAX);
+    /// assert_eq!(result[0].1.as_deref(), Some(&20));
+    /// ```
+    pub fn range_bounds<R: RangeBounds<K>>(
+        &self,
+        range: R,
+        result: &mut [(K, Option<Arc<V>>)],
+        guard: &epoch::Guard,
+    ) -> usize {
+        self.scan_encoded_bounds(
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            result,
+            guard,
+        )
+    }
+
+    fn scan_encoded_bounds(
+        &self,
+        start: Bound<[u8; K_LEN]>,
+        end: Bound<[u8; K_LEN]>,
+        result: &mut [(K, Option<Arc<V>>)],
+        guard: &epoch::Guard,
+    ) -> usize {
+        let mut result =
+            Converted::new(result, |k, v| (K::from_key_bytes(k), Some(clone_value(v))));
+        self.inner.range_bounds(start, end, &mut result, guard)
+    }
+
+    /// Returns the number of keys within `range`, without copying them out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// for i in 0..100 {
+    ///     tree.insert(i, Arc::new(i), &guard).unwrap();
+    /// }
+    ///
+    /// assert_eq!(tree.count_range(10..20, &guard), 10);
+    /// assert_eq!(tree.count_range(..=10, &guard), 11);
+    /// assert_eq!(tree.count_range(.., &guard), 100);
+    /// ```
+    pub fn count_range<R: RangeBounds<K>>(&self, range: R, guard: &epoch::Guard) -> usize {
+        self.inner.count_range(
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            guard,
+        )
+    }
+
+    /// Scans the keys whose first `prefix_len` encoded bytes equal those of `prefix`, in ascending order.
+    /// Writes them to `result` and returns the number of keys scanned, at most the length of `result`.
+    ///
+    /// The scan descends straight to the subtree holding the prefix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<u64, String> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1 << 48 | 1, Arc::new("a".to_string()), &guard).unwrap();
+    /// tree.insert(2 << 48 | 1, Arc::new("b".to_string()), &guard).unwrap();
+    ///
+    /// let mut result = vec![(0, None); 4];
+    /// assert_eq!(tree.scan_prefix(&(2 << 48), 2, &mut result, &guard), 1);
+    /// assert_eq!(result[0].0, 2 << 48 | 1);
+    /// assert_eq!(result[0].1.as_ref().unwrap().as_str(), "b");
+    /// ```
+    pub fn scan_prefix(
+        &self,
+        prefix: &K,
+        prefix_len: usize,
+        result: &mut [(K, Option<Arc<V>>)],
+        guard: &epoch::Guard,
+    ) -> usize {
+        let mut result =
+            Converted::new(result, |k, v| (K::from_key_bytes(k), Some(clone_value(v))));
+        self.inner
+            .scan_prefix(&prefix.to_key_bytes(), prefix_len, &mut result, guard)
+    }
+
+    /// Returns the number of keys whose first `prefix_len` encoded bytes equal those of `prefix`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<u64, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// for i in 0..5 {
+    ///     tree.insert(1 << 48 | i, Arc::new(0), &guard).unwrap();
+    /// }
+    /// assert_eq!(tree.count_prefix(&(1 << 48), 2, &guard), 5);
+    /// assert_eq!(tree.count_prefix(&(2 << 48), 2, &guard), 0);
+    /// ```
+    pub fn count_prefix(&self, prefix: &K, prefix_len: usize, guard: &epoch::Guard) -> usize {
+        self.inner
+            .count_prefix(&prefix.to_key_bytes(), prefix_len, guard)
+    }
+
+    /// Removes all keys whose first `prefix_len` encoded bytes equal those of `prefix`,
+    /// returns the number of removed entries.
+    /// The removal is atomic like [remove_range](Self::remove_range).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<u64, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1 << 48 | 7, Arc::new(1), &guard).unwrap();
+    /// tree.insert(2 << 48 | 7, Arc::new(2), &guard).unwrap();
+    /// assert_eq!(tree.remove_prefix(&(1 << 48), 2, &guard), 1);
+    /// assert!(tree.get(1 << 48 | 7, &guard).is_none());
+    /// assert_eq!(*tree.get(2 << 48 | 7, &guard).unwrap(), 2);
+    /// ```
+    pub fn remove_prefix(&self, prefix: &K, prefix_len: usize, guard: &epoch::Guard) -> usize {
+        let (start, end) = prefix_bounds(prefix, prefix_len);
+        self.inner.remove_range(start, end, guard)
+    }
+
+    /// Same as [range](Self::range), but the keys are scanned in descending order,
+    /// so the buffer holds the largest keys below `end`, largest first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// for i in 0..10 {
+    ///     tree.insert(i, Arc::new(i * 10), &guard).unwrap();
+    /// }
+    ///
+    /// let mut result = vec![(0usize, None::<Arc<usize>>); 2];
+    /// let scanned = tree.range_rev(&0, &5, &mut result, &guard);
+    /// assert_eq!(scanned, 2);
+    /// assert_eq!(result[0].0, 4);
+    /// assert_eq!(result[1].0, 3);
+    /// assert_eq!(result[0].1.as_deref(), Some(&40));
+    /// ```
+    pub fn range_rev(
+        &self,
+        start: &K,
+        end: &K,
+        result: &mut [(K, Option<Arc<V>>)],
+        guard: &epoch::Guard,
+    ) -> usize {
+        let start_bytes = start.to_key_bytes();
+        let end_bytes = end.to_key_bytes();
 
-        // Create a temporary buffer for the raw results
-        let mut raw_result: Vec<([u8; 8], usize)> = vec![([0; 8], 0); result.len()];
+        let mut result =
+            Converted::new(result, |k, v| (K::from_key_bytes(k), Some(clone_value(v))));
+        self.inner
+            .range_rev(&start_bytes, &end_bytes, &mut result, guard)
+    }
 
-        let scanned = self
-            .inner
-            .range(&start_bytes, &end_bytes, &mut raw_result, guard);
+    /// Returns the entry with the smallest key, `None` if the tree is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    /// assert!(tree.first(&guard).is_none());
+    ///
+    /// tree.insert(3, Arc::new(String::from("c")), &guard).unwrap();
+    /// tree.insert(1, Arc::new(String::from("a")), &guard).unwrap();
+    /// let (k, v) = tree.first(&guard).unwrap();
+    /// assert_eq!((k, v.as_str()), (1, "a"));
+    /// ```
+    pub fn first(&self, guard: &epoch::Guard) -> Option<(K, Arc<V>)> {
+        self.inner.first(guard).map(clone_entry)
+    }
 
-        // Convert the raw results to the expected format
-        for i in 0..scanned {
-            let key_bytes = raw_result[i].0;
-            let val_ptr = raw_result[i].1;
+    /// Returns the entry with the largest key, `None` if the tree is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, String> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(3, Arc::new(String::from("c")), &guard).unwrap();
+    /// tree.insert(1, Arc::new(String::from("a")), &guard).unwrap();
+    /// let (k, v) = tree.last(&guard).unwrap();
+    /// assert_eq!((k, v.as_str()), (3, "c"));
+    /// ```
+    pub fn last(&self, guard: &epoch::Guard) -> Option<(K, Arc<V>)> {
+        self.inner.last(guard).map(clone_entry)
+    }
 
-            let key = K::from(usize::from_be_bytes(key_bytes));
+    /// Returns the entry with the smallest key strictly greater than `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1, Arc::new(10), &guard).unwrap();
+    /// tree.insert(3, Arc::new(30), &guard).unwrap();
+    ///
+    /// assert_eq!(tree.successor(&1, &guard).map(|(k, v)| (k, *v)), Some((3, 30)));
+    /// assert!(tree.successor(&3, &guard).is_none());
+    /// ```
+    pub fn successor(&self, key: &K, guard: &epoch::Guard) -> Option<(K, Arc<V>)> {
+        let key = key.to_key_bytes();
+        self.inner.successor(&key, guard).map(clone_entry)
+    }
 
-            // Convert the usize pointer back to Arc<V>
-            // Safety: The pointer was previously inserted with expose_provenance
-            let owned = unsafe { arc_from_usize::<V>(val_ptr) };
-            let arc_value = owned.clone();
-            _ = Arc::into_raw(owned); // Leak to maintain reference in tree
+    /// Returns the entry with the largest key strictly less than `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1, Arc::new(10), &guard).unwrap();
+    /// tree.insert(3, Arc::new(30), &guard).unwrap();
+    ///
+    /// assert_eq!(tree.predecessor(&3, &guard).map(|(k, v)| (k, *v)), Some((1, 10)));
+    /// assert!(tree.predecessor(&1, &guard).is_none());
+    /// ```
+    pub fn predecessor(&self, key: &K, guard: &epoch::Guard) -> Option<(K, Arc<V>)> {
+        let key = key.to_key_bytes();
+        self.inner.predecessor(&key, guard).map(clone_entry)
+    }
 
-            result[i] = (key, Some(arc_value));
-        }
+    /// Returns the entry with the largest key less than or equal to `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1, Arc::new(10), &guard).unwrap();
+    /// tree.insert(3, Arc::new(30), &guard).unwrap();
+    ///
+    /// assert_eq!(tree.floor(&2, &guard).map(|(k, v)| (k, *v)), Some((1, 10)));
+    /// assert_eq!(tree.floor(&3, &guard).map(|(k, v)| (k, *v)), Some((3, 30)));
+    /// ```
+    pub fn floor(&self, key: &K, guard: &epoch::Guard) -> Option<(K, Arc<V>)> {
+        let key = key.to_key_bytes();
+        self.inner.floor(&key, guard).map(clone_entry)
+    }
 
-        scanned
+    /// Returns the entry with the smallest key greater than or equal to `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::Congee;
+    /// use std::sync::Arc;
+    ///
+    /// let tree: Congee<usize, usize> = Congee::new();
+    /// let guard = tree.pin();
+    /// tree.insert(1, Arc::new(10), &guard).unwrap();
+    /// tree.insert(3, Arc::new(30), &guard).unwrap();
+    ///
+    /// assert_eq!(tree.ceiling(&2, &guard).map(|(k, v)| (k, *v)), Some((3, 30)));
+    /// assert!(tree.ceiling(&4, &guard).is_none());
+    /// ```
+    pub fn ceiling(&self, key: &K, guard: &epoch::Guard) -> Option<(K, Arc<V>)> {
+        let key = key.to_key_bytes();
+        self.inner.ceiling(&key, guard).map(clone_entry)
     }
 
     /// Compute or insert the value if the key is not in the tree, or update if it exists.
     /// Returns the old value if the key existed, None if it was inserted.
     ///
     /// Note that the function `f` is a FnMut and it must be safe to execute multiple times.
+    /// Values made by attempts that had to retry are dropped, use [get_or_insert_with](Self::get_or_insert_with)
+    /// if the value must be made only once.
     /// The `f` is expected to be short and fast as it will hold an exclusive lock on the leaf node.
     ///
     /// # Examples
@@ -429,9 +1175,9 @@ where
     where
         F: FnMut(Option<Arc<V>>) -> Arc<V>,
     {
-        let usize_key = usize::from(key);
-        let key_bytes: [u8; 8] = usize_key.to_be_bytes();
+        let key_bytes = key.to_key_bytes();
 
+        let mut pending = None;
         let mut inner_f = |existing_ptr: Option<usize>| -> usize {
             let existing_arc = if let Some(ptr) = existing_ptr {
                 // Safety: The pointer was previously inserted with expose_provenance
@@ -443,14 +1189,30 @@ where
                 None
             };
 
+            // The closure only runs again when the previous attempt failed before writing its value.
+            if let Some(ptr) = pending.take() {
+                // Safety: the value was never inserted, so we still own it.
+                drop(unsafe { arc_from_usize::<V>(ptr) });
+            }
             let new_arc = f(existing_arc);
-            let new_ptr = Arc::into_raw(new_arc);
-            new_ptr.expose_provenance()
+            let new_ptr = Arc::into_raw(new_arc).expose_provenance();
+            pending = Some(new_ptr);
+            new_ptr
         };
 
         let old_ptr = self
             .inner
-            .compute_or_insert(&key_bytes, &mut inner_f, guard)?;
+            .compute_or_insert(&key_bytes, &mut inner_f, guard);
+        let old_ptr = match old_ptr {
+            Ok(old_ptr) => old_ptr,
+            Err(e) => {
+                if let Some(ptr) = pending {
+                    // Safety: the value was never inserted, so we still own it.
+                    drop(unsafe { arc_from_usize::<V>(ptr) });
+                }
+                return Err(e);
+            }
+        };
 
         if let Some(ptr) = old_ptr {
             // There was an old value, return it
@@ -476,7 +1238,7 @@ where
 mod tests {
     use super::*;
     use std::sync::Arc;
-    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[cfg(all(feature = "shuttle", test))]
     use shuttle::thread;
@@ -1025,4 +1787,146 @@ mod tests {
             assert_eq!(value.as_ref(), &format!("updated_value-{i}"));
         }
     }
+
+    #[test]
+    fn test_insert_if_absent() {
+        let tree: Congee<usize, String> = Congee::new();
+        let guard = tree.pin();
+
+        let first = Arc::new(String::from("first"));
+        assert!(
+            tree.insert_if_absent(1, first.clone(), &guard)
+                .unwrap()
+                .is_ok()
+        );
+
+        let second = Arc::new(String::from("second"));
+        let existing = tree
+            .insert_if_absent(1, second.clone(), &guard)
+            .unwrap()
+            .unwrap_err();
+        assert!(Arc::ptr_eq(&existing, &first));
+        assert_eq!(tree.get(1, &guard).unwrap().as_ref(), "first");
+
+        // The rejected value is released right away.
+        assert_eq!(Arc::strong_count(&second), 1);
+        drop(existing);
+        assert_eq!(Arc::strong_count(&first), 2);
+    }
+
+    #[test]
+    fn test_compare_exchange() {
+        let tree: Congee<usize, String> = Congee::new();
+        let guard = tree.pin();
+        let v1 = Arc::new(String::from("v1"));
+        tree.insert(1, v1.clone(), &guard).unwrap();
+
+        // A mismatch hands the new value back to its owner.
+        let other = Arc::new(String::from("v1"));
+        let v2 = Arc::new(String::from("v2"));
+        let actual = tree
+            .compare_exchange(1, &other, Some(v2.clone()), &guard)
+            .unwrap_err();
+        assert!(Arc::ptr_eq(&actual.unwrap(), &v1));
+        assert_eq!(Arc::strong_count(&v2), 1);
+
+        let installed = tree
+            .compare_exchange(1, &v1, Some(v2.clone()), &guard)
+            .unwrap();
+        assert!(Arc::ptr_eq(&installed.unwrap(), &v2));
+        assert!(Arc::ptr_eq(&tree.get(1, &guard).unwrap(), &v2));
+
+        assert_eq!(tree.compare_exchange(1, &v2, None, &guard), Ok(None));
+        assert!(tree.get(1, &guard).is_none());
+        assert_eq!(tree.compare_exchange(1, &v2, None, &guard), Err(None));
+    }
+
+    #[test]
+    fn test_get_or_insert_with_concurrent() {
+        let tree: Arc<Congee<usize, usize>> = Arc::new(Congee::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+        for t in 0..4 {
+            let tree = tree.clone();
+            let calls = calls.clone();
+            handles.push(thread::spawn(move || {
+                let guard = tree.pin();
+                (0..1_000)
+                    .map(|k| {
+                        let mut runs = 0;
+                        let v = tree
+                            .get_or_insert_with(
+                                k,
+                                || {
+                                    runs += 1;
+                                    calls.fetch_add(1, Ordering::Relaxed);
+                                    t
+                                },
+                                &guard,
+                            )
+                            .unwrap();
+                        assert!(runs <= 1);
+                        *v
+                    })
+                    .collect::<Vec<_>>()
+            }));
+        }
+        let results: Vec<Vec<usize>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        // Every thread sees the value of the first insert.
+        let guard = tree.pin();
+        for k in 0..1_000 {
+            let v = *tree.get(k, &guard).unwrap();
+            assert!(results.iter().all(|r| r[k] == v));
+        }
+        assert!(calls.load(Ordering::Relaxed) >= 1_000);
+    }
+
+    #[test]
+    fn test_compute_or_insert_releases_every_value() {
+        struct Counted(Arc<AtomicUsize>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+
+        let live = Arc::new(AtomicUsize::new(0));
+        let tree: Arc<Congee<usize, Counted>> = Arc::new(Congee::new());
+        let mut handles = vec![];
+        for _ in 0..4 {
+            let tree = tree.clone();
+            let live = live.clone();
+            handles.push(thread::spawn(move || {
+                for k in 0..2_000 {
+                    let guard = tree.pin();
+                    tree.compute_or_insert(
+                        k % 4,
+                        |_| {
+                            // Widens the window for other writers, so some attempts have to retry.
+                            std::thread::yield_now();
+                            live.fetch_add(1, Ordering::Relaxed);
+                            Arc::new(Counted(live.clone()))
+                        },
+                        &guard,
+                    )
+                    .unwrap();
+                }
+            }));
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+        drop(tree);
+
+        // Replaced values are released once the epoch advances.
+        for _ in 0..1_000 {
+            if live.load(Ordering::Relaxed) == 0 {
+                break;
+            }
+            crossbeam_epoch::pin().flush();
+            std::thread::yield_now();
+        }
+        assert_eq!(live.load(Ordering::Relaxed), 0);
+    }
 }
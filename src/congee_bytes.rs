@@ -0,0 +1,485 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use crate::{
+    Allocator, DefaultAllocator, congee_bytes_inner::CongeeBytesInner, epoch, error::OOMError,
+};
+
+/// The adaptive radix tree with variable length byte keys.
+///
+/// Unlike [CongeeRaw](crate::CongeeRaw), keys can be of any length, and a key can be the prefix of another key.
+/// Each key is stored in a leaf together with its value, lookups only compare the first 8 bytes of
+/// a node prefix and verify the full key against the leaf.
+pub struct CongeeBytes<
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static = DefaultAllocator,
+> where
+    usize: From<V>,
+{
+    inner: CongeeBytesInner<A>,
+    pt_val: PhantomData<V>,
+}
+
+impl<V: Copy + From<usize>> Default for CongeeBytes<V>
+where
+    usize: From<V>,
+{
+    fn default() -> Self {
+        Self::new(DefaultAllocator {})
+    }
+}
+
+impl<V: Copy + From<usize>, A: Allocator + Clone + Send> CongeeBytes<V, A>
+where
+    usize: From<V>,
+{
+    /// Returns a copy of the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    ///
+    /// tree.insert(b"hello", 42, &guard).unwrap();
+    /// assert_eq!(tree.get(b"hello", &guard), Some(42));
+    /// assert_eq!(tree.get(b"hell", &guard), None);
+    /// ```
+    #[inline]
+    pub fn get(&self, key: &[u8], guard: &epoch::Guard) -> Option<V> {
+        let v = self.inner.get(key, guard)?;
+        Some(V::from(v))
+    }
+
+    /// Enters an epoch.
+    /// Note: this can be expensive, try to reuse it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    /// ```
+    #[inline]
+    pub fn pin(&self) -> epoch::Guard {
+        crossbeam_epoch::pin()
+    }
+
+    /// Create an empty tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeBytes, DefaultAllocator};
+    /// let tree = CongeeBytes::<usize>::new(DefaultAllocator {});
+    /// ```
+    #[inline]
+    pub fn new(allocator: A) -> Self {
+        Self::new_with_drainer(allocator, |_k, _v| {})
+    }
+
+    /// Create an empty tree with a drainer.
+    ///
+    /// The drainer is called on each of the key-value pairs when the tree is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeBytes, DefaultAllocator};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let drained = Arc::new(Mutex::new(Vec::new()));
+    /// let drained_inner = drained.clone();
+    /// let drainer = move |k: &[u8], v: usize| {
+    ///     drained_inner.lock().unwrap().push((k.to_vec(), v));
+    /// };
+    ///
+    /// let tree = CongeeBytes::<usize>::new_with_drainer(DefaultAllocator {}, drainer);
+    /// let pin = tree.pin();
+    /// tree.insert(b"key", 42, &pin).unwrap();
+    /// drop(pin);
+    /// drop(tree);
+    /// assert_eq!(*drained.lock().unwrap(), vec![(b"key".to_vec(), 42)]);
+    /// ```
+    pub fn new_with_drainer(allocator: A, drainer: impl Fn(&[u8], V) + 'static) -> Self {
+        let drainer = Arc::new(move |k: &[u8], v: usize| drainer(k, V::from(v)));
+        CongeeBytes {
+            inner: CongeeBytesInner::new(allocator, drainer),
+            pt_val: PhantomData,
+        }
+    }
+
+    /// Returns if the tree is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    /// assert!(tree.is_empty(&guard));
+    /// tree.insert(b"", 42, &guard).unwrap();
+    /// assert!(!tree.is_empty(&guard));
+    /// ```
+    pub fn is_empty(&self, guard: &epoch::Guard) -> bool {
+        self.inner.is_empty(guard)
+    }
+
+    /// Removes key-value pair from the tree, returns the value if the key was found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    ///
+    /// tree.insert(b"hello", 42, &guard).unwrap();
+    /// assert_eq!(tree.remove(b"hello", &guard), Some(42));
+    /// assert!(tree.get(b"hello", &guard).is_none());
+    /// ```
+    #[inline]
+    pub fn remove(&self, key: &[u8], guard: &epoch::Guard) -> Option<V> {
+        let (old, new) = self.inner.compute_if_present(key, &mut |_v| None, guard)?;
+        debug_assert!(new.is_none());
+        Some(V::from(old))
+    }
+
+    /// Insert a key-value pair to the tree, returns the previous value if the key was already present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    ///
+    /// tree.insert(b"a", 1, &guard).unwrap();
+    /// tree.insert(b"ab", 2, &guard).unwrap();
+    /// let old = tree.insert(b"a", 3, &guard).unwrap();
+    /// assert_eq!(old, Some(1));
+    /// assert_eq!(tree.get(b"ab", &guard), Some(2));
+    /// ```
+    #[inline]
+    pub fn insert(&self, key: &[u8], v: V, guard: &epoch::Guard) -> Result<Option<V>, OOMError> {
+        let val = self.inner.insert(key, usize::from(v), guard);
+        val.map(|inner| inner.map(|v| V::from(v)))
+    }
+
+    /// Scan the tree with the range of [start, end), in lexicographic order of the keys,
+    /// write the result to the `result` buffer.
+    /// It scans the length of `result` or the number of the keys within the range, whichever is smaller;
+    /// returns the number of the keys scanned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    ///
+    /// tree.insert(b"apple", 1, &guard).unwrap();
+    /// tree.insert(b"banana", 2, &guard).unwrap();
+    /// tree.insert(b"cherry", 3, &guard).unwrap();
+    ///
+    /// let mut result = vec![(Vec::new(), 0); 4];
+    /// let scanned = tree.range(b"b", b"d", &mut result, &guard);
+    /// assert_eq!(scanned, 2);
+    /// assert_eq!(result[0], (b"banana".to_vec(), 2));
+    /// assert_eq!(result[1], (b"cherry".to_vec(), 3));
+    /// ```
+    #[inline]
+    pub fn range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        result: &mut [(Vec<u8>, V)],
+        guard: &epoch::Guard,
+    ) -> usize {
+        let mut raw = vec![(Vec::new(), 0); result.len()];
+        let v = self.inner.range(start, end, &mut raw, guard);
+        for (r, (k, v)) in result.iter_mut().zip(raw.into_iter().take(v)) {
+            *r = (k, V::from(v));
+        }
+        v
+    }
+
+    /// Compute and update the value if the key presents in the tree.
+    /// Returns the (old, new) value
+    ///
+    /// Note that the function `f` is a FnMut and it must be safe to execute multiple times.
+    /// The `f` is expected to be short and fast as it will hold a exclusive lock on the leaf node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    ///
+    /// tree.insert(b"counter", 42, &guard).unwrap();
+    /// let old = tree.compute_if_present(b"counter", |v| Some(v + 1), &guard).unwrap();
+    /// assert_eq!(old, (42, Some(43)));
+    /// assert_eq!(tree.get(b"counter", &guard), Some(43));
+    /// ```
+    #[inline]
+    pub fn compute_if_present<F>(
+        &self,
+        key: &[u8],
+        mut f: F,
+        guard: &epoch::Guard,
+    ) -> Option<(usize, Option<usize>)>
+    where
+        F: FnMut(usize) -> Option<usize>,
+    {
+        self.inner.compute_if_present(key, &mut f, guard)
+    }
+
+    /// Compute or insert the value if the key is not in the tree.
+    /// Returns the Option(old) value
+    ///
+    /// Note that the function `f` is a FnMut and it must be safe to execute multiple times.
+    /// The `f` is expected to be short and fast as it will hold a exclusive lock on the leaf node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    ///
+    /// let old = tree.compute_or_insert(b"counter", |v| v.map_or(1, |v| v + 1), &guard).unwrap();
+    /// assert!(old.is_none());
+    /// let old = tree.compute_or_insert(b"counter", |v| v.map_or(1, |v| v + 1), &guard).unwrap();
+    /// assert_eq!(old, Some(1));
+    /// assert_eq!(tree.get(b"counter", &guard), Some(2));
+    /// ```
+    pub fn compute_or_insert<F>(
+        &self,
+        key: &[u8],
+        mut f: F,
+        guard: &epoch::Guard,
+    ) -> Result<Option<V>, OOMError>
+    where
+        F: FnMut(Option<usize>) -> usize,
+    {
+        let u_val = self.inner.compute_or_insert(key, &mut f, guard)?;
+        Ok(u_val.map(|v| V::from(v)))
+    }
+
+    /// Update the value if the old value matches with the new one.
+    /// Returns the current value.
+    ///
+    /// # Examples:
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(b"key", 42, &guard).unwrap();
+    ///
+    /// let v = tree.compare_exchange(b"key", &42, Some(43), &guard).unwrap();
+    /// assert_eq!(v, Some(43));
+    /// let v = tree.compare_exchange(b"key", &42, Some(44), &guard);
+    /// assert_eq!(v, Err(Some(43)));
+    /// ```
+    pub fn compare_exchange(
+        &self,
+        key: &[u8],
+        old: &V,
+        new: Option<V>,
+        guard: &epoch::Guard,
+    ) -> Result<Option<V>, Option<V>> {
+        let new_v = new.map(|v| usize::from(v));
+        let mut fc = |v: usize| -> Option<usize> {
+            if v == usize::from(*old) {
+                new_v
+            } else {
+                Some(v)
+            }
+        };
+        let v = self.inner.compute_if_present(key, &mut fc, guard);
+        match v {
+            Some((actual_old, actual_new)) => {
+                if actual_old == usize::from(*old) && actual_new == new_v {
+                    Ok(new)
+                } else {
+                    Err(actual_new.map(|v| V::from(v)))
+                }
+            }
+            None => Err(None),
+        }
+    }
+
+    /// Retrieve all keys from the tree, in lexicographic order.
+    /// Isolation level: read committed.
+    ///
+    /// # Examples:
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree = CongeeBytes::<usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(b"b", 1, &guard).unwrap();
+    /// tree.insert(b"a", 2, &guard).unwrap();
+    ///
+    /// assert_eq!(tree.keys(), vec![b"a".to_vec(), b"b".to_vec()]);
+    /// ```
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        self.inner.keys()
+    }
+
+    /// Returns the allocator used by the tree.
+    ///
+    /// # Examples:
+    /// ```
+    /// use congee::CongeeBytes;
+    /// let tree: CongeeBytes<usize> = CongeeBytes::default();
+    /// let allocator = tree.allocator();
+    /// ```
+    pub fn allocator(&self) -> &A {
+        self.inner.allocator()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::leak_check::LeakCheckAllocator;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn prefix_keys() {
+        let tree = CongeeBytes::<usize>::default();
+        let guard = tree.pin();
+        let keys: [&[u8]; 7] = [b"", b"\0", b"\0\0", b"a", b"a\0", b"ab", b"abc"];
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(tree.insert(k, i, &guard).unwrap(), None);
+        }
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(tree.get(k, &guard), Some(i));
+        }
+        assert_eq!(tree.get(b"abcd", &guard), None);
+        assert_eq!(tree.get(b"\x01", &guard), None);
+
+        let expected: Vec<Vec<u8>> = keys.iter().map(|k| k.to_vec()).collect();
+        assert_eq!(tree.keys(), expected);
+
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(tree.remove(k, &guard), Some(i));
+            assert_eq!(tree.get(k, &guard), None);
+        }
+        assert!(tree.is_empty(&guard));
+    }
+
+    #[test]
+    fn long_shared_prefix() {
+        let tree = CongeeBytes::<usize, _>::new(LeakCheckAllocator::new());
+        let guard = tree.pin();
+
+        // Shares a prefix longer than what a single node can hold.
+        let base = vec![7u8; 600];
+        let mut keys = Vec::new();
+        for split in [0, 5, 8, 9, 254, 255, 256, 300, 511, 599] {
+            for last in [1u8, 2] {
+                let mut k = base.clone();
+                k[split] = last;
+                keys.push(k);
+            }
+        }
+        keys.push(base.clone());
+        keys.push(base[..300].to_vec());
+
+        for (i, k) in keys.iter().enumerate() {
+            tree.insert(k, i, &guard).unwrap();
+        }
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(tree.get(k, &guard), Some(i));
+        }
+
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(tree.keys(), sorted);
+
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(tree.remove(k, &guard), Some(i));
+            for (j, k) in keys.iter().enumerate().skip(i + 1) {
+                assert_eq!(tree.get(k, &guard), Some(j));
+            }
+        }
+        assert!(tree.is_empty(&guard));
+    }
+
+    #[test]
+    fn range_matches_btree() {
+        let tree = CongeeBytes::<usize, _>::new(LeakCheckAllocator::new());
+        let guard = tree.pin();
+        let mut map = BTreeMap::new();
+
+        let mut state = 0x1234_5678_u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for i in 0..2_000 {
+            let len = (next() % 12) as usize;
+            let key: Vec<u8> = (0..len).map(|_| (next() % 4) as u8).collect();
+            tree.insert(&key, i, &guard).unwrap();
+            map.insert(key, i);
+        }
+        for _ in 0..500 {
+            let len = (next() % 12) as usize;
+            let key: Vec<u8> = (0..len).map(|_| (next() % 4) as u8).collect();
+            assert_eq!(tree.remove(&key, &guard), map.remove(&key));
+        }
+
+        let mut result = vec![(Vec::new(), 0); 64];
+        for _ in 0..200 {
+            let a: Vec<u8> = (0..(next() % 6)).map(|_| (next() % 4) as u8).collect();
+            let b: Vec<u8> = (0..(next() % 6)).map(|_| (next() % 4) as u8).collect();
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            let scanned = tree.range(&start, &end, &mut result, &guard);
+            let expected: Vec<(Vec<u8>, usize)> = map
+                .range(start.clone()..end.clone())
+                .take(64)
+                .map(|(k, v)| (k.clone(), *v))
+                .collect();
+            assert_eq!(&result[..scanned], expected.as_slice());
+        }
+    }
+
+    #[test]
+    fn concurrent_insert_remove() {
+        let tree = Arc::new(CongeeBytes::<usize>::default());
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let tree = tree.clone();
+                std::thread::spawn(move || {
+                    let guard = tree.pin();
+                    for i in 0..2_000usize {
+                        let key = format!("user/{t}/{i}");
+                        tree.insert(key.as_bytes(), i, &guard).unwrap();
+                    }
+                    for i in (0..2_000usize).step_by(2) {
+                        let key = format!("user/{t}/{i}");
+                        assert_eq!(tree.remove(key.as_bytes(), &guard), Some(i));
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let guard = tree.pin();
+        for t in 0..4 {
+            for i in 0..2_000usize {
+                let key = format!("user/{t}/{i}");
+                let expected = if i % 2 == 0 { None } else { Some(i) };
+                assert_eq!(tree.get(key.as_bytes(), &guard), expected);
+            }
+        }
+        assert_eq!(tree.keys().len(), 4_000);
+    }
+}
@@ -0,0 +1,845 @@
+use std::{
+    alloc::Layout,
+    ptr::NonNull,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crossbeam_epoch::Guard;
+
+use crate::{
+    Allocator, DefaultAllocator,
+    error::{ArtError, OOMError},
+    lock::{ReadGuard, WriteGuard},
+    nodes::{BaseNode, MAX_PREFIX_CNT, MAX_PREFIX_LEN, Node, Node4, NodePtr, Parent},
+    utils::Backoff,
+};
+#[cfg(all(feature = "shuttle", test))]
+use shuttle::sync::atomic::AtomicPtr;
+#[cfg(not(all(feature = "shuttle", test)))]
+use std::sync::atomic::AtomicPtr;
+
+/// Encodes the key so that no key is a prefix of another one, while keeping the byte order:
+/// `0x00` is escaped as `0x00 0x01`, and the key is terminated by `0x00 0x00`.
+pub(crate) fn encode_key(key: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(key.len() + 2);
+    for &b in key {
+        encoded.push(b);
+        if b == 0 {
+            encoded.push(1);
+        }
+    }
+    encoded.extend_from_slice(&[0, 0]);
+    encoded
+}
+
+/// Reverse of [encode_key].
+pub(crate) fn decode_key(encoded: &[u8]) -> Vec<u8> {
+    let body = &encoded[..encoded.len() - 2];
+    let mut key = Vec::with_capacity(body.len());
+    let mut iter = body.iter();
+    while let Some(&b) = iter.next() {
+        key.push(b);
+        if b == 0 {
+            iter.next();
+        }
+    }
+    key
+}
+
+/// A leaf holds the full (encoded) key and its value, the key bytes are stored right after the header.
+///
+/// The key never changes once the leaf is published, the value is only updated while holding the
+/// write lock of the node that points to the leaf.
+#[repr(C)]
+pub(crate) struct Leaf {
+    value: AtomicUsize,
+    key_len: usize,
+}
+
+impl Leaf {
+    fn layout(key_len: usize) -> Layout {
+        Layout::from_size_align(
+            std::mem::size_of::<Leaf>() + key_len,
+            std::mem::align_of::<Leaf>(),
+        )
+        .unwrap()
+    }
+
+    fn make<'a, A: Allocator>(
+        key: &[u8],
+        value: usize,
+        allocator: &'a A,
+    ) -> Result<AllocatedLeaf<'a, A>, ArtError> {
+        let ptr = allocator
+            .allocate(Self::layout(key.len()))
+            .map_err(|_e| ArtError::Oom)?;
+        let leaf = ptr.as_ptr() as *mut Leaf;
+        unsafe {
+            std::ptr::write(
+                leaf,
+                Leaf {
+                    value: AtomicUsize::new(value),
+                    key_len: key.len(),
+                },
+            );
+            std::ptr::copy_nonoverlapping(key.as_ptr(), leaf.add(1) as *mut u8, key.len());
+            Ok(AllocatedLeaf {
+                ptr: NonNull::new_unchecked(leaf),
+                allocator,
+            })
+        }
+    }
+
+    pub(crate) fn key(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts((self as *const Leaf).add(1) as *const u8, self.key_len)
+        }
+    }
+
+    pub(crate) fn value(&self) -> usize {
+        self.value.load(Ordering::Acquire)
+    }
+
+    unsafe fn drop_leaf<A: Allocator>(leaf: NonNull<Leaf>, allocator: A) {
+        let layout = Self::layout(unsafe { leaf.as_ref() }.key_len);
+        unsafe {
+            allocator.deallocate(leaf.cast::<u8>(), layout);
+        }
+    }
+}
+
+/// Same as `AllocatedNode`, the leaf is deallocated on drop unless it is converted into a `NodePtr`.
+struct AllocatedLeaf<'a, A: Allocator> {
+    ptr: NonNull<Leaf>,
+    allocator: &'a A,
+}
+
+impl<A: Allocator> AllocatedLeaf<'_, A> {
+    fn as_node_ptr(&self) -> NodePtr {
+        NodePtr::from_payload(self.ptr.as_ptr().expose_provenance() | LEAF_TAG)
+    }
+
+    fn into_node_ptr(self) -> NodePtr {
+        let ptr = self.as_node_ptr();
+        std::mem::forget(self);
+        ptr
+    }
+}
+
+impl<A: Allocator> Drop for AllocatedLeaf<'_, A> {
+    fn drop(&mut self) {
+        let layout = Leaf::layout(unsafe { self.ptr.as_ref() }.key_len);
+        unsafe {
+            self.allocator.deallocate(self.ptr.cast::<u8>(), layout);
+        }
+    }
+}
+
+/// Keys have no fixed length, so the level doesn't tell leaves from nodes,
+/// leaves are tagged with the lowest bit of the pointer instead (both are 8-byte aligned).
+const LEAF_TAG: usize = 1;
+
+/// The child of a node, either a leaf or another node.
+pub(crate) enum Child {
+    Leaf(NonNull<Leaf>),
+    Node(NonNull<BaseNode>),
+}
+
+impl Child {
+    pub(crate) fn from_ptr(ptr: NodePtr) -> Self {
+        let addr = ptr.as_payload();
+        if addr & LEAF_TAG != 0 {
+            Child::Leaf(unsafe {
+                NonNull::new_unchecked(std::ptr::with_exposed_provenance_mut(addr & !LEAF_TAG))
+            })
+        } else {
+            Child::Node(unsafe { ptr.as_sub_node_unchecked() })
+        }
+    }
+}
+
+type DrainCallback = Arc<dyn Fn(&[u8], usize)>;
+
+/// ART with variable length keys.
+///
+/// Keys are stored in leaves, a node only stores the first `MAX_PREFIX_LEN` bytes of its prefix,
+/// the remaining bytes are skipped on lookup (and verified against the leaf), or loaded from any
+/// leaf below the node when they are needed, e.g., on insert and scan.
+pub(crate) struct CongeeBytesInner<A: Allocator + Clone + Send + 'static = DefaultAllocator> {
+    root: AtomicPtr<BaseNode>,
+    drain_callback: DrainCallback,
+    allocator: A,
+}
+
+unsafe impl<A: Allocator + Clone + Send> Send for CongeeBytesInner<A> {}
+unsafe impl<A: Allocator + Clone + Send> Sync for CongeeBytesInner<A> {}
+
+impl<A: Allocator + Clone + Send> Drop for CongeeBytesInner<A> {
+    fn drop(&mut self) {
+        unsafe {
+            self.drop_subtree(self.load_root());
+        }
+
+        // see this: https://github.com/XiangpengHao/congee/issues/20
+        for _ in 0..128 {
+            crossbeam_epoch::pin().flush();
+        }
+    }
+}
+
+impl<A: Allocator + Clone + Send> CongeeBytesInner<A> {
+    pub(crate) fn new(allocator: A, drain_callback: DrainCallback) -> Self {
+        let root = BaseNode::make_node::<Node4, A>(&[], &allocator)
+            .expect("Can't allocate memory for root node!");
+        CongeeBytesInner {
+            root: AtomicPtr::new(root.into_non_null().cast::<BaseNode>().as_ptr()),
+            drain_callback,
+            allocator,
+        }
+    }
+
+    #[inline]
+    fn load_root(&self) -> NonNull<BaseNode> {
+        let root_ptr = self.root.load(std::sync::atomic::Ordering::Relaxed);
+        // SAFETY: The root pointer is always non-null after initialization.
+        unsafe { NonNull::new_unchecked(root_ptr) }
+    }
+
+    /// Only called when the tree is dropped, no one else can access the tree.
+    unsafe fn drop_subtree(&self, node: NonNull<BaseNode>) {
+        for (_k, child) in unsafe { node.as_ref() }.get_children(0, 255) {
+            match Child::from_ptr(child) {
+                Child::Leaf(leaf) => {
+                    let leaf_ref = unsafe { leaf.as_ref() };
+                    (self.drain_callback)(&decode_key(leaf_ref.key()), leaf_ref.value());
+                    unsafe { Leaf::drop_leaf(leaf, self.allocator.clone()) };
+                }
+                Child::Node(sub_node) => unsafe { self.drop_subtree(sub_node) },
+            }
+        }
+        unsafe { BaseNode::drop_node(node, self.allocator.clone()) };
+    }
+
+    fn retire_node(&self, mut node: WriteGuard, guard: &Guard) {
+        node.mark_obsolete();
+        let ptr = node.as_mut() as *mut BaseNode as usize;
+        std::mem::forget(node);
+        let allocator = self.allocator.clone();
+        guard.defer(move || unsafe {
+            BaseNode::drop_node(NonNull::new_unchecked(ptr as *mut BaseNode), allocator);
+        });
+    }
+
+    fn retire_leaf(&self, leaf: NonNull<Leaf>, guard: &Guard) {
+        let ptr = leaf.as_ptr().expose_provenance();
+        let allocator = self.allocator.clone();
+        guard.defer(move || unsafe {
+            let leaf = NonNull::new_unchecked(std::ptr::with_exposed_provenance_mut(ptr));
+            Leaf::drop_leaf(leaf, allocator);
+        });
+    }
+
+    /// Returns any leaf below the node, all of them share the prefix of the node.
+    fn any_leaf(node: &ReadGuard) -> Result<NonNull<Leaf>, ArtError> {
+        let mut child = node.as_ref().get_children(0, 255).next();
+        node.check_version()?;
+        loop {
+            // An empty node is being removed, restart.
+            let (_k, ptr) = child.ok_or(ArtError::VersionNotMatch)?;
+            match Child::from_ptr(ptr) {
+                Child::Leaf(leaf) => return Ok(leaf),
+                Child::Node(sub_node) => {
+                    let sub_node = BaseNode::read_lock(sub_node)?;
+                    child = sub_node.as_ref().get_children(0, 255).next();
+                    sub_node.check_version()?;
+                }
+            }
+        }
+    }
+
+    /// Returns the full prefix of a node at `depth`, including the bytes that are not stored in the node.
+    fn full_prefix(node: &ReadGuard, depth: usize) -> Result<Vec<u8>, ArtError> {
+        let prefix_len = node.as_ref().prefix_len();
+        if prefix_len <= MAX_PREFIX_LEN {
+            let prefix = node.as_ref().prefix().to_vec();
+            node.check_version()?;
+            return Ok(prefix);
+        }
+
+        let leaf = Self::any_leaf(node)?;
+        let prefix = unsafe { leaf.as_ref() }
+            .key()
+            .get(depth..depth + prefix_len)
+            .ok_or(ArtError::VersionNotMatch)?
+            .to_vec();
+        node.check_version()?;
+        Ok(prefix)
+    }
+
+    /// Creates the nodes that hold two children under a common prefix.
+    /// Prefixes longer than `MAX_PREFIX_CNT` are split into a chain of single child nodes.
+    fn make_branch(
+        &self,
+        common: &[u8],
+        a: (u8, NodePtr),
+        b: (u8, NodePtr),
+    ) -> Result<NodePtr, ArtError> {
+        let mut chain = Vec::new();
+        let mut edges = Vec::new();
+        let mut rest = common;
+        while rest.len() > MAX_PREFIX_CNT {
+            chain.push(BaseNode::make_node::<Node4, A>(
+                &rest[..MAX_PREFIX_CNT],
+                &self.allocator,
+            )?);
+            edges.push(rest[MAX_PREFIX_CNT]);
+            rest = &rest[MAX_PREFIX_CNT + 1..];
+        }
+
+        let mut bottom = BaseNode::make_node::<Node4, A>(rest, &self.allocator)?;
+        bottom.as_mut().insert(a.0, a.1);
+        bottom.as_mut().insert(b.0, b.1);
+
+        let mut child = bottom.into_note_ptr();
+        while let Some(mut n) = chain.pop() {
+            n.as_mut().insert(edges.pop().unwrap(), child);
+            child = n.into_note_ptr();
+        }
+        Ok(child)
+    }
+
+    pub(crate) fn is_empty(&self, _guard: &Guard) -> bool {
+        loop {
+            let root = self.load_root();
+            if let Ok(node) = BaseNode::read_lock(root) {
+                let is_empty = node.as_ref().meta.count() == 0;
+                if node.check_version().is_ok() {
+                    return is_empty;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, key: &[u8], _guard: &Guard) -> Option<usize> {
+        let key = encode_key(key);
+        'outer: loop {
+            let mut depth = 0;
+            let mut node = if let Ok(v) = BaseNode::read_lock(self.load_root()) {
+                v
+            } else {
+                continue;
+            };
+
+            loop {
+                // Only the stored part of the prefix is compared, the leaf has the full key.
+                let prefix_len = node.as_ref().prefix_len();
+                if key.len() <= depth + prefix_len
+                    || !key[depth..].starts_with(node.as_ref().prefix())
+                {
+                    if node.check_version().is_err() {
+                        continue 'outer;
+                    }
+                    return None;
+                }
+                depth += prefix_len;
+
+                let child = node.as_ref().get_child(key[depth]);
+                if node.check_version().is_err() {
+                    continue 'outer;
+                }
+
+                match Child::from_ptr(child?) {
+                    Child::Leaf(leaf) => {
+                        let leaf = unsafe { leaf.as_ref() };
+                        if leaf.key() != key.as_slice() {
+                            return None;
+                        }
+                        return Some(leaf.value());
+                    }
+                    Child::Node(sub_node) => {
+                        depth += 1;
+                        node = if let Ok(n) = BaseNode::read_lock(sub_node) {
+                            n
+                        } else {
+                            continue 'outer;
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn insert_inner<F>(
+        &self,
+        key: &[u8],
+        tid_func: &mut F,
+        guard: &Guard,
+    ) -> Result<Option<usize>, ArtError>
+    where
+        F: FnMut(Option<usize>) -> usize,
+    {
+        let mut parent = Parent::Root(&self.root);
+        let mut node = BaseNode::read_lock(self.load_root())?;
+        let mut depth = 0;
+
+        loop {
+            if node.as_ref().prefix_len() > 0 {
+                let prefix = Self::full_prefix(&node, depth)?;
+                let mismatch = prefix.iter().zip(&key[depth..]).position(|(a, b)| a != b);
+
+                if let Some(i) = mismatch {
+                    let (parent_key, parent_node) = match parent {
+                        Parent::Node(key, node) => (key, node),
+                        Parent::Root(_) => {
+                            unreachable!("Root node should not have a prefix");
+                        }
+                    };
+
+                    let mut write_p = parent_node.upgrade().map_err(|(_n, v)| v)?;
+                    let mut write_n = node.upgrade().map_err(|(_n, v)| v)?;
+
+                    let new_leaf = Leaf::make(key, tid_func(None), &self.allocator)?;
+                    let mut new_middle_node =
+                        BaseNode::make_node::<Node4, A>(&prefix[..i], &self.allocator)?;
+                    new_middle_node
+                        .as_mut()
+                        .insert(prefix[i], NodePtr::from_node_ref(write_n.as_ref()));
+                    new_middle_node
+                        .as_mut()
+                        .insert(key[depth + i], new_leaf.into_node_ptr());
+
+                    write_n.as_mut().set_prefix(&prefix[i + 1..]);
+                    write_p
+                        .as_mut()
+                        .change(parent_key, new_middle_node.into_note_ptr());
+                    return Ok(None);
+                }
+                depth += prefix.len();
+            }
+
+            let node_key = key[depth];
+            let child = node.as_ref().get_child(node_key);
+            node.check_version()?;
+
+            let child = if let Some(c) = child {
+                c
+            } else {
+                let new_leaf = Leaf::make(key, tid_func(None), &self.allocator)?;
+                BaseNode::insert_and_unlock(
+                    node,
+                    parent,
+                    (node_key, new_leaf.as_node_ptr()),
+                    &self.allocator,
+                    guard,
+                    || {},
+                )?;
+                new_leaf.into_node_ptr();
+                return Ok(None);
+            };
+
+            match Child::from_ptr(child) {
+                Child::Leaf(leaf) => {
+                    if let Parent::Node(_, p) = parent {
+                        p.unlock()?;
+                    }
+
+                    let leaf_ref = unsafe { leaf.as_ref() };
+                    let existing = leaf_ref.key();
+                    if existing == key {
+                        let old = leaf_ref.value();
+                        let new = tid_func(Some(old));
+                        if old == new {
+                            node.check_version()?;
+                            return Ok(Some(old));
+                        }
+
+                        let _write_n = node.upgrade().map_err(|(_n, v)| v)?;
+                        leaf_ref.value.store(new, Ordering::Release);
+                        return Ok(Some(old));
+                    }
+
+                    // Two different keys share this slot, push the existing leaf down.
+                    let common = existing[depth + 1..]
+                        .iter()
+                        .zip(&key[depth + 1..])
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    let next_depth = depth + 1 + common;
+
+                    let mut write_n = node.upgrade().map_err(|(_n, v)| v)?;
+                    let new_leaf = Leaf::make(key, tid_func(None), &self.allocator)?;
+                    let branch = self.make_branch(
+                        &key[depth + 1..next_depth],
+                        (existing[next_depth], child),
+                        (key[next_depth], new_leaf.as_node_ptr()),
+                    )?;
+                    new_leaf.into_node_ptr();
+                    write_n.as_mut().change(node_key, branch);
+                    return Ok(None);
+                }
+                Child::Node(sub_node) => {
+                    parent = Parent::Node(node_key, node);
+                    node = BaseNode::read_lock(sub_node)?;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn compute_or_insert<F>(
+        &self,
+        key: &[u8],
+        insert_func: &mut F,
+        guard: &Guard,
+    ) -> Result<Option<usize>, OOMError>
+    where
+        F: FnMut(Option<usize>) -> usize,
+    {
+        let key = encode_key(key);
+        let backoff = Backoff::new();
+        loop {
+            match self.insert_inner(&key, insert_func, guard) {
+                Ok(v) => return Ok(v),
+                Err(e) => match e {
+                    ArtError::Locked | ArtError::VersionNotMatch => {
+                        backoff.spin();
+                        continue;
+                    }
+                    ArtError::Oom => return Err(OOMError::new()),
+                },
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn insert(
+        &self,
+        key: &[u8],
+        tid: usize,
+        guard: &Guard,
+    ) -> Result<Option<usize>, OOMError> {
+        self.compute_or_insert(key, &mut |_| tid, guard)
+    }
+
+    #[inline]
+    fn compute_if_present_inner<F>(
+        &self,
+        key: &[u8],
+        remapping_function: &mut F,
+        guard: &Guard,
+    ) -> Result<Option<(usize, Option<usize>)>, ArtError>
+    where
+        F: FnMut(usize) -> Option<usize>,
+    {
+        // (node, the key of the child we took, depth of the node)
+        let mut path: Vec<(ReadGuard, u8, usize)> = Vec::new();
+        let mut node = BaseNode::read_lock(self.load_root())?;
+        let mut depth = 0;
+
+        loop {
+            let node_depth = depth;
+            let prefix_len = node.as_ref().prefix_len();
+            if key.len() <= depth + prefix_len || !key[depth..].starts_with(node.as_ref().prefix())
+            {
+                node.check_version()?;
+                return Ok(None);
+            }
+            depth += prefix_len;
+
+            let node_key = key[depth];
+            let child = node.as_ref().get_child(node_key);
+            node.check_version()?;
+
+            let child = match child {
+                Some(c) => c,
+                None => return Ok(None),
+            };
+
+            match Child::from_ptr(child) {
+                Child::Leaf(leaf) => {
+                    let leaf_ref = unsafe { leaf.as_ref() };
+                    if leaf_ref.key() != key {
+                        return Ok(None);
+                    }
+                    let old = leaf_ref.value();
+                    node.check_version()?;
+
+                    match remapping_function(old) {
+                        Some(new) => {
+                            if new == old {
+                                // the value is not change, early return;
+                                return Ok(Some((old, Some(old))));
+                            }
+                            let _write_n = node.upgrade().map_err(|(_n, v)| v)?;
+                            leaf_ref.value.store(new, Ordering::Release);
+                            return Ok(Some((old, Some(new))));
+                        }
+                        None => {
+                            self.remove_leaf(path, node, node_depth, node_key, leaf, guard)?;
+                            return Ok(Some((old, None)));
+                        }
+                    }
+                }
+                Child::Node(sub_node) => {
+                    path.push((node, node_key, node_depth));
+                    node = BaseNode::read_lock(sub_node)?;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Removes `leaf` from `node`, nodes left with a single child are merged into their parent,
+    /// nodes left with no child are removed.
+    fn remove_leaf(
+        &self,
+        mut path: Vec<(ReadGuard, u8, usize)>,
+        node: ReadGuard,
+        node_depth: usize,
+        node_key: u8,
+        leaf: NonNull<Leaf>,
+        guard: &Guard,
+    ) -> Result<(), ArtError> {
+        let value_count = node.as_ref().value_count();
+
+        if path.is_empty() || value_count > 2 {
+            let mut write_n = node.upgrade().map_err(|(_n, v)| v)?;
+            write_n.as_mut().remove(node_key);
+            self.retire_leaf(leaf, guard);
+            return Ok(());
+        }
+
+        if value_count == 2 {
+            let sibling = node
+                .as_ref()
+                .get_children(0, 255)
+                .find(|(k, _)| *k != node_key);
+            node.check_version()?;
+            let (_sibling_key, sibling_ptr) = sibling.ok_or(ArtError::VersionNotMatch)?;
+            let (parent, parent_key, _) = path.pop().unwrap();
+
+            match Child::from_ptr(sibling_ptr) {
+                Child::Leaf(_) => {
+                    let mut write_p = parent.upgrade().map_err(|(_n, v)| v)?;
+                    let write_n = node.upgrade().map_err(|(_n, v)| v)?;
+                    write_p.as_mut().change(parent_key, sibling_ptr);
+                    self.retire_node(write_n, guard);
+                }
+                Child::Node(sibling) => {
+                    let sibling = BaseNode::read_lock(sibling)?;
+                    let merged_len = node.as_ref().prefix_len() + 1 + sibling.as_ref().prefix_len();
+                    if merged_len > MAX_PREFIX_CNT {
+                        // The merged prefix is too long, keep the single child node.
+                        let mut write_n = node.upgrade().map_err(|(_n, v)| v)?;
+                        write_n.as_mut().remove(node_key);
+                        self.retire_leaf(leaf, guard);
+                        return Ok(());
+                    }
+
+                    let any_leaf = Self::any_leaf(&sibling)?;
+                    let merged_prefix = unsafe { any_leaf.as_ref() }
+                        .key()
+                        .get(node_depth..node_depth + merged_len)
+                        .ok_or(ArtError::VersionNotMatch)?
+                        .to_vec();
+
+                    let mut write_p = parent.upgrade().map_err(|(_n, v)| v)?;
+                    let write_n = node.upgrade().map_err(|(_n, v)| v)?;
+                    let mut write_s = sibling.upgrade().map_err(|(_n, v)| v)?;
+                    write_s.as_mut().set_prefix(&merged_prefix);
+                    write_p.as_mut().change(parent_key, sibling_ptr);
+                    self.retire_node(write_n, guard);
+                }
+            }
+            self.retire_leaf(leaf, guard);
+            return Ok(());
+        }
+
+        // The node only holds this leaf, which happens on a chain of nodes that
+        // share a long prefix, remove the chain from the first node that has other children.
+        let mut obsolete = vec![node];
+        let (target, target_key) = loop {
+            let (p, k, _) = path.pop().unwrap();
+            if path.is_empty() || p.as_ref().value_count() > 1 {
+                break (p, k);
+            }
+            obsolete.push(p);
+        };
+
+        let mut write_t = target.upgrade().map_err(|(_n, v)| v)?;
+        let mut write_obsolete = Vec::with_capacity(obsolete.len());
+        for n in obsolete {
+            write_obsolete.push(n.upgrade().map_err(|(_n, v)| v)?);
+        }
+        write_t.as_mut().remove(target_key);
+        for n in write_obsolete {
+            self.retire_node(n, guard);
+        }
+        self.retire_leaf(leaf, guard);
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn compute_if_present<F>(
+        &self,
+        key: &[u8],
+        remapping_function: &mut F,
+        guard: &Guard,
+    ) -> Option<(usize, Option<usize>)>
+    where
+        F: FnMut(usize) -> Option<usize>,
+    {
+        let key = encode_key(key);
+        let backoff = Backoff::new();
+        loop {
+            match self.compute_if_present_inner(&key, &mut *remapping_function, guard) {
+                Ok(n) => return n,
+                Err(_) => backoff.spin(),
+            }
+        }
+    }
+
+    /// Visits the leaves within [start, end) in order, stops when `visit` returns false.
+    /// `None` means the scan is unbounded on that side.
+    fn scan(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        visit: &mut dyn FnMut(&Leaf) -> bool,
+    ) -> Result<(), ArtError> {
+        let root = BaseNode::read_lock(self.load_root())?;
+        Self::scan_node(root, 0, start, end, visit)?;
+        Ok(())
+    }
+
+    /// `start` and `end` are only set if the path to this node equals their first `depth` bytes,
+    /// returns false if the scan is stopped by `visit`.
+    fn scan_node(
+        node: ReadGuard,
+        depth: usize,
+        mut start: Option<&[u8]>,
+        mut end: Option<&[u8]>,
+        visit: &mut dyn FnMut(&Leaf) -> bool,
+    ) -> Result<bool, ArtError> {
+        let prefix_len = node.as_ref().prefix_len();
+        if prefix_len > 0 {
+            let prefix = Self::full_prefix(&node, depth)?;
+            let bound_part =
+                |b: &[u8]| -> Vec<u8> { b.iter().skip(depth).take(prefix_len).copied().collect() };
+            if let Some(s) = start {
+                match prefix.cmp(&bound_part(s)) {
+                    std::cmp::Ordering::Less => return Ok(true),
+                    std::cmp::Ordering::Greater => start = None,
+                    std::cmp::Ordering::Equal => {}
+                }
+            }
+            if let Some(e) = end {
+                match prefix.cmp(&bound_part(e)) {
+                    std::cmp::Ordering::Greater => return Ok(true),
+                    std::cmp::Ordering::Less => end = None,
+                    std::cmp::Ordering::Equal => {}
+                }
+            }
+        }
+
+        let depth = depth + prefix_len;
+        let low = match start.and_then(|s| s.get(depth)) {
+            Some(b) => Some(*b),
+            None => {
+                start = None;
+                None
+            }
+        };
+        let high = match end {
+            Some(e) => match e.get(depth) {
+                Some(b) => Some(*b),
+                // every key below is larger than the end
+                None => return Ok(true),
+            },
+            None => None,
+        };
+
+        for (k, child) in node
+            .as_ref()
+            .get_children(low.unwrap_or(0), high.unwrap_or(255))
+        {
+            node.check_version()?;
+            match Child::from_ptr(child) {
+                Child::Leaf(leaf) => {
+                    let leaf = unsafe { leaf.as_ref() };
+                    let key = leaf.key();
+                    let in_range = start.is_none_or(|s| key >= s) && end.is_none_or(|e| key < e);
+                    if in_range && !visit(leaf) {
+                        node.check_version()?;
+                        return Ok(false);
+                    }
+                }
+                Child::Node(sub_node) => {
+                    let sub_node = BaseNode::read_lock(sub_node)?;
+                    let sub_start = start.filter(|_| Some(k) == low);
+                    let sub_end = end.filter(|_| Some(k) == high);
+                    if !Self::scan_node(sub_node, depth + 1, sub_start, sub_end, visit)? {
+                        node.check_version()?;
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+        node.check_version()?;
+        Ok(true)
+    }
+
+    #[inline]
+    pub(crate) fn range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        result: &mut [(Vec<u8>, usize)],
+        _guard: &Guard,
+    ) -> usize {
+        let start = encode_key(start);
+        let end = encode_key(end);
+        if start >= end || result.is_empty() {
+            return 0;
+        }
+
+        let backoff = Backoff::new();
+        loop {
+            let mut scanned = 0;
+            let res = self.scan(Some(&start), Some(&end), &mut |leaf| {
+                result[scanned] = (decode_key(leaf.key()), leaf.value());
+                scanned += 1;
+                scanned < result.len()
+            });
+            match res {
+                Ok(()) => return scanned,
+                Err(_) => backoff.spin(),
+            }
+        }
+    }
+
+    pub(crate) fn keys(&self) -> Vec<Vec<u8>> {
+        let backoff = Backoff::new();
+        loop {
+            let mut keys = Vec::new();
+            let res = self.scan(None, None, &mut |leaf| {
+                keys.push(decode_key(leaf.key()));
+                true
+            });
+            match res {
+                Ok(()) => return keys,
+                Err(_) => backoff.spin(),
+            }
+        }
+    }
+
+    pub(crate) fn allocator(&self) -> &A {
+        &self.allocator
+    }
+}
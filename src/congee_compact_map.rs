@@ -0,0 +1,670 @@
+//! # CongeeCompactMap - A memory efficient, serializable version of CongeeRaw
+//!
+//! The map counterpart of [CongeeCompactSet](crate::CongeeCompactSet): a read-only, flattened copy of a
+//! [CongeeRaw](crate::CongeeRaw) in a single contiguous byte array, created with `CongeeRaw::to_compact_map()`.
+//!
+//! ## Data Layout
+//!
+//! ```text
+//! [Header: 8 bytes][Nodes: nodes_len bytes][Values: len * value_width bytes]
+//!
+//! Header:
+//! - value_width: u8   - Bytes per value: 1, 2, 4 or 8, the smallest that holds the largest value
+//! - reserved: [u8; 3]
+//! - nodes_len: u32    - Length of the node section
+//! ```
+//!
+//! The nodes use the layout of `CongeeCompactSet`, in level-order with the root at offset 0,
+//! except that every leaf node is followed by the rank of its first key (u32):
+//! the number of keys in the map that are smaller than it.
+//!
+//! The values are stored little endian in ascending key order, so the value of a key is at its rank:
+//! the rank of the leaf node plus the position of the key within the node.
+//! Iterating in key order reads the values sequentially.
+//!
+//! An empty map is serialized as an empty byte array.
+//!
+//! ## Supported Operations
+//!
+//! Supported: Point lookups (get, contains_key), ascending iteration over all entries or a range (iter, range_iter)
+//! Not supported: Insertions, deletions, updates (read-only structure)
+
+use std::{
+    cmp::Ordering,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::{
+    CompactSetStats, KeyEncoding,
+    congee_compact_set::{CompactNode, NodeType, read_node},
+};
+
+const HEADER_LEN: usize = 8;
+
+/// Builds the compact map from the nodes written with leaf ranks and the values in ascending key order.
+pub(crate) fn assemble(nodes: Vec<u8>, values: &[usize]) -> Vec<u8> {
+    let Some(&max) = values.iter().max() else {
+        return Vec::new();
+    };
+    let value_width = match max {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x1_0000..=0xffff_ffff => 4,
+        _ => 8,
+    };
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + nodes.len() + values.len() * value_width);
+    buf.push(value_width as u8);
+    buf.extend_from_slice(&[0; 3]);
+    buf.extend_from_slice(&(nodes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&nodes);
+    for &v in values {
+        buf.extend_from_slice(&(v as u64).to_le_bytes()[..value_width]);
+    }
+    buf
+}
+
+#[inline]
+fn read_u32(data: &[u8], at: usize) -> usize {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize
+}
+
+/// A memory efficient, serializable version of CongeeRaw
+pub struct CongeeCompactMap<'a, K, V, const K_LEN: usize = 8>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    usize: From<V>,
+{
+    nodes: &'a [u8],
+    values: &'a [u8],
+    value_width: usize,
+    _phantom: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, const K_LEN: usize> CongeeCompactMap<'a, K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    usize: From<V>,
+{
+    /// Creates a new CongeeCompactMap from serialized byte data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the header does not match the length of `data`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeCompactMap, CongeeRaw};
+    ///
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(42, 4200, &guard).unwrap();
+    ///
+    /// let data = tree.to_compact_map();
+    /// let compact_map = CongeeCompactMap::<usize, usize>::new(&data);
+    /// assert_eq!(compact_map.get(&42), Some(4200));
+    /// ```
+    pub fn new(data: &'a [u8]) -> Self {
+        if data.is_empty() {
+            return Self {
+                nodes: data,
+                values: data,
+                value_width: 1,
+                _phantom: PhantomData,
+            };
+        }
+
+        assert!(data.len() >= HEADER_LEN, "compact map header is truncated");
+        let value_width = data[0] as usize;
+        assert!(
+            matches!(value_width, 1 | 2 | 4 | 8),
+            "invalid compact map value width {value_width}"
+        );
+        let nodes_len = read_u32(data, 4);
+        assert!(
+            HEADER_LEN + nodes_len <= data.len(),
+            "compact map nodes are truncated"
+        );
+        let (nodes, values) = data[HEADER_LEN..].split_at(nodes_len);
+        assert!(
+            values.len().is_multiple_of(value_width),
+            "compact map values are truncated"
+        );
+        Self {
+            nodes,
+            values,
+            value_width,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.values.len() / self.value_width
+    }
+
+    /// Returns true if the map holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the value of the key, if present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeCompactMap, CongeeRaw};
+    ///
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 10, &guard).unwrap();
+    /// tree.insert(1 << 40, 20, &guard).unwrap();
+    ///
+    /// let data = tree.to_compact_map();
+    /// let compact_map = CongeeCompactMap::<usize, usize>::new(&data);
+    /// assert_eq!(compact_map.get(&(1 << 40)), Some(20));
+    /// assert_eq!(compact_map.get(&2), None);
+    /// ```
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.rank(&key.to_key_bytes()).map(|rank| self.value(rank))
+    }
+
+    /// Returns true if the key is present.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.rank(&key.to_key_bytes()).is_some()
+    }
+
+    /// Returns an iterator over all entries in key order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeCompactMap, CongeeRaw};
+    ///
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(2, 43, &guard).unwrap();
+    /// tree.insert(1, 42, &guard).unwrap();
+    ///
+    /// let data = tree.to_compact_map();
+    /// let compact_map = CongeeCompactMap::<usize, usize>::new(&data);
+    /// assert_eq!(compact_map.iter().collect::<Vec<_>>(), vec![(1, 42), (2, 43)]);
+    /// ```
+    pub fn iter(&self) -> CongeeCompactMapIter<'_, 'a, K, V, K_LEN> {
+        self.range_iter(..)
+    }
+
+    /// Returns an iterator over the entries within `range` in key order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeCompactMap, CongeeRaw};
+    ///
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// for i in 0..10 {
+    ///     tree.insert(i, i * 10, &guard).unwrap();
+    /// }
+    ///
+    /// let data = tree.to_compact_map();
+    /// let compact_map = CongeeCompactMap::<usize, usize>::new(&data);
+    /// let entries: Vec<_> = compact_map.range_iter(3..=5).collect();
+    /// assert_eq!(entries, vec![(3, 30), (4, 40), (5, 50)]);
+    /// assert_eq!(compact_map.range_iter(8..).count(), 2);
+    /// ```
+    pub fn range_iter<R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> CongeeCompactMapIter<'_, 'a, K, V, K_LEN> {
+        let end = range.end_bound().map(|k| k.to_key_bytes());
+        let mut iter = CongeeCompactMapIter {
+            map: self,
+            stack: Vec::new(),
+            key: [0; K_LEN],
+            end,
+        };
+        if !self.is_empty() {
+            match range.start_bound() {
+                Bound::Included(k) => iter.seek(&k.to_key_bytes(), true),
+                Bound::Excluded(k) => iter.seek(&k.to_key_bytes(), false),
+                Bound::Unbounded => iter.push(0, 0),
+            }
+        }
+        iter
+    }
+
+    /// Provides metrics of the nodes and the values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeCompactMap, CongeeRaw};
+    ///
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(42, 7, &guard).unwrap();
+    ///
+    /// let data = tree.to_compact_map();
+    /// let stats = CongeeCompactMap::<usize, usize>::new(&data).stats();
+    /// assert_eq!(stats.value_width, 1);
+    /// println!("{stats}");
+    /// ```
+    pub fn stats(&self) -> CompactMapStats {
+        let mut stats = CompactMapStats {
+            value_width: self.value_width,
+            value_bytes: self.values.len(),
+            ..Default::default()
+        };
+        let nodes = &mut stats.nodes;
+        nodes.total_data_size = self.total_memory_bytes();
+
+        let mut offset = 0;
+        while offset < self.nodes.len() {
+            let node = read_node(self.nodes, offset);
+            let children_start = offset + 4 + node.prefix.len();
+            nodes.total_nodes += 1;
+            nodes.header_bytes += 4;
+            nodes.prefix_bytes += node.prefix.len();
+            nodes.children_bytes += node.end - children_start;
+            nodes.total_children += node.children.len();
+            match self.nodes[offset] {
+                NodeType::N4_INTERNAL => nodes.n4_internal_count += 1,
+                NodeType::N16_INTERNAL => nodes.n16_internal_count += 1,
+                NodeType::N48_INTERNAL => nodes.n48_internal_count += 1,
+                NodeType::N256_INTERNAL => nodes.n256_internal_count += 1,
+                NodeType::N4_LEAF => nodes.n4_leaf_count += 1,
+                NodeType::N16_LEAF => nodes.n16_leaf_count += 1,
+                NodeType::N48_LEAF => nodes.n48_leaf_count += 1,
+                _ => nodes.n256_leaf_count += 1,
+            }
+            offset = node.end;
+            if node.is_leaf {
+                nodes.kv_pairs += node.children.len();
+                stats.rank_bytes += 4;
+                offset += 4;
+            }
+        }
+        stats
+    }
+
+    /// Returns total memory usage
+    pub fn total_memory_bytes(&self) -> usize {
+        if self.is_empty() {
+            0
+        } else {
+            HEADER_LEN + self.nodes.len() + self.values.len()
+        }
+    }
+
+    fn value(&self, rank: usize) -> V {
+        let at = rank * self.value_width;
+        let mut bytes = [0u8; 8];
+        bytes[..self.value_width].copy_from_slice(&self.values[at..at + self.value_width]);
+        V::from(u64::from_le_bytes(bytes) as usize)
+    }
+
+    /// Returns the number of keys smaller than `key` if `key` is present.
+    fn rank(&self, key: &[u8; K_LEN]) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+        let data = self.nodes;
+        let mut offset = 0;
+        let mut key_pos = 0;
+
+        loop {
+            let node_type = data[offset];
+            let prefix_len = data[offset + 1] as usize;
+            let children_len = u16::from_le_bytes([data[offset + 2], data[offset + 3]]) as usize;
+            let prefix_start = offset + 4;
+            if key_pos + prefix_len >= K_LEN
+                || key[key_pos..key_pos + prefix_len]
+                    != data[prefix_start..prefix_start + prefix_len]
+            {
+                return None;
+            }
+            key_pos += prefix_len;
+
+            let byte = key[key_pos];
+            let children_start = prefix_start + prefix_len;
+            // The key bytes of N4 and N16 nodes.
+            let keys = || &data[children_start..children_start + children_len];
+
+            offset = match node_type {
+                NodeType::N4_LEAF | NodeType::N16_LEAF => {
+                    let i = keys().iter().position(|&k| k == byte)?;
+                    return Some(read_u32(data, children_start + children_len) + i);
+                }
+                NodeType::N48_LEAF | NodeType::N256_LEAF => {
+                    let bitmap = &data[children_start..children_start + 32];
+                    let (byte_idx, bit_idx) = (byte as usize / 8, byte % 8);
+                    if bitmap[byte_idx] & (1u8 << bit_idx) == 0 {
+                        return None;
+                    }
+                    let below = bitmap[..byte_idx]
+                        .iter()
+                        .map(|b| b.count_ones() as usize)
+                        .sum::<usize>()
+                        + (bitmap[byte_idx] & ((1u8 << bit_idx) - 1)).count_ones() as usize;
+                    return Some(read_u32(data, children_start + 32) + below);
+                }
+                NodeType::N4_INTERNAL | NodeType::N16_INTERNAL => {
+                    let i = keys().iter().position(|&k| k == byte)?;
+                    read_u32(data, children_start + children_len + i * 4)
+                }
+                NodeType::N48_INTERNAL => {
+                    let index = data[children_start + byte as usize] as usize;
+                    if index == 0 {
+                        return None;
+                    }
+                    read_u32(data, children_start + 256 + (index - 1) * 4)
+                }
+                _ => match read_u32(data, children_start + byte as usize * 4) {
+                    0 => return None,
+                    child => child,
+                },
+            };
+            key_pos += 1;
+        }
+    }
+}
+
+/// An iterator over the entries of a [CongeeCompactMap] in key order.
+pub struct CongeeCompactMapIter<'m, 'a, K, V, const K_LEN: usize = 8>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    usize: From<V>,
+{
+    map: &'m CongeeCompactMap<'a, K, V, K_LEN>,
+    stack: Vec<Frame<'a>>,
+    key: [u8; K_LEN],
+    end: Bound<[u8; K_LEN]>,
+}
+
+/// A node on the path to the current key.
+struct Frame<'a> {
+    node: CompactNode<'a>,
+    /// The index of the next child to visit.
+    next: usize,
+    /// The position of the child key byte.
+    edge_pos: usize,
+    /// The rank of the first key, leaf nodes only.
+    first_rank: usize,
+}
+
+impl<K, V, const K_LEN: usize> CongeeCompactMapIter<'_, '_, K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    usize: From<V>,
+{
+    /// Descends into the node at `offset` whose prefix starts at `key_pos`.
+    fn push(&mut self, offset: usize, key_pos: usize) {
+        let node = read_node(self.map.nodes, offset);
+        let edge_pos = key_pos + node.prefix.len();
+        self.key[key_pos..edge_pos].copy_from_slice(node.prefix);
+        let first_rank = if node.is_leaf {
+            read_u32(self.map.nodes, node.end)
+        } else {
+            0
+        };
+        self.stack.push(Frame {
+            node,
+            next: 0,
+            edge_pos,
+            first_rank,
+        });
+    }
+
+    /// Positions the iterator at the first key after `start`, or at `start` itself if `inclusive`.
+    fn seek(&mut self, start: &[u8; K_LEN], inclusive: bool) {
+        let mut offset = 0;
+        let mut key_pos = 0;
+        loop {
+            self.push(offset, key_pos);
+            let frame = self.stack.last_mut().unwrap();
+            match frame.node.prefix.cmp(&start[key_pos..frame.edge_pos]) {
+                // Every key below the node is after `start`.
+                Ordering::Greater => return,
+                // Every key below the node is before `start`.
+                Ordering::Less => {
+                    frame.next = frame.node.children.len();
+                    return;
+                }
+                Ordering::Equal => {}
+            }
+
+            let byte = start[frame.edge_pos];
+            let i = frame.node.children.partition_point(|&(k, _)| k < byte);
+            frame.next = i;
+            match frame.node.children.get(i) {
+                Some(&(k, child)) if k == byte => {
+                    if frame.node.is_leaf {
+                        if !inclusive {
+                            frame.next += 1;
+                        }
+                        return;
+                    }
+                    frame.next += 1;
+                    self.key[frame.edge_pos] = byte;
+                    key_pos = frame.edge_pos + 1;
+                    offset = child;
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+impl<K, V, const K_LEN: usize> Iterator for CongeeCompactMapIter<'_, '_, K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    usize: From<V>,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            let Some(&(k, offset)) = frame.node.children.get(frame.next) else {
+                self.stack.pop();
+                continue;
+            };
+            let index = frame.next;
+            frame.next += 1;
+            let edge_pos = frame.edge_pos;
+            self.key[edge_pos] = k;
+            if !frame.node.is_leaf {
+                self.push(offset, edge_pos + 1);
+                continue;
+            }
+
+            let past_end = match &self.end {
+                Bound::Included(end) => self.key > *end,
+                Bound::Excluded(end) => self.key >= *end,
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                self.stack.clear();
+                return None;
+            }
+            let rank = frame.first_rank + index;
+            return Some((K::from_key_bytes(self.key), self.map.value(rank)));
+        }
+    }
+}
+
+/// Statistics of a [CongeeCompactMap].
+#[derive(Default, Debug, Clone)]
+pub struct CompactMapStats {
+    /// Statistics of the nodes, `total_data_size` covers the whole map.
+    pub nodes: CompactSetStats,
+    /// Bytes of the ranks stored after the leaf nodes.
+    pub rank_bytes: usize,
+    /// Bytes per value.
+    pub value_width: usize,
+    pub value_bytes: usize,
+}
+
+impl CompactMapStats {
+    pub fn bytes_per_entry(&self) -> f64 {
+        self.nodes.bytes_per_key()
+    }
+}
+
+impl std::fmt::Display for CompactMapStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.nodes)?;
+        writeln!(f, "Rank Bytes:      {:>8}", self.rank_bytes)?;
+        writeln!(
+            f,
+            "Value Bytes:     {:>8} ({} bytes per value)",
+            self.value_bytes, self.value_width
+        )?;
+        writeln!(f, "Bytes per Entry: {:>8.2}", self.bytes_per_entry())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{CongeeRaw, DefaultAllocator, tests::compact_key_patterns};
+
+    fn build(entries: &BTreeMap<usize, usize>) -> Vec<u8> {
+        let tree = CongeeRaw::<usize, usize>::default();
+        let guard = tree.pin();
+        for (&k, &v) in entries {
+            tree.insert(k, v, &guard).unwrap();
+        }
+        tree.to_compact_map()
+    }
+
+    #[test]
+    fn test_get() {
+        for keys in compact_key_patterns() {
+            let expected: BTreeMap<usize, usize> = keys.iter().map(|&k| (k, k ^ 0x5555)).collect();
+            let data = build(&expected);
+            let compact = CongeeCompactMap::<usize, usize>::new(&data);
+
+            assert_eq!(compact.len(), expected.len());
+            for (k, v) in &expected {
+                assert_eq!(compact.get(k), Some(*v), "key 0x{k:016x}");
+            }
+            for k in keys.iter().map(|k| k.wrapping_add(1)) {
+                assert_eq!(compact.get(&k), expected.get(&k).copied());
+            }
+            assert!(!compact.contains_key(&(1 << 63 | 12345)));
+        }
+    }
+
+    #[test]
+    fn test_range_iter() {
+        for keys in compact_key_patterns() {
+            let expected: BTreeMap<usize, usize> = keys.iter().map(|&k| (k, k % 1000)).collect();
+            let data = build(&expected);
+            let compact = CongeeCompactMap::<usize, usize>::new(&data);
+            assert!(compact.iter().eq(expected.clone()));
+
+            let mut bounds: Vec<usize> = keys.iter().step_by(97).copied().collect();
+            bounds.extend(keys.iter().step_by(101).map(|k| k.wrapping_add(1)));
+            bounds.extend([0, 1 << 40, usize::MAX]);
+            for &start in &bounds {
+                for &end in bounds.iter().filter(|&&end| end >= start).take(4) {
+                    assert!(
+                        compact
+                            .range_iter(start..=end)
+                            .eq(expected.range(start..=end).map(|(k, v)| (*k, *v)))
+                    );
+                    let excluded = (Bound::Excluded(start), Bound::Excluded(end));
+                    if start < end {
+                        assert!(
+                            compact
+                                .range_iter(excluded)
+                                .eq(expected.range(excluded).map(|(k, v)| (*k, *v)))
+                        );
+                    }
+                }
+                assert!(
+                    compact
+                        .range_iter(start..)
+                        .eq(expected.range(start..).map(|(k, v)| (*k, *v)))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_value_width() {
+        for (max, width) in [
+            (0, 1),
+            (255, 1),
+            (256, 2),
+            (u32::MAX as usize, 4),
+            (1 << 32, 8),
+        ] {
+            let expected: BTreeMap<usize, usize> = [(1, 0), (2, max), (1 << 20, 7)].into();
+            let data = build(&expected);
+            let compact = CongeeCompactMap::<usize, usize>::new(&data);
+            let stats = compact.stats();
+            assert_eq!(stats.value_width, width);
+            assert_eq!(stats.value_bytes, 3 * width);
+            assert_eq!(stats.nodes.kv_pairs, 3);
+            assert_eq!(stats.nodes.total_data_size, data.len());
+            assert!(compact.iter().eq(expected));
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let tree = CongeeRaw::<usize, usize>::default();
+        let guard = tree.pin();
+        tree.insert(1, 1, &guard).unwrap();
+        tree.remove(&1, &guard);
+
+        let data = tree.to_compact_map();
+        let compact = CongeeCompactMap::<usize, usize>::new(&data);
+        assert!(compact.is_empty());
+        assert_eq!(compact.len(), 0);
+        assert_eq!(compact.get(&1), None);
+        assert_eq!(compact.iter().count(), 0);
+        assert_eq!(compact.stats().nodes.total_nodes, 0);
+    }
+
+    #[test]
+    fn test_key_encodings() {
+        let tree = CongeeRaw::<i64, usize>::default();
+        let guard = tree.pin();
+        for k in -500i64..500 {
+            tree.insert(k * 1_000_003, k.unsigned_abs() as usize, &guard)
+                .unwrap();
+        }
+        let data = tree.to_compact_map();
+        let compact = CongeeCompactMap::<i64, usize>::new(&data);
+        assert!(compact.iter().eq(tree.iter(&guard)));
+        assert!(
+            compact
+                .range_iter(-3_000_009..=0)
+                .map(|(k, _v)| k)
+                .eq((-3..=0).map(|k| k * 1_000_003))
+        );
+
+        let tree = CongeeRaw::<u32, usize, DefaultAllocator, 4>::default();
+        for k in 0..1000u32 {
+            tree.insert(k * 31, k as usize, &guard).unwrap();
+        }
+        let data = tree.to_compact_map();
+        let compact = CongeeCompactMap::<u32, usize, 4>::new(&data);
+        assert_eq!(compact.get(&(31 * 999)), Some(999));
+        assert_eq!(compact.get(&30), None);
+        assert!(compact.iter().eq(tree.iter(&guard)));
+    }
+}
@@ -57,10 +57,11 @@
 //!
 //! ## Supported Operations
 //!
-//! Supported: Key lookups only (contains)
+//! Supported: Key lookups (contains) and ascending iteration (iter)
 //! Not supported: Insertions, deletions, updates (read-only structure)
 //!
-//! The structure is created by converting from a `CongeeSet` using `to_compact_set()`.
+//! The structure is created by converting from a `CongeeSet` using `to_compact_set()`,
+//! and converted back into a mutable `CongeeSet` with `CongeeSet::from_compact_set()`.
 
 use std::marker::PhantomData;
 
@@ -86,6 +87,90 @@ struct NodeHeader {
     children_len: u16,
 }
 
+/// A node decoded from the compact layout.
+pub(crate) struct CompactNode<'a> {
+    pub(crate) prefix: &'a [u8],
+    /// Whether the children are keys rather than nodes.
+    pub(crate) is_leaf: bool,
+    /// The key byte and the node offset of each child in ascending key order, the offset is 0 in leaf nodes.
+    pub(crate) children: Vec<(u8, usize)>,
+    /// The offset right after the node.
+    pub(crate) end: usize,
+}
+
+/// Decodes the node at `offset` of the compact layout in `data`.
+///
+/// # Panics
+///
+/// Panics if `data` holds no valid node at `offset`.
+pub(crate) fn read_node(data: &[u8], offset: usize) -> CompactNode<'_> {
+    try_read_node(data, offset).unwrap_or_else(|| panic!("Malformed node at offset {offset}"))
+}
+
+/// Decodes the node at `offset` of the compact layout in `data`, `None` if the node type is unknown
+/// or the node runs past the end of `data`.
+pub(crate) fn try_read_node(data: &[u8], offset: usize) -> Option<CompactNode<'_>> {
+    let header = data.get(offset..offset.checked_add(4)?)?;
+    let node_type = header[0];
+    let prefix_len = header[1] as usize;
+    let children_len = u16::from_le_bytes([header[2], header[3]]) as usize;
+    let prefix_start = offset + 4;
+    let children_start = prefix_start + prefix_len;
+    let children_size = match node_type {
+        NodeType::N48_INTERNAL => 256 + children_len * 4,
+        NodeType::N256_INTERNAL => 256 * 4,
+        NodeType::N48_LEAF | NodeType::N256_LEAF => 32,
+        NodeType::N4_LEAF | NodeType::N16_LEAF => children_len,
+        NodeType::N4_INTERNAL | NodeType::N16_INTERNAL => children_len * 5,
+        _ => return None,
+    };
+    let prefix = data.get(prefix_start..children_start)?;
+    let body = data.get(children_start..children_start + children_size)?;
+    let read_offset = |at: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(body.get(at..at + 4)?.try_into().unwrap()) as usize)
+    };
+
+    let (is_leaf, children) = match node_type {
+        NodeType::N4_LEAF | NodeType::N16_LEAF => (true, body.iter().map(|&k| (k, 0)).collect()),
+        NodeType::N48_LEAF | NodeType::N256_LEAF => (
+            true,
+            (0..=u8::MAX)
+                .filter(|&k| body[k as usize / 8] & (1u8 << (k % 8)) != 0)
+                .map(|k| (k, 0))
+                .collect(),
+        ),
+        NodeType::N4_INTERNAL | NodeType::N16_INTERNAL => (
+            false,
+            (0..children_len)
+                .map(|i| Some((body[i], read_offset(children_len + i * 4)?)))
+                .collect::<Option<_>>()?,
+        ),
+        NodeType::N48_INTERNAL => (
+            false,
+            (0..=u8::MAX)
+                .filter_map(|k| match body[k as usize] as usize {
+                    0 => None,
+                    index => Some(read_offset(256 + (index - 1) * 4).map(|offset| (k, offset))),
+                })
+                .collect::<Option<_>>()?,
+        ),
+        _ => (
+            false,
+            (0..=u8::MAX)
+                .map(|k| (k, read_offset(k as usize * 4).unwrap()))
+                .filter(|&(_k, offset)| offset != 0)
+                .collect(),
+        ),
+    };
+
+    Some(CompactNode {
+        prefix,
+        is_leaf,
+        children,
+        end: children_start + children_size,
+    })
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct CompactSetStats {
     pub total_data_size: usize,
@@ -436,7 +521,7 @@ where
     /// ```
     /// use congee::{CongeeSet, CongeeCompactSet};
     ///
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     /// set.insert(42, &guard).unwrap();
     ///
@@ -545,7 +630,7 @@ where
     /// ```
     /// use congee::{CongeeSet, CongeeCompactSet};
     ///
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     /// set.insert(42, &guard).unwrap();
     ///
@@ -767,6 +852,42 @@ where
         }
     }
 
+    /// Returns an iterator over the keys in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeSet, CongeeCompactSet};
+    ///
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// for k in [3, 1, 2] {
+    ///     set.insert(k, &guard).unwrap();
+    /// }
+    ///
+    /// let data = set.to_compact_set();
+    /// let compact_set = CongeeCompactSet::<usize>::new(&data);
+    /// assert_eq!(compact_set.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+    /// ```
+    pub fn iter(&self) -> CongeeCompactSetIter<'_, 'a, K> {
+        CongeeCompactSetIter::new(self)
+    }
+
+    /// Returns true if the set holds no keys.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Decodes the node at `offset`.
+    pub(crate) fn read_node(&self, offset: usize) -> CompactNode<'a> {
+        read_node(self.data, offset)
+    }
+
+    /// Decodes the node at `offset`, `None` if the data holds no valid node there.
+    pub(crate) fn try_read_node(&self, offset: usize) -> Option<CompactNode<'a>> {
+        try_read_node(self.data, offset)
+    }
+
     /// Print the compact set in a human readable format
     pub fn debug_print(&self) {
         println!("\n=== CongeeCompactSet Debug Structure ===");
@@ -867,7 +988,7 @@ where
     /// ```
     /// use congee::{CongeeSet, CongeeCompactSet};
     ///
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     /// set.insert(42, &guard).unwrap();
     ///
@@ -968,10 +1089,71 @@ where
     }
 }
 
+/// An iterator over the keys of a [CongeeCompactSet] in ascending order.
+pub struct CongeeCompactSetIter<'s, 'a, K: Copy + From<usize>>
+where
+    usize: From<K>,
+{
+    set: &'s CongeeCompactSet<'a, K>,
+    /// The nodes on the path to the current key, with the next child to visit
+    /// and the position of the child key byte.
+    stack: Vec<(CompactNode<'a>, usize, usize)>,
+    key: [u8; 8],
+}
+
+impl<'s, 'a, K: Copy + From<usize>> CongeeCompactSetIter<'s, 'a, K>
+where
+    usize: From<K>,
+{
+    fn new(set: &'s CongeeCompactSet<'a, K>) -> Self {
+        let mut iter = Self {
+            set,
+            stack: Vec::new(),
+            key: [0; 8],
+        };
+        if !set.is_empty() {
+            iter.push(0, 0);
+        }
+        iter
+    }
+
+    /// Descends into the node at `offset` whose prefix starts at `key_pos`.
+    fn push(&mut self, offset: usize, key_pos: usize) {
+        let node = self.set.read_node(offset);
+        let edge_pos = key_pos + node.prefix.len();
+        self.key[key_pos..edge_pos].copy_from_slice(node.prefix);
+        self.stack.push((node, 0, edge_pos));
+    }
+}
+
+impl<K: Copy + From<usize>> Iterator for CongeeCompactSetIter<'_, '_, K>
+where
+    usize: From<K>,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        loop {
+            let (node, next, edge_pos) = self.stack.last_mut()?;
+            let Some(&(k, offset)) = node.children.get(*next) else {
+                self.stack.pop();
+                continue;
+            };
+            *next += 1;
+            let edge_pos = *edge_pos;
+            self.key[edge_pos] = k;
+            if node.is_leaf {
+                return Some(K::from(usize::from_be_bytes(self.key)));
+            }
+            self.push(offset, edge_pos + 1);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::CongeeSet;
+    use crate::{CongeeSet, tests::compact_key_patterns};
 
     #[test]
     fn test_sequential_keys() {
@@ -1431,4 +1613,90 @@ mod tests {
             "Access distribution should sum to ~100%"
         );
     }
+
+    #[test]
+    fn test_iter() {
+        for keys in compact_key_patterns() {
+            let tree = CongeeSet::<usize>::default();
+            let guard = tree.pin();
+            for &k in &keys {
+                tree.insert(k, &guard).unwrap();
+            }
+
+            let data = tree.to_compact_set();
+            let compact = CongeeCompactSet::<usize>::new(&data);
+            assert!(compact.iter().eq(tree.iter(&guard)));
+        }
+    }
+
+    #[test]
+    fn test_from_compact_set() {
+        for keys in compact_key_patterns() {
+            let tree = CongeeSet::<usize>::default();
+            let guard = tree.pin();
+            for &k in &keys {
+                tree.insert(k, &guard).unwrap();
+            }
+
+            let data = tree.to_compact_set();
+            let compact = CongeeCompactSet::<usize>::new(&data);
+            let loaded =
+                CongeeSet::<usize>::from_compact_set(crate::DefaultAllocator {}, &compact).unwrap();
+            assert_eq!(loaded.len(&guard), tree.len(&guard));
+            assert!(loaded.iter(&guard).eq(tree.iter(&guard)));
+            // Inserting only ever grows nodes to the smallest type that fits, so the rebuilt nodes are the same.
+            assert_eq!(loaded.to_compact_set(), data);
+
+            // The rebuilt set is a regular, mutable set.
+            let mut expected: std::collections::BTreeSet<usize> = keys.iter().copied().collect();
+            for &k in keys.iter().step_by(2) {
+                assert!(loaded.remove(&k, &guard));
+                expected.remove(&k);
+            }
+            loaded.insert(3, &guard).unwrap();
+            expected.insert(3);
+            assert_eq!(loaded.len(&guard), expected.len());
+            assert!(loaded.iter(&guard).eq(expected));
+        }
+    }
+
+    #[test]
+    fn test_from_malformed_compact_set() {
+        let tree = CongeeSet::<usize>::default();
+        let guard = tree.pin();
+        for k in (0..300).chain((0..20).map(|k| k << 40)) {
+            tree.insert(k * 3, &guard).unwrap();
+        }
+        let data = tree.to_compact_set();
+        let load = |data: &[u8]| {
+            CongeeSet::<usize>::from_compact_set(
+                crate::DefaultAllocator {},
+                &CongeeCompactSet::new(data),
+            )
+        };
+
+        let mut bad = data.clone();
+        bad[0] = 8;
+        assert!(matches!(
+            load(&bad),
+            Err(crate::CompactSetError::Malformed { offset: 0 })
+        ));
+        assert!(matches!(
+            load(&data[..data.len() - 1]),
+            Err(crate::CompactSetError::Malformed { .. })
+        ));
+
+        // Damaged data is either rejected or builds a valid set, it never panics.
+        for i in 0..data.len() {
+            for flip in [1, 0x80, 0xff] {
+                let mut bad = data.clone();
+                bad[i] ^= flip;
+                if let Ok(loaded) = load(&bad) {
+                    let keys: Vec<usize> = loaded.iter(&guard).collect();
+                    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                    assert_eq!(keys.len(), loaded.len(&guard));
+                }
+            }
+        }
+    }
 }
@@ -1,14 +1,22 @@
-use std::{marker::PhantomData, ptr::NonNull, sync::Arc};
+use std::{cmp, collections::BTreeMap, marker::PhantomData, ops::Bound, ptr::NonNull, sync::Arc};
 
 use crossbeam_epoch::Guard;
 
 use crate::{
-    Allocator, DefaultAllocator, cast_ptr,
-    error::{ArtError, OOMError},
-    lock::ReadGuard,
-    nodes::{BaseNode, ChildIsPayload, ChildIsSubNode, Node, Node4, NodePtr, NodeType, Parent},
-    range_scan::RangeScan,
-    utils::{Backoff, KeyTracker},
+    Allocator, CongeeCompactSet, DefaultAllocator,
+    bulk_load::{BulkLoader, SortedBuildError},
+    error::{ArtError, CompactSetError, OOMError, TransactionError},
+    iter::{RawIter, inclusive_bounds, key_predecessor, key_successor, prefix_range},
+    lock::{ReadGuard, WriteGuard},
+    nodes::{
+        BaseNode, ChildIsPayload, ChildIsSubNode, MAX_PREFIX_LEN, Node, Node4, NodePtr, NodeType,
+        Parent, PtrType,
+    },
+    range_remove::{self, DetachedSubtree, RangeRemoval},
+    range_scan::{RangeScan, ScanOutput},
+    snapshot::History,
+    transaction::TransactionCommit,
+    utils::{Backoff, KeyTracker, ShardedCounter, prefetch},
 };
 #[cfg(all(feature = "shuttle", test))]
 use shuttle::sync::atomic::AtomicPtr;
@@ -24,6 +32,10 @@ pub(crate) struct CongeeInner<
     pub(crate) root: AtomicPtr<BaseNode>,
     drain_callback: Arc<dyn Fn([u8; K_LEN], usize)>,
     allocator: A,
+    /// The number of keys, updated after each insert and remove.
+    len: Arc<ShardedCounter>,
+    /// The snapshots of the tree and the values they still need.
+    history: History<K_LEN>,
     _pt_key: PhantomData<[u8; K_LEN]>,
 }
 
@@ -104,10 +116,62 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
             root: AtomicPtr::new(root.into_non_null().cast::<BaseNode>().as_ptr()),
             drain_callback,
             allocator,
+            len: Arc::new(ShardedCounter::new(0)),
+            history: History::new(),
             _pt_key: PhantomData,
         }
     }
 
+    /// Builds a tree from entries in strictly ascending key order, while they are consumed.
+    /// If the tree can't be built, the payloads consumed so far are passed to `drain_callback`
+    /// and the rest of `entries` is dropped without being read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not in strictly ascending order.
+    pub(crate) fn from_sorted_iter(
+        allocator: A,
+        drain_callback: Arc<dyn Fn([u8; K_LEN], usize)>,
+        entries: impl IntoIterator<Item = ([u8; K_LEN], usize)>,
+    ) -> Result<Self, OOMError> {
+        let loader = BulkLoader::new(&allocator);
+        let (root, len) = match loader.build_sorted(entries.into_iter(), &*drain_callback) {
+            Ok(built) => built,
+            Err(SortedBuildError::Oom) => return Err(OOMError::new()),
+            Err(SortedBuildError::Unsorted(i)) => panic!(
+                "keys must be in strictly ascending order, found an out of order key at index {i}"
+            ),
+        };
+        Ok(CongeeInner {
+            root: AtomicPtr::new(root.as_ptr()),
+            drain_callback,
+            allocator,
+            len: Arc::new(ShardedCounter::new(len)),
+            history: History::new(),
+            _pt_key: PhantomData,
+        })
+    }
+
+    /// Rebuilds the tree of a compact set node by node, every key gets the payload `1`.
+    pub(crate) fn from_compact_set<K: Copy + From<usize>>(
+        allocator: A,
+        drain_callback: Arc<dyn Fn([u8; K_LEN], usize)>,
+        compact: &CongeeCompactSet<K>,
+    ) -> Result<Self, CompactSetError>
+    where
+        usize: From<K>,
+    {
+        let (root, len) = BulkLoader::<K_LEN, A>::new(&allocator).build_compact(compact)?;
+        Ok(CongeeInner {
+            root: AtomicPtr::new(root.as_ptr()),
+            drain_callback,
+            allocator,
+            len: Arc::new(ShardedCounter::new(len)),
+            history: History::new(),
+            _pt_key: PhantomData,
+        })
+    }
+
     #[inline]
     fn load_root(&self) -> NonNull<BaseNode> {
         let root_ptr = self.root.load(std::sync::atomic::Ordering::Relaxed);
@@ -117,16 +181,14 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
 }
 
 impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
-    pub(crate) fn is_empty(&self, _guard: &Guard) -> bool {
-        loop {
-            let root = self.load_root();
-            if let Ok(node) = BaseNode::read_lock(root) {
-                let is_empty = node.as_ref().meta.count() == 0;
-                if node.check_version().is_ok() {
-                    return is_empty;
-                }
-            }
-        }
+    pub(crate) fn is_empty(&self, guard: &Guard) -> bool {
+        self.len(guard) == 0
+    }
+
+    /// The number of keys in the tree, in O(1).
+    /// Exact when no insert or remove runs concurrently, otherwise those may or may not be counted.
+    pub(crate) fn len(&self, _guard: &Guard) -> usize {
+        self.len.get()
     }
 
     #[inline]
@@ -153,20 +215,101 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
 
                 let child_node = child_node?;
 
-                cast_ptr!(child_node => {
-                    Payload(tid) => {
+                match child_node.downcast::<K_LEN>(level) {
+                    PtrType::Payload(tid) => {
                         return Some(tid);
-                    },
-                    SubNode(sub_node) => {
+                    }
+                    PtrType::SubNode(sub_node) => {
                         level += 1;
 
-                        node = if let Ok(n) = BaseNode::read_lock(sub_node) {
+                        let next_node = if let Ok(n) = BaseNode::read_lock(sub_node) {
                             n
                         } else {
                             continue 'outer;
                         };
+                        // The child might have been moved down by a prefix split, which changes the
+                        // level of its children, so the parent must still be valid after locking the child.
+                        if node.check_version().is_err() {
+                            continue 'outer;
+                        }
+                        node = next_node;
                     }
-                });
+                }
+            }
+        }
+    }
+
+    /// Looks up all `keys` in order, passing the value of each key to `found`.
+    /// Each lookup starts from the deepest node on the path of the previous key that the key still passes,
+    /// so sorted keys sharing prefixes skip most of the traversal.
+    pub(crate) fn get_many(
+        &self,
+        keys: impl Iterator<Item = [u8; K_LEN]>,
+        mut found: impl FnMut(Option<usize>),
+        _guard: &Guard,
+    ) {
+        // Nodes on the path of the previous key, with the level of the key byte that selects their child.
+        let mut path: Vec<(ReadGuard, usize)> = Vec::with_capacity(K_LEN);
+        let mut prev: Option<[u8; K_LEN]> = None;
+        for key in keys {
+            // A node can be reused if the key matches the previous one up to the node's child level.
+            let common = prev.map_or(0, |p| {
+                p.iter().zip(&key).take_while(|(a, b)| a == b).count()
+            });
+            while path.last().is_some_and(|(_, level)| *level > common) {
+                path.pop();
+            }
+            found(self.get_from_path(&key, &mut path));
+            prev = Some(key);
+        }
+    }
+
+    /// Continues the lookup of `key` from the last node of `path`, extending the path along the way.
+    /// Falls back to a lookup from the root if any node on the way has changed.
+    fn get_from_path<'a>(
+        &'a self,
+        key: &[u8; K_LEN],
+        path: &mut Vec<(ReadGuard<'a>, usize)>,
+    ) -> Option<usize> {
+        'outer: loop {
+            if path.is_empty() {
+                let Ok(root) = BaseNode::read_lock(self.load_root()) else {
+                    continue;
+                };
+                let level = root.as_ref().check_prefix(key, 0)?;
+                path.push((root, level));
+            }
+
+            loop {
+                let (node, level) = path.last().unwrap();
+                let level = *level;
+                let child_node = node
+                    .as_ref()
+                    .get_child(unsafe { *key.get_unchecked(level) });
+                if node.check_version().is_err() {
+                    path.clear();
+                    continue 'outer;
+                }
+
+                match child_node?.downcast::<K_LEN>(level) {
+                    PtrType::Payload(tid) => {
+                        return Some(tid);
+                    }
+                    PtrType::SubNode(sub_node) => {
+                        prefetch(sub_node.as_ptr());
+                        let Ok(next_node) = BaseNode::read_lock(sub_node) else {
+                            path.clear();
+                            continue 'outer;
+                        };
+                        // Same as `get`, the child is only valid if the parent has not changed.
+                        if node.check_version().is_err() {
+                            path.clear();
+                            continue 'outer;
+                        }
+                        let next_level = next_node.as_ref().check_prefix(key, level + 1)?;
+                        path.push((next_node, next_level));
+                    }
+                }
             }
         }
     }
@@ -195,18 +338,22 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
     ) -> Result<(), ArtError> {
         let root = self.load_root();
         let mut key_tracker = KeyTracker::empty();
-        Self::recursive_dfs(root, &mut key_tracker, 0, visitor)?;
+        Self::recursive_dfs(root, None, &mut key_tracker, 0, visitor)?;
         Ok(())
     }
 
     fn recursive_dfs<V: CongeeVisitor<K_LEN>>(
         node_ptr: NonNull<BaseNode>,
+        parent: Option<&ReadGuard>,
         key_tracker: &mut KeyTracker<K_LEN>,
         tree_level: usize,
         visitor: &mut V,
     ) -> Result<(), ArtError> {
         visitor.pre_visit_sub_node(node_ptr, tree_level);
         let node_lock = BaseNode::read_lock(node_ptr)?;
+        if let Some(p) = parent {
+            p.check_version()?;
+        }
 
         // Add this node's prefix to the key tracker
         let node_prefix = node_lock.as_ref().prefix();
@@ -220,19 +367,27 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
             // Add the edge key to the tracker
             key_tracker.push(k);
 
-            cast_ptr!(child_ptr => {
-                Payload(tid) => {
+            // The level of the child depends on the prefix we read, validate it before using the child.
+            node_lock.check_version()?;
+            match child_ptr.downcast::<K_LEN>(key_tracker.len() - 1) {
+                PtrType::Payload(tid) => {
                     // We've reached a leaf, construct the key from the tracker
                     let mut key: [u8; K_LEN] = [0; K_LEN];
                     let tracker_slice = key_tracker.as_slice();
                     let copy_len = tracker_slice.len().min(K_LEN);
                     key[..copy_len].copy_from_slice(&tracker_slice[..copy_len]);
                     visitor.visit_payload(key, tid);
-                },
-                SubNode(sub_node) => {
-                    Self::recursive_dfs(sub_node, key_tracker, tree_level + 1, visitor)?;
                 }
-            });
+                PtrType::SubNode(sub_node) => {
+                    Self::recursive_dfs(
+                        sub_node,
+                        Some(&node_lock),
+                        key_tracker,
+                        tree_level + 1,
+                        visitor,
+                    )?;
+                }
+            }
 
             // Remove the edge key from the tracker
             key_tracker.pop();
@@ -258,6 +413,53 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
         }
     }
 
+    /// Builds the nodes holding `k[level..]` below a new edge.
+    /// A node stores at most `MAX_PREFIX_LEN` prefix bytes, longer remainders are split into a chain of nodes.
+    fn make_path(
+        &self,
+        k: &[u8; K_LEN],
+        level: usize,
+        payload: impl FnOnce() -> usize,
+    ) -> Result<NodePtr, ArtError> {
+        let mut path = Vec::new();
+        let mut start = level;
+        loop {
+            let end = std::cmp::min(start + MAX_PREFIX_LEN, K_LEN - 1);
+            path.push((
+                BaseNode::make_node::<Node4, A>(&k[start..end], &self.allocator)?,
+                k[end],
+            ));
+            if end == K_LEN - 1 {
+                break;
+            }
+            start = end + 1;
+        }
+
+        let mut child = NodePtr::from_payload(payload());
+        while let Some((mut node, key)) = path.pop() {
+            node.as_mut().insert(key, child);
+            child = node.into_note_ptr();
+        }
+        Ok(child)
+    }
+
+    /// Frees the nodes created by `make_path`, the payload is left untouched.
+    fn drop_path(&self, k: &[u8; K_LEN], level: usize, ptr: NodePtr) {
+        let mut ptr = ptr;
+        let mut start = level;
+        loop {
+            let end = std::cmp::min(start + MAX_PREFIX_LEN, K_LEN - 1);
+            let node = unsafe { ptr.as_sub_node_unchecked() };
+            let next = unsafe { node.as_ref() }.get_child(k[end]);
+            unsafe { BaseNode::drop_node(node, self.allocator.clone()) };
+            match next {
+                Some(n) if end < K_LEN - 1 => ptr = n,
+                _ => return,
+            }
+            start = end + 1;
+        }
+    }
+
     #[inline]
     fn insert_inner<F>(
         &self,
@@ -293,18 +495,8 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                             match Self::is_last_level(level) {
                                 Ok(_is_last_level) => NodePtr::from_payload(tid_func(None)),
                                 Err(_is_sub_node) => {
-                                    // Create a new node that will hold the remaining part of the key
-                                    // The prefix should be the remaining bytes after current level
-                                    let remaining_prefix = &k[level + 1..k.len() - 1];
-                                    let mut n4 = BaseNode::make_node::<Node4, A>(
-                                        remaining_prefix,
-                                        &self.allocator,
-                                    )?;
-                                    n4.as_mut().insert(
-                                        k[k.len() - 1],
-                                        NodePtr::from_payload(tid_func(None)),
-                                    );
-                                    n4.into_note_ptr()
+                                    // Create new node(s) that will hold the remaining part of the key
+                                    self.make_path(k, level + 1, || tid_func(None))?
                                 }
                             }
                         };
@@ -315,16 +507,15 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                             (node_key, new_leaf),
                             &self.allocator,
                             guard,
+                            || self.history.record(|w| w(k, None), guard),
                         ) {
-                            cast_ptr!(new_leaf => {
-                                Payload(_) => {},
-                                SubNode(sub_node) => unsafe {
-                                    BaseNode::drop_node(sub_node, self.allocator.clone());
-                                }
-                            });
+                            if Self::is_last_level(level).is_err() {
+                                self.drop_path(k, level + 1, new_leaf);
+                            }
                             return Err(e);
                         }
 
+                        self.len.add(1);
                         return Ok(None);
                     };
 
@@ -332,8 +523,8 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                         p.unlock()?;
                     }
 
-                    cast_ptr!(next_node => {
-                        Payload(old) => {
+                    match next_node.downcast::<K_LEN>(level) {
+                        PtrType::Payload(old) => {
                             // At this point, the level must point to the last u8 of the key,
                             // meaning that we are updating an existing value.
                             let new = tid_func(Some(old));
@@ -343,18 +534,19 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                             }
 
                             let mut write_n = node.upgrade().map_err(|(_n, v)| v)?;
+                            self.history.record(|w| w(k, Some(old)), guard);
 
                             write_n
                                 .as_mut()
                                 .change(node_key, NodePtr::from_payload(new));
                             return Ok(Some(old));
-                        },
-                        SubNode(sub_node) => {
+                        }
+                        PtrType::SubNode(sub_node) => {
                             parent = Parent::Node(node_key, node);
                             node = BaseNode::read_lock(sub_node)?;
                             level += 1;
                         }
-                    });
+                    }
                 }
 
                 Some((no_match_key, prefix)) => {
@@ -367,6 +559,7 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
 
                     let mut write_p = parent_node.upgrade().map_err(|(_n, v)| v)?;
                     let mut write_n = node.upgrade().map_err(|(_n, v)| v)?;
+                    self.history.record(|w| w(k, None), guard);
 
                     // 1) Create new node which will be parent of node, Set common prefix, level to this node
                     let mut new_middle_node = BaseNode::make_node::<Node4, A>(
@@ -381,18 +574,12 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                             .as_mut()
                             .insert(k[next_level], NodePtr::from_payload(tid_func(None)));
                     } else {
-                        // otherwise create a new node
-                        let mut single_new_node = BaseNode::make_node::<Node4, A>(
-                            &k[(next_level + 1)..k.len() - 1],
-                            &self.allocator,
-                        )?;
-
-                        single_new_node
-                            .as_mut()
-                            .insert(k[k.len() - 1], NodePtr::from_payload(tid_func(None)));
+                        // otherwise create new node(s) for the rest of the key
+                        let single_new_node =
+                            self.make_path(k, next_level + 1, || tid_func(None))?;
                         new_middle_node
                             .as_mut()
-                            .insert(k[next_level], single_new_node.into_note_ptr());
+                            .insert(k[next_level], single_new_node);
                     }
 
                     new_middle_node
@@ -409,6 +596,7 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                         .as_mut()
                         .set_prefix(&prefix[0..(prefix_len - (next_level - level + 1))]);
 
+                    self.len.add(1);
                     return Ok(None);
                 }
             }
@@ -462,17 +650,595 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
         }
     }
 
+    /// Inserts the key if it's absent, returns the existing value otherwise.
+    /// An existing payload is left untouched, `insert_inner` only writes when the computed value differs.
     #[inline]
-    pub(crate) fn range(
+    pub(crate) fn insert_if_absent(
+        &self,
+        k: &[u8; K_LEN],
+        tid: usize,
+        guard: &Guard,
+    ) -> Result<Option<usize>, OOMError> {
+        self.compute_or_insert(k, &mut |old| old.unwrap_or(tid), guard)
+    }
+
+    /// Inserts all entries in order, returns the old value of each key.
+    /// Consecutive keys that land in the same last level node are inserted under a single lock of that node.
+    /// On OOM, the entries before the failed one are inserted.
+    pub(crate) fn insert_batch(
+        &self,
+        entries: &[([u8; K_LEN], usize)],
+        guard: &Guard,
+    ) -> Result<Vec<Option<usize>>, OOMError> {
+        let mut old = Vec::with_capacity(entries.len());
+        let mut rest = entries;
+        while !rest.is_empty() {
+            let backoff = Backoff::new();
+            let inserted = loop {
+                match self.insert_batch_inner(rest, &mut old, guard) {
+                    Ok(n) => break n,
+                    Err(e) => match e {
+                        ArtError::Locked | ArtError::VersionNotMatch => {
+                            backoff.spin();
+                            continue;
+                        }
+                        ArtError::Oom => return Err(OOMError::new()),
+                    },
+                }
+            };
+            rest = &rest[inserted..];
+        }
+        Ok(old)
+    }
+
+    /// Inserts the leading entries of `entries` that share the last level node of the first key.
+    /// Falls back to a single `insert_inner` if that node doesn't exist yet.
+    /// Returns the number of inserted entries, their old values are appended to `old`.
+    fn insert_batch_inner(
+        &self,
+        entries: &[([u8; K_LEN], usize)],
+        old: &mut Vec<Option<usize>>,
+        guard: &Guard,
+    ) -> Result<usize, ArtError> {
+        let (k, tid) = &entries[0];
+        let mut parent = Parent::Root(&self.root);
+        let mut node = BaseNode::read_lock(self.load_root())?;
+        let mut level = 0usize;
+
+        loop {
+            let mut next_level = level;
+            if node
+                .as_ref()
+                .check_prefix_not_match(k, &mut next_level)
+                .is_some()
+            {
+                break;
+            }
+            level = next_level;
+            if level == K_LEN - 1 {
+                return self.insert_into_last_level(node, parent, entries, old, guard);
+            }
+
+            let next_node = node.as_ref().get_child(k[level]);
+            node.check_version()?;
+            let Some(next_node) = next_node else {
+                break;
+            };
+
+            if let Parent::Node(_, p) = parent {
+                p.unlock()?;
+            }
+            // The child is a sub node, payloads are only found at the last level.
+            let sub_node = unsafe { next_node.as_sub_node_unchecked() };
+            parent = Parent::Node(k[level], node);
+            node = BaseNode::read_lock(sub_node)?;
+            level += 1;
+        }
+
+        // The path of the key is incomplete, it is created by a regular insert.
+        old.push(self.insert_inner(k, &mut |_| *tid, guard)?);
+        Ok(1)
+    }
+
+    /// Inserts the leading entries of `entries` that belong to `node`, whose children are payloads.
+    fn insert_into_last_level<'a>(
+        &'a self,
+        node: ReadGuard<'a>,
+        parent: Parent<'a>,
+        entries: &[([u8; K_LEN], usize)],
+        old: &mut Vec<Option<usize>>,
+        guard: &Guard,
+    ) -> Result<usize, ArtError> {
+        let node_key = &entries[0].0[..K_LEN - 1];
+        let cnt = entries
+            .iter()
+            .take_while(|(k, _)| &k[..K_LEN - 1] == node_key)
+            .count();
+
+        // The final payload and the existing payload of each child, a key can appear more than once in the batch.
+        let mut children: Vec<(u8, usize, Option<usize>)> = Vec::with_capacity(cnt);
+        let mut child_idx = [u16::MAX; 256];
+        let mut old_values = Vec::with_capacity(cnt);
+        for (k, tid) in entries[..cnt].iter() {
+            let key = k[K_LEN - 1];
+            let idx = child_idx[key as usize] as usize;
+            if let Some((_, payload, _)) = children.get_mut(idx) {
+                old_values.push(Some(*payload));
+                *payload = *tid;
+            } else {
+                let existing = node.as_ref().get_child(key).map(|c| c.as_payload());
+                old_values.push(existing);
+                child_idx[key as usize] = children.len() as u16;
+                children.push((key, *tid, existing));
+            }
+        }
+        node.check_version()?;
+
+        let to_ptr =
+            |(key, tid, _): &(u8, usize, Option<usize>)| (*key, NodePtr::from_payload(*tid));
+        let new: Vec<(u8, NodePtr)> = children
+            .iter()
+            .filter(|c| c.2.is_none())
+            .map(to_ptr)
+            .collect();
+        let changed: Vec<(u8, NodePtr)> = children
+            .iter()
+            .filter(|c| c.2.is_some())
+            .map(to_ptr)
+            .collect();
+        let record = || {
+            self.history.record(
+                |w| {
+                    let mut k = entries[0].0;
+                    for (key, _, existing) in children.iter() {
+                        k[K_LEN - 1] = *key;
+                        w(&k, *existing);
+                    }
+                },
+                guard,
+            )
+        };
+        BaseNode::insert_many_and_unlock(
+            node,
+            parent,
+            &new,
+            &changed,
+            &self.allocator,
+            guard,
+            record,
+        )?;
+        self.len.add(new.len() as isize);
+
+        old.extend(old_values);
+        Ok(cnt)
+    }
+
+    #[inline]
+    pub(crate) fn range<O: ScanOutput<K_LEN> + ?Sized>(
+        &self,
+        start: &[u8; K_LEN],
+        end: &[u8; K_LEN],
+        result: &mut O,
+        _guard: &Guard,
+    ) -> usize {
+        let root = self.load_root();
+        let range_scan = RangeScan::new(start, end, result, root);
+        Self::run_range_scan(range_scan)
+    }
+
+    /// Same as `range`, but keys equal to `end` are included.
+    pub(crate) fn range_inclusive<O: ScanOutput<K_LEN> + ?Sized>(
+        &self,
+        start: &[u8; K_LEN],
+        end: &[u8; K_LEN],
+        result: &mut O,
+        _guard: &Guard,
+    ) -> usize {
+        let root = self.load_root();
+        let range_scan = RangeScan::new(start, end, result, root).with_inclusive_end();
+        Self::run_range_scan(range_scan)
+    }
+
+    /// Same as `range`, but the keys are visited in descending order, starting right below `end`.
+    pub(crate) fn range_rev<O: ScanOutput<K_LEN> + ?Sized>(
         &self,
         start: &[u8; K_LEN],
         end: &[u8; K_LEN],
-        result: &mut [([u8; K_LEN], usize)],
+        result: &mut O,
         _guard: &Guard,
     ) -> usize {
         let root = self.load_root();
-        let mut range_scan = RangeScan::new(start, end, result, root);
+        let range_scan = RangeScan::new(start, end, result, root).with_reverse();
+        Self::run_range_scan(range_scan)
+    }
+
+    /// Same as `range`, but each end can be included, excluded or unbounded.
+    pub(crate) fn range_bounds<O: ScanOutput<K_LEN> + ?Sized>(
+        &self,
+        start: Bound<[u8; K_LEN]>,
+        end: Bound<[u8; K_LEN]>,
+        result: &mut O,
+        guard: &Guard,
+    ) -> usize {
+        match inclusive_bounds(start, end) {
+            Some((start, end)) => self.range_inclusive(&start, &end, result, guard),
+            None => 0,
+        }
+    }
+
+    /// Counts the keys within the bounds without copying them out.
+    pub(crate) fn count_range(
+        &self,
+        start: Bound<[u8; K_LEN]>,
+        end: Bound<[u8; K_LEN]>,
+        _guard: &Guard,
+    ) -> usize {
+        let Some((start, end)) = inclusive_bounds(start, end) else {
+            return 0;
+        };
+        let root = self.load_root();
+        let range_scan = RangeScan::new(&start, &end, &mut [][..], root)
+            .with_inclusive_end()
+            .with_count_only();
+        Self::run_range_scan(range_scan)
+    }
+
+    /// Scans the keys whose first `prefix_len` bytes equal those of `prefix`.
+    /// The scan descends straight to the node covering the prefix and copies it, without comparing keys to bounds.
+    pub(crate) fn scan_prefix<O: ScanOutput<K_LEN> + ?Sized>(
+        &self,
+        prefix: &[u8; K_LEN],
+        prefix_len: usize,
+        result: &mut O,
+        _guard: &Guard,
+    ) -> usize {
+        let (start, end) = prefix_range(prefix, prefix_len);
+        let root = self.load_root();
+        let range_scan = RangeScan::new(&start, &end, result, root)
+            .with_inclusive_end()
+            .with_prefix_len(prefix_len);
+        Self::run_range_scan(range_scan)
+    }
+
+    /// Counts the keys whose first `prefix_len` bytes equal those of `prefix`, like `scan_prefix`.
+    pub(crate) fn count_prefix(
+        &self,
+        prefix: &[u8; K_LEN],
+        prefix_len: usize,
+        _guard: &Guard,
+    ) -> usize {
+        let (start, end) = prefix_range(prefix, prefix_len);
+        let root = self.load_root();
+        let range_scan = RangeScan::new(&start, &end, &mut [][..], root)
+            .with_inclusive_end()
+            .with_count_only()
+            .with_prefix_len(prefix_len);
+        Self::run_range_scan(range_scan)
+    }
+
+    /// Same as `range_rev`, but keys equal to `end` are included.
+    pub(crate) fn range_inclusive_rev<O: ScanOutput<K_LEN> + ?Sized>(
+        &self,
+        start: &[u8; K_LEN],
+        end: &[u8; K_LEN],
+        result: &mut O,
+        _guard: &Guard,
+    ) -> usize {
+        let root = self.load_root();
+        let range_scan = RangeScan::new(start, end, result, root)
+            .with_inclusive_end()
+            .with_reverse();
+        Self::run_range_scan(range_scan)
+    }
+
+    /// Removes all keys within the bounds, returns the number of removed entries.
+    /// The drain callback is called for every removed entry once no reader can see it anymore.
+    pub(crate) fn remove_range(
+        &self,
+        start: Bound<[u8; K_LEN]>,
+        end: Bound<[u8; K_LEN]>,
+        guard: &Guard,
+    ) -> usize {
+        let Some((start, end)) = inclusive_bounds(start, end) else {
+            return 0;
+        };
+
+        let backoff = Backoff::new();
+        let removal = loop {
+            match RangeRemoval::new(&start, &end, self.load_root()).lock() {
+                Ok(removal) => break removal,
+                Err(_) => backoff.spin(),
+            }
+        };
+
+        self.history.record(|w| removal.for_each_removed(w), guard);
+        let applied = removal.apply();
+        if let Some(write_n) = applied.emptied_top {
+            self.unlink_emptied(&start, write_n, guard);
+        }
+        for write_n in applied.unlinked {
+            self.retire_node(write_n, guard);
+        }
+        let mut removed_cnt = applied.removed.len();
+        for subtree in applied.detached {
+            removed_cnt += self.retire_subtree(subtree, guard);
+        }
+        self.len.add(-(removed_cnt as isize));
+        self.drain_deferred(applied.removed, guard);
+        removed_cnt
+    }
+
+    /// Writes `writes` atomically if every key in `reads` still has the recorded value, `None` for absent keys.
+    pub(crate) fn commit(
+        &self,
+        reads: &BTreeMap<[u8; K_LEN], Option<usize>>,
+        writes: &BTreeMap<[u8; K_LEN], Option<usize>>,
+        guard: &Guard,
+    ) -> Result<(), TransactionError> {
+        let backoff = Backoff::new();
+        loop {
+            match TransactionCommit::new(&self.root, &self.allocator, &self.history)
+                .run(reads, writes, guard)
+            {
+                Ok(Ok(added)) => {
+                    self.len.add(added);
+                    return Ok(());
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_) => backoff.spin(),
+            }
+        }
+    }
+
+    /// Removes the entries for which `f` returns false, returns the number of removed entries.
+    /// Each entry is checked and removed atomically, but entries inserted concurrently may or may not be visited.
+    pub(crate) fn retain<F>(&self, mut f: F, guard: &Guard) -> usize
+    where
+        F: FnMut(&[u8; K_LEN], usize) -> bool,
+    {
+        let mut removed = Vec::new();
+        for (k, _v) in RawIter::new(self, Bound::Unbounded, Bound::Unbounded, guard) {
+            let mut keep = |v| if f(&k, v) { Some(v) } else { None };
+            if let Some((old, None)) = self.compute_if_present(&k, &mut keep, guard) {
+                removed.push((k, old));
+            }
+        }
+        let removed_cnt = removed.len();
+        self.drain_deferred(removed, guard);
+        removed_cnt
+    }
+
+    /// Calls the drain callback on removed entries once all readers that might still see them are gone.
+    fn drain_deferred(&self, removed: Vec<([u8; K_LEN], usize)>, guard: &Guard) {
+        if removed.is_empty() {
+            return;
+        }
+        let drain_callback = self.drain_callback.clone();
+        // Safety: the closure owns everything it uses. The tree is `Send` and `Sync`,
+        // the drain callback can already be called from any thread that drops the tree.
+        unsafe {
+            guard.defer_unchecked(move || {
+                for (k, v) in removed {
+                    drain_callback(k, v);
+                }
+            });
+        }
+    }
+
+    /// The entry with the smallest key.
+    pub(crate) fn first(&self, guard: &Guard) -> Option<([u8; K_LEN], usize)> {
+        self.ceiling(&[0; K_LEN], guard)
+    }
+
+    /// The entry with the largest key.
+    pub(crate) fn last(&self, guard: &Guard) -> Option<([u8; K_LEN], usize)> {
+        self.floor(&[u8::MAX; K_LEN], guard)
+    }
+
+    /// The entry with the smallest key greater than or equal to `k`.
+    pub(crate) fn ceiling(&self, k: &[u8; K_LEN], _guard: &Guard) -> Option<([u8; K_LEN], usize)> {
+        self.seek(k, false)
+    }
+
+    /// The entry with the largest key less than or equal to `k`.
+    pub(crate) fn floor(&self, k: &[u8; K_LEN], _guard: &Guard) -> Option<([u8; K_LEN], usize)> {
+        self.seek(k, true)
+    }
+
+    /// Goes down from the root to the entry closest to `k`, restarting when a node changes underneath.
+    /// Returns the smallest key at or above `k`, or the largest key at or below `k` when `rev` is set.
+    fn seek(&self, k: &[u8; K_LEN], rev: bool) -> Option<([u8; K_LEN], usize)> {
+        let backoff = Backoff::new();
+        loop {
+            let root = BaseNode::read_lock(self.load_root());
+            match root.and_then(|root| Self::seek_in(&root, KeyTracker::empty(), k, true, rev)) {
+                Ok(entry) => return entry,
+                Err(_) => backoff.spin(),
+            }
+        }
+    }
+
+    /// Takes the first child of `node` in the direction of the search that can hold the entry,
+    /// and backtracks to its next sibling when the subtree below it holds none.
+    /// `key_tracker` holds the key up to the byte leading to `node`, without the prefix of `node`,
+    /// and `bounded` tells whether it equals `k` so far. Once past `k`, the first entry of the subtree is taken.
+    fn seek_in(
+        node: &ReadGuard,
+        mut key_tracker: KeyTracker<K_LEN>,
+        k: &[u8; K_LEN],
+        mut bounded: bool,
+        rev: bool,
+    ) -> Result<Option<([u8; K_LEN], usize)>, ArtError> {
+        for &b in node.as_ref().prefix() {
+            if bounded {
+                let ord = b.cmp(&k[key_tracker.len()]);
+                if ord == cmp::Ordering::Equal {
+                    // Still on the path of `k`.
+                } else if (ord == cmp::Ordering::Greater) != rev {
+                    bounded = false;
+                } else {
+                    // Every key below the node is on the wrong side of `k`.
+                    node.check_version()?;
+                    return Ok(None);
+                }
+            }
+            key_tracker.push(b);
+        }
+
+        let level = key_tracker.len();
+        let (lo, hi) = match (bounded, rev) {
+            (false, _) => (0, u8::MAX),
+            (true, false) => (k[level], u8::MAX),
+            (true, true) => (0, k[level]),
+        };
+        let children = node.as_ref().get_children(lo, hi);
+        let (forward, backward) = if rev {
+            (None, Some(children.rev()))
+        } else {
+            (Some(children), None)
+        };
+        for (key, child) in forward
+            .into_iter()
+            .flatten()
+            .chain(backward.into_iter().flatten())
+        {
+            // The level of the child depends on the prefix we read, validate it before using the child.
+            node.check_version()?;
+            key_tracker.push(key);
+            let entry = match child.downcast::<K_LEN>(level) {
+                PtrType::Payload(payload) => {
+                    let last_level_key = unsafe { key_tracker.as_last_level_unchecked() };
+                    Some((*last_level_key.key(), payload))
+                }
+                PtrType::SubNode(sub_node) => {
+                    let child = BaseNode::read_lock(sub_node)?;
+                    node.check_version()?;
+                    let bounded = bounded && key == k[level];
+                    Self::seek_in(&child, key_tracker.clone(), k, bounded, rev)?
+                }
+            };
+            if entry.is_some() {
+                return Ok(entry);
+            }
+            key_tracker.pop();
+        }
+        // The iteration might have stopped early on a concurrent removal.
+        node.check_version()?;
+        Ok(None)
+    }
+
+    /// The entry with the smallest key strictly greater than `k`.
+    pub(crate) fn successor(&self, k: &[u8; K_LEN], guard: &Guard) -> Option<([u8; K_LEN], usize)> {
+        self.ceiling(&key_successor(*k)?, guard)
+    }
+
+    /// The entry with the largest key strictly less than `k`.
+    pub(crate) fn predecessor(
+        &self,
+        k: &[u8; K_LEN],
+        guard: &Guard,
+    ) -> Option<([u8; K_LEN], usize)> {
+        self.floor(&key_predecessor(*k)?, guard)
+    }
+
+    /// Takes a snapshot of the tree, returns its version. Each snapshot must be released with `release_snapshot`.
+    pub(crate) fn take_snapshot(&self) -> u64 {
+        self.history.open()
+    }
+
+    pub(crate) fn release_snapshot(&self) {
+        self.history.close()
+    }
+
+    /// The value of `k` in the snapshot at `version`.
+    pub(crate) fn get_at(&self, k: &[u8; K_LEN], version: u64, guard: &Guard) -> Option<usize> {
+        let current = self.get(k, guard);
+        // Writes are recorded before they change the tree, so the log read after the tree holds every write seen.
+        self.history.undo(k, version, guard).unwrap_or(current)
+    }
+
+    /// Scans the next batch of the snapshot at `version` from the start of `[start, end]`, or from its end if `reverse`,
+    /// and appends the entries to `out` in scan order. Returns the part of the range left to scan.
+    ///
+    /// The tree and the undo log are each scanned into one half of `scratch`,
+    /// the batch ends where the first of them is cut off.
+    pub(crate) fn scan_at(
+        &self,
+        version: u64,
+        (start, end): ([u8; K_LEN], [u8; K_LEN]),
+        reverse: bool,
+        scratch: &mut [([u8; K_LEN], usize)],
+        out: &mut impl Extend<([u8; K_LEN], usize)>,
+        guard: &Guard,
+    ) -> Option<([u8; K_LEN], [u8; K_LEN])> {
+        let half = scratch.len() / 2;
+        let (live, logged) = scratch.split_at_mut(half);
+        let n = if reverse {
+            self.range_inclusive_rev(&start, &end, live, guard)
+        } else {
+            self.range_inclusive(&start, &end, live, guard)
+        };
+        let m = self.history.scan_log(&start, &end, reverse, logged, guard);
+
+        let before = |a: &[u8; K_LEN], b: &[u8; K_LEN]| if reverse { a > b } else { a < b };
+        let mut covered: Option<[u8; K_LEN]> = None;
+        for (batch, cnt) in [(&*live, n), (&*logged, m)] {
+            if cnt > 0 && cnt == batch.len() {
+                let last = batch[cnt - 1].0;
+                if covered.is_none_or(|c| before(&last, &c)) {
+                    covered = Some(last);
+                }
+            }
+        }
+        let in_batch = |k: &[u8; K_LEN]| covered.is_none_or(|c| !before(&c, k));
 
+        // Merges the keys of both batches in scan order, a key with records takes its value from them.
+        let (mut i, mut j) = (0, 0);
+        loop {
+            let l = live[..n].get(i).copied().filter(|(k, _)| in_batch(k));
+            let r = logged[..m].get(j).copied().filter(|(k, _)| in_batch(k));
+            let (k, current, records) = match (l, r) {
+                (None, None) => break,
+                (Some((lk, lv)), Some((rk, rv))) if lk == rk => {
+                    (i, j) = (i + 1, j + 1);
+                    (lk, Some(lv), Some(rv))
+                }
+                (Some((lk, lv)), Some((rk, _))) if before(&lk, &rk) => {
+                    i += 1;
+                    (lk, Some(lv), None)
+                }
+                (Some((lk, lv)), None) => {
+                    i += 1;
+                    (lk, Some(lv), None)
+                }
+                (_, Some((rk, rv))) => {
+                    j += 1;
+                    (rk, None, Some(rv))
+                }
+            };
+            let value = records
+                .and_then(|head| History::<K_LEN>::undo_records(head, version))
+                .unwrap_or(current);
+            if let Some(v) = value {
+                out.extend(Some((k, v)));
+            }
+        }
+
+        let covered = covered?;
+        if reverse {
+            key_predecessor(covered)
+                .filter(|k| *k >= start)
+                .map(|k| (start, k))
+        } else {
+            key_successor(covered)
+                .filter(|k| *k <= end)
+                .map(|k| (k, end))
+        }
+    }
+
+    fn run_range_scan<O: ScanOutput<K_LEN> + ?Sized>(
+        mut range_scan: RangeScan<'_, O, K_LEN>,
+    ) -> usize {
         if !range_scan.is_valid_key_pair() {
             return 0;
         }
@@ -501,7 +1267,10 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
     where
         F: FnMut(usize) -> Option<usize>,
     {
-        let mut parent: Option<(ReadGuard, u8)> = None;
+        // The ancestors of `node` with the key and level of the edge taken from each, a removal that leaves
+        // nodes empty needs to unlink them from the lowest ancestor that keeps other children.
+        let mut path: [Option<(ReadGuard, u8, usize)>; K_LEN] = std::array::from_fn(|_| None);
+        let mut depth = 0;
         let mut node_key: u8;
         let mut level = 0;
         let root = self.load_root();
@@ -524,8 +1293,8 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                 None => return Ok(None),
             };
 
-            cast_ptr!(child_node => {
-                Payload(tid) => {
+            match child_node.downcast::<K_LEN>(level) {
+                PtrType::Payload(tid) => {
                     let new_v = remapping_function(tid);
 
                     match new_v {
@@ -535,6 +1304,7 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                                 return Ok(Some((tid, Some(tid))));
                             }
                             let mut write_n = node.upgrade().map_err(|(_n, v)| v)?;
+                            self.history.record(|w| w(k, Some(tid)), guard);
                             write_n
                                 .as_mut()
                                 .change(k[level], NodePtr::from_payload(new_v));
@@ -543,38 +1313,233 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                         }
                         None => {
                             // new value is none, we need to delete this entry
-                            debug_assert!(parent.is_some()); // reaching leaf means we must have parent, bcs root can't be leaf
-                            if node.as_ref().value_count() == 1 {
-                                let (parent_node, parent_key) = parent.unwrap();
-                                let mut write_p = parent_node.upgrade().map_err(|(_n, v)| v)?;
-
-                                let mut write_n = node.upgrade().map_err(|(_n, v)| v)?;
-
-                                write_p.as_mut().remove(parent_key);
-
-                                write_n.mark_obsolete();
-                                let allocator = self.allocator.clone();
-                                guard.defer(move || unsafe {
-                                    let ptr = NonNull::from(write_n.as_mut());
-                                    std::mem::forget(write_n);
-                                    BaseNode::drop_node(ptr, allocator);
-                                });
-                            } else {
-                                let mut write_n = node.upgrade().map_err(|(_n, v)| v)?;
-
-                                write_n.as_mut().remove(node_key);
-                            }
+                            let record = || self.history.record(|w| w(k, Some(tid)), guard);
+                            self.remove_from_path(
+                                path,
+                                depth,
+                                (node, node_key, level),
+                                guard,
+                                record,
+                            )?;
+                            self.len.add(-1);
                             return Ok(Some((tid, None)));
                         }
                     }
-                },
-                SubNode(sub_node) => {
+                }
+                PtrType::SubNode(sub_node) => {
+                    let next_node = BaseNode::read_lock(sub_node)?;
+                    node.check_version()?;
+                    path[depth] = Some((node, node_key, level));
+                    depth += 1;
                     level += 1;
-                    parent = Some((node, node_key));
-                    node = BaseNode::read_lock(sub_node)?;
+                    node = next_node;
                 }
+            }
+        }
+    }
+
+    /// Removes the edge `node_key` from `node`, whose ancestors are `path[..depth]`.
+    /// Nodes left without children are unlinked and freed, all the way up to the first ancestor that keeps
+    /// other children; that ancestor may then shrink or be merged with its only remaining child.
+    /// `before_write` runs once all nodes that change are locked and before anything changes.
+    fn remove_from_path<'a>(
+        &'a self,
+        mut path: [Option<(ReadGuard<'a>, u8, usize)>; K_LEN],
+        mut depth: usize,
+        mut target: (ReadGuard<'a>, u8, usize),
+        guard: &Guard,
+        before_write: impl FnOnce(),
+    ) -> Result<(), ArtError> {
+        let mut emptied = Vec::new();
+        while depth > 0 && target.0.as_ref().value_count() == 1 {
+            // The root is never removed, so every node that becomes empty has a parent.
+            let write_n = target.0.upgrade().map_err(|(_n, v)| v)?;
+            emptied.push(write_n);
+            depth -= 1;
+            target = path[depth].take().unwrap();
+        }
+
+        let (node, node_key, level) = target;
+        let parent = if depth == 0 {
+            Parent::Root(&self.root)
+        } else {
+            let (parent_node, parent_key, _) = path[depth - 1].take().unwrap();
+            Parent::Node(parent_key, parent_node)
+        };
+        self.remove_and_unlock(node, level, parent, node_key, guard, before_write)?;
+
+        for write_n in emptied {
+            self.retire_node(write_n, guard);
+        }
+        Ok(())
+    }
+
+    /// Removes `key` from `node`, whose children are at `level`.
+    /// A non-root `Node4` left with a single sub node is merged into that child when their prefixes fit in one node,
+    /// otherwise the node may shrink to a smaller type.
+    fn remove_and_unlock<'a>(
+        &'a self,
+        node: ReadGuard<'a>,
+        level: usize,
+        parent: Parent<'a>,
+        key: u8,
+        guard: &Guard,
+        before_write: impl FnOnce(),
+    ) -> Result<(), ArtError> {
+        let mergeable = node.as_ref().get_type() == NodeType::N4
+            && node.as_ref().value_count() == 2
+            && Self::is_last_level(level).is_err();
+        match parent {
+            Parent::Node(parent_key, parent_node) if mergeable => {
+                self.remove_and_merge(node, parent_node, parent_key, key, guard, before_write)
+            }
+            parent => {
+                BaseNode::remove_and_unlock(node, parent, key, &self.allocator, guard, before_write)
+            }
+        }
+    }
+
+    /// Removes `key` from a two-child `Node4` and replaces the node with its remaining child,
+    /// prepending the prefix of the node and the edge byte to the prefix of the child.
+    fn remove_and_merge<'a>(
+        &'a self,
+        node: ReadGuard<'a>,
+        parent_node: ReadGuard<'a>,
+        parent_key: u8,
+        key: u8,
+        guard: &Guard,
+        before_write: impl FnOnce(),
+    ) -> Result<(), ArtError> {
+        let remaining = node.as_ref().get_children(0, 255).find(|(k, _)| *k != key);
+        node.check_version()?;
+        let (child_key, child) = remaining.ok_or(ArtError::VersionNotMatch)?;
+
+        let child = BaseNode::read_lock(unsafe { child.as_sub_node_unchecked() })?;
+        node.check_version()?;
+
+        let node_prefix = node.as_ref().prefix();
+        let child_prefix = child.as_ref().prefix();
+        let merged_len = node_prefix.len() + 1 + child_prefix.len();
+        if merged_len > MAX_PREFIX_LEN {
+            return BaseNode::remove_and_unlock(
+                node,
+                Parent::Node(parent_key, parent_node),
+                key,
+                &self.allocator,
+                guard,
+                before_write,
+            );
+        }
+
+        let mut merged = [0; MAX_PREFIX_LEN];
+        merged[..node_prefix.len()].copy_from_slice(node_prefix);
+        merged[node_prefix.len()] = child_key;
+        merged[node_prefix.len() + 1..merged_len].copy_from_slice(child_prefix);
+
+        let mut write_p = parent_node.upgrade().map_err(|(_n, v)| v)?;
+        let write_n = node.upgrade().map_err(|(_n, v)| v)?;
+        let mut write_c = child.upgrade().map_err(|(_n, v)| v)?;
+        before_write();
+
+        write_c.as_mut().set_prefix(&merged[..merged_len]);
+        write_p
+            .as_mut()
+            .change(parent_key, NodePtr::from_node_ref(write_c.as_ref()));
+
+        self.retire_node(write_n, guard);
+        Ok(())
+    }
+
+    /// Marks a node that is no longer reachable as obsolete and frees it once no reader can hold it.
+    fn retire_node(&self, mut write_n: WriteGuard, guard: &Guard) {
+        write_n.mark_obsolete();
+        let delete_n = write_n.as_mut() as *mut BaseNode as usize;
+        std::mem::forget(write_n);
+        let allocator = self.allocator.clone();
+        guard.defer(move || unsafe {
+            let delete_n = NonNull::new(delete_n as *mut BaseNode).unwrap();
+            BaseNode::drop_node(delete_n, allocator);
+        });
+    }
+
+    /// Retires a subtree unlinked by a range removal, returns the number of entries in it.
+    /// All its nodes are locked, so no other thread changes it anymore,
+    /// the entries are drained once no reader can see them.
+    fn retire_subtree(&self, subtree: DetachedSubtree<K_LEN>, guard: &Guard) -> usize {
+        let DetachedSubtree {
+            node: mut write_n,
+            below,
+            mut key,
+            count,
+        } = subtree;
+        for mut write_c in below {
+            write_c.mark_obsolete();
+            std::mem::forget(write_c);
+        }
+        write_n.mark_obsolete();
+        let root = write_n.as_mut() as *mut BaseNode as usize;
+        std::mem::forget(write_n);
+
+        let allocator = self.allocator.clone();
+        let drain_callback = self.drain_callback.clone();
+        // Safety: the closure owns everything it uses, and no thread can reach the subtree once it runs.
+        // The drain callback can already be called from any thread that drops the tree.
+        unsafe {
+            guard.defer_unchecked(move || {
+                let root = NonNull::new(root as *mut BaseNode).unwrap();
+                range_remove::drain_subtree(root, &mut key, &*drain_callback, &allocator);
             });
         }
+        count
+    }
+
+    /// Unlinks a node left without children by a range removal, it stays locked until it is unlinked.
+    /// Its parent is then shrunk, merged with its remaining child, or unlinked as well, the same way as
+    /// after removing a single key. `k` is a key whose path goes through the node.
+    fn unlink_emptied(&self, k: &[u8; K_LEN], write_n: WriteGuard, guard: &Guard) {
+        let target = NonNull::from(write_n.as_ref());
+        let backoff = Backoff::new();
+        while self.unlink_emptied_inner(k, target, guard).is_err() {
+            backoff.spin();
+        }
+        self.retire_node(write_n, guard);
+    }
+
+    fn unlink_emptied_inner(
+        &self,
+        k: &[u8; K_LEN],
+        target: NonNull<BaseNode>,
+        guard: &Guard,
+    ) -> Result<(), ArtError> {
+        let mut path: [Option<(ReadGuard, u8, usize)>; K_LEN] = std::array::from_fn(|_| None);
+        let mut depth = 0;
+        let mut level = 0;
+        let mut node = BaseNode::read_lock(self.load_root())?;
+
+        loop {
+            // The target stays linked on the path of `k`, so a mismatch can only come from an inconsistent read.
+            level = node
+                .as_ref()
+                .check_prefix(k, level)
+                .ok_or(ArtError::VersionNotMatch)?;
+            let node_key = k[level];
+            let child_node = node.as_ref().get_child(node_key);
+            node.check_version()?;
+
+            let Some(PtrType::SubNode(sub_node)) = child_node.map(|n| n.downcast::<K_LEN>(level))
+            else {
+                return Err(ArtError::VersionNotMatch);
+            };
+            if sub_node == target {
+                return self.remove_from_path(path, depth, (node, node_key, level), guard, || {});
+            }
+            let next_node = BaseNode::read_lock(sub_node)?;
+            node.check_version()?;
+            path[depth] = Some((node, node_key, level));
+            depth += 1;
+            level += 1;
+            node = next_node;
+        }
     }
 
     #[inline]
@@ -601,6 +1566,21 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
     }
 
     pub(crate) fn to_compact_set(&self) -> Vec<u8> {
+        self.to_compact_nodes(None)
+    }
+
+    /// Serializes the tree into the layout of [CongeeCompactMap](crate::CongeeCompactMap).
+    pub(crate) fn to_compact_map(&self) -> Vec<u8> {
+        let mut values = Vec::new();
+        let nodes = self.to_compact_nodes(Some(&mut values));
+        crate::congee_compact_map::assemble(nodes, &values)
+    }
+
+    /// Serializes the nodes in the layout of [CongeeCompactSet](crate::CongeeCompactSet).
+    ///
+    /// With `values`, every leaf node is followed by the rank of its first key (u32),
+    /// and the payloads are collected into `values` in ascending key order.
+    fn to_compact_nodes(&self, values: Option<&mut Vec<usize>>) -> Vec<u8> {
         use crate::congee_compact_set::NodeType as CompactNodeType;
         use std::collections::VecDeque;
 
@@ -616,33 +1596,35 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
         }
 
         // drop(node);
-        queue.push_back(root);
+        queue.push_back((root, 0));
 
         // First pass: collect all nodes and assign indices
         let mut nodes_data = Vec::new();
         let mut node_counter = 0u32;
 
-        while let Some(node_ptr) = queue.pop_front() {
+        while let Some((node_ptr, level)) = queue.pop_front() {
             let node = BaseNode::read_lock(node_ptr).unwrap();
             let node_prefix = node.as_ref().prefix().to_vec();
 
             // Collect children data first to identify node type and count
             let mut children: Vec<(u8, Option<u32>)> = Vec::new();
+            let mut payloads = Vec::new();
             let mut is_leaf = false;
 
             for (key, child_ptr) in node.as_ref().get_children(0, 255) {
-                cast_ptr!(child_ptr => {
-                    Payload(_) => {
+                match child_ptr.downcast::<K_LEN>(level + node_prefix.len()) {
+                    PtrType::Payload(payload) => {
                         children.push((key, None)); // Leaf child, no node index
+                        payloads.push(payload);
                         is_leaf = true;
-                    },
-                    SubNode(sub_node) => {
+                    }
+                    PtrType::SubNode(sub_node) => {
                         // Assign the next available node index
                         node_counter += 1;
                         children.push((key, Some(node_counter)));
-                        queue.push_back(sub_node);
+                        queue.push_back((sub_node, level + node_prefix.len() + 1));
                     }
-                });
+                }
             }
 
             // Determine node type based on base node type and whether it's a leaf
@@ -657,14 +1639,36 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                 (NodeType::N256, true) => CompactNodeType::N256_LEAF,
             };
 
-            nodes_data.push((node_type, node_prefix, children, is_leaf));
+            nodes_data.push((node_type, node_prefix, children, is_leaf, payloads));
+        }
+
+        // Rank the keys of the leaf nodes in ascending key order, the children are sorted by key
+        let with_ranks = values.is_some();
+        let mut first_ranks = vec![0u32; nodes_data.len()];
+        if let Some(values) = values {
+            let mut stack = vec![0usize];
+            while let Some(i) = stack.pop() {
+                let (_, _, children, is_leaf, payloads) = &nodes_data[i];
+                if *is_leaf {
+                    first_ranks[i] = values.len() as u32;
+                    values.extend_from_slice(payloads);
+                } else {
+                    stack.extend(
+                        children
+                            .iter()
+                            .rev()
+                            .filter_map(|(_, idx)| *idx)
+                            .map(|idx| idx as usize),
+                    );
+                }
+            }
         }
 
         // Calculate all node offsets first
         let mut node_offsets = Vec::new();
         let mut current_offset = 0usize;
 
-        for (node_type, node_prefix, children, _is_leaf) in &nodes_data {
+        for (node_type, node_prefix, children, is_leaf, _payloads) in &nodes_data {
             node_offsets.push(current_offset);
 
             // Calculate node size: header + prefix + children
@@ -679,11 +1683,15 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                 _ => children.len() * 5,                                   // key + offset pairs
             };
 
-            current_offset += header_size + prefix_size + children_size;
+            let rank_size = if *is_leaf && with_ranks { 4 } else { 0 };
+
+            current_offset += header_size + prefix_size + children_size + rank_size;
         }
 
         // Second pass: serialize all nodes, replace indices with actual offsets
-        for (node_type, node_prefix, children, is_leaf) in nodes_data.into_iter() {
+        for (i, (node_type, node_prefix, children, is_leaf, _payloads)) in
+            nodes_data.into_iter().enumerate()
+        {
             // Write node header
             buf.push(node_type);
             buf.push(node_prefix.len() as u8);
@@ -783,6 +1791,10 @@ impl<const K_LEN: usize, A: Allocator + Clone + Send> CongeeInner<K_LEN, A> {
                     }
                 }
             }
+
+            if is_leaf && with_ranks {
+                buf.extend_from_slice(&first_ranks[i].to_le_bytes());
+            }
         }
 
         buf
@@ -1,24 +1,39 @@
-use std::{marker::PhantomData, sync::Arc};
+use std::{
+    io::{Read, Write},
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
 
-use crate::{Allocator, CongeeInner, DefaultAllocator, epoch, error::OOMError, stats};
+use crate::{
+    Allocator, CongeeInner, CongeeRawIter, CongeeRawSnapshot, DefaultAllocator, KeyEncoding,
+    checkpoint, epoch,
+    error::{CheckpointError, OOMError},
+    iter::{RawIter, encode_bound, prefix_bounds},
+    range_scan::Converted,
+    stats,
+    transaction::Transaction,
+};
 
 /// The adaptive radix tree.
+///
+/// `K_LEN` is the length of the key in bytes, keys are converted to bytes by [KeyEncoding].
 pub struct CongeeRaw<
-    K: Copy + From<usize>,
+    K: KeyEncoding<K_LEN>,
     V: Copy + From<usize>,
     A: Allocator + Clone + Send + 'static = DefaultAllocator,
+    const K_LEN: usize = 8,
 > where
-    usize: From<K>,
     usize: From<V>,
 {
-    inner: CongeeInner<8, A>,
+    inner: CongeeInner<K_LEN, A>,
     pt_key: PhantomData<K>,
     pt_val: PhantomData<V>,
 }
 
-impl<K: Copy + From<usize>, V: Copy + From<usize>> Default for CongeeRaw<K, V>
+impl<K: KeyEncoding<K_LEN>, V: Copy + From<usize>, const K_LEN: usize> Default
+    for CongeeRaw<K, V, DefaultAllocator, K_LEN>
 where
-    usize: From<K>,
     usize: From<V>,
 {
     fn default() -> Self {
@@ -26,9 +41,9 @@ where
     }
 }
 
-impl<K: Copy + From<usize>, V: Copy + From<usize>, A: Allocator + Clone + Send> CongeeRaw<K, V, A>
+impl<K: KeyEncoding<K_LEN>, V: Copy + From<usize>, A: Allocator + Clone + Send, const K_LEN: usize>
+    CongeeRaw<K, V, A, K_LEN>
 where
-    usize: From<K>,
     usize: From<V>,
 {
     /// Returns a copy of the value corresponding to the key.
@@ -37,7 +52,7 @@ where
     ///
     /// ```
     /// use congee::CongeeRaw;
-    /// let tree = CongeeRaw::default();
+    /// let tree = CongeeRaw::<usize, usize>::default();
     /// let guard = tree.pin();
     ///
     /// tree.insert(1, 42, &guard);
@@ -45,12 +60,43 @@ where
     /// ```
     #[inline]
     pub fn get(&self, key: &K, guard: &epoch::Guard) -> Option<V> {
-        let key = usize::from(*key);
-        let key: [u8; 8] = key.to_be_bytes();
+        let key = key.to_key_bytes();
         let v = self.inner.get(&key, guard)?;
         Some(V::from(v))
     }
 
+    /// Looks up all `keys` at once, writing the value of `keys[i]` to `values[i]`.
+    ///
+    /// Consecutive lookups share the part of the traversal their keys have in common,
+    /// sorting the keys makes this much faster than calling [get](Self::get) for each key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` and `values` have different lengths.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 10, &guard).unwrap();
+    /// tree.insert(3, 30, &guard).unwrap();
+    ///
+    /// let mut values = [None; 3];
+    /// tree.get_many(&[1, 2, 3], &mut values, &guard);
+    /// assert_eq!(values, [Some(10), None, Some(30)]);
+    /// ```
+    pub fn get_many(&self, keys: &[K], values: &mut [Option<V>], guard: &epoch::Guard) {
+        assert_eq!(keys.len(), values.len());
+        let mut values = values.iter_mut();
+        self.inner.get_many(
+            keys.iter().map(|k| k.to_key_bytes()),
+            |raw| *values.next().unwrap() = raw.map(V::from),
+            guard,
+        );
+    }
+
     /// Enters an epoch.
     /// Note: this can be expensive, try to reuse it.
     ///
@@ -81,7 +127,12 @@ where
 
     /// Create an empty [Art] tree with a drainer.
     ///
-    /// The drainer is called on each of the value when the tree is dropped.
+    /// The drainer is called on the entries the tree releases without handing them back to the caller:
+    /// the entries removed by [remove_range](Self::remove_range), [remove_prefix](Self::remove_prefix)
+    /// and [retain](Self::retain), once no reader can see them anymore, and the entries left when the tree is dropped.
+    /// Operations that return the old value, such as [insert](Self::insert), [remove](Self::remove),
+    /// [compare_exchange](Self::compare_exchange) and the compute functions, don't call it,
+    /// and neither does [Transaction::commit](crate::Transaction::commit).
     ///
     /// # Examples
     ///
@@ -101,15 +152,18 @@ where
     ///
     /// let tree = CongeeRaw::<usize, usize>::new_with_drainer(DefaultAllocator {}, drainer);
     /// let pin = tree.pin();
+    /// tree.insert(1, 41, &pin).unwrap();
+    /// assert_eq!(tree.remove(&1, &pin), Some(41));
+    /// assert_eq!(deleted_key.load(std::sync::atomic::Ordering::Relaxed), 0);
+    ///
     /// tree.insert(1, 42, &pin).unwrap();
     /// drop(tree);
     /// assert_eq!(deleted_key.load(std::sync::atomic::Ordering::Relaxed), 1);
     /// assert_eq!(deleted_value.load(std::sync::atomic::Ordering::Relaxed), 42);
     /// ```
     pub fn new_with_drainer(allocator: A, drainer: impl Fn(K, V) + 'static) -> Self {
-        let drainer = Arc::new(move |k: [u8; 8], v: usize| {
-            drainer(K::from(usize::from_be_bytes(k)), V::from(v))
-        });
+        let drainer =
+            Arc::new(move |k: [u8; K_LEN], v: usize| drainer(K::from_key_bytes(k), V::from(v)));
         CongeeRaw {
             inner: CongeeInner::new(allocator, drainer),
             pt_key: PhantomData,
@@ -117,13 +171,83 @@ where
         }
     }
 
+    /// Builds a tree from key-value pairs in strictly ascending key order.
+    ///
+    /// The nodes are built bottom-up with their final size,
+    /// which is much faster than inserting the keys one by one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not in strictly ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeRaw, DefaultAllocator};
+    /// let tree = CongeeRaw::<usize, usize>::from_sorted_iter(
+    ///     DefaultAllocator {},
+    ///     (0..1000).map(|k| (k, k * 2)),
+    /// )
+    /// .unwrap();
+    /// let guard = tree.pin();
+    /// assert_eq!(tree.get(&500, &guard), Some(1000));
+    /// assert_eq!(tree.keys().len(), 1000);
+    /// ```
+    pub fn from_sorted_iter(
+        allocator: A,
+        iter: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, OOMError> {
+        let entries = iter
+            .into_iter()
+            .map(|(k, v)| (k.to_key_bytes(), usize::from(v)));
+        Ok(CongeeRaw {
+            inner: CongeeInner::from_sorted_iter(allocator, Arc::new(|_k, _v| {}), entries)?,
+            pt_key: PhantomData,
+            pt_val: PhantomData,
+        })
+    }
+
+    /// Rebuilds a tree from a checkpoint written by [write_snapshot](Self::write_snapshot).
+    ///
+    /// The nodes are built bottom-up like [from_sorted_iter](Self::from_sorted_iter), without replaying inserts.
+    /// Every block is checked before its entries are loaded, truncated or corrupted input is rejected with a
+    /// [CheckpointError] describing the problem.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CheckpointError, CongeeRaw, DefaultAllocator};
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    ///
+    /// let mut buf = Vec::new();
+    /// tree.write_snapshot(&mut buf, &guard).unwrap();
+    /// let loaded = CongeeRaw::<usize, usize>::read_snapshot(DefaultAllocator {}, &buf[..]).unwrap();
+    /// assert_eq!(loaded.get(&1, &guard), Some(42));
+    ///
+    /// let truncated = CongeeRaw::<usize, usize>::read_snapshot(DefaultAllocator {}, &buf[..20]);
+    /// assert!(matches!(truncated, Err(CheckpointError::Truncated)));
+    /// ```
+    pub fn read_snapshot(allocator: A, reader: impl Read) -> Result<Self, CheckpointError> {
+        let mut entries = checkpoint::Reader::<_, K_LEN>::new(reader, checkpoint::Kind::Map)?;
+        let inner =
+            CongeeInner::from_sorted_iter(allocator, Arc::new(|_k, _v| {}), entries.by_ref())?;
+        entries.finish()?;
+        Ok(CongeeRaw {
+            inner,
+            pt_key: PhantomData,
+            pt_val: PhantomData,
+        })
+    }
+
     /// Returns if the tree is empty.
     ///
     /// # Examples
     ///
     /// ```
     /// use congee::CongeeRaw;
-    /// let tree = CongeeRaw::default();
+    /// let tree = CongeeRaw::<usize, usize>::default();
     /// let guard = tree.pin();
     /// assert!(tree.is_empty(&guard));
     /// tree.insert(1, 42, &guard);
@@ -133,13 +257,33 @@ where
         self.inner.is_empty(guard)
     }
 
+    /// Returns the number of keys in the tree, in O(1).
+    ///
+    /// The count is exact when no insert or remove runs concurrently, otherwise those may or may not be counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// assert_eq!(tree.len(&guard), 0);
+    /// tree.insert(1, 42, &guard).unwrap();
+    /// tree.insert(1, 43, &guard).unwrap();
+    /// tree.insert(2, 42, &guard).unwrap();
+    /// assert_eq!(tree.len(&guard), 2);
+    /// ```
+    pub fn len(&self, guard: &epoch::Guard) -> usize {
+        self.inner.len(guard)
+    }
+
     /// Removes key-value pair from the tree, returns the value if the key was found.
     ///
     /// # Examples
     ///
     /// ```
     /// use congee::CongeeRaw;
-    /// let tree = CongeeRaw::default();
+    /// let tree = CongeeRaw::<usize, usize>::default();
     /// let guard = tree.pin();
     ///
     /// tree.insert(1, 42, &guard);
@@ -149,20 +293,73 @@ where
     /// ```
     #[inline]
     pub fn remove(&self, k: &K, guard: &epoch::Guard) -> Option<V> {
-        let key = usize::from(*k);
-        let key: [u8; 8] = key.to_be_bytes();
+        let key = k.to_key_bytes();
         let (old, new) = self.inner.compute_if_present(&key, &mut |_v| None, guard)?;
         debug_assert!(new.is_none());
         Some(V::from(old))
     }
 
+    /// Removes all keys within `range` and returns the number of removed entries.
+    ///
+    /// The removal is atomic: concurrent operations see either all keys of the range or none of them.
+    /// Subtrees that fall entirely within the range are detached as a whole.
+    /// The drainer of the tree is called for every removed entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// for i in 0..100 {
+    ///     tree.insert(i, i, &guard).unwrap();
+    /// }
+    ///
+    /// assert_eq!(tree.remove_range(10..20, &guard), 10);
+    /// assert!(tree.get(&10, &guard).is_none());
+    /// assert_eq!(tree.get(&20, &guard), Some(20));
+    /// assert_eq!(tree.remove_range(90.., &guard), 10);
+    /// ```
+    pub fn remove_range<R: RangeBounds<K>>(&self, range: R, guard: &epoch::Guard) -> usize {
+        self.inner.remove_range(
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            guard,
+        )
+    }
+
+    /// Keeps only the entries for which `f` returns true, returns the number of removed entries.
+    ///
+    /// Each entry is checked and removed atomically, entries inserted concurrently may or may not be visited.
+    /// Note that `f` must be safe to execute multiple times on the same entry.
+    /// The drainer of the tree is called for every removed entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// for i in 0..10 {
+    ///     tree.insert(i, i * 10, &guard).unwrap();
+    /// }
+    ///
+    /// let removed = tree.retain(|_k, v| v % 20 == 0, &guard);
+    /// assert_eq!(removed, 5);
+    /// assert_eq!(tree.keys(), vec![0, 2, 4, 6, 8]);
+    /// ```
+    pub fn retain<F: FnMut(K, V) -> bool>(&self, mut f: F, guard: &epoch::Guard) -> usize {
+        self.inner
+            .retain(|k, v| f(K::from_key_bytes(*k), V::from(v)), guard)
+    }
+
     /// Insert a key-value pair to the tree, returns the previous value if the key was already present.
     ///
     /// # Examples
     ///
     /// ```
     /// use congee::CongeeRaw;
-    /// let tree = CongeeRaw::default();
+    /// let tree = CongeeRaw::<usize, usize>::default();
     /// let guard = tree.pin();
     ///
     /// tree.insert(1, 42, &guard);
@@ -172,22 +369,84 @@ where
     /// ```
     #[inline]
     pub fn insert(&self, k: K, v: V, guard: &epoch::Guard) -> Result<Option<V>, OOMError> {
-        let key = usize::from(k);
-        let key: [u8; 8] = key.to_be_bytes();
+        let key = k.to_key_bytes();
         let val = self.inner.insert(&key, usize::from(v), guard);
         val.map(|inner| inner.map(|v| V::from(v)))
     }
 
-    /// Scan the tree with the range of [start, end], write the result to the
+    /// Inserts a key-value pair only if the key is absent.
+    /// Returns `Err` with the existing value if the key is already present, which is never overwritten.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    ///
+    /// assert_eq!(tree.insert_if_absent(1, 42, &guard).unwrap(), Ok(()));
+    /// assert_eq!(tree.insert_if_absent(1, 43, &guard).unwrap(), Err(42));
+    /// assert_eq!(tree.get(&1, &guard), Some(42));
+    /// ```
+    #[inline]
+    pub fn insert_if_absent(
+        &self,
+        k: K,
+        v: V,
+        guard: &epoch::Guard,
+    ) -> Result<Result<(), V>, OOMError> {
+        let key = k.to_key_bytes();
+        match self.inner.insert_if_absent(&key, usize::from(v), guard)? {
+            Some(old) => Ok(Err(V::from(old))),
+            None => Ok(Ok(())),
+        }
+    }
+
+    /// Inserts all key-value pairs in order, returns the old value of each key.
+    ///
+    /// Consecutive keys that share the node holding their values are inserted under a single lock of that node,
+    /// which grows at most once, so sorted batches are much faster than calling [insert](Self::insert) for each pair.
+    /// A key that appears more than once gets the last value, its later occurrences report the earlier values as old.
+    ///
+    /// On error, the pairs before the failed one are inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(2, 20, &guard).unwrap();
+    ///
+    /// let old = tree.insert_batch(&[(1, 1), (2, 2), (3, 3), (3, 4)], &guard).unwrap();
+    /// assert_eq!(old, vec![None, Some(20), None, Some(3)]);
+    /// assert_eq!(tree.get(&3, &guard), Some(4));
+    /// ```
+    pub fn insert_batch(
+        &self,
+        entries: &[(K, V)],
+        guard: &epoch::Guard,
+    ) -> Result<Vec<Option<V>>, OOMError> {
+        let entries: Vec<([u8; K_LEN], usize)> = entries
+            .iter()
+            .map(|(k, v)| (k.to_key_bytes(), usize::from(*v)))
+            .collect();
+        let old = self.inner.insert_batch(&entries, guard)?;
+        Ok(old.into_iter().map(|v| v.map(V::from)).collect())
+    }
+
+    /// Scan the tree with the range of [start, end), write the result to the
     /// `result` buffer.
     /// It scans the length of `result` or the number of the keys within the range, whichever is smaller;
     /// returns the number of the keys scanned.
+    /// The entries are written straight to `result` without allocating,
+    /// entries past the returned count may have been overwritten by a scan that restarted on a concurrent write.
     ///
     /// # Examples
     ///
     /// ```
     /// use congee::CongeeRaw;
-    /// let tree = CongeeRaw::default();
+    /// let tree = CongeeRaw::<usize, usize>::default();
     /// let guard = tree.pin();
     ///
     /// tree.insert(1, 42, &guard);
@@ -200,28 +459,325 @@ where
     /// assert_eq!(result, [(1, 42), (0, 0)]);
     /// ```
     #[inline]
-    pub fn range(
+    pub fn range(&self, start: &K, end: &K, result: &mut [(K, V)], guard: &epoch::Guard) -> usize {
+        let start = start.to_key_bytes();
+        let end = end.to_key_bytes();
+        let mut result = Converted::new(result, |k, v| (K::from_key_bytes(k), V::from(v)));
+        self.inner.range(&start, &end, &mut result, guard)
+    }
+
+    /// Same as [range](Self::range), but takes any [RangeBounds], so each end can be included, excluded or unbounded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    /// tree.insert(usize::MAX, 43, &guard).unwrap();
+    ///
+    /// let mut result = [(0, 0); 2];
+    /// assert_eq!(tree.range_bounds(1..=1, &mut result, &guard), 1);
+    /// assert_eq!(result[0], (1, 42));
+    ///
+    /// assert_eq!(tree.range_bounds(2.., &mut result, &guard), 1);
+    /// assert_eq!(result[0], (usize::MAX, 43));
+    ///
+    /// assert_eq!(tree.range_bounds(.., &mut result, &guard), 2);
+    /// ```
+    #[inline]
+    pub fn range_bounds<R: RangeBounds<K>>(
+        &self,
+        range: R,
+        result: &mut [(K, V)],
+        guard: &epoch::Guard,
+    ) -> usize {
+        self.scan_encoded_bounds(
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            result,
+            guard,
+        )
+    }
+
+    fn scan_encoded_bounds(
+        &self,
+        start: Bound<[u8; K_LEN]>,
+        end: Bound<[u8; K_LEN]>,
+        result: &mut [(K, V)],
+        guard: &epoch::Guard,
+    ) -> usize {
+        let mut result = Converted::new(result, |k, v| (K::from_key_bytes(k), V::from(v)));
+        self.inner.range_bounds(start, end, &mut result, guard)
+    }
+
+    /// Returns the number of keys within `range`, without copying them out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// for i in 0..100 {
+    ///     tree.insert(i, i, &guard).unwrap();
+    /// }
+    ///
+    /// assert_eq!(tree.count_range(10..20, &guard), 10);
+    /// assert_eq!(tree.count_range(..=10, &guard), 11);
+    /// assert_eq!(tree.count_range(.., &guard), 100);
+    /// ```
+    pub fn count_range<R: RangeBounds<K>>(&self, range: R, guard: &epoch::Guard) -> usize {
+        self.inner.count_range(
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            guard,
+        )
+    }
+
+    /// Scans the keys whose first `prefix_len` encoded bytes equal those of `prefix`, in ascending order.
+    /// Writes them to `result` and returns the number of keys scanned, at most the length of `result`.
+    ///
+    /// The scan descends straight to the subtree holding the prefix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<u64, usize>::default();
+    /// let guard = tree.pin();
+    /// // Keys are (tenant: u16, object: u48).
+    /// for tenant in 0..3u64 {
+    ///     for object in 0..10u64 {
+    ///         tree.insert(tenant << 48 | object, object as usize, &guard).unwrap();
+    ///     }
+    /// }
+    ///
+    /// let mut result = [(0, 0); 16];
+    /// assert_eq!(tree.scan_prefix(&(1 << 48), 2, &mut result, &guard), 10);
+    /// assert_eq!(result[0], (1 << 48, 0));
+    /// assert_eq!(result[9], (1 << 48 | 9, 9));
+    /// ```
+    pub fn scan_prefix(
+        &self,
+        prefix: &K,
+        prefix_len: usize,
+        result: &mut [(K, V)],
+        guard: &epoch::Guard,
+    ) -> usize {
+        let mut result = Converted::new(result, |k, v| (K::from_key_bytes(k), V::from(v)));
+        self.inner
+            .scan_prefix(&prefix.to_key_bytes(), prefix_len, &mut result, guard)
+    }
+
+    /// Returns the number of keys whose first `prefix_len` encoded bytes equal those of `prefix`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<u64, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(0x0102_0000_0000_0000, 1, &guard).unwrap();
+    /// tree.insert(0x0102_ffff_ffff_ffff, 2, &guard).unwrap();
+    /// tree.insert(0x0103_0000_0000_0000, 3, &guard).unwrap();
+    /// assert_eq!(tree.count_prefix(&0x0102_0000_0000_0000, 2, &guard), 2);
+    /// assert_eq!(tree.count_prefix(&0x0100_0000_0000_0000, 1, &guard), 3);
+    /// ```
+    pub fn count_prefix(&self, prefix: &K, prefix_len: usize, guard: &epoch::Guard) -> usize {
+        self.inner
+            .count_prefix(&prefix.to_key_bytes(), prefix_len, guard)
+    }
+
+    /// Removes all keys whose first `prefix_len` encoded bytes equal those of `prefix`,
+    /// returns the number of removed entries.
+    /// The removal is atomic like [remove_range](Self::remove_range).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<u64, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1 << 48 | 7, 1, &guard).unwrap();
+    /// tree.insert(2 << 48 | 7, 2, &guard).unwrap();
+    /// assert_eq!(tree.remove_prefix(&(1 << 48), 2, &guard), 1);
+    /// assert_eq!(tree.get(&(1 << 48 | 7), &guard), None);
+    /// assert_eq!(tree.get(&(2 << 48 | 7), &guard), Some(2));
+    /// ```
+    pub fn remove_prefix(&self, prefix: &K, prefix_len: usize, guard: &epoch::Guard) -> usize {
+        let (start, end) = prefix_bounds(prefix, prefix_len);
+        self.inner.remove_range(start, end, guard)
+    }
+
+    /// Same as [range](Self::range), but the keys are scanned in descending order,
+    /// so the buffer holds the largest keys below `end`, largest first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// for i in 0..10 {
+    ///     tree.insert(i, i * 10, &guard).unwrap();
+    /// }
+    ///
+    /// let mut result = [(0, 0); 3];
+    /// let scanned = tree.range_rev(&0, &8, &mut result, &guard);
+    /// assert_eq!(scanned, 3);
+    /// assert_eq!(result, [(7, 70), (6, 60), (5, 50)]);
+    /// ```
+    #[inline]
+    pub fn range_rev(
         &self,
         start: &K,
         end: &K,
-        result: &mut [(usize, usize)],
+        result: &mut [(K, V)],
         guard: &epoch::Guard,
     ) -> usize {
-        let start = usize::from(*start);
-        let end = usize::from(*end);
-        let start: [u8; 8] = start.to_be_bytes();
-        let end: [u8; 8] = end.to_be_bytes();
-        let result_ref = unsafe {
-            std::slice::from_raw_parts_mut(
-                result.as_mut_ptr() as *mut ([u8; 8], usize),
-                result.len(),
-            )
-        };
-        let v = self.inner.range(&start, &end, result_ref, guard);
-        for i in 0..v {
-            result[i].0 = usize::from_be_bytes(result_ref[i].0);
-        }
-        v
+        let start = start.to_key_bytes();
+        let end = end.to_key_bytes();
+        let mut result = Converted::new(result, |k, v| (K::from_key_bytes(k), V::from(v)));
+        self.inner.range_rev(&start, &end, &mut result, guard)
+    }
+
+    /// Returns the entry with the smallest key, `None` if the tree is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// assert_eq!(tree.first(&guard), None);
+    ///
+    /// tree.insert(3, 30, &guard).unwrap();
+    /// tree.insert(1, 10, &guard).unwrap();
+    /// assert_eq!(tree.first(&guard), Some((1, 10)));
+    /// ```
+    #[inline]
+    pub fn first(&self, guard: &epoch::Guard) -> Option<(K, V)> {
+        self.inner.first(guard).map(Self::decode_entry)
+    }
+
+    /// Returns the entry with the largest key, `None` if the tree is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(3, 30, &guard).unwrap();
+    /// tree.insert(1, 10, &guard).unwrap();
+    /// assert_eq!(tree.last(&guard), Some((3, 30)));
+    /// ```
+    #[inline]
+    pub fn last(&self, guard: &epoch::Guard) -> Option<(K, V)> {
+        self.inner.last(guard).map(Self::decode_entry)
+    }
+
+    /// Returns the entry with the smallest key strictly greater than `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 10, &guard).unwrap();
+    /// tree.insert(3, 30, &guard).unwrap();
+    ///
+    /// assert_eq!(tree.successor(&1, &guard), Some((3, 30)));
+    /// assert_eq!(tree.successor(&3, &guard), None);
+    /// ```
+    #[inline]
+    pub fn successor(&self, key: &K, guard: &epoch::Guard) -> Option<(K, V)> {
+        let key = key.to_key_bytes();
+        self.inner.successor(&key, guard).map(Self::decode_entry)
+    }
+
+    /// Returns the entry with the largest key strictly less than `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 10, &guard).unwrap();
+    /// tree.insert(3, 30, &guard).unwrap();
+    ///
+    /// assert_eq!(tree.predecessor(&3, &guard), Some((1, 10)));
+    /// assert_eq!(tree.predecessor(&1, &guard), None);
+    /// ```
+    #[inline]
+    pub fn predecessor(&self, key: &K, guard: &epoch::Guard) -> Option<(K, V)> {
+        let key = key.to_key_bytes();
+        self.inner.predecessor(&key, guard).map(Self::decode_entry)
+    }
+
+    /// Returns the entry with the largest key less than or equal to `key`.
+    ///
+    /// Handy for interval lookups, e.g. finding the extent that starts at or before an offset.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// // extents keyed by their start offset, the value is the length
+    /// tree.insert(0, 100, &guard).unwrap();
+    /// tree.insert(100, 50, &guard).unwrap();
+    ///
+    /// assert_eq!(tree.floor(&120, &guard), Some((100, 50)));
+    /// assert_eq!(tree.floor(&100, &guard), Some((100, 50)));
+    /// assert_eq!(tree.floor(&99, &guard), Some((0, 100)));
+    /// ```
+    #[inline]
+    pub fn floor(&self, key: &K, guard: &epoch::Guard) -> Option<(K, V)> {
+        let key = key.to_key_bytes();
+        self.inner.floor(&key, guard).map(Self::decode_entry)
+    }
+
+    /// Returns the entry with the smallest key greater than or equal to `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 10, &guard).unwrap();
+    /// tree.insert(3, 30, &guard).unwrap();
+    ///
+    /// assert_eq!(tree.ceiling(&2, &guard), Some((3, 30)));
+    /// assert_eq!(tree.ceiling(&3, &guard), Some((3, 30)));
+    /// assert_eq!(tree.ceiling(&4, &guard), None);
+    /// ```
+    #[inline]
+    pub fn ceiling(&self, key: &K, guard: &epoch::Guard) -> Option<(K, V)> {
+        let key = key.to_key_bytes();
+        self.inner.ceiling(&key, guard).map(Self::decode_entry)
+    }
+
+    fn decode_entry((k, v): ([u8; K_LEN], usize)) -> (K, V) {
+        (K::from_key_bytes(k), V::from(v))
     }
 
     /// Compute and update the value if the key presents in the tree.
@@ -234,7 +790,7 @@ where
     ///
     /// ```
     /// use congee::CongeeRaw;
-    /// let tree = CongeeRaw::default();
+    /// let tree = CongeeRaw::<usize, usize>::default();
     /// let guard = tree.pin();
     ///
     /// tree.insert(1, 42, &guard);
@@ -253,8 +809,7 @@ where
     where
         F: FnMut(usize) -> Option<usize>,
     {
-        let key = usize::from(*key);
-        let key: [u8; 8] = key.to_be_bytes();
+        let key = key.to_key_bytes();
         self.inner.compute_if_present(&key, &mut f, guard)
     }
 
@@ -268,7 +823,7 @@ where
     ///
     /// ```
     /// use congee::CongeeRaw;
-    /// let tree = CongeeRaw::default();
+    /// let tree = CongeeRaw::<usize, usize>::default();
     /// let guard = tree.pin();
     ///
     /// tree.insert(1, 42, &guard);
@@ -294,8 +849,7 @@ where
     where
         F: FnMut(Option<usize>) -> usize,
     {
-        let key = usize::from(key);
-        let key: [u8; 8] = key.to_be_bytes();
+        let key = key.to_key_bytes();
         let u_val = self.inner.compute_or_insert(&key, &mut f, guard)?;
         Ok(u_val.map(|v| V::from(v)))
     }
@@ -311,7 +865,7 @@ where
     /// # Examples:
     /// ```
     /// use congee::CongeeRaw;
-    /// let tree = CongeeRaw::default();
+    /// let tree = CongeeRaw::<usize, usize>::default();
     /// let guard = tree.pin();
     /// tree.insert(1, 42, &guard);
     ///
@@ -326,8 +880,7 @@ where
         new: Option<V>,
         guard: &epoch::Guard,
     ) -> Result<Option<V>, Option<V>> {
-        let key = usize::from(*key);
-        let key: [u8; 8] = key.to_be_bytes();
+        let key = key.to_key_bytes();
         let new_v = new.map(|v| usize::from(v));
         let mut fc = |v: usize| -> Option<usize> {
             if v == usize::from(*old) {
@@ -349,13 +902,158 @@ where
         }
     }
 
+    /// Starts a transaction that reads and writes many keys and commits them atomically.
+    ///
+    /// Reads are checked at commit time, the commit fails with [TransactionError::Conflict](crate::TransactionError::Conflict)
+    /// if any key read by the transaction has changed in the meantime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeRaw, TransactionError};
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 100, &guard).unwrap();
+    ///
+    /// // Move 30 from key 1 to key 2, retrying on conflicts.
+    /// loop {
+    ///     let mut txn = tree.transaction(&guard);
+    ///     let from = txn.get(&1).unwrap();
+    ///     let to = txn.get(&2).unwrap_or(0);
+    ///     txn.insert(1, from - 30);
+    ///     txn.insert(2, to + 30);
+    ///     match txn.commit() {
+    ///         Ok(()) => break,
+    ///         Err(TransactionError::Conflict) => continue,
+    ///         Err(e) => panic!("{e}"),
+    ///     }
+    /// }
+    /// assert_eq!(tree.get(&1, &guard), Some(70));
+    /// assert_eq!(tree.get(&2, &guard), Some(30));
+    /// ```
+    pub fn transaction<'a>(&'a self, guard: &'a epoch::Guard) -> Transaction<'a, K, V, A, K_LEN> {
+        Transaction::new(&self.inner, guard)
+    }
+
+    /// Takes a read-only view of the tree as of a single point in time.
+    ///
+    /// Unlike [range](Self::range) and [iter](Self::iter), reads on the snapshot see one consistent state,
+    /// however long they take. Writers don't wait for the snapshot: while any snapshot is alive,
+    /// each write records the value it replaces, and the records are freed once the last snapshot is dropped.
+    /// The guard stays pinned as long as the snapshot is alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    ///
+    /// let snapshot = tree.snapshot(&guard);
+    /// tree.insert(1, 43, &guard).unwrap();
+    /// tree.insert(2, 44, &guard).unwrap();
+    ///
+    /// assert_eq!(snapshot.get(&1), Some(42));
+    /// assert_eq!(snapshot.iter().collect::<Vec<_>>(), vec![(1, 42)]);
+    /// assert_eq!(tree.get(&1, &guard), Some(43));
+    /// ```
+    pub fn snapshot<'a>(
+        &'a self,
+        guard: &'a epoch::Guard,
+    ) -> CongeeRawSnapshot<'a, K, V, A, K_LEN> {
+        CongeeRawSnapshot::new(&self.inner, guard)
+    }
+
+    /// Returns an iterator over all entries in key order.
+    ///
+    /// The entries are fetched lazily, so the iterator never needs a pre-sized buffer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(2, 43, &guard).unwrap();
+    /// tree.insert(1, 42, &guard).unwrap();
+    ///
+    /// let entries: Vec<_> = tree.iter(&guard).collect();
+    /// assert_eq!(entries, vec![(1, 42), (2, 43)]);
+    /// ```
+    pub fn iter<'a>(&'a self, guard: &'a epoch::Guard) -> CongeeRawIter<'a, K, V, A, K_LEN> {
+        self.range_iter(.., guard)
+    }
+
+    /// Returns an iterator over the entries within `range` in key order.
+    /// Call `rev` on the iterator to walk the entries from the largest key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// for i in 0..10 {
+    ///     tree.insert(i, i * 10, &guard).unwrap();
+    /// }
+    ///
+    /// let keys: Vec<_> = tree.range_iter(3..=5, &guard).map(|(k, _v)| k).collect();
+    /// assert_eq!(keys, vec![3, 4, 5]);
+    /// assert_eq!(tree.range_iter(8.., &guard).count(), 2);
+    ///
+    /// let latest: Vec<_> = tree.range_iter(..7, &guard).rev().take(2).collect();
+    /// assert_eq!(latest, vec![(6, 60), (5, 50)]);
+    /// ```
+    pub fn range_iter<'a, R: RangeBounds<K>>(
+        &'a self,
+        range: R,
+        guard: &'a epoch::Guard,
+    ) -> CongeeRawIter<'a, K, V, A, K_LEN> {
+        CongeeRawIter::new(RawIter::new(
+            &self.inner,
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            guard,
+        ))
+    }
+
+    /// Iterates over the keys whose first `prefix_len` encoded bytes equal those of `prefix`, in ascending order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<u64, usize>::default();
+    /// let guard = tree.pin();
+    /// for k in [0x0100, 0x01ff, 0x0200] {
+    ///     tree.insert(k, k as usize, &guard).unwrap();
+    /// }
+    ///
+    /// let keys: Vec<u64> = tree.iter_prefix(&0x0100, 7, &guard).map(|(k, _)| k).collect();
+    /// assert_eq!(keys, vec![0x0100, 0x01ff]);
+    /// ```
+    pub fn iter_prefix<'a>(
+        &'a self,
+        prefix: &K,
+        prefix_len: usize,
+        guard: &'a epoch::Guard,
+    ) -> CongeeRawIter<'a, K, V, A, K_LEN> {
+        let (start, end) = prefix_bounds(prefix, prefix_len);
+        CongeeRawIter::new(RawIter::new(&self.inner, start, end, guard))
+    }
+
     /// Retrieve all keys from ART.
     /// Isolation level: read committed.
     ///
     /// # Examples:
     /// ```
     /// use congee::CongeeRaw;
-    /// let tree = CongeeRaw::default();
+    /// let tree = CongeeRaw::<usize, usize>::default();
     /// let guard = tree.pin();
     /// tree.insert(1, 42, &guard);
     /// tree.insert(2, 43, &guard);
@@ -367,13 +1065,67 @@ where
         self.inner
             .keys()
             .into_iter()
-            .map(|k| {
-                let key = usize::from_be_bytes(k);
-                K::from(key)
-            })
+            .map(K::from_key_bytes)
             .collect()
     }
 
+    /// Writes a checkpoint of the tree, which [read_snapshot](Self::read_snapshot) loads back.
+    ///
+    /// The format starts with a header holding a magic number, the format version and the key length,
+    /// followed by one block per group of keys sharing their first `K_LEN - 1` bytes and a trailer with the number
+    /// of entries; the header, every block and the trailer carry a CRC-32 checksum.
+    ///
+    /// # Consistency
+    ///
+    /// The checkpoint is not a point-in-time image of the tree.
+    /// The blocks are written during a single traversal of the tree without copying it and without stopping writers,
+    /// so an entry written concurrently may or may not be in the checkpoint,
+    /// and of two entries written one after the other, the checkpoint may hold only the later one.
+    /// Stop the writers while the checkpoint is written if it must match the tree at a single moment.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeRaw;
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    ///
+    /// let mut buf = Vec::new();
+    /// tree.write_snapshot(&mut buf, &guard).unwrap();
+    /// ```
+    pub fn write_snapshot(
+        &self,
+        writer: &mut impl Write,
+        guard: &epoch::Guard,
+    ) -> Result<(), CheckpointError> {
+        checkpoint::write(
+            writer,
+            checkpoint::Kind::Map,
+            self.iter(guard)
+                .map(|(k, v)| (k.to_key_bytes(), usize::from(v))),
+        )
+    }
+
+    /// Serializes the tree into the read-only layout of [CongeeCompactMap](crate::CongeeCompactMap),
+    /// the values are stored with the fewest bytes that hold the largest of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeCompactMap, CongeeRaw};
+    /// let tree = CongeeRaw::<usize, usize>::default();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    ///
+    /// let data = tree.to_compact_map();
+    /// let compact_map = CongeeCompactMap::<usize, usize>::new(&data);
+    /// assert_eq!(compact_map.get(&1), Some(42));
+    /// ```
+    pub fn to_compact_map(&self) -> Vec<u8> {
+        self.inner.to_compact_map()
+    }
+
     /// Returns the allocator used by the tree.
     ///
     /// # Examples:
@@ -1,30 +1,39 @@
-use std::{marker::PhantomData, sync::Arc};
+use std::{
+    io::{Read, Write},
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
 
-use crate::{Allocator, CongeeInner, DefaultAllocator, epoch, error::OOMError, stats};
+use crate::{
+    Allocator, CongeeCompactSet, CongeeInner, CongeeSetIter, CongeeSetSnapshot, DefaultAllocator,
+    KeyEncoding, checkpoint, epoch,
+    error::{CheckpointError, CompactSetError, OOMError},
+    iter::{RawIter, encode_bound, prefix_bounds},
+    range_scan::Converted,
+    stats,
+};
 
 /// A concurrent set-like data structure implemented using an adaptive radix tree.
+///
+/// `K_LEN` is the length of the key in bytes, keys are converted to bytes by [KeyEncoding].
 pub struct CongeeSet<
-    K: Copy + From<usize>,
+    K: KeyEncoding<K_LEN>,
     A: Allocator + Clone + Send + 'static = DefaultAllocator,
-> where
-    usize: From<K>,
-{
-    inner: CongeeInner<8, A>,
+    const K_LEN: usize = 8,
+> {
+    inner: CongeeInner<K_LEN, A>,
     pt_key: PhantomData<K>,
 }
 
-impl<K: Copy + From<usize>> Default for CongeeSet<K>
-where
-    usize: From<K>,
-{
+impl<K: KeyEncoding<K_LEN>, const K_LEN: usize> Default for CongeeSet<K, DefaultAllocator, K_LEN> {
     fn default() -> Self {
         Self::new(DefaultAllocator {})
     }
 }
 
-impl<K: Copy + From<usize>, A: Allocator + Clone + Send> CongeeSet<K, A>
-where
-    usize: From<K>,
+impl<K: KeyEncoding<K_LEN>, A: Allocator + Clone + Send, const K_LEN: usize>
+    CongeeSet<K, A, K_LEN>
 {
     /// Creates a new empty CongeeSet.
     ///
@@ -41,7 +50,9 @@ where
 
     /// Creates a new empty CongeeSet with a drainer.
     ///
-    /// The drainer is called on each key when the set is dropped.
+    /// The drainer is called on the keys removed by [remove_range](Self::remove_range),
+    /// [remove_prefix](Self::remove_prefix) and [retain](Self::retain), once no reader can see them anymore,
+    /// and on the keys left when the set is dropped. [remove](Self::remove) doesn't call it.
     ///
     /// # Examples
     ///
@@ -63,14 +74,71 @@ where
     /// assert_eq!(deleted_key.load(std::sync::atomic::Ordering::Relaxed), 1);
     /// ```
     pub fn new_with_drainer(allocator: A, drainer: impl Fn(K) + 'static) -> Self {
-        let drainer =
-            Arc::new(move |k: [u8; 8], _v: usize| drainer(K::from(usize::from_be_bytes(k))));
+        let drainer = Arc::new(move |k: [u8; K_LEN], _v: usize| drainer(K::from_key_bytes(k)));
         CongeeSet {
             inner: CongeeInner::new(allocator, drainer),
             pt_key: PhantomData,
         }
     }
 
+    /// Builds a set from keys in strictly ascending order.
+    ///
+    /// The nodes are built bottom-up with their final size,
+    /// which is much faster than inserting the keys one by one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not in strictly ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeSet, DefaultAllocator};
+    /// let set = CongeeSet::<usize>::from_sorted_iter(DefaultAllocator {}, (0..1000).step_by(3))
+    ///     .unwrap();
+    /// let guard = set.pin();
+    /// assert!(set.contains(&999, &guard));
+    /// assert!(!set.contains(&998, &guard));
+    /// ```
+    pub fn from_sorted_iter(
+        allocator: A,
+        iter: impl IntoIterator<Item = K>,
+    ) -> Result<Self, OOMError> {
+        let entries = iter.into_iter().map(|k| (k.to_key_bytes(), 1));
+        Ok(CongeeSet {
+            inner: CongeeInner::from_sorted_iter(allocator, Arc::new(|_k, _v| {}), entries)?,
+            pt_key: PhantomData,
+        })
+    }
+
+    /// Rebuilds a set from a checkpoint written by [write_snapshot](Self::write_snapshot).
+    ///
+    /// Truncated or corrupted input, or a checkpoint of a [CongeeRaw](crate::CongeeRaw), is rejected with a [CheckpointError].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeSet, DefaultAllocator};
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(1, &guard).unwrap();
+    ///
+    /// let mut buf = Vec::new();
+    /// set.write_snapshot(&mut buf, &guard).unwrap();
+    /// let loaded = CongeeSet::<usize>::read_snapshot(DefaultAllocator {}, &buf[..]).unwrap();
+    /// assert!(loaded.contains(&1, &guard));
+    /// ```
+    pub fn read_snapshot(allocator: A, reader: impl Read) -> Result<Self, CheckpointError> {
+        let mut keys = checkpoint::Reader::<_, K_LEN>::new(reader, checkpoint::Kind::Set)?;
+        let entries = keys.by_ref().map(|(k, _v)| (k, 1));
+        let inner = CongeeInner::from_sorted_iter(allocator, Arc::new(|_k, _v| {}), entries)?;
+        keys.finish()?;
+        Ok(CongeeSet {
+            inner,
+            pt_key: PhantomData,
+        })
+    }
+
     /// Enters an epoch.
     /// Note: this can be expensive, try to reuse it.
     ///
@@ -92,7 +160,7 @@ where
     ///
     /// ```
     /// use congee::CongeeSet;
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     /// assert!(set.is_empty(&guard));
     /// set.insert(1, &guard).unwrap();
@@ -108,7 +176,7 @@ where
     ///
     /// ```
     /// use congee::CongeeSet;
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     ///
     /// set.insert(1, &guard).unwrap();
@@ -117,11 +185,42 @@ where
     /// ```
     #[inline]
     pub fn contains(&self, key: &K, guard: &epoch::Guard) -> bool {
-        let key = usize::from(*key);
-        let key: [u8; 8] = key.to_be_bytes();
+        let key = key.to_key_bytes();
         self.inner.get(&key, guard).is_some()
     }
 
+    /// Checks all `keys` at once, writing whether `keys[i]` is in the set to `result[i]`.
+    ///
+    /// Consecutive lookups share the part of the traversal their keys have in common,
+    /// sorting the keys makes this much faster than calling [contains](Self::contains) for each key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` and `result` have different lengths.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(1, &guard).unwrap();
+    /// set.insert(3, &guard).unwrap();
+    ///
+    /// let mut result = [false; 3];
+    /// set.contains_many(&[1, 2, 3], &mut result, &guard);
+    /// assert_eq!(result, [true, false, true]);
+    /// ```
+    pub fn contains_many(&self, keys: &[K], result: &mut [bool], guard: &epoch::Guard) {
+        assert_eq!(keys.len(), result.len());
+        let mut result = result.iter_mut();
+        self.inner.get_many(
+            keys.iter().map(|k| k.to_key_bytes()),
+            |value| *result.next().unwrap() = value.is_some(),
+            guard,
+        );
+    }
+
     /// Inserts a key into the set.
     /// Returns true if the key was newly inserted, false if it was already present.
     ///
@@ -129,7 +228,7 @@ where
     ///
     /// ```
     /// use congee::CongeeSet;
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     ///
     /// assert!(set.insert(1, &guard).unwrap());
@@ -137,10 +236,9 @@ where
     /// ```
     #[inline]
     pub fn insert(&self, k: K, guard: &epoch::Guard) -> Result<bool, OOMError> {
-        let key = usize::from(k);
-        let key: [u8; 8] = key.to_be_bytes();
-        // Use a dummy value (1) since we only care about key presence
-        let old = self.inner.insert(&key, 1, guard)?;
+        let key = k.to_key_bytes();
+        // Use a dummy value (1) since we only care about key presence, a present key is not written again
+        let old = self.inner.insert_if_absent(&key, 1, guard)?;
         Ok(old.is_none()) // true if newly inserted, false if already present
     }
 
@@ -151,7 +249,7 @@ where
     ///
     /// ```
     /// use congee::CongeeSet;
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     ///
     /// set.insert(1, &guard).unwrap();
@@ -160,8 +258,7 @@ where
     /// ```
     #[inline]
     pub fn remove(&self, k: &K, guard: &epoch::Guard) -> bool {
-        let key = usize::from(*k);
-        let key: [u8; 8] = key.to_be_bytes();
+        let key = k.to_key_bytes();
         let (old, new) = match self.inner.compute_if_present(&key, &mut |_v| None, guard) {
             Some(result) => result,
             None => return false, // Key not present
@@ -170,6 +267,156 @@ where
         old == 1 // Should always be 1 for sets, but check to be safe
     }
 
+    /// Removes all keys within `range` and returns the number of removed keys.
+    ///
+    /// The removal is atomic: concurrent operations see either all keys of the range or none of them.
+    /// The drainer of the set is called for every removed key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// for i in 0..10 {
+    ///     set.insert(i, &guard).unwrap();
+    /// }
+    ///
+    /// assert_eq!(set.remove_range(2..=7, &guard), 6);
+    /// assert_eq!(set.iter(&guard).collect::<Vec<_>>(), vec![0, 1, 8, 9]);
+    /// ```
+    pub fn remove_range<R: RangeBounds<K>>(&self, range: R, guard: &epoch::Guard) -> usize {
+        self.inner.remove_range(
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            guard,
+        )
+    }
+
+    /// Keeps only the keys for which `f` returns true, returns the number of removed keys.
+    ///
+    /// Each key is checked and removed atomically, keys inserted concurrently may or may not be visited.
+    /// The drainer of the set is called for every removed key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// for i in 0..10 {
+    ///     set.insert(i, &guard).unwrap();
+    /// }
+    ///
+    /// assert_eq!(set.retain(|k| k % 3 == 0, &guard), 6);
+    /// assert_eq!(set.iter(&guard).collect::<Vec<_>>(), vec![0, 3, 6, 9]);
+    /// ```
+    pub fn retain<F: FnMut(K) -> bool>(&self, mut f: F, guard: &epoch::Guard) -> usize {
+        self.inner.retain(|k, _v| f(K::from_key_bytes(*k)), guard)
+    }
+
+    /// Returns an iterator over all keys in order.
+    ///
+    /// The keys are fetched lazily, so the iterator never needs a pre-sized buffer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(2, &guard).unwrap();
+    /// set.insert(1, &guard).unwrap();
+    ///
+    /// assert_eq!(set.iter(&guard).collect::<Vec<_>>(), vec![1, 2]);
+    /// ```
+    pub fn iter<'a>(&'a self, guard: &'a epoch::Guard) -> CongeeSetIter<'a, K, A, K_LEN> {
+        self.range_iter(.., guard)
+    }
+
+    /// Takes a read-only view of the set as of a single point in time.
+    ///
+    /// Reads on the snapshot see one consistent state, however long they take.
+    /// Writers don't wait for the snapshot, the keys they replace are recorded until the last snapshot is dropped.
+    /// The guard stays pinned as long as the snapshot is alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(1, &guard).unwrap();
+    ///
+    /// let snapshot = set.snapshot(&guard);
+    /// set.remove(&1, &guard);
+    /// set.insert(2, &guard).unwrap();
+    ///
+    /// assert!(snapshot.contains(&1));
+    /// assert_eq!(snapshot.iter().collect::<Vec<_>>(), vec![1]);
+    /// ```
+    pub fn snapshot<'a>(&'a self, guard: &'a epoch::Guard) -> CongeeSetSnapshot<'a, K, A, K_LEN> {
+        CongeeSetSnapshot::new(&self.inner, guard)
+    }
+
+    /// Returns an iterator over the keys within `range` in order.
+    /// Call `rev` on the iterator to walk the keys from the largest one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// for i in 0..10 {
+    ///     set.insert(i, &guard).unwrap();
+    /// }
+    ///
+    /// assert_eq!(set.range_iter(3..6, &guard).collect::<Vec<_>>(), vec![3, 4, 5]);
+    /// assert_eq!(set.range_iter(..=1, &guard).collect::<Vec<_>>(), vec![0, 1]);
+    /// assert_eq!(set.range_iter(3..6, &guard).rev().collect::<Vec<_>>(), vec![5, 4, 3]);
+    /// ```
+    pub fn range_iter<'a, R: RangeBounds<K>>(
+        &'a self,
+        range: R,
+        guard: &'a epoch::Guard,
+    ) -> CongeeSetIter<'a, K, A, K_LEN> {
+        CongeeSetIter::new(RawIter::new(
+            &self.inner,
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            guard,
+        ))
+    }
+
+    /// Iterates over the keys whose first `prefix_len` encoded bytes equal those of `prefix`, in ascending order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<u64>::default();
+    /// let guard = set.pin();
+    /// for k in [0x0a00_0000_0000_0001, 0x0a00_0000_0000_0002, 0x0b00_0000_0000_0001] {
+    ///     set.insert(k, &guard).unwrap();
+    /// }
+    /// let keys: Vec<u64> = set.iter_prefix(&0x0a00_0000_0000_0000, 1, &guard).collect();
+    /// assert_eq!(keys, vec![0x0a00_0000_0000_0001, 0x0a00_0000_0000_0002]);
+    /// ```
+    pub fn iter_prefix<'a>(
+        &'a self,
+        prefix: &K,
+        prefix_len: usize,
+        guard: &'a epoch::Guard,
+    ) -> CongeeSetIter<'a, K, A, K_LEN> {
+        let (start, end) = prefix_bounds(prefix, prefix_len);
+        CongeeSetIter::new(RawIter::new(&self.inner, start, end, guard))
+    }
+
     /// Retrieves all keys from the set.
     /// Isolation level: read committed.
     ///
@@ -177,7 +424,7 @@ where
     ///
     /// ```
     /// use congee::CongeeSet;
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     /// set.insert(1, &guard).unwrap();
     /// set.insert(2, &guard).unwrap();
@@ -190,21 +437,53 @@ where
         self.inner
             .keys()
             .into_iter()
-            .map(|k| {
-                let key = usize::from_be_bytes(k);
-                K::from(key)
-            })
+            .map(K::from_key_bytes)
             .collect()
     }
 
-    /// Returns the number of keys in the set.
-    /// Note: This operation is O(n) as it needs to count all keys.
+    /// Writes a checkpoint of the set, which [read_snapshot](Self::read_snapshot) loads back.
+    ///
+    /// The format is the one of [CongeeRaw::write_snapshot](crate::CongeeRaw::write_snapshot) without values.
+    ///
+    /// # Consistency
+    ///
+    /// The checkpoint is not a point-in-time image of the set.
+    /// It is written from a single traversal of the set while writers keep going,
+    /// so a key inserted or removed concurrently may or may not be in the checkpoint.
+    /// Stop the writers while the checkpoint is written if it must match the set at a single moment.
     ///
     /// # Examples
     ///
     /// ```
     /// use congee::CongeeSet;
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(1, &guard).unwrap();
+    ///
+    /// let mut buf = Vec::new();
+    /// set.write_snapshot(&mut buf, &guard).unwrap();
+    /// ```
+    pub fn write_snapshot(
+        &self,
+        writer: &mut impl Write,
+        guard: &epoch::Guard,
+    ) -> Result<(), CheckpointError> {
+        checkpoint::write(
+            writer,
+            checkpoint::Kind::Set,
+            self.iter(guard).map(|k| (k.to_key_bytes(), 1)),
+        )
+    }
+
+    /// Returns the number of keys in the set, in O(1).
+    ///
+    /// The count is exact when no insert or remove runs concurrently, otherwise those may or may not be counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     ///
     /// assert_eq!(set.len(&guard), 0);
@@ -213,17 +492,19 @@ where
     /// assert_eq!(set.len(&guard), 2);
     /// ```
     pub fn len(&self, guard: &epoch::Guard) -> usize {
-        self.inner.value_count(guard)
+        self.inner.len(guard)
     }
 
-    /// Scans keys in the range [start, end] and writes them to the result buffer.
+    /// Scans keys in the range [start, end) and writes them to the result buffer.
     /// Returns the number of keys scanned.
+    /// The keys are written straight to `result` without allocating,
+    /// keys past the returned count may have been overwritten by a scan that restarted on a concurrent write.
     ///
     /// # Examples
     ///
     /// ```
     /// use congee::CongeeSet;
-    /// let set = CongeeSet::default();
+    /// let set = CongeeSet::<usize>::default();
     /// let guard = set.pin();
     ///
     /// set.insert(1, &guard).unwrap();
@@ -237,23 +518,317 @@ where
     /// ```
     #[inline]
     pub fn range(&self, start: &K, end: &K, result: &mut [K], guard: &epoch::Guard) -> usize {
-        let start_key = usize::from(*start);
-        let end_key = usize::from(*end);
-        let start_bytes: [u8; 8] = start_key.to_be_bytes();
-        let end_bytes: [u8; 8] = end_key.to_be_bytes();
-
-        // Create temporary buffer for (key, value) pairs
-        let mut temp_result: Vec<([u8; 8], usize)> = vec![([0; 8], 0); result.len()];
-        let scanned = self
-            .inner
-            .range(&start_bytes, &end_bytes, &mut temp_result, guard);
-
-        // Convert the keys back to K type
-        for (i, (key_bytes, _)) in temp_result.iter().enumerate().take(scanned) {
-            result[i] = K::from(usize::from_be_bytes(*key_bytes));
-        }
+        let start_bytes = start.to_key_bytes();
+        let end_bytes = end.to_key_bytes();
+        let mut result = Converted::new(result, |k, _v| K::from_key_bytes(k));
+        self.inner
+            .range(&start_bytes, &end_bytes, &mut result, guard)
+    }
+
+    /// Same as [range](Self::range), but takes any [RangeBounds], so each end can be included, excluded or unbounded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(1, &guard).unwrap();
+    /// set.insert(3, &guard).unwrap();
+    /// set.insert(usize::MAX, &guard).unwrap();
+    ///
+    /// let mut result = [0; 3];
+    /// assert_eq!(set.range_bounds(1..=3, &mut result, &guard), 2);
+    /// assert_eq!(result[..2], [1, 3]);
+    ///
+    /// assert_eq!(set.range_bounds(3.., &mut result, &guard), 2);
+    /// assert_eq!(result[..2], [3, usize::MAX]);
+    /// ```
+    #[inline]
+    pub fn range_bounds<R: RangeBounds<K>>(
+        &self,
+        range: R,
+        result: &mut [K],
+        guard: &epoch::Guard,
+    ) -> usize {
+        self.scan_encoded_bounds(
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            result,
+            guard,
+        )
+    }
+
+    fn scan_encoded_bounds(
+        &self,
+        start: Bound<[u8; K_LEN]>,
+        end: Bound<[u8; K_LEN]>,
+        result: &mut [K],
+        guard: &epoch::Guard,
+    ) -> usize {
+        let mut result = Converted::new(result, |k, _v| K::from_key_bytes(k));
+        self.inner.range_bounds(start, end, &mut result, guard)
+    }
+
+    /// Returns the number of keys within `range`, without copying them out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// for i in 0..100 {
+    ///     set.insert(i, &guard).unwrap();
+    /// }
+    ///
+    /// assert_eq!(set.count_range(10..20, &guard), 10);
+    /// assert_eq!(set.count_range(..=10, &guard), 11);
+    /// assert_eq!(set.count_range(.., &guard), 100);
+    /// ```
+    pub fn count_range<R: RangeBounds<K>>(&self, range: R, guard: &epoch::Guard) -> usize {
+        self.inner.count_range(
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+            guard,
+        )
+    }
+
+    /// Scans the keys whose first `prefix_len` encoded bytes equal those of `prefix`, in ascending order.
+    /// Writes them to `result` and returns the number of keys scanned, at most the length of `result`.
+    ///
+    /// The scan descends straight to the subtree holding the prefix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<u64>::default();
+    /// let guard = set.pin();
+    /// for k in [0x0a00_0000_0000_0001, 0x0a00_0000_0000_0002, 0x0b00_0000_0000_0001] {
+    ///     set.insert(k, &guard).unwrap();
+    /// }
+    ///
+    /// let mut result = [0; 4];
+    /// assert_eq!(set.scan_prefix(&0x0a00_0000_0000_0000, 1, &mut result, &guard), 2);
+    /// assert_eq!(&result[..2], &[0x0a00_0000_0000_0001, 0x0a00_0000_0000_0002]);
+    /// ```
+    pub fn scan_prefix(
+        &self,
+        prefix: &K,
+        prefix_len: usize,
+        result: &mut [K],
+        guard: &epoch::Guard,
+    ) -> usize {
+        let mut result = Converted::new(result, |k, _v| K::from_key_bytes(k));
+        self.inner
+            .scan_prefix(&prefix.to_key_bytes(), prefix_len, &mut result, guard)
+    }
+
+    /// Returns the number of keys whose first `prefix_len` encoded bytes equal those of `prefix`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<u64>::default();
+    /// let guard = set.pin();
+    /// for k in [0x0a00_0000_0000_0001, 0x0a00_0000_0000_0002, 0x0b00_0000_0000_0001] {
+    ///     set.insert(k, &guard).unwrap();
+    /// }
+    /// assert_eq!(set.count_prefix(&0x0b00_0000_0000_0000, 1, &guard), 1);
+    /// ```
+    pub fn count_prefix(&self, prefix: &K, prefix_len: usize, guard: &epoch::Guard) -> usize {
+        self.inner
+            .count_prefix(&prefix.to_key_bytes(), prefix_len, guard)
+    }
+
+    /// Removes all keys whose first `prefix_len` encoded bytes equal those of `prefix`,
+    /// returns the number of removed keys.
+    /// The removal is atomic like [remove_range](Self::remove_range).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than the key length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<u64>::default();
+    /// let guard = set.pin();
+    /// for k in [0x0a00_0000_0000_0001, 0x0a00_0000_0000_0002, 0x0b00_0000_0000_0001] {
+    ///     set.insert(k, &guard).unwrap();
+    /// }
+    /// assert_eq!(set.remove_prefix(&0x0a00_0000_0000_0000, 1, &guard), 2);
+    /// assert_eq!(set.iter(&guard).collect::<Vec<_>>(), vec![0x0b00_0000_0000_0001]);
+    /// ```
+    pub fn remove_prefix(&self, prefix: &K, prefix_len: usize, guard: &epoch::Guard) -> usize {
+        let (start, end) = prefix_bounds(prefix, prefix_len);
+        self.inner.remove_range(start, end, guard)
+    }
+
+    /// Same as [range](Self::range), but the keys are scanned in descending order,
+    /// so the buffer holds the largest keys below `end`, largest first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    ///
+    /// set.insert(1, &guard).unwrap();
+    /// set.insert(3, &guard).unwrap();
+    /// set.insert(5, &guard).unwrap();
+    ///
+    /// let mut result = [0; 2];
+    /// let scanned = set.range_rev(&0, &5, &mut result, &guard);
+    /// assert_eq!(scanned, 2);
+    /// assert_eq!(result, [3, 1]);
+    /// ```
+    #[inline]
+    pub fn range_rev(&self, start: &K, end: &K, result: &mut [K], guard: &epoch::Guard) -> usize {
+        let start_bytes = start.to_key_bytes();
+        let end_bytes = end.to_key_bytes();
+
+        let mut result = Converted::new(result, |k, _v| K::from_key_bytes(k));
+        self.inner
+            .range_rev(&start_bytes, &end_bytes, &mut result, guard)
+    }
+
+    /// Returns the smallest key, `None` if the set is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// assert_eq!(set.first(&guard), None);
+    ///
+    /// set.insert(3, &guard).unwrap();
+    /// set.insert(1, &guard).unwrap();
+    /// assert_eq!(set.first(&guard), Some(1));
+    /// ```
+    #[inline]
+    pub fn first(&self, guard: &epoch::Guard) -> Option<K> {
+        self.inner.first(guard).map(|(k, _)| K::from_key_bytes(k))
+    }
+
+    /// Returns the largest key, `None` if the set is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(3, &guard).unwrap();
+    /// set.insert(1, &guard).unwrap();
+    /// assert_eq!(set.last(&guard), Some(3));
+    /// ```
+    #[inline]
+    pub fn last(&self, guard: &epoch::Guard) -> Option<K> {
+        self.inner.last(guard).map(|(k, _)| K::from_key_bytes(k))
+    }
+
+    /// Returns the smallest key strictly greater than `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(1, &guard).unwrap();
+    /// set.insert(3, &guard).unwrap();
+    ///
+    /// assert_eq!(set.successor(&1, &guard), Some(3));
+    /// assert_eq!(set.successor(&3, &guard), None);
+    /// ```
+    #[inline]
+    pub fn successor(&self, key: &K, guard: &epoch::Guard) -> Option<K> {
+        let key = key.to_key_bytes();
+        self.inner
+            .successor(&key, guard)
+            .map(|(k, _)| K::from_key_bytes(k))
+    }
+
+    /// Returns the largest key strictly less than `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(1, &guard).unwrap();
+    /// set.insert(3, &guard).unwrap();
+    ///
+    /// assert_eq!(set.predecessor(&3, &guard), Some(1));
+    /// assert_eq!(set.predecessor(&1, &guard), None);
+    /// ```
+    #[inline]
+    pub fn predecessor(&self, key: &K, guard: &epoch::Guard) -> Option<K> {
+        let key = key.to_key_bytes();
+        self.inner
+            .predecessor(&key, guard)
+            .map(|(k, _)| K::from_key_bytes(k))
+    }
 
-        scanned
+    /// Returns the largest key less than or equal to `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(1, &guard).unwrap();
+    /// set.insert(3, &guard).unwrap();
+    ///
+    /// assert_eq!(set.floor(&2, &guard), Some(1));
+    /// assert_eq!(set.floor(&3, &guard), Some(3));
+    /// assert_eq!(set.floor(&0, &guard), None);
+    /// ```
+    #[inline]
+    pub fn floor(&self, key: &K, guard: &epoch::Guard) -> Option<K> {
+        let key = key.to_key_bytes();
+        self.inner
+            .floor(&key, guard)
+            .map(|(k, _)| K::from_key_bytes(k))
+    }
+
+    /// Returns the smallest key greater than or equal to `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::CongeeSet;
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(1, &guard).unwrap();
+    /// set.insert(3, &guard).unwrap();
+    ///
+    /// assert_eq!(set.ceiling(&2, &guard), Some(3));
+    /// assert_eq!(set.ceiling(&1, &guard), Some(1));
+    /// assert_eq!(set.ceiling(&4, &guard), None);
+    /// ```
+    #[inline]
+    pub fn ceiling(&self, key: &K, guard: &epoch::Guard) -> Option<K> {
+        let key = key.to_key_bytes();
+        self.inner
+            .ceiling(&key, guard)
+            .map(|(k, _)| K::from_key_bytes(k))
     }
 
     /// Display the internal node statistics.
@@ -273,11 +848,47 @@ where
     pub fn allocator(&self) -> &A {
         self.inner.allocator()
     }
+}
 
+impl<K: KeyEncoding<8>, A: Allocator + Clone + Send> CongeeSet<K, A, 8> {
     /// Serializes the current tree into a compact v2 binary format
     pub fn to_compact_set(&self) -> Vec<u8> {
         self.inner.to_compact_set()
     }
+
+    /// Rebuilds a mutable set from a compact set.
+    ///
+    /// The nodes are built directly from the compact nodes, without inserting the keys one by one.
+    /// Data that is not a valid compact set fails with [CompactSetError::Malformed], nothing is left allocated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{CongeeCompactSet, CongeeSet, DefaultAllocator};
+    /// let set = CongeeSet::<usize>::default();
+    /// let guard = set.pin();
+    /// set.insert(42, &guard).unwrap();
+    ///
+    /// let data = set.to_compact_set();
+    /// let compact = CongeeCompactSet::<usize>::new(&data);
+    /// let loaded = CongeeSet::<usize>::from_compact_set(DefaultAllocator {}, &compact).unwrap();
+    /// assert!(loaded.contains(&42, &guard));
+    /// loaded.insert(7, &guard).unwrap();
+    /// assert_eq!(loaded.len(&guard), 2);
+    /// ```
+    pub fn from_compact_set(
+        allocator: A,
+        compact: &CongeeCompactSet<K>,
+    ) -> Result<Self, CompactSetError>
+    where
+        K: Copy + From<usize>,
+        usize: From<K>,
+    {
+        Ok(CongeeSet {
+            inner: CongeeInner::from_compact_set(allocator, Arc::new(|_k, _v| {}), compact)?,
+            pt_key: PhantomData,
+        })
+    }
 }
 
 #[cfg(test)]
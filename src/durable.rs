@@ -0,0 +1,629 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Write},
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use crate::{
+    Allocator, CongeeRaw, CongeeRawIter, DefaultAllocator, KeyEncoding, checkpoint, epoch,
+    error::{CheckpointError, DurableError},
+    utils::crc32,
+};
+
+const CHECKPOINT: &str = "checkpoint";
+const CHECKPOINT_TMP: &str = "checkpoint.tmp";
+const SEGMENT_PREFIX: &str = "wal-";
+const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1 << 20;
+/// The number of locks that order the writes to the keys hashed to them.
+const KEY_LOCKS: usize = 64;
+
+const PUT: u8 = 0;
+const DELETE: u8 = 1;
+/// The length and the checksum of the payload.
+const RECORD_HEADER_LEN: usize = 8;
+
+/// Log records not yet written to the log, in sequence order.
+struct Sequencer {
+    buffer: Vec<u8>,
+    last_lsn: u64,
+}
+
+/// The log segment that receives new records.
+struct Segment {
+    file: File,
+    number: u64,
+}
+
+/// A [CongeeRaw] whose writes survive crashes.
+///
+/// Every write that changes the tree appends its outcome, the new value of the key or its removal,
+/// to an append-only log in `dir` and returns only after the log is synced to disk.
+/// Concurrent writers share a sync (group commit), so the cost of a sync is spread over all writes waiting for it.
+///
+/// Every `checkpoint_interval` logged writes, and on [checkpoint](Self::checkpoint), the tree is written to
+/// a checkpoint file and the log before it is deleted.
+/// [open](Self::open) loads the last checkpoint and replays the log after it. A damaged or incomplete record at the end
+/// of the log, left by a crash in the middle of a write, is cut off; such writes were never acknowledged.
+/// A damaged record followed by valid ones fails [open](Self::open) with [DurableError::CorruptedLog].
+///
+/// Writes to different keys run concurrently, writes to the same key are ordered by a lock that the key shares with
+/// the other keys hashed to it. Writes are visible to readers before they are durable.
+pub struct DurableCongee<
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static = DefaultAllocator,
+    const K_LEN: usize = 8,
+> where
+    usize: From<V>,
+{
+    tree: CongeeRaw<K, V, A, K_LEN>,
+    dir: PathBuf,
+    key_locks: Box<[Mutex<()>]>,
+    sequencer: Mutex<Sequencer>,
+    segment: Mutex<Segment>,
+    durable_lsn: AtomicU64,
+    checkpoint_lsn: AtomicU64,
+    checkpoint_interval: u64,
+    checkpointing: Mutex<()>,
+    poisoned: AtomicBool,
+}
+
+/// Locks the mutex, a panic while it was held leaves nothing half done.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Makes the creation, removal and renaming of files in `dir` durable.
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+fn segment_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{SEGMENT_PREFIX}{number:016x}"))
+}
+
+/// The numbers of the log segments in `dir`, in ascending order.
+fn list_segments(dir: &Path) -> std::io::Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let number = name
+            .to_str()
+            .and_then(|n| n.strip_prefix(SEGMENT_PREFIX))
+            .and_then(|n| u64::from_str_radix(n, 16).ok());
+        if let Some(number) = number {
+            segments.push(number);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+fn create_segment(dir: &Path, number: u64) -> std::io::Result<File> {
+    let file = OpenOptions::new()
+        .create_new(true)
+        .append(true)
+        .open(segment_path(dir, number))?;
+    sync_dir(dir)?;
+    Ok(file)
+}
+
+fn encode_record<const K_LEN: usize>(
+    buffer: &mut Vec<u8>,
+    lsn: u64,
+    key: &[u8; K_LEN],
+    value: Option<usize>,
+) {
+    let mut payload = Vec::with_capacity(8 + 1 + K_LEN + 8);
+    payload.extend_from_slice(&lsn.to_le_bytes());
+    match value {
+        Some(v) => {
+            payload.push(PUT);
+            payload.extend_from_slice(key);
+            payload.extend_from_slice(&(v as u64).to_le_bytes());
+        }
+        None => {
+            payload.push(DELETE);
+            payload.extend_from_slice(key);
+        }
+    }
+    buffer.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&crc32(&payload).to_le_bytes());
+    buffer.extend_from_slice(&payload);
+}
+
+/// The length, sequence number, key and new value of a log record, `None` for a removal.
+type Record<const K_LEN: usize> = (usize, u64, [u8; K_LEN], Option<usize>);
+
+/// The outcome of decoding a log record.
+enum Decoded<const K_LEN: usize> {
+    Record(Record<K_LEN>),
+    /// The record runs past the end of the data, as a write cut short by a crash leaves it.
+    Incomplete,
+    /// The record fails its checksum or holds an impossible length or operation. A crash can leave this too,
+    /// when the file grew before the record's bytes reached the disk.
+    Damaged,
+}
+
+/// Decodes the record at the start of `data`.
+fn decode_record<const K_LEN: usize>(data: &[u8]) -> Decoded<K_LEN> {
+    let Some(header) = data.get(..RECORD_HEADER_LEN) else {
+        return Decoded::Incomplete;
+    };
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if len != 8 + 1 + K_LEN && len != 8 + 1 + K_LEN + 8 {
+        return Decoded::Damaged;
+    }
+    let Some(payload) = data.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len) else {
+        return Decoded::Incomplete;
+    };
+    if crc32(payload) != crc {
+        return Decoded::Damaged;
+    }
+    let lsn = u64::from_le_bytes(payload[..8].try_into().unwrap());
+    let key: [u8; K_LEN] = payload[9..9 + K_LEN].try_into().unwrap();
+    let value = match (payload[8], len == 8 + 1 + K_LEN) {
+        (PUT, false) => {
+            match usize::try_from(u64::from_le_bytes(payload[9 + K_LEN..].try_into().unwrap())) {
+                Ok(v) => Some(v),
+                Err(_) => return Decoded::Damaged,
+            }
+        }
+        (DELETE, true) => None,
+        _ => return Decoded::Damaged,
+    };
+    Decoded::Record((RECORD_HEADER_LEN + len, lsn, key, value))
+}
+
+/// Whether a valid record starts anywhere in `data`, so the log goes on past a damaged record.
+fn valid_record_in<const K_LEN: usize>(data: &[u8]) -> bool {
+    (0..data.len()).any(|at| matches!(decode_record::<K_LEN>(&data[at..]), Decoded::Record(_)))
+}
+
+/// The index of the lock that orders the writes to `key`.
+fn key_lock_index(key: &[u8]) -> usize {
+    key.iter()
+        .fold(0usize, |h, &b| h.wrapping_mul(31).wrapping_add(b as usize))
+        % KEY_LOCKS
+}
+
+impl<K: KeyEncoding<K_LEN>, V: Copy + From<usize>, A: Allocator + Clone + Send, const K_LEN: usize>
+    DurableCongee<K, V, A, K_LEN>
+where
+    usize: From<V>,
+{
+    /// Opens the tree stored in `dir`, creating the directory if it doesn't exist.
+    ///
+    /// The last checkpoint is loaded and the log after it is replayed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{DefaultAllocator, DurableCongee};
+    /// let dir = std::env::temp_dir().join(format!("congee-doc-open-{}", std::process::id()));
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    ///
+    /// let tree = DurableCongee::<usize, usize>::open(&dir, DefaultAllocator {}).unwrap();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    /// drop(tree);
+    ///
+    /// let tree = DurableCongee::<usize, usize>::open(&dir, DefaultAllocator {}).unwrap();
+    /// assert_eq!(tree.get(&1, &tree.pin()), Some(42));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn open(dir: impl AsRef<Path>, allocator: A) -> Result<Self, DurableError> {
+        Self::open_with_checkpoint_interval(dir, allocator, DEFAULT_CHECKPOINT_INTERVAL)
+    }
+
+    /// Opens the tree stored in `dir` like [open](Self::open),
+    /// checkpointing it after every `checkpoint_interval` logged writes, or never if it is zero.
+    pub fn open_with_checkpoint_interval(
+        dir: impl AsRef<Path>,
+        allocator: A,
+        checkpoint_interval: u64,
+    ) -> Result<Self, DurableError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        // Left by a crash in the middle of a checkpoint, the previous checkpoint is still complete.
+        match fs::remove_file(dir.join(CHECKPOINT_TMP)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        let (tree, checkpoint_lsn) = match File::open(dir.join(CHECKPOINT)) {
+            Ok(file) => {
+                let mut reader = BufReader::new(file);
+                let mut header = [0u8; 12];
+                reader
+                    .read_exact(&mut header)
+                    .map_err(CheckpointError::from)?;
+                let lsn = u64::from_le_bytes(header[..8].try_into().unwrap());
+                if crc32(&header[..8]) != u32::from_le_bytes(header[8..].try_into().unwrap()) {
+                    return Err(DurableError::Checkpoint(CheckpointError::CorruptedHeader));
+                }
+                (CongeeRaw::read_snapshot(allocator, reader)?, lsn)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (CongeeRaw::new(allocator), 0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let guard = tree.pin();
+        let segments = list_segments(&dir)?;
+        let mut last_lsn = checkpoint_lsn;
+        for (i, number) in segments.iter().enumerate() {
+            let path = segment_path(&dir, *number);
+            let data = fs::read(&path)?;
+            let mut at = 0;
+            while at < data.len() {
+                let (len, lsn, key, value) = match decode_record::<K_LEN>(&data[at..]) {
+                    Decoded::Record(record) => record,
+                    Decoded::Incomplete | Decoded::Damaged
+                        if i + 1 == segments.len()
+                            && !valid_record_in::<K_LEN>(&data[at + 1..]) =>
+                    {
+                        // A torn write at the end of the log, cut it off so new records follow the valid ones.
+                        let file = OpenOptions::new().write(true).open(&path)?;
+                        file.set_len(at as u64)?;
+                        file.sync_all()?;
+                        break;
+                    }
+                    _ => {
+                        return Err(DurableError::CorruptedLog {
+                            segment: *number,
+                            offset: at as u64,
+                        });
+                    }
+                };
+                if lsn > last_lsn {
+                    match value {
+                        Some(v) => {
+                            tree.insert(K::from_key_bytes(key), V::from(v), &guard)?;
+                        }
+                        None => {
+                            tree.remove(&K::from_key_bytes(key), &guard);
+                        }
+                    }
+                    last_lsn = lsn;
+                }
+                at += len;
+            }
+        }
+
+        // New records follow the valid ones of the last segment.
+        let segment = match segments.last() {
+            Some(&number) => Segment {
+                file: OpenOptions::new()
+                    .append(true)
+                    .open(segment_path(&dir, number))?,
+                number,
+            },
+            None => Segment {
+                file: create_segment(&dir, 0)?,
+                number: 0,
+            },
+        };
+        drop(guard);
+        Ok(Self {
+            tree,
+            dir,
+            key_locks: (0..KEY_LOCKS).map(|_| Mutex::new(())).collect(),
+            sequencer: Mutex::new(Sequencer {
+                buffer: Vec::new(),
+                last_lsn,
+            }),
+            segment: Mutex::new(segment),
+            durable_lsn: AtomicU64::new(last_lsn),
+            checkpoint_lsn: AtomicU64::new(checkpoint_lsn),
+            checkpoint_interval,
+            checkpointing: Mutex::new(()),
+            poisoned: AtomicBool::new(false),
+        })
+    }
+
+    /// Enters an epoch.
+    /// Note: this can be expensive, try to reuse it.
+    pub fn pin(&self) -> epoch::Guard {
+        self.tree.pin()
+    }
+
+    /// Returns a copy of the value corresponding to the key.
+    pub fn get(&self, key: &K, guard: &epoch::Guard) -> Option<V> {
+        self.tree.get(key, guard)
+    }
+
+    /// Returns the number of keys in the tree.
+    pub fn len(&self, guard: &epoch::Guard) -> usize {
+        self.tree.len(guard)
+    }
+
+    /// Returns true if the tree has no keys.
+    pub fn is_empty(&self, guard: &epoch::Guard) -> bool {
+        self.tree.is_empty(guard)
+    }
+
+    /// Iterates over all entries in ascending key order.
+    pub fn iter<'a>(&'a self, guard: &'a epoch::Guard) -> CongeeRawIter<'a, K, V, A, K_LEN> {
+        self.tree.iter(guard)
+    }
+
+    /// Iterates over the entries within `range` in ascending key order.
+    pub fn range_iter<'a, R: RangeBounds<K>>(
+        &'a self,
+        range: R,
+        guard: &'a epoch::Guard,
+    ) -> CongeeRawIter<'a, K, V, A, K_LEN> {
+        self.tree.range_iter(range, guard)
+    }
+
+    /// Inserts a key-value pair durably, returns the previous value if the key was already present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{DefaultAllocator, DurableCongee};
+    /// let dir = std::env::temp_dir().join(format!("congee-doc-insert-{}", std::process::id()));
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    ///
+    /// let tree = DurableCongee::<usize, usize>::open(&dir, DefaultAllocator {}).unwrap();
+    /// let guard = tree.pin();
+    /// assert_eq!(tree.insert(1, 42, &guard).unwrap(), None);
+    /// assert_eq!(tree.insert(1, 43, &guard).unwrap(), Some(42));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn insert(&self, k: K, v: V, guard: &epoch::Guard) -> Result<Option<V>, DurableError> {
+        let key = k.to_key_bytes();
+        let old = self.logged(&key, guard, || match self.tree.insert(k, v, guard) {
+            Ok(old) => (Ok(old), Some(Some(usize::from(v)))),
+            Err(e) => (Err(e), None),
+        })?;
+        Ok(old?)
+    }
+
+    /// Removes a key durably, returns the removed value if the key was present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{DefaultAllocator, DurableCongee};
+    /// let dir = std::env::temp_dir().join(format!("congee-doc-remove-{}", std::process::id()));
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    ///
+    /// let tree = DurableCongee::<usize, usize>::open(&dir, DefaultAllocator {}).unwrap();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    /// assert_eq!(tree.remove(&1, &guard).unwrap(), Some(42));
+    /// assert_eq!(tree.remove(&1, &guard).unwrap(), None);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn remove(&self, k: &K, guard: &epoch::Guard) -> Result<Option<V>, DurableError> {
+        let key = k.to_key_bytes();
+        self.logged(&key, guard, || {
+            let old = self.tree.remove(k, guard);
+            (old, old.map(|_| None))
+        })
+    }
+
+    /// Updates the value durably if the current value matches `old`, like [CongeeRaw::compare_exchange].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{DefaultAllocator, DurableCongee};
+    /// let dir = std::env::temp_dir().join(format!("congee-doc-cas-{}", std::process::id()));
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    ///
+    /// let tree = DurableCongee::<usize, usize>::open(&dir, DefaultAllocator {}).unwrap();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    /// assert_eq!(tree.compare_exchange(&1, &42, Some(43), &guard).unwrap(), Ok(Some(43)));
+    /// assert_eq!(tree.compare_exchange(&1, &42, None, &guard).unwrap(), Err(Some(43)));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn compare_exchange(
+        &self,
+        key: &K,
+        old: &V,
+        new: Option<V>,
+        guard: &epoch::Guard,
+    ) -> Result<Result<Option<V>, Option<V>>, DurableError> {
+        let k = key.to_key_bytes();
+        self.logged(&k, guard, || {
+            let r = self.tree.compare_exchange(key, old, new, guard);
+            let change = r.is_ok().then(|| new.map(usize::from));
+            (r, change)
+        })
+    }
+
+    /// Computes a new value for a key durably if it exists, like [CongeeRaw::compute_if_present].
+    ///
+    /// `f` runs while holding the lock of the key, writes to the keys that share the lock wait for it, so it should be short.
+    /// `f` must not write to this tree: such a write may need the same lock and deadlock.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{DefaultAllocator, DurableCongee};
+    /// let dir = std::env::temp_dir().join(format!("congee-doc-compute-{}", std::process::id()));
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    ///
+    /// let tree = DurableCongee::<usize, usize>::open(&dir, DefaultAllocator {}).unwrap();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    /// let r = tree.compute_if_present(&1, |v| Some(v + 1), &guard).unwrap();
+    /// assert_eq!(r, Some((42, Some(43))));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn compute_if_present<F>(
+        &self,
+        key: &K,
+        f: F,
+        guard: &epoch::Guard,
+    ) -> Result<Option<(usize, Option<usize>)>, DurableError>
+    where
+        F: FnMut(usize) -> Option<usize>,
+    {
+        let k = key.to_key_bytes();
+        self.logged(&k, guard, || {
+            let r = self.tree.compute_if_present(key, f, guard);
+            (r, r.map(|(_old, new)| new))
+        })
+    }
+
+    /// Writes the tree to a new checkpoint and deletes the log it covers.
+    ///
+    /// Writers only wait while the log switches to a new segment, the checkpoint is written from a traversal
+    /// of the live tree afterwards. Writes that run meanwhile may or may not be in it, they are in the log after it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use congee::{DefaultAllocator, DurableCongee};
+    /// let dir = std::env::temp_dir().join(format!("congee-doc-checkpoint-{}", std::process::id()));
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    ///
+    /// let tree = DurableCongee::<usize, usize>::open(&dir, DefaultAllocator {}).unwrap();
+    /// let guard = tree.pin();
+    /// tree.insert(1, 42, &guard).unwrap();
+    /// tree.checkpoint(&guard).unwrap();
+    /// drop(tree);
+    ///
+    /// let tree = DurableCongee::<usize, usize>::open(&dir, DefaultAllocator {}).unwrap();
+    /// assert_eq!(tree.get(&1, &tree.pin()), Some(42));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn checkpoint(&self, guard: &epoch::Guard) -> Result<(), DurableError> {
+        let _checkpointing = lock(&self.checkpointing);
+        self.checkpoint_locked(guard)
+    }
+
+    fn checkpoint_locked(&self, guard: &epoch::Guard) -> Result<(), DurableError> {
+        self.check_poisoned()?;
+        // Switch to a new segment, so the older segments hold exactly the records up to `lsn`.
+        // Every such write is applied to the tree before the traversal starts, every later one is replayed after
+        // the checkpoint; records hold the new value of their key, so replaying a write the traversal saw is harmless.
+        let (lsn, old_segment) = {
+            let mut segment = lock(&self.segment);
+            let mut sequencer = lock(&self.sequencer);
+            let lsn = sequencer.last_lsn;
+            let buffer = std::mem::take(&mut sequencer.buffer);
+            drop(sequencer);
+
+            self.flush(&mut segment.file, &buffer)?;
+            self.durable_lsn.store(lsn, Ordering::Release);
+            let number = segment.number + 1;
+            let file = create_segment(&self.dir, number)?;
+            let old = std::mem::replace(&mut *segment, Segment { file, number });
+            (lsn, old.number)
+        };
+
+        let tmp = self.dir.join(CHECKPOINT_TMP);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        writer.write_all(&lsn.to_le_bytes())?;
+        writer.write_all(&crc32(&lsn.to_le_bytes()).to_le_bytes())?;
+        checkpoint::write(
+            &mut writer,
+            checkpoint::Kind::Map,
+            self.tree
+                .iter(guard)
+                .map(|(k, v)| (k.to_key_bytes(), usize::from(v))),
+        )?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, self.dir.join(CHECKPOINT))?;
+        sync_dir(&self.dir)?;
+        self.checkpoint_lsn.store(lsn, Ordering::Release);
+
+        for number in list_segments(&self.dir)? {
+            if number <= old_segment {
+                fs::remove_file(segment_path(&self.dir, number))?;
+            }
+        }
+        sync_dir(&self.dir)?;
+        Ok(())
+    }
+
+    /// Runs `op` on the tree and appends the change it reports to the log, then waits until the log is durable.
+    ///
+    /// `op` returns its result and the new value of `key` if it changed the tree, `Some(None)` for a removal.
+    /// It runs under the lock of `key`, which is held until the change is in the log, so the log has the changes
+    /// to a key in the order they were applied. Changes to different keys commute, as each record holds a new value.
+    fn logged<T>(
+        &self,
+        key: &[u8; K_LEN],
+        guard: &epoch::Guard,
+        op: impl FnOnce() -> (T, Option<Option<usize>>),
+    ) -> Result<T, DurableError> {
+        self.check_poisoned()?;
+        let (result, lsn) = {
+            let _key_lock = lock(&self.key_locks[key_lock_index(key)]);
+            let (result, change) = op();
+            let mut sequencer = lock(&self.sequencer);
+            if let Some(value) = change {
+                sequencer.last_lsn += 1;
+                let lsn = sequencer.last_lsn;
+                encode_record(&mut sequencer.buffer, lsn, key, value);
+            }
+            // Even without a change, the result may depend on writes that are not durable yet.
+            (result, sequencer.last_lsn)
+        };
+        self.wait_durable(lsn)?;
+
+        let checkpoint_lsn = self.checkpoint_lsn.load(Ordering::Acquire);
+        if self.checkpoint_interval > 0
+            && lsn.saturating_sub(checkpoint_lsn) >= self.checkpoint_interval
+            && let Ok(_checkpointing) = self.checkpointing.try_lock()
+        {
+            // The write is already durable, a failed checkpoint is retried by a later write.
+            let _ = self.checkpoint_locked(guard);
+        }
+        Ok(result)
+    }
+
+    /// Waits until the record `lsn` is on disk, syncing all pending records if no other writer is doing it.
+    fn wait_durable(&self, lsn: u64) -> Result<(), DurableError> {
+        while self.durable_lsn.load(Ordering::Acquire) < lsn {
+            let mut segment = lock(&self.segment);
+            if self.durable_lsn.load(Ordering::Acquire) >= lsn {
+                break;
+            }
+            self.check_poisoned()?;
+            let (buffer, last_lsn) = {
+                let mut sequencer = lock(&self.sequencer);
+                (std::mem::take(&mut sequencer.buffer), sequencer.last_lsn)
+            };
+            self.flush(&mut segment.file, &buffer)?;
+            self.durable_lsn.store(last_lsn, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    fn flush(&self, file: &mut File, buffer: &[u8]) -> Result<(), DurableError> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = file.write_all(buffer).and_then(|_| file.sync_data()) {
+            self.poisoned.store(true, Ordering::Release);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn check_poisoned(&self) -> Result<(), DurableError> {
+        if self.poisoned.load(Ordering::Acquire) {
+            return Err(DurableError::Poisoned);
+        }
+        Ok(())
+    }
+}
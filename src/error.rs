@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
 
 #[derive(Debug)]
 pub(crate) enum ArtError {
@@ -30,3 +31,188 @@ impl Display for OOMError {
 }
 
 impl Error for OOMError {}
+
+/// The reasons a [Transaction](crate::Transaction) fails to commit, the tree is left unchanged either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionError {
+    /// A key read by the transaction has changed since, the transaction can be retried.
+    Conflict,
+    /// The allocator is out of memory.
+    Oom,
+}
+
+impl Display for TransactionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::Conflict => write!(f, "A key read by the transaction has changed"),
+            TransactionError::Oom => write!(f, "Allocator is out of memory!"),
+        }
+    }
+}
+
+impl Error for TransactionError {}
+
+/// The reasons a [CongeeCompactSet](crate::CongeeCompactSet) can't be rebuilt into a [CongeeSet](crate::CongeeSet).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactSetError {
+    /// The data is not a valid compact set, at the offset of the first bad node.
+    Malformed { offset: usize },
+    /// The allocator is out of memory.
+    Oom,
+}
+
+impl Display for CompactSetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CompactSetError::Malformed { offset } => {
+                write!(f, "Malformed compact set node at offset {offset}")
+            }
+            CompactSetError::Oom => write!(f, "Allocator is out of memory!"),
+        }
+    }
+}
+
+impl Error for CompactSetError {}
+
+/// The reasons a checkpoint can't be written or read back.
+#[derive(Debug)]
+pub enum CheckpointError {
+    /// The underlying reader or writer failed.
+    Io(io::Error),
+    /// The input ended before the trailer that closes the checkpoint.
+    Truncated,
+    /// The input does not start with the checkpoint magic bytes.
+    BadMagic,
+    /// The checkpoint was written by an unsupported version of the format.
+    UnsupportedVersion(u32),
+    /// The checkpoint holds a different kind of tree, such as a set read as a map.
+    KindMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The checkpoint was written by a tree with a different key length.
+    KeyLengthMismatch { expected: usize, found: usize },
+    /// The checksum of the header doesn't match its content.
+    CorruptedHeader,
+    /// The checksum of a block doesn't match its content, blocks are counted from zero.
+    CorruptedBlock { block: u64 },
+    /// The checksum of the trailer after the last block doesn't match its content.
+    CorruptedTrailer,
+    /// The content has a valid checksum but is not a valid checkpoint, such as keys out of order.
+    Malformed(&'static str),
+    /// The allocator is out of memory.
+    Oom,
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => CheckpointError::Truncated,
+            _ => CheckpointError::Io(e),
+        }
+    }
+}
+
+impl From<OOMError> for CheckpointError {
+    fn from(_: OOMError) -> Self {
+        CheckpointError::Oom
+    }
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "Checkpoint I/O failed: {e}"),
+            CheckpointError::Truncated => write!(f, "Checkpoint is truncated"),
+            CheckpointError::BadMagic => write!(f, "Not a checkpoint, the magic bytes don't match"),
+            CheckpointError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported checkpoint format version {v}")
+            }
+            CheckpointError::KindMismatch { expected, found } => {
+                write!(f, "Expected a checkpoint of a {expected}, found a {found}")
+            }
+            CheckpointError::KeyLengthMismatch { expected, found } => write!(
+                f,
+                "Expected a checkpoint with {expected} byte keys, found {found} byte keys"
+            ),
+            CheckpointError::CorruptedHeader => write!(f, "Checkpoint header checksum mismatch"),
+            CheckpointError::CorruptedBlock { block } => {
+                write!(f, "Checkpoint block {block} checksum mismatch")
+            }
+            CheckpointError::CorruptedTrailer => write!(f, "Checkpoint trailer checksum mismatch"),
+            CheckpointError::Malformed(reason) => write!(f, "Malformed checkpoint: {reason}"),
+            CheckpointError::Oom => write!(f, "Allocator is out of memory!"),
+        }
+    }
+}
+
+impl Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CheckpointError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The reasons a [DurableCongee](crate::DurableCongee) fails to open or to make a write durable.
+#[derive(Debug)]
+pub enum DurableError {
+    /// Reading or writing the log or the checkpoint failed.
+    Io(io::Error),
+    /// The checkpoint can't be read back.
+    Checkpoint(CheckpointError),
+    /// A log record is damaged where no crash can leave one, before valid records or in a segment other than the last,
+    /// at the byte offset of the first bad record.
+    CorruptedLog { segment: u64, offset: u64 },
+    /// An earlier write to the log failed, later writes are refused since the log may have lost records.
+    Poisoned,
+    /// The allocator is out of memory.
+    Oom,
+}
+
+impl From<io::Error> for DurableError {
+    fn from(e: io::Error) -> Self {
+        DurableError::Io(e)
+    }
+}
+
+impl From<CheckpointError> for DurableError {
+    fn from(e: CheckpointError) -> Self {
+        match e {
+            CheckpointError::Io(e) => DurableError::Io(e),
+            CheckpointError::Oom => DurableError::Oom,
+            e => DurableError::Checkpoint(e),
+        }
+    }
+}
+
+impl From<OOMError> for DurableError {
+    fn from(_: OOMError) -> Self {
+        DurableError::Oom
+    }
+}
+
+impl Display for DurableError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DurableError::Io(e) => write!(f, "Log I/O failed: {e}"),
+            DurableError::Checkpoint(e) => write!(f, "{e}"),
+            DurableError::CorruptedLog { segment, offset } => {
+                write!(f, "Log segment {segment} is corrupted at offset {offset}")
+            }
+            DurableError::Poisoned => write!(f, "An earlier write to the log failed"),
+            DurableError::Oom => write!(f, "Allocator is out of memory!"),
+        }
+    }
+}
+
+impl Error for DurableError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DurableError::Io(e) => Some(e),
+            DurableError::Checkpoint(e) => Some(e),
+            _ => None,
+        }
+    }
+}
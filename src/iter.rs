@@ -0,0 +1,536 @@
+use std::{collections::VecDeque, marker::PhantomData, ops::Bound, sync::Arc};
+
+use crate::{Allocator, CongeeInner, KeyEncoding, congee::clone_entry, epoch};
+
+/// Number of entries fetched from the tree at a time.
+const ITER_BATCH_SIZE: usize = 64;
+
+/// The smallest key greater than `key`, `None` if `key` is the largest key.
+pub(crate) fn key_successor<const K_LEN: usize>(mut key: [u8; K_LEN]) -> Option<[u8; K_LEN]> {
+    for b in key.iter_mut().rev() {
+        if *b == u8::MAX {
+            *b = 0;
+        } else {
+            *b += 1;
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// The largest key smaller than `key`, `None` if `key` is the smallest key.
+pub(crate) fn key_predecessor<const K_LEN: usize>(mut key: [u8; K_LEN]) -> Option<[u8; K_LEN]> {
+    for b in key.iter_mut().rev() {
+        if *b == 0 {
+            *b = u8::MAX;
+        } else {
+            *b -= 1;
+            return Some(key);
+        }
+    }
+    None
+}
+
+pub(crate) fn encode_bound<K: KeyEncoding<K_LEN>, const K_LEN: usize>(
+    bound: Bound<&K>,
+) -> Bound<[u8; K_LEN]> {
+    bound.map(|k| k.to_key_bytes())
+}
+
+/// The bounds of all keys whose first `prefix_len` encoded bytes equal those of `prefix`.
+///
+/// # Panics
+///
+/// Panics if `prefix_len` is larger than the key length.
+pub(crate) fn prefix_bounds<K: KeyEncoding<K_LEN>, const K_LEN: usize>(
+    prefix: &K,
+    prefix_len: usize,
+) -> (Bound<[u8; K_LEN]>, Bound<[u8; K_LEN]>) {
+    let (start, end) = prefix_range(&prefix.to_key_bytes(), prefix_len);
+    (Bound::Included(start), Bound::Included(end))
+}
+
+/// The smallest and largest key whose first `prefix_len` bytes equal those of `prefix`.
+///
+/// # Panics
+///
+/// Panics if `prefix_len` is larger than the key length.
+pub(crate) fn prefix_range<const K_LEN: usize>(
+    prefix: &[u8; K_LEN],
+    prefix_len: usize,
+) -> ([u8; K_LEN], [u8; K_LEN]) {
+    assert!(
+        prefix_len <= K_LEN,
+        "prefix length {prefix_len} is larger than the key length {K_LEN}"
+    );
+    let mut start = *prefix;
+    let mut end = start;
+    start[prefix_len..].fill(0);
+    end[prefix_len..].fill(u8::MAX);
+    (start, end)
+}
+
+/// Converts a pair of bounds to an inclusive `[start, end]` range, `None` if no key falls within the bounds.
+pub(crate) fn inclusive_bounds<const K_LEN: usize>(
+    start: Bound<[u8; K_LEN]>,
+    end: Bound<[u8; K_LEN]>,
+) -> Option<([u8; K_LEN], [u8; K_LEN])> {
+    let start = match start {
+        Bound::Included(k) => Some(k),
+        Bound::Excluded(k) => key_successor(k),
+        Bound::Unbounded => Some([0; K_LEN]),
+    };
+    let end = match end {
+        Bound::Included(k) => Some(k),
+        Bound::Excluded(k) => key_predecessor(k),
+        Bound::Unbounded => Some([u8::MAX; K_LEN]),
+    };
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// Lazily walks the keys within a range in order, from either end.
+///
+/// Entries are fetched in batches with a range scan, the next batch resumes right after the last returned key,
+/// so concurrent modifications never cause a key to be returned twice or out of order.
+/// The front and the back share the range that is not fetched yet, so they never cross.
+pub(crate) struct RawIter<'a, const K_LEN: usize, A: Allocator + Clone + Send + 'static> {
+    tree: &'a CongeeInner<K_LEN, A>,
+    guard: &'a epoch::Guard,
+    /// The inclusive range not fetched yet, `None` if everything is fetched.
+    remaining: Option<([u8; K_LEN], [u8; K_LEN])>,
+    /// Fetched entries in ascending order.
+    front: VecDeque<([u8; K_LEN], usize)>,
+    /// Fetched entries in descending order.
+    back: VecDeque<([u8; K_LEN], usize)>,
+    scratch: Vec<([u8; K_LEN], usize)>,
+    /// The version of the snapshot to read, `None` to read the current tree.
+    version: Option<u64>,
+}
+
+impl<'a, const K_LEN: usize, A: Allocator + Clone + Send + 'static> RawIter<'a, K_LEN, A> {
+    pub(crate) fn new(
+        tree: &'a CongeeInner<K_LEN, A>,
+        start: Bound<[u8; K_LEN]>,
+        end: Bound<[u8; K_LEN]>,
+        guard: &'a epoch::Guard,
+    ) -> Self {
+        Self {
+            tree,
+            guard,
+            remaining: inclusive_bounds(start, end),
+            front: VecDeque::new(),
+            back: VecDeque::new(),
+            scratch: Vec::new(),
+            version: None,
+        }
+    }
+
+    /// Reads the snapshot at `version` instead of the current tree.
+    pub(crate) fn at_version(mut self, version: u64) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Fetch the next batch, scanning downwards from the end of the remaining range if `reverse`.
+    fn fetch(&mut self, reverse: bool) {
+        if let Some(version) = self.version {
+            return self.fetch_at(version, reverse);
+        }
+        let Some((start, end)) = self.remaining else {
+            return;
+        };
+
+        self.scratch.resize(ITER_BATCH_SIZE, ([0; K_LEN], 0));
+        let n = if reverse {
+            self.tree
+                .range_inclusive_rev(&start, &end, &mut self.scratch[..], self.guard)
+        } else {
+            self.tree
+                .range_inclusive(&start, &end, &mut self.scratch[..], self.guard)
+        };
+
+        self.remaining = if n < ITER_BATCH_SIZE {
+            None
+        } else if reverse {
+            key_predecessor(self.scratch[n - 1].0)
+                .filter(|k| *k >= start)
+                .map(|k| (start, k))
+        } else {
+            key_successor(self.scratch[n - 1].0)
+                .filter(|k| *k <= end)
+                .map(|k| (k, end))
+        };
+
+        let fetched = self.scratch.iter().take(n).copied();
+        if reverse {
+            self.back.extend(fetched);
+        } else {
+            self.front.extend(fetched);
+        }
+    }
+
+    /// Like `fetch`, reading the snapshot at `version`.
+    fn fetch_at(&mut self, version: u64, reverse: bool) {
+        self.scratch.resize(2 * ITER_BATCH_SIZE, ([0; K_LEN], 0));
+        let fetched = if reverse {
+            &mut self.back
+        } else {
+            &mut self.front
+        };
+        // A batch can hold only keys inserted after the snapshot was taken.
+        while fetched.is_empty()
+            && let Some(range) = self.remaining
+        {
+            self.remaining = self.tree.scan_at(
+                version,
+                range,
+                reverse,
+                &mut self.scratch,
+                fetched,
+                self.guard,
+            );
+        }
+    }
+}
+
+impl<const K_LEN: usize, A: Allocator + Clone + Send + 'static> Iterator for RawIter<'_, K_LEN, A> {
+    type Item = ([u8; K_LEN], usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front.is_empty() {
+            self.fetch(false);
+        }
+        // Once the whole range is fetched, the smallest entries left may sit in the back buffer.
+        self.front.pop_front().or_else(|| self.back.pop_back())
+    }
+}
+
+impl<const K_LEN: usize, A: Allocator + Clone + Send + 'static> DoubleEndedIterator
+    for RawIter<'_, K_LEN, A>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.back.is_empty() {
+            self.fetch(true);
+        }
+        self.back.pop_front().or_else(|| self.front.pop_back())
+    }
+}
+
+/// An ordered iterator over the entries of a [CongeeRaw](crate::CongeeRaw).
+///
+/// Created by [CongeeRaw::iter](crate::CongeeRaw::iter) and [CongeeRaw::range_iter](crate::CongeeRaw::range_iter).
+/// Isolation level: read committed, except for the iterators of a [CongeeRawSnapshot](crate::CongeeRawSnapshot).
+pub struct CongeeRawIter<
+    'a,
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static,
+    const K_LEN: usize,
+> {
+    inner: RawIter<'a, K_LEN, A>,
+    pt: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, A, const K_LEN: usize> CongeeRawIter<'a, K, V, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static,
+{
+    pub(crate) fn new(inner: RawIter<'a, K_LEN, A>) -> Self {
+        Self {
+            inner,
+            pt: PhantomData,
+        }
+    }
+}
+
+impl<K, V, A, const K_LEN: usize> Iterator for CongeeRawIter<'_, K, V, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|(k, v)| (K::from_key_bytes(k), V::from(v)))
+    }
+}
+
+impl<K, V, A, const K_LEN: usize> DoubleEndedIterator for CongeeRawIter<'_, K, V, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next_back()
+            .map(|(k, v)| (K::from_key_bytes(k), V::from(v)))
+    }
+}
+
+/// An ordered iterator over the keys of a [CongeeSet](crate::CongeeSet).
+///
+/// Created by [CongeeSet::iter](crate::CongeeSet::iter) and [CongeeSet::range_iter](crate::CongeeSet::range_iter).
+/// Isolation level: read committed, except for the iterators of a [CongeeSetSnapshot](crate::CongeeSetSnapshot).
+pub struct CongeeSetIter<
+    'a,
+    K: KeyEncoding<K_LEN>,
+    A: Allocator + Clone + Send + 'static,
+    const K_LEN: usize,
+> {
+    inner: RawIter<'a, K_LEN, A>,
+    pt: PhantomData<K>,
+}
+
+impl<'a, K, A, const K_LEN: usize> CongeeSetIter<'a, K, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    A: Allocator + Clone + Send + 'static,
+{
+    pub(crate) fn new(inner: RawIter<'a, K_LEN, A>) -> Self {
+        Self {
+            inner,
+            pt: PhantomData,
+        }
+    }
+}
+
+impl<K, A, const K_LEN: usize> Iterator for CongeeSetIter<'_, K, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    A: Allocator + Clone + Send + 'static,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _v)| K::from_key_bytes(k))
+    }
+}
+
+impl<K, A, const K_LEN: usize> DoubleEndedIterator for CongeeSetIter<'_, K, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    A: Allocator + Clone + Send + 'static,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, _v)| K::from_key_bytes(k))
+    }
+}
+
+/// An ordered iterator over the entries of a [Congee](crate::Congee).
+///
+/// Created by [Congee::iter](crate::Congee::iter) and [Congee::range_iter](crate::Congee::range_iter).
+/// Isolation level: read committed, except for the iterators of a [CongeeSnapshot](crate::CongeeSnapshot).
+pub struct CongeeIter<'a, K: KeyEncoding<K_LEN>, V: Sync + Send + 'static, const K_LEN: usize> {
+    inner: RawIter<'a, K_LEN, crate::DefaultAllocator>,
+    pt: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, const K_LEN: usize> CongeeIter<'a, K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Sync + Send + 'static,
+{
+    pub(crate) fn new(inner: RawIter<'a, K_LEN, crate::DefaultAllocator>) -> Self {
+        Self {
+            inner,
+            pt: PhantomData,
+        }
+    }
+}
+
+impl<K, V, const K_LEN: usize> Iterator for CongeeIter<'_, K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Sync + Send + 'static,
+{
+    type Item = (K, Arc<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(clone_entry)
+    }
+}
+
+impl<K, V, const K_LEN: usize> DoubleEndedIterator for CongeeIter<'_, K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Sync + Send + 'static,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(clone_entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CongeeRaw, CongeeSet};
+    use std::collections::BTreeMap;
+    use std::ops::RangeBounds;
+
+    #[test]
+    fn successor_predecessor() {
+        assert_eq!(key_successor([0, 0xff]), Some([1, 0]));
+        assert_eq!(key_successor([0xff, 0xff]), None);
+        assert_eq!(key_predecessor([1, 0]), Some([0, 0xff]));
+        assert_eq!(key_predecessor([0, 0]), None);
+    }
+
+    #[test]
+    fn range_iter_matches_btree() {
+        let tree = CongeeRaw::<usize, usize>::default();
+        let guard = tree.pin();
+        let mut expected = BTreeMap::new();
+        for i in 0..1_000usize {
+            let k = i * 7 + (i % 3);
+            tree.insert(k, i, &guard).unwrap();
+            expected.insert(k, i);
+        }
+
+        fn check<R: RangeBounds<usize> + Clone>(
+            tree: &CongeeRaw<usize, usize>,
+            expected: &BTreeMap<usize, usize>,
+            range: R,
+            guard: &epoch::Guard,
+        ) {
+            let got: Vec<_> = tree.range_iter(range.clone(), guard).collect();
+            let want: Vec<_> = expected.range(range).map(|(k, v)| (*k, *v)).collect();
+            assert_eq!(got, want);
+        }
+
+        check(&tree, &expected, .., &guard);
+        check(&tree, &expected, 0..=0, &guard);
+        check(&tree, &expected, 7..14, &guard);
+        check(&tree, &expected, 7..=14, &guard);
+        check(&tree, &expected, 100.., &guard);
+        check(&tree, &expected, ..=700, &guard);
+        check(&tree, &expected, 6_990.., &guard);
+        check(&tree, &expected, 8_000.., &guard);
+        check(&tree, &expected, 500..500, &guard);
+        check(
+            &tree,
+            &expected,
+            (Bound::Excluded(7), Bound::Included(6_000)),
+            &guard,
+        );
+        assert_eq!(tree.iter(&guard).count(), 1_000);
+    }
+
+    #[test]
+    fn double_ended_matches_btree() {
+        let tree = CongeeRaw::<usize, usize>::default();
+        let guard = tree.pin();
+        let mut expected = BTreeMap::new();
+        for i in 0..1_000usize {
+            let k = i * 7 + (i % 3);
+            tree.insert(k, i, &guard).unwrap();
+            expected.insert(k, i);
+        }
+
+        let got: Vec<_> = tree.iter(&guard).rev().collect();
+        let want: Vec<_> = expected.iter().rev().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(got, want);
+
+        let got: Vec<_> = tree.range_iter(10..=5_000, &guard).rev().collect();
+        let want: Vec<_> = expected
+            .range(10..=5_000)
+            .rev()
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        assert_eq!(got, want);
+
+        // Alternate both ends with odd strides so the buffers meet in the middle of a batch.
+        for stride in [1, 3, 100] {
+            let mut got = tree.range_iter(100..6_000, &guard);
+            let mut want = expected.range(100..6_000).map(|(k, v)| (*k, *v));
+            loop {
+                for _ in 0..stride {
+                    assert_eq!(got.next(), want.next());
+                }
+                let back = got.next_back();
+                assert_eq!(back, want.next_back());
+                if back.is_none() {
+                    break;
+                }
+            }
+            assert!(got.next().is_none());
+        }
+    }
+
+    #[test]
+    fn rev_iter_edge_keys() {
+        let set = CongeeSet::<usize>::default();
+        let guard = set.pin();
+        assert!(set.iter(&guard).next_back().is_none());
+
+        set.insert(0, &guard).unwrap();
+        set.insert(usize::MAX, &guard).unwrap();
+        assert_eq!(
+            set.iter(&guard).rev().collect::<Vec<_>>(),
+            vec![usize::MAX, 0]
+        );
+        assert_eq!(set.range_iter(..usize::MAX, &guard).next_back(), Some(0));
+        assert_eq!(set.range_iter(1.., &guard).next_back(), Some(usize::MAX));
+    }
+
+    #[test]
+    fn iter_edge_keys() {
+        let set = CongeeSet::<usize>::default();
+        let guard = set.pin();
+        set.insert(0, &guard).unwrap();
+        set.insert(usize::MAX, &guard).unwrap();
+        assert_eq!(set.iter(&guard).collect::<Vec<_>>(), vec![0, usize::MAX]);
+        assert_eq!(
+            set.range_iter(1.., &guard).collect::<Vec<_>>(),
+            vec![usize::MAX]
+        );
+        assert_eq!(
+            set.range_iter(..usize::MAX, &guard).collect::<Vec<_>>(),
+            vec![0]
+        );
+        assert_eq!(set.range_iter(1..usize::MAX, &guard).count(), 0);
+    }
+
+    #[test]
+    fn iter_with_concurrent_inserts() {
+        let tree = Arc::new(CongeeRaw::<usize, usize>::default());
+        {
+            let guard = tree.pin();
+            for i in (0..10_000).step_by(2) {
+                tree.insert(i, i, &guard).unwrap();
+            }
+        }
+
+        let writer = {
+            let tree = tree.clone();
+            std::thread::spawn(move || {
+                let guard = tree.pin();
+                for i in (1..10_000).step_by(2) {
+                    tree.insert(i, i, &guard).unwrap();
+                }
+            })
+        };
+
+        let guard = tree.pin();
+        let mut last = None;
+        let mut even = 0;
+        for (k, v) in tree.iter(&guard) {
+            assert_eq!(k, v);
+            assert!(last < Some(k));
+            last = Some(k);
+            if k % 2 == 0 {
+                even += 1;
+            }
+        }
+        assert_eq!(even, 5_000);
+        writer.join().unwrap();
+    }
+}
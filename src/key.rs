@@ -0,0 +1,209 @@
+use std::mem::size_of;
+
+/// Types that can be used as the key of the tree, `N` is the length of the encoded key in bytes.
+///
+/// The tree orders keys by comparing their encoded bytes lexicographically,
+/// implementations must preserve the natural ordering of the type, i.e., `a < b` iff `encode(a) < encode(b)`,
+/// so that range scans follow the order of the key type.
+///
+/// Implemented for unsigned and signed integers, `f64` (ordered by [f64::total_cmp]), `char`,
+/// byte arrays, and pairs of these.
+///
+/// # Examples
+///
+/// ```
+/// use congee::{CongeeRaw, DefaultAllocator};
+///
+/// // 16 byte keys, e.g., UUIDs.
+/// let tree: CongeeRaw<u128, usize, DefaultAllocator, 16> = CongeeRaw::default();
+/// let guard = tree.pin();
+/// tree.insert(u128::MAX, 42, &guard).unwrap();
+/// assert_eq!(tree.get(&u128::MAX, &guard), Some(42));
+///
+/// // Negative keys are ordered before positive keys.
+/// let tree: CongeeRaw<i64, usize> = CongeeRaw::default();
+/// tree.insert(-1, 1, &guard).unwrap();
+/// tree.insert(1, 2, &guard).unwrap();
+/// let mut result = [(0, 0); 2];
+/// assert_eq!(tree.range(&i64::MIN, &i64::MAX, &mut result, &guard), 2);
+/// assert_eq!(result, [(-1, 1), (1, 2)]);
+/// ```
+pub trait KeyEncoding<const N: usize>: Copy {
+    /// Encodes the key into bytes that preserve the ordering of the key.
+    fn to_key_bytes(&self) -> [u8; N];
+
+    /// Decodes the bytes produced by [KeyEncoding::to_key_bytes] back to the key.
+    fn from_key_bytes(bytes: [u8; N]) -> Self;
+}
+
+macro_rules! impl_key_encoding_for_uint {
+    ($($t:ty),*) => {
+        $(
+            impl KeyEncoding<{ size_of::<$t>() }> for $t {
+                #[inline]
+                fn to_key_bytes(&self) -> [u8; size_of::<$t>()] {
+                    self.to_be_bytes()
+                }
+
+                #[inline]
+                fn from_key_bytes(bytes: [u8; size_of::<$t>()]) -> Self {
+                    <$t>::from_be_bytes(bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_key_encoding_for_uint!(u8, u16, u32, u64, u128, usize);
+
+/// Flipping the sign bit maps `MIN..=MAX` to `0..=u::MAX` in order.
+macro_rules! impl_key_encoding_for_int {
+    ($($t:ty => $u:ty),*) => {
+        $(
+            impl KeyEncoding<{ size_of::<$t>() }> for $t {
+                #[inline]
+                fn to_key_bytes(&self) -> [u8; size_of::<$t>()] {
+                    ((*self as $u) ^ (1 << (<$u>::BITS - 1))).to_be_bytes()
+                }
+
+                #[inline]
+                fn from_key_bytes(bytes: [u8; size_of::<$t>()]) -> Self {
+                    (<$u>::from_be_bytes(bytes) ^ (1 << (<$u>::BITS - 1))) as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_key_encoding_for_int!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128, isize => usize);
+
+/// Same transform as [f64::total_cmp]: negative values have all bits flipped, positive values have the sign bit flipped.
+impl KeyEncoding<8> for f64 {
+    #[inline]
+    fn to_key_bytes(&self) -> [u8; 8] {
+        let bits = self.to_bits();
+        let mask = if bits >> 63 == 1 { u64::MAX } else { 1 << 63 };
+        (bits ^ mask).to_be_bytes()
+    }
+
+    #[inline]
+    fn from_key_bytes(bytes: [u8; 8]) -> Self {
+        let bits = u64::from_be_bytes(bytes);
+        let mask = if bits >> 63 == 1 { 1 << 63 } else { u64::MAX };
+        f64::from_bits(bits ^ mask)
+    }
+}
+
+impl KeyEncoding<4> for char {
+    #[inline]
+    fn to_key_bytes(&self) -> [u8; 4] {
+        (*self as u32).to_be_bytes()
+    }
+
+    #[inline]
+    fn from_key_bytes(bytes: [u8; 4]) -> Self {
+        char::from_u32(u32::from_be_bytes(bytes)).expect("invalid char key")
+    }
+}
+
+impl<const N: usize> KeyEncoding<N> for [u8; N] {
+    #[inline]
+    fn to_key_bytes(&self) -> [u8; N] {
+        *self
+    }
+
+    #[inline]
+    fn from_key_bytes(bytes: [u8; N]) -> Self {
+        bytes
+    }
+}
+
+/// Pairs are encoded by concatenating the encoding of each element, which orders them lexicographically.
+macro_rules! impl_key_encoding_for_pair {
+    ($($a:ty),*) => {
+        $(
+            impl_key_encoding_for_pair!(@with $a; u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f64, char);
+        )*
+    };
+    (@with $a:ty; $($b:ty),*) => {
+        $(
+            impl KeyEncoding<{ size_of::<$a>() + size_of::<$b>() }> for ($a, $b) {
+                #[inline]
+                fn to_key_bytes(&self) -> [u8; size_of::<$a>() + size_of::<$b>()] {
+                    let mut bytes = [0; size_of::<$a>() + size_of::<$b>()];
+                    let (a, b) = bytes.split_at_mut(size_of::<$a>());
+                    a.copy_from_slice(&KeyEncoding::<{ size_of::<$a>() }>::to_key_bytes(&self.0));
+                    b.copy_from_slice(&KeyEncoding::<{ size_of::<$b>() }>::to_key_bytes(&self.1));
+                    bytes
+                }
+
+                #[inline]
+                fn from_key_bytes(bytes: [u8; size_of::<$a>() + size_of::<$b>()]) -> Self {
+                    let (a, b) = bytes.split_at(size_of::<$a>());
+                    (
+                        <$a as KeyEncoding<{ size_of::<$a>() }>>::from_key_bytes(a.try_into().unwrap()),
+                        <$b as KeyEncoding<{ size_of::<$b>() }>>::from_key_bytes(b.try_into().unwrap()),
+                    )
+                }
+            }
+        )*
+    };
+}
+
+impl_key_encoding_for_pair!(
+    u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f64, char
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ordered<T: PartialOrd + std::fmt::Debug + KeyEncoding<N>, const N: usize>(
+        keys: &[T],
+    ) {
+        for w in keys.windows(2) {
+            assert!(w[0] < w[1]);
+            assert!(
+                w[0].to_key_bytes() < w[1].to_key_bytes(),
+                "{:?} {:?}",
+                w[0],
+                w[1]
+            );
+        }
+        for k in keys {
+            assert_eq!(T::from_key_bytes(k.to_key_bytes()), *k);
+        }
+    }
+
+    #[test]
+    fn signed_order() {
+        assert_ordered(&[i8::MIN, -1, 0, 1, i8::MAX]);
+        assert_ordered(&[i32::MIN, -70_000, -1, 0, 1, 70_000, i32::MAX]);
+        assert_ordered(&[i64::MIN, -(1 << 40), -1, 0, 1, 1 << 40, i64::MAX]);
+    }
+
+    #[test]
+    fn float_order() {
+        assert_ordered(&[
+            f64::NEG_INFINITY,
+            f64::MIN,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            0.0,
+            f64::MIN_POSITIVE,
+            1.5,
+            f64::MAX,
+            f64::INFINITY,
+        ]);
+        assert!((-0.0f64).to_key_bytes() < 0.0f64.to_key_bytes());
+        assert!(f64::NAN.to_key_bytes() > f64::INFINITY.to_key_bytes());
+    }
+
+    #[test]
+    fn char_and_pair_order() {
+        assert_ordered(&['\0', 'A', 'a', 'é', '\u{10FFFF}']);
+        assert_ordered(&[(-1i32, 'z'), (0, 'a'), (0, 'b'), (1, '\0')]);
+        assert_ordered(&[(u8::MAX, i64::MIN), (u8::MAX, 0), (u8::MAX, i64::MAX)]);
+        assert_ordered(&[(1u64, u64::MAX), (2u64, 0u64)]);
+    }
+}
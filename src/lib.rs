@@ -3,16 +3,29 @@
 #![allow(clippy::len_without_is_empty)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+mod bulk_load;
+mod checkpoint;
 mod congee;
+mod congee_bytes;
+mod congee_bytes_inner;
+pub mod congee_compact_map;
 pub mod congee_compact_set;
 mod congee_inner;
 mod congee_raw;
 mod congee_set;
+mod durable;
 mod error;
+mod iter;
+mod key;
 mod lock;
 mod nodes;
+mod range_remove;
 mod range_scan;
+#[cfg(feature = "serde")]
+mod serialize;
+mod snapshot;
 mod stats;
+mod transaction;
 mod utils;
 use congee_inner::CongeeInner;
 
@@ -25,7 +38,15 @@ pub mod epoch {
 }
 
 pub use congee::Congee;
-pub use congee_compact_set::{CompactSetStats, CongeeCompactSet};
+pub use congee_bytes::CongeeBytes;
+pub use congee_compact_map::{CompactMapStats, CongeeCompactMap, CongeeCompactMapIter};
+pub use congee_compact_set::{CompactSetStats, CongeeCompactSet, CongeeCompactSetIter};
 pub use congee_raw::CongeeRaw;
 pub use congee_set::CongeeSet;
+pub use durable::DurableCongee;
+pub use error::{CheckpointError, CompactSetError, DurableError, TransactionError};
+pub use iter::{CongeeIter, CongeeRawIter, CongeeSetIter};
+pub use key::KeyEncoding;
+pub use snapshot::{CongeeRawSnapshot, CongeeSetSnapshot, CongeeSnapshot};
+pub use transaction::Transaction;
 pub use utils::{Allocator, DefaultAllocator, MemoryStatsAllocator};
@@ -25,7 +25,9 @@ impl<'a, T: Node> TypedReadGuard<'a, T> {
             .compare_exchange_weak(
                 self.version,
                 new_version,
-                Ordering::Release,
+                // Sequentially consistent, so a writer that sees no snapshot alive after locking
+                // is seen holding the lock by snapshots created afterwards, see `History`.
+                Ordering::SeqCst,
                 Ordering::Relaxed,
             ) {
             Ok(_) => Ok(TypedWriteGuard {
@@ -121,7 +123,8 @@ impl<'a> ReadGuard<'a> {
         match self.as_ref().version_lock_obsolete.compare_exchange_weak(
             self.version,
             new_version,
-            Ordering::Release,
+            // See `TypedReadGuard::upgrade`.
+            Ordering::SeqCst,
             Ordering::Relaxed,
         ) {
             Ok(_) => Ok(WriteGuard {
@@ -19,12 +19,19 @@ use crate::{
     },
 };
 
-pub(crate) const MAX_KEY_LEN: usize = 8;
-
 /// Prefix has to be 8 bytes for better alignment.
-const MAX_PREFIX_LEN: usize = 8;
+pub(crate) const MAX_PREFIX_LEN: usize = 8;
+
+/// Longest prefix a single node can represent, bytes beyond `MAX_PREFIX_LEN` are not stored.
+pub(crate) const MAX_PREFIX_CNT: usize = u8::MAX as usize;
 pub(crate) type Prefix = [u8; MAX_PREFIX_LEN];
 
+/// A node is replaced by the next smaller type once a removal leaves it with at most this many children.
+/// The thresholds leave room in the smaller node, so a few inserts right after shrinking don't grow it back.
+const N16_SHRINK_THRESHOLD: usize = 3;
+const N48_SHRINK_THRESHOLD: usize = 12;
+const N256_SHRINK_THRESHOLD: usize = 37;
+
 /// Represents either a normal parent node or the root of the tree
 pub(crate) enum Parent<'a> {
     Node(u8, ReadGuard<'a>),
@@ -41,6 +48,16 @@ pub(crate) enum NodeType {
 }
 
 impl NodeType {
+    /// The maximum number of children of the node type.
+    pub(crate) fn capacity(&self) -> usize {
+        match *self {
+            NodeType::N4 => 4,
+            NodeType::N16 => 16,
+            NodeType::N48 => 48,
+            NodeType::N256 => 256,
+        }
+    }
+
     pub(crate) fn node_layout(&self) -> std::alloc::Layout {
         match *self {
             NodeType::N4 => std::alloc::Layout::from_size_align(
@@ -69,6 +86,8 @@ impl NodeType {
 
 pub(crate) trait Node {
     fn base(&self) -> &BaseNode;
+    fn base_mut(&mut self) -> &mut BaseNode;
+    #[cfg(test)]
     fn is_full(&self) -> bool;
     fn insert(&mut self, key: u8, node: NodePtr);
     fn change(&mut self, key: u8, val: NodePtr) -> NodePtr;
@@ -99,6 +118,17 @@ impl Iterator for NodeIter<'_> {
     }
 }
 
+impl DoubleEndedIterator for NodeIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            NodeIter::N4(iter) => iter.next_back(),
+            NodeIter::N16(iter) => iter.next_back(),
+            NodeIter::N48(iter) => iter.next_back(),
+            NodeIter::N256(iter) => iter.next_back(),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub(crate) struct BaseNode {
@@ -195,15 +225,17 @@ macro_rules! gen_method_mut {
 gen_method!(get_child, (k: u8), Option<NodePtr>);
 gen_method!(get_children, (start: u8, end: u8), NodeIter<'_>);
 
+gen_method_mut!(insert, (key: u8, node: NodePtr), ());
 gen_method_mut!(change, (key: u8, val: NodePtr), NodePtr);
 gen_method_mut!(remove, (key: u8), ());
 
 impl BaseNode {
     pub(crate) fn new(n_type: NodeType, prefix: &[u8]) -> Self {
-        let mut prefix_v: [u8; MAX_KEY_LEN] = [0; MAX_KEY_LEN];
+        let mut prefix_v: Prefix = [0; MAX_PREFIX_LEN];
 
-        assert!(prefix.len() <= MAX_KEY_LEN);
-        for (i, v) in prefix.iter().enumerate() {
+        // Only the first `MAX_PREFIX_LEN` bytes are stored, the rest is checked optimistically.
+        assert!(prefix.len() <= MAX_PREFIX_CNT);
+        for (i, v) in prefix.iter().take(MAX_PREFIX_LEN).enumerate() {
             prefix_v[i] = *v;
         }
 
@@ -256,13 +288,20 @@ impl BaseNode {
 
     pub(crate) fn set_prefix(&mut self, prefix: &[u8]) {
         let len = prefix.len();
+        debug_assert!(len <= MAX_PREFIX_CNT);
         self.meta.prefix_cnt = len as u8;
 
-        for (i, v) in prefix.iter().enumerate() {
+        for (i, v) in prefix.iter().take(MAX_PREFIX_LEN).enumerate() {
             self.meta.prefix[i] = *v;
         }
     }
 
+    /// Copies the prefix (including the length of the part that is not stored) from `other`.
+    pub(crate) fn copy_prefix_from(&mut self, other: &BaseNode) {
+        self.meta.prefix_cnt = other.meta.prefix_cnt;
+        self.meta.prefix = other.meta.prefix;
+    }
+
     pub(crate) fn get_type(&self) -> NodeType {
         self.meta.node_type
     }
@@ -333,38 +372,63 @@ impl BaseNode {
         (version & 0b11) != 0
     }
 
+    /// The stored part of the prefix, at most `MAX_PREFIX_LEN` bytes.
     pub(crate) fn prefix(&self) -> &[u8] {
-        unsafe {
-            self.meta
-                .prefix
-                .get_unchecked(..self.meta.prefix_cnt as usize)
-        }
+        let len = (self.meta.prefix_cnt as usize).min(MAX_PREFIX_LEN);
+        unsafe { self.meta.prefix.get_unchecked(..len) }
     }
 
+    /// The full length of the prefix, which can be longer than the stored part.
+    pub(crate) fn prefix_len(&self) -> usize {
+        self.meta.prefix_cnt as usize
+    }
+
+    /// Inserts `new_children` and changes the payloads of `changed_children` under a single lock of the node.
+    /// If the new children don't fit, the node is replaced by a `BiggerT` node, which must be large enough to hold them.
+    /// `before_write` runs once the node is locked and before anything changes.
     pub(crate) fn insert_grow<CurT: Node, BiggerT: Node, A: Allocator + Send + Clone + 'static>(
         n: TypedReadGuard<CurT>,
         parent: Parent,
-        val: (u8, NodePtr),
+        new_children: &[(u8, NodePtr)],
+        changed_children: &[(u8, NodePtr)],
         allocator: &A,
         guard: &Guard,
+        before_write: impl FnOnce(),
     ) -> Result<(), ArtError> {
-        if !n.as_ref().is_full() {
+        let needed = n.as_ref().base().value_count() + new_children.len();
+        if needed <= CurT::get_type().capacity() {
             if let Parent::Node(_, p) = parent {
                 p.unlock()?;
             }
 
             let mut write_n = n.upgrade().map_err(|v| v.1)?;
+            before_write();
 
-            write_n.as_mut().insert(val.0, val.1);
+            for (k, child) in new_children {
+                write_n.as_mut().insert(*k, *child);
+            }
+            for (k, child) in changed_children {
+                write_n.as_mut().change(*k, *child);
+            }
             return Ok(());
         }
-
         let mut write_n = n.upgrade().map_err(|v| v.1)?;
-
-        let mut n_big =
-            BaseNode::make_node::<BiggerT, A>(write_n.as_ref().base().prefix(), allocator)?;
+        // The count read before the upgrade is only known to be consistent once the upgrade succeeds.
+        debug_assert!(needed <= BiggerT::get_type().capacity());
+        before_write();
+
+        let mut n_big = BaseNode::make_node::<BiggerT, A>(&[], allocator)?;
+        n_big
+            .as_mut()
+            .base_mut()
+            .copy_prefix_from(write_n.as_ref().base());
         write_n.as_ref().copy_to(n_big.as_mut());
-        n_big.as_mut().insert(val.0, val.1);
+        for (k, child) in new_children {
+            n_big.as_mut().insert(*k, *child);
+        }
+        for (k, child) in changed_children {
+            n_big.as_mut().change(*k, *child);
+        }
 
         match parent {
             Parent::Node(parent_key, parent_guard) => {
@@ -397,36 +461,256 @@ impl BaseNode {
         val: (u8, NodePtr),
         allocator: &'a A,
         guard: &Guard,
+        before_write: impl FnOnce(),
     ) -> Result<(), ArtError> {
+        let new_children = &[val];
         match node.as_ref().get_type() {
             NodeType::N4 => Self::insert_grow::<Node4, Node16, A>(
                 node.into_typed(),
                 parent,
-                val,
+                new_children,
+                &[],
                 allocator,
                 guard,
+                before_write,
             ),
             NodeType::N16 => Self::insert_grow::<Node16, Node48, A>(
                 node.into_typed(),
                 parent,
-                val,
+                new_children,
+                &[],
                 allocator,
                 guard,
+                before_write,
             ),
             NodeType::N48 => Self::insert_grow::<Node48, Node256, A>(
                 node.into_typed(),
                 parent,
-                val,
+                new_children,
+                &[],
                 allocator,
                 guard,
+                before_write,
             ),
             NodeType::N256 => Self::insert_grow::<Node256, Node256, A>(
                 node.into_typed(),
                 parent,
-                val,
+                new_children,
+                &[],
+                allocator,
+                guard,
+                before_write,
+            ),
+        }
+    }
+
+    /// Like `insert_and_unlock`, but inserts and changes many children at once.
+    /// A node that can't hold all new children grows directly into the smallest type that can.
+    pub(crate) fn insert_many_and_unlock<'a, A: Allocator + Send + Clone + 'static>(
+        node: ReadGuard<'a>,
+        parent: Parent<'a>,
+        new_children: &[(u8, NodePtr)],
+        changed_children: &[(u8, NodePtr)],
+        allocator: &'a A,
+        guard: &Guard,
+        before_write: impl FnOnce(),
+    ) -> Result<(), ArtError> {
+        match node.as_ref().get_type() {
+            NodeType::N4 => Self::grow_to_fit::<Node4, A>(
+                node.into_typed(),
+                parent,
+                new_children,
+                changed_children,
                 allocator,
                 guard,
+                before_write,
             ),
+            NodeType::N16 => Self::grow_to_fit::<Node16, A>(
+                node.into_typed(),
+                parent,
+                new_children,
+                changed_children,
+                allocator,
+                guard,
+                before_write,
+            ),
+            NodeType::N48 => Self::grow_to_fit::<Node48, A>(
+                node.into_typed(),
+                parent,
+                new_children,
+                changed_children,
+                allocator,
+                guard,
+                before_write,
+            ),
+            NodeType::N256 => Self::grow_to_fit::<Node256, A>(
+                node.into_typed(),
+                parent,
+                new_children,
+                changed_children,
+                allocator,
+                guard,
+                before_write,
+            ),
+        }
+    }
+
+    fn grow_to_fit<CurT: Node, A: Allocator + Send + Clone + 'static>(
+        n: TypedReadGuard<CurT>,
+        parent: Parent,
+        new_children: &[(u8, NodePtr)],
+        changed_children: &[(u8, NodePtr)],
+        allocator: &A,
+        guard: &Guard,
+        before_write: impl FnOnce(),
+    ) -> Result<(), ArtError> {
+        let needed = n.as_ref().base().value_count() + new_children.len();
+        if needed <= NodeType::N4.capacity() {
+            Self::insert_grow::<CurT, Node4, A>(
+                n,
+                parent,
+                new_children,
+                changed_children,
+                allocator,
+                guard,
+                before_write,
+            )
+        } else if needed <= NodeType::N16.capacity() {
+            Self::insert_grow::<CurT, Node16, A>(
+                n,
+                parent,
+                new_children,
+                changed_children,
+                allocator,
+                guard,
+                before_write,
+            )
+        } else if needed <= NodeType::N48.capacity() {
+            Self::insert_grow::<CurT, Node48, A>(
+                n,
+                parent,
+                new_children,
+                changed_children,
+                allocator,
+                guard,
+                before_write,
+            )
+        } else {
+            Self::insert_grow::<CurT, Node256, A>(
+                n,
+                parent,
+                new_children,
+                changed_children,
+                allocator,
+                guard,
+                before_write,
+            )
+        }
+    }
+
+    pub(crate) fn remove_shrink<
+        CurT: Node,
+        SmallerT: Node,
+        A: Allocator + Send + Clone + 'static,
+    >(
+        n: TypedReadGuard<CurT>,
+        parent: Parent,
+        key: u8,
+        allocator: &A,
+        guard: &Guard,
+        before_write: impl FnOnce(),
+    ) -> Result<(), ArtError> {
+        let mut write_n = n.upgrade().map_err(|v| v.1)?;
+        before_write();
+
+        // The old node is left untouched until the parent is locked, so a failed attempt can simply retry.
+        let mut n_small = BaseNode::make_node::<SmallerT, A>(&[], allocator)?;
+        n_small
+            .as_mut()
+            .base_mut()
+            .copy_prefix_from(write_n.as_ref().base());
+        for (k, child) in write_n.as_ref().get_children(0, 255) {
+            if k != key {
+                n_small.as_mut().insert(k, child);
+            }
+        }
+
+        match parent {
+            Parent::Node(parent_key, parent_guard) => {
+                let mut write_p = parent_guard.upgrade().map_err(|v| v.1)?;
+                write_p.as_mut().change(parent_key, n_small.into_note_ptr());
+            }
+            Parent::Root(root_atomic_ptr) => {
+                root_atomic_ptr.store(
+                    n_small.into_non_null().cast::<BaseNode>().as_ptr(),
+                    Ordering::Release,
+                );
+            }
+        }
+
+        write_n.mark_obsolete();
+        let delete_n = write_n.as_mut() as *mut CurT as usize;
+        std::mem::forget(write_n);
+        let allocator: A = allocator.clone();
+        guard.defer(move || unsafe {
+            let delete_n = NonNull::new(delete_n as *mut BaseNode).unwrap();
+            BaseNode::drop_node(delete_n, allocator);
+        });
+        Ok(())
+    }
+
+    /// Removes `key` from the node, replacing the node with a smaller one if it becomes sparse.
+    /// `before_write` runs once the node is locked and before anything changes.
+    pub(crate) fn remove_and_unlock<'a, A: Allocator + Send + Clone + 'static>(
+        node: ReadGuard<'a>,
+        parent: Parent<'a>,
+        key: u8,
+        allocator: &'a A,
+        guard: &Guard,
+        before_write: impl FnOnce(),
+    ) -> Result<(), ArtError> {
+        let remaining = node.as_ref().value_count().saturating_sub(1);
+        match node.as_ref().get_type() {
+            NodeType::N16 if remaining <= N16_SHRINK_THRESHOLD => {
+                Self::remove_shrink::<Node16, Node4, A>(
+                    node.into_typed(),
+                    parent,
+                    key,
+                    allocator,
+                    guard,
+                    before_write,
+                )
+            }
+            NodeType::N48 if remaining <= N48_SHRINK_THRESHOLD => {
+                Self::remove_shrink::<Node48, Node16, A>(
+                    node.into_typed(),
+                    parent,
+                    key,
+                    allocator,
+                    guard,
+                    before_write,
+                )
+            }
+            NodeType::N256 if remaining <= N256_SHRINK_THRESHOLD => {
+                Self::remove_shrink::<Node256, Node48, A>(
+                    node.into_typed(),
+                    parent,
+                    key,
+                    allocator,
+                    guard,
+                    before_write,
+                )
+            }
+            _ => {
+                if let Parent::Node(_, p) = parent {
+                    p.unlock()?;
+                }
+
+                let mut write_n = node.upgrade().map_err(|v| v.1)?;
+                before_write();
+                write_n.as_mut().remove(key);
+                Ok(())
+            }
         }
     }
 
@@ -5,6 +5,11 @@ mod node_4;
 mod node_48;
 mod node_ptr;
 
-pub(crate) use base_node::{BaseNode, Node, NodeType, Parent};
+pub(crate) use base_node::{
+    BaseNode, MAX_PREFIX_CNT, MAX_PREFIX_LEN, Node, NodeIter, NodeType, Parent,
+};
 pub(crate) use node_4::Node4;
-pub(crate) use node_ptr::{AllocatedNode, ChildIsPayload, ChildIsSubNode, NodePtr};
+pub(crate) use node_16::Node16;
+pub(crate) use node_48::Node48;
+pub(crate) use node_256::Node256;
+pub(crate) use node_ptr::{AllocatedNode, ChildIsPayload, ChildIsSubNode, NodePtr, PtrType};
@@ -96,6 +96,22 @@ impl Iterator for Node16Iter<'_> {
     }
 }
 
+impl DoubleEndedIterator for Node16Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.start_pos > self.end_pos {
+            return None;
+        }
+        let key = self.node.keys[self.end_pos];
+        let child = self.node.children[self.end_pos];
+        if self.end_pos == 0 {
+            self.start_pos = 1;
+        } else {
+            self.end_pos -= 1;
+        }
+        Some((key, child))
+    }
+}
+
 impl Node for Node16 {
     fn get_type() -> NodeType {
         NodeType::N16
@@ -171,6 +187,11 @@ impl Node for Node16 {
         &self.base
     }
 
+    fn base_mut(&mut self) -> &mut BaseNode {
+        &mut self.base
+    }
+
+    #[cfg(test)]
     fn is_full(&self) -> bool {
         self.base.meta.count() == 16
     }
@@ -339,4 +360,26 @@ mod tests {
         assert!(search_node.get_child(100).is_none());
         assert!(search_node.get_child(40).is_none());
     }
+
+    #[test]
+    fn test_reverse_iterator() {
+        let mut node = create_test_node();
+        for k in [0, 30, 100, 255] {
+            node.insert(k, NodePtr::from_payload(k as usize));
+        }
+
+        let keys: Vec<u8> = node.get_children(0, 255).rev().map(|(k, _)| k).collect();
+        assert_eq!(keys, [255, 100, 30, 0]);
+
+        let keys: Vec<u8> = node.get_children(1, 254).rev().map(|(k, _)| k).collect();
+        assert_eq!(keys, [100, 30]);
+
+        let mut iter = node.get_children(0, 255);
+        assert!(matches!(iter.next_back(), Some((255, _))));
+        assert!(matches!(iter.next(), Some((0, _))));
+        assert!(matches!(iter.next_back(), Some((100, _))));
+        assert!(matches!(iter.next(), Some((30, _))));
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+    }
 }
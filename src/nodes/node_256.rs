@@ -41,9 +41,9 @@ impl Node256 {
 }
 
 pub(crate) struct Node256Iter<'a> {
-    start: u8,
-    end: u8,
-    idx: u16,
+    start: u16,
+    /// Exclusive
+    end: u16,
     node: &'a Node256,
 }
 
@@ -51,21 +51,29 @@ impl Iterator for Node256Iter<'_> {
     type Item = (u8, NodePtr);
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let cur = self.idx + self.start as u16;
+        while self.start < self.end {
+            let cur = self.start as usize;
+            self.start += 1;
 
-            if cur > self.end as u16 {
-                return None;
+            if self.node.get_mask(cur) {
+                return Some((cur as u8, self.node.children[cur]));
             }
+        }
+        None
+    }
+}
 
-            self.idx += 1;
+impl DoubleEndedIterator for Node256Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while self.start < self.end {
+            self.end -= 1;
+            let cur = self.end as usize;
 
-            if self.node.get_mask(cur as usize) {
-                return Some((cur as u8, self.node.children[cur as usize]));
-            } else {
-                continue;
+            if self.node.get_mask(cur) {
+                return Some((cur as u8, self.node.children[cur]));
             }
         }
+        None
     }
 }
 
@@ -76,9 +84,8 @@ impl Node for Node256 {
 
     fn get_children(&self, start: u8, end: u8) -> NodeIter<'_> {
         NodeIter::N256(Node256Iter {
-            start,
-            end,
-            idx: 0,
+            start: start as u16,
+            end: end as u16 + 1,
             node: self,
         })
     }
@@ -95,6 +102,11 @@ impl Node for Node256 {
         &self.base
     }
 
+    fn base_mut(&mut self) -> &mut BaseNode {
+        &mut self.base
+    }
+
+    #[cfg(test)]
     fn is_full(&self) -> bool {
         false
     }
@@ -274,4 +286,26 @@ mod tests {
         assert!(node.get_child(0).is_none());
         assert!(node.get_child(255).is_none());
     }
+
+    #[test]
+    fn test_reverse_iterator() {
+        let mut node = create_test_node();
+        for k in [0, 30, 100, 255] {
+            node.insert(k, NodePtr::from_payload(k as usize));
+        }
+
+        let keys: Vec<u8> = node.get_children(0, 255).rev().map(|(k, _)| k).collect();
+        assert_eq!(keys, [255, 100, 30, 0]);
+
+        let keys: Vec<u8> = node.get_children(1, 254).rev().map(|(k, _)| k).collect();
+        assert_eq!(keys, [100, 30]);
+
+        let mut iter = node.get_children(0, 255);
+        assert!(matches!(iter.next_back(), Some((255, _))));
+        assert!(matches!(iter.next(), Some((0, _))));
+        assert!(matches!(iter.next_back(), Some((100, _))));
+        assert!(matches!(iter.next(), Some((30, _))));
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+    }
 }
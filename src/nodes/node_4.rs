@@ -42,6 +42,23 @@ impl Iterator for Node4Iter<'_> {
     }
 }
 
+impl DoubleEndedIterator for Node4Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.idx >= self.cnt {
+                return None;
+            }
+            self.cnt -= 1;
+            let cur = self.cnt;
+
+            let key = self.node.keys[cur as usize];
+            if key >= self.start && key <= self.end {
+                return Some((key, self.node.children[cur as usize]));
+            }
+        }
+    }
+}
+
 impl Node for Node4 {
     fn get_type() -> NodeType {
         NodeType::N4
@@ -77,6 +94,11 @@ impl Node for Node4 {
         &self.base
     }
 
+    fn base_mut(&mut self) -> &mut BaseNode {
+        &mut self.base
+    }
+
+    #[cfg(test)]
     fn is_full(&self) -> bool {
         self.base.meta.count() == 4
     }
@@ -248,4 +270,25 @@ mod tests {
         assert!(full_node.get_child(40).is_some());
         assert!(full_node.get_child(50).is_none());
     }
+
+    #[test]
+    fn test_reverse_iterator() {
+        let mut node = create_test_node();
+        for k in [0, 30, 255] {
+            node.insert(k, NodePtr::from_payload(k as usize));
+        }
+
+        let keys: Vec<u8> = node.get_children(0, 255).rev().map(|(k, _)| k).collect();
+        assert_eq!(keys, [255, 30, 0]);
+
+        let keys: Vec<u8> = node.get_children(1, 254).rev().map(|(k, _)| k).collect();
+        assert_eq!(keys, [30]);
+
+        let mut iter = node.get_children(0, 255);
+        assert!(matches!(iter.next_back(), Some((255, _))));
+        assert!(matches!(iter.next(), Some((0, _))));
+        assert!(matches!(iter.next_back(), Some((30, _))));
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+    }
 }
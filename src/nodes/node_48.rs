@@ -1,5 +1,3 @@
-use crate::cast_ptr;
-
 use super::{
     NodePtr,
     base_node::{BaseNode, Node, NodeIter, NodeType},
@@ -37,6 +35,7 @@ impl Node48 {
 
 pub(crate) struct Node48Iter<'a> {
     start: u16,
+    /// Exclusive
     end: u16,
     node: &'a Node48,
 }
@@ -45,11 +44,7 @@ impl Iterator for Node48Iter<'_> {
     type Item = (u8, NodePtr);
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if self.start > self.end {
-                return None;
-            }
-
+        while self.start < self.end {
             let key = self.start as usize;
             self.start += 1;
 
@@ -58,6 +53,22 @@ impl Iterator for Node48Iter<'_> {
                 return Some((key as u8, self.node.children[child_loc as usize]));
             }
         }
+        None
+    }
+}
+
+impl DoubleEndedIterator for Node48Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while self.start < self.end {
+            self.end -= 1;
+            let key = self.end as usize;
+
+            let child_loc = self.node.child_idx[key];
+            if child_loc != EMPTY_MARKER {
+                return Some((key as u8, self.node.children[child_loc as usize]));
+            }
+        }
+        None
     }
 }
 
@@ -79,7 +90,7 @@ impl Node for Node48 {
     fn get_children(&self, start: u8, end: u8) -> NodeIter<'_> {
         NodeIter::N48(Node48Iter {
             start: start as u16,
-            end: end as u16,
+            end: end as u16 + 1,
             node: self,
         })
     }
@@ -96,18 +107,19 @@ impl Node for Node48 {
         &self.base
     }
 
+    fn base_mut(&mut self) -> &mut BaseNode {
+        &mut self.base
+    }
+
+    #[cfg(test)]
     fn is_full(&self) -> bool {
         self.base.meta.count() == 48
     }
 
     fn insert(&mut self, key: u8, node: NodePtr) {
         let pos = self.next_empty as usize;
-        let next_empty = cast_ptr!(self.children[pos] => {
-            Payload(payload) => payload,
-            SubNode(_sub_node) => {
-                unreachable!()
-            }
-        });
+        // Empty slots form a free list, each holds the index of the next empty slot.
+        let next_empty = self.children[pos].as_payload();
         self.next_empty = next_empty as u8;
 
         debug_assert!(pos < 48);
@@ -282,4 +294,26 @@ mod tests {
         assert!(cycle_node.get_child(99).is_some());
         assert!(cycle_node.get_child(84).is_some());
     }
+
+    #[test]
+    fn test_reverse_iterator() {
+        let mut node = create_test_node();
+        for k in [0, 30, 100, 255] {
+            node.insert(k, NodePtr::from_payload(k as usize));
+        }
+
+        let keys: Vec<u8> = node.get_children(0, 255).rev().map(|(k, _)| k).collect();
+        assert_eq!(keys, [255, 100, 30, 0]);
+
+        let keys: Vec<u8> = node.get_children(1, 254).rev().map(|(k, _)| k).collect();
+        assert_eq!(keys, [100, 30]);
+
+        let mut iter = node.get_children(0, 255);
+        assert!(matches!(iter.next_back(), Some((255, _))));
+        assert!(matches!(iter.next(), Some((0, _))));
+        assert!(matches!(iter.next_back(), Some((100, _))));
+        assert!(matches!(iter.next(), Some((30, _))));
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+    }
 }
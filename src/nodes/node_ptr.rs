@@ -29,60 +29,18 @@ impl ChildIsSubNode<'_> {
     }
 }
 
-const HIGH_BIT_MASK: usize = 1 << (usize::BITS - 1);
-
-/// Macro for pattern matching on NodePtr.
-///
-/// # Usage
-///
-/// ```ignore
-/// use congee::cast_ptr;
-///
-/// let result = cast_ptr!(node_ptr => {
-///     Payload(val) => {
-///         println!("Found payload: {}", val);
-///         val * 2
-///     },
-///     SubNode(ptr) => {
-///         println!("Found subnode at: {:?}", ptr.as_ptr());
-///         0
-///     }
-/// });
-/// ```
-#[macro_export]
-macro_rules! cast_ptr {
-    ($node_ptr:expr => {
-        Payload($payload_var:pat) => $payload_expr:expr,
-        SubNode($subnode_var:pat) => $subnode_expr:expr $(,)?
-    }) => {{
-        let __node_ptr = $node_ptr;
-        if __node_ptr.is_payload() {
-            let $payload_var = unsafe { __node_ptr.as_payload_unchecked() };
-            $payload_expr
-        } else {
-            let $subnode_var = unsafe { __node_ptr.as_sub_node_unchecked() };
-            $subnode_expr
-        }
-    }};
-
-    ($node_ptr:expr => {
-        SubNode($subnode_var:pat) => $subnode_expr:expr,
-        Payload($payload_var:pat) => $payload_expr:expr $(,)?
-    }) => {{
-        let __node_ptr = $node_ptr;
-        if __node_ptr.is_payload() {
-            let $payload_var = unsafe { __node_ptr.as_payload_unchecked() };
-            $payload_expr
-        } else {
-            let $subnode_var = unsafe { __node_ptr.as_sub_node_unchecked() };
-            $subnode_expr
-        }
-    }};
+/// What a [NodePtr] points to, decided by the level of the child in the tree.
+pub(crate) enum PtrType {
+    Payload(usize),
+    SubNode(NonNull<BaseNode>),
 }
 
-/// A pointer to a node in the tree.
+/// A pointer to a node in the tree, or a payload.
 ///
-/// Use `cast_ptr!` macro to pattern match on it.
+/// The pointer carries no tag, so payloads can use all 64 bits.
+/// Whether it is a payload or a sub node is decided by the level of the child:
+/// children at the last byte of the key are payloads, all others are sub nodes.
+/// Use [NodePtr::downcast] to tell them apart.
 #[derive(Clone, Copy)]
 pub(crate) struct NodePtr {
     val: usize,
@@ -90,14 +48,7 @@ pub(crate) struct NodePtr {
 
 impl Debug for NodePtr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        cast_ptr!(self => {
-            Payload(val) => {
-                write!(f, "Payload: {val:?}")
-            },
-            SubNode(ptr) => {
-                write!(f, "SubNode: {ptr:?}")
-            }
-        })
+        write!(f, "NodePtr: {:#x}", self.val)
     }
 }
 
@@ -108,26 +59,33 @@ impl NodePtr {
 
     pub(crate) fn from_node(ptr: NonNull<BaseNode>) -> Self {
         Self {
-            val: ptr.as_ptr() as usize | HIGH_BIT_MASK,
+            val: ptr.as_ptr() as usize,
         }
     }
 
     pub(crate) fn from_node_ref(ptr: &BaseNode) -> Self {
         Self {
-            val: ptr as *const _ as usize | HIGH_BIT_MASK,
+            val: ptr as *const _ as usize,
         }
     }
 
-    pub(crate) fn is_payload(&self) -> bool {
-        self.val & HIGH_BIT_MASK == 0
+    /// `current_level` is the index of the key byte that leads to this child.
+    #[inline]
+    pub(crate) fn downcast<const K_LEN: usize>(&self, current_level: usize) -> PtrType {
+        debug_assert!(current_level < K_LEN);
+        if current_level == K_LEN - 1 {
+            PtrType::Payload(self.val)
+        } else {
+            PtrType::SubNode(unsafe { self.as_sub_node_unchecked() })
+        }
     }
 
-    pub(crate) unsafe fn as_payload_unchecked(&self) -> usize {
+    pub(crate) fn as_payload(&self) -> usize {
         self.val
     }
 
     pub(crate) unsafe fn as_sub_node_unchecked(&self) -> NonNull<BaseNode> {
-        unsafe { NonNull::new_unchecked((self.val & !HIGH_BIT_MASK) as *mut BaseNode) }
+        unsafe { NonNull::new_unchecked(self.val as *mut BaseNode) }
     }
 }
 
@@ -176,7 +134,7 @@ impl<'a, N: Node, A: Allocator> Drop for AllocatedNode<'a, N, A> {
 
 #[cfg(test)]
 mod tests {
-    use crate::cast_ptr;
+    use super::{NodePtr, PtrType};
     use crate::nodes::{BaseNode, Node, Node4};
     use crate::{Allocator, CongeeRaw, DefaultAllocator, MemoryStatsAllocator}; // Import the macro
 
@@ -251,31 +209,27 @@ mod tests {
             "No deallocation should occur when converting to NodePtr because std::mem::forget is called"
         );
 
-        cast_ptr!(node_ptr => {
-            Payload(_) => unreachable!(),
-            SubNode(sub_node) => unsafe {
+        match node_ptr.downcast::<8>(0) {
+            PtrType::Payload(_) => unreachable!(),
+            PtrType::SubNode(sub_node) => unsafe {
                 tree.allocator().deallocate(
                     std::ptr::NonNull::new(sub_node.as_ptr() as *mut u8).unwrap(),
                     Node4::get_type().node_layout(),
                 );
-            }
-        });
+            },
+        }
     }
 
     #[test]
-    fn test_downcast_macro_with_payload() {
-        let payload_value = 42usize;
-        let node_ptr = super::NodePtr::from_payload(payload_value);
-
-        let result = cast_ptr!(node_ptr => {
-            Payload(val) => {
-                format!("Payload: {val}")
-            },
-            SubNode(ptr) => {
-                format!("SubNode: {ptr:?}")
-            }
-        });
-
-        assert_eq!(result, "Payload: 42");
+    fn test_downcast_by_level() {
+        let node_ptr = NodePtr::from_payload(usize::MAX);
+        match node_ptr.downcast::<8>(7) {
+            PtrType::Payload(val) => assert_eq!(val, usize::MAX),
+            PtrType::SubNode(_) => unreachable!(),
+        }
+        match node_ptr.downcast::<8>(6) {
+            PtrType::Payload(_) => unreachable!(),
+            PtrType::SubNode(ptr) => assert_eq!(ptr.as_ptr() as usize, usize::MAX),
+        }
     }
 }
@@ -0,0 +1,418 @@
+use crate::error::ArtError;
+use crate::{
+    Allocator,
+    lock::{ReadGuard, WriteGuard},
+    nodes::{BaseNode, NodePtr, PtrType},
+    utils::KeyTracker,
+};
+use std::cmp;
+use std::ptr::NonNull;
+
+/// A node on the border of the range, only some of its children are removed.
+struct BoundaryNode<G> {
+    node: G,
+    /// Edges to remove from the node.
+    edges: Vec<u8>,
+    /// All children of the node are removed, so the node itself is unlinked from its parent.
+    emptied: bool,
+}
+
+/// A subtree that falls entirely within the range, it is unlinked as a whole.
+pub(crate) struct DetachedSubtree<'a, const K_LEN: usize> {
+    pub(crate) node: WriteGuard<'a>,
+    /// Every node below the root of the subtree, locked so that operations that already passed the root
+    /// fail on them and restart from the root of the tree, where the subtree is gone.
+    pub(crate) below: Vec<WriteGuard<'a>>,
+    /// The key up to and including the prefix of the node.
+    pub(crate) key: KeyTracker<K_LEN>,
+    /// The number of entries in the subtree.
+    pub(crate) count: usize,
+}
+
+/// Removes all keys in the inclusive range `[start, end]`.
+///
+/// The removal starts at the deepest node that covers both ends of the range, the nodes above it are left alone.
+/// Only the nodes whose edges change and the nodes of the detached subtrees are write-locked,
+/// the other nodes read on the way are checked to be unchanged once the locks are taken.
+/// Subtrees that fall entirely within the range are unlinked as a whole from their parent.
+pub(crate) struct RangeRemoval<'a, const K_LEN: usize> {
+    start: &'a [u8; K_LEN],
+    end: &'a [u8; K_LEN],
+    root: NonNull<BaseNode>,
+    boundary: Vec<BoundaryNode<ReadGuard<'a>>>,
+    /// The first boundary node is not the root and is left without children,
+    /// it is unlinked from its parent after the removal.
+    unlink_top: bool,
+    detached: Vec<(ReadGuard<'a>, KeyTracker<K_LEN>)>,
+    removed: Vec<([u8; K_LEN], usize)>,
+}
+
+/// A removal whose nodes are locked, nothing is modified until it is applied.
+pub(crate) struct LockedRemoval<'a, const K_LEN: usize> {
+    boundary: Vec<BoundaryNode<WriteGuard<'a>>>,
+    unlink_top: bool,
+    detached: Vec<DetachedSubtree<'a, K_LEN>>,
+    removed: Vec<([u8; K_LEN], usize)>,
+}
+
+/// An applied removal, the nodes in it are still locked.
+pub(crate) struct AppliedRemoval<'a, const K_LEN: usize> {
+    /// Boundary nodes left without children, already unlinked from their parents.
+    pub(crate) unlinked: Vec<WriteGuard<'a>>,
+    /// The node the removal started at, if it is left without children. It is still linked to its parent.
+    pub(crate) emptied_top: Option<WriteGuard<'a>>,
+    pub(crate) detached: Vec<DetachedSubtree<'a, K_LEN>>,
+    /// The entries removed from the boundary nodes.
+    pub(crate) removed: Vec<([u8; K_LEN], usize)>,
+}
+
+impl<'a, const K_LEN: usize> RangeRemoval<'a, K_LEN> {
+    pub(crate) fn new(
+        start: &'a [u8; K_LEN],
+        end: &'a [u8; K_LEN],
+        root: NonNull<BaseNode>,
+    ) -> Self {
+        debug_assert!(start <= end);
+        Self {
+            start,
+            end,
+            root,
+            boundary: Vec::new(),
+            unlink_top: false,
+            detached: Vec::new(),
+            removed: Vec::new(),
+        }
+    }
+
+    /// Finds the nodes affected by the removal and locks the ones that change, nothing is modified yet.
+    /// On error all locks are released and the removal can be retried.
+    pub(crate) fn lock(mut self) -> Result<LockedRemoval<'a, K_LEN>, ArtError> {
+        self.find()?;
+
+        let mut boundary = Vec::with_capacity(self.boundary.len() + 1);
+        let mut unchanged = Vec::new();
+        for b in self.boundary {
+            if b.edges.is_empty() && !b.emptied {
+                unchanged.push(b.node);
+                continue;
+            }
+            boundary.push(BoundaryNode {
+                node: b.node.upgrade().map_err(|(_n, e)| e)?,
+                edges: b.edges,
+                emptied: b.emptied,
+            });
+        }
+        let mut detached = Vec::with_capacity(self.detached.len());
+        for (node, key) in self.detached {
+            let node = node.upgrade().map_err(|(_n, e)| e)?;
+            let mut below = Vec::new();
+            let count = lock_subtree::<K_LEN>(&node, key.len(), &mut below)?;
+            detached.push(DetachedSubtree {
+                node,
+                below,
+                key,
+                count,
+            });
+        }
+        for node in unchanged {
+            node.check_version()?;
+        }
+
+        Ok(LockedRemoval {
+            boundary,
+            unlink_top: self.unlink_top,
+            detached,
+            removed: self.removed,
+        })
+    }
+
+    /// Goes down to the deepest node that covers both ends of the range and collects the nodes below it that change.
+    fn find(&mut self) -> Result<(), ArtError> {
+        let mut is_root = true;
+        let mut node = BaseNode::read_lock(self.root)?;
+        let mut key_tracker = KeyTracker::<K_LEN>::empty();
+        loop {
+            let level = key_tracker.len();
+            let k = self.start[level];
+            if k != self.end[level] {
+                break;
+            }
+            let Some(child) = node.as_ref().get_child(k) else {
+                // No key of the range is in the tree.
+                node.check_version()?;
+                return Ok(());
+            };
+            node.check_version()?;
+            let PtrType::SubNode(sub_node) = child.downcast::<K_LEN>(level) else {
+                break;
+            };
+
+            let child = BaseNode::read_lock(sub_node)?;
+            let prefix = child.as_ref().prefix();
+            let covers = prefix.iter().enumerate().all(|(i, b)| {
+                self.start.get(level + 1 + i) == Some(b) && self.end.get(level + 1 + i) == Some(b)
+            });
+            child.check_version()?;
+            if !covers {
+                break;
+            }
+            key_tracker.push(k);
+            for i in 0..prefix.len() {
+                key_tracker.push(self.start[level + 1 + i]);
+            }
+            is_root = false;
+            node = child;
+        }
+
+        self.boundary.push(BoundaryNode {
+            node,
+            edges: Vec::new(),
+            emptied: false,
+        });
+        let remaining = self.find_children(0, key_tracker, true, true)?;
+        if remaining == 0 && !is_root {
+            self.boundary[0].emptied = true;
+            self.unlink_top = true;
+        }
+        Ok(())
+    }
+
+    /// Collects the children of `self.boundary[idx]` that overlap the range.
+    /// `key_tracker` holds the key up to and including the prefix of the node,
+    /// `check_start` and `check_end` tell whether the key so far equals the start and the end of the range.
+    /// Returns the number of children left in the node after the removal.
+    fn find_children(
+        &mut self,
+        idx: usize,
+        mut key_tracker: KeyTracker<K_LEN>,
+        check_start: bool,
+        check_end: bool,
+    ) -> Result<usize, ArtError> {
+        let level = key_tracker.len();
+        let lo = if check_start { self.start[level] } else { 0 };
+        let hi = if check_end { self.end[level] } else { u8::MAX };
+
+        let node = &self.boundary[idx].node;
+        let count = node.as_ref().value_count();
+        let children: Vec<(u8, NodePtr)> = node.as_ref().get_children(lo, hi).collect();
+        node.check_version()?;
+
+        let mut edges = Vec::new();
+        for (k, child) in children {
+            key_tracker.push(k);
+            let remove_edge = match child.downcast::<K_LEN>(level) {
+                PtrType::Payload(payload) => {
+                    let key = unsafe { key_tracker.as_last_level_unchecked() };
+                    self.removed.push((*key.key(), payload));
+                    true
+                }
+                PtrType::SubNode(sub_node) => {
+                    let child = BaseNode::read_lock(sub_node)?;
+                    self.find_sub_node(
+                        child,
+                        key_tracker.clone(),
+                        check_start && k == lo,
+                        check_end && k == hi,
+                    )?
+                }
+            };
+            if remove_edge {
+                edges.push(k);
+            }
+            key_tracker.pop();
+        }
+
+        let remaining = count - edges.len();
+        self.boundary[idx].edges = edges;
+        Ok(remaining)
+    }
+
+    /// Collects a child node that overlaps the range.
+    /// `key_tracker` holds the key up to the byte leading to `node`, without the prefix of `node`.
+    /// Returns whether the edge to the node should be removed from its parent.
+    fn find_sub_node(
+        &mut self,
+        node: ReadGuard<'a>,
+        mut key_tracker: KeyTracker<K_LEN>,
+        mut check_start: bool,
+        mut check_end: bool,
+    ) -> Result<bool, ArtError> {
+        for b in node.as_ref().prefix() {
+            let level = key_tracker.len();
+            if check_start {
+                match b.cmp(&self.start[level]) {
+                    cmp::Ordering::Less => {
+                        node.check_version()?;
+                        return Ok(false);
+                    }
+                    cmp::Ordering::Greater => check_start = false,
+                    cmp::Ordering::Equal => {}
+                }
+            }
+            if check_end {
+                match b.cmp(&self.end[level]) {
+                    cmp::Ordering::Greater => {
+                        node.check_version()?;
+                        return Ok(false);
+                    }
+                    cmp::Ordering::Less => check_end = false,
+                    cmp::Ordering::Equal => {}
+                }
+            }
+            key_tracker.push(*b);
+        }
+
+        if !check_start && !check_end {
+            self.detached.push((node, key_tracker));
+            return Ok(true);
+        }
+
+        let idx = self.boundary.len();
+        self.boundary.push(BoundaryNode {
+            node,
+            edges: Vec::new(),
+            emptied: false,
+        });
+        let remaining = self.find_children(idx, key_tracker, check_start, check_end)?;
+        if remaining == 0 {
+            self.boundary[idx].emptied = true;
+        }
+        Ok(remaining == 0)
+    }
+}
+
+impl<'a, const K_LEN: usize> LockedRemoval<'a, K_LEN> {
+    /// Passes every entry the removal removes to `f`.
+    pub(crate) fn for_each_removed(&self, f: &mut dyn FnMut(&[u8; K_LEN], Option<usize>)) {
+        for (k, v) in self.removed.iter() {
+            f(k, Some(*v));
+        }
+        for subtree in self.detached.iter() {
+            let mut key_tracker = subtree.key.clone();
+            // The subtree is locked, so its nodes don't change.
+            unsafe { visit_subtree(subtree.node.as_ref().into(), &mut key_tracker, f) };
+        }
+    }
+
+    /// Removes the edges from the boundary nodes and unlocks the nodes that keep children.
+    pub(crate) fn apply(self) -> AppliedRemoval<'a, K_LEN> {
+        let mut unlinked = Vec::new();
+        let mut emptied_top = None;
+        let mut kept = Vec::with_capacity(self.boundary.len());
+        for (i, mut b) in self.boundary.into_iter().enumerate() {
+            for k in b.edges {
+                b.node.as_mut().remove(k);
+            }
+            if i == 0 && self.unlink_top {
+                emptied_top = Some(b.node);
+            } else if b.emptied {
+                unlinked.push(b.node);
+            } else {
+                kept.push(b.node);
+            }
+        }
+        // Unlock only after every edge is removed.
+        drop(kept);
+        AppliedRemoval {
+            unlinked,
+            emptied_top,
+            detached: self.detached,
+            removed: self.removed,
+        }
+    }
+}
+
+/// Write-locks every node below `node`, returns the number of entries below it.
+/// `level` is the length of the key up to and including the prefix of `node`.
+/// The children of a locked node never change, so they are read without validation.
+fn lock_subtree<'a, const K_LEN: usize>(
+    node: &WriteGuard<'a>,
+    level: usize,
+    locked: &mut Vec<WriteGuard<'a>>,
+) -> Result<usize, ArtError> {
+    let mut count = 0;
+    for (_k, child) in node.as_ref().get_children(0, u8::MAX) {
+        match child.downcast::<K_LEN>(level) {
+            PtrType::Payload(_) => count += 1,
+            PtrType::SubNode(sub_node) => {
+                let child = BaseNode::read_lock(sub_node)?
+                    .upgrade()
+                    .map_err(|(_n, e)| e)?;
+                let child_level = level + 1 + child.as_ref().prefix_len();
+                count += lock_subtree::<K_LEN>(&child, child_level, locked)?;
+                locked.push(child);
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Passes every entry below `node` to `f`.
+/// `key_tracker` holds the key up to and including the prefix of `node`.
+///
+/// # Safety
+/// No other thread can modify the subtree meanwhile.
+unsafe fn visit_subtree<const K_LEN: usize>(
+    node: NonNull<BaseNode>,
+    key_tracker: &mut KeyTracker<K_LEN>,
+    f: &mut dyn FnMut(&[u8; K_LEN], Option<usize>),
+) {
+    let level = key_tracker.len();
+    for (k, child) in unsafe { node.as_ref() }.get_children(0, u8::MAX) {
+        key_tracker.push(k);
+        match child.downcast::<K_LEN>(level) {
+            PtrType::Payload(payload) => {
+                let key = unsafe { key_tracker.as_last_level_unchecked() };
+                f(key.key(), Some(payload));
+            }
+            PtrType::SubNode(sub_node) => {
+                let prefix = unsafe { sub_node.as_ref() }.prefix();
+                let prefix_len = prefix.len();
+                for b in prefix {
+                    key_tracker.push(*b);
+                }
+                unsafe { visit_subtree(sub_node, key_tracker, f) };
+                for _ in 0..prefix_len {
+                    key_tracker.pop();
+                }
+            }
+        }
+        key_tracker.pop();
+    }
+}
+
+/// Passes every entry below `node` to `drain` and frees the nodes.
+/// `key_tracker` holds the key up to and including the prefix of `node`.
+///
+/// # Safety
+/// No other thread can reach the subtree anymore.
+pub(crate) unsafe fn drain_subtree<const K_LEN: usize, A: Allocator + Clone>(
+    node: NonNull<BaseNode>,
+    key_tracker: &mut KeyTracker<K_LEN>,
+    drain: &dyn Fn([u8; K_LEN], usize),
+    allocator: &A,
+) {
+    let level = key_tracker.len();
+    for (k, child) in unsafe { node.as_ref() }.get_children(0, u8::MAX) {
+        key_tracker.push(k);
+        match child.downcast::<K_LEN>(level) {
+            PtrType::Payload(payload) => {
+                let key = unsafe { key_tracker.as_last_level_unchecked() };
+                drain(*key.key(), payload);
+            }
+            PtrType::SubNode(sub_node) => {
+                let prefix = unsafe { sub_node.as_ref() }.prefix();
+                let prefix_len = prefix.len();
+                for b in prefix {
+                    key_tracker.push(*b);
+                }
+                unsafe { drain_subtree(sub_node, key_tracker, drain, allocator) };
+                for _ in 0..prefix_len {
+                    key_tracker.pop();
+                }
+            }
+        }
+        key_tracker.pop();
+    }
+    unsafe { BaseNode::drop_node(node, allocator.clone()) };
+}
@@ -1,9 +1,8 @@
-use crate::cast_ptr;
 use crate::error::ArtError;
 use crate::utils::LastLevelKey;
 use crate::{
     lock::ReadGuard,
-    nodes::{BaseNode, NodePtr},
+    nodes::{BaseNode, NodeIter, NodePtr, PtrType},
     utils::KeyTracker,
 };
 use std::cmp;
@@ -15,20 +14,67 @@ enum PrefixCheckEqualsResult {
     NotMatch,
 }
 
-pub(crate) struct RangeScan<'a, const K_LEN: usize> {
+/// Where a scan writes the entries it finds, in the order of the scan.
+pub(crate) trait ScanOutput<const K_LEN: usize> {
+    /// The number of entries that fit.
+    fn capacity(&self) -> usize;
+
+    /// Writes the entry at position `i`, a scan that restarts writes the positions again.
+    fn write(&mut self, i: usize, key: [u8; K_LEN], payload: usize);
+}
+
+impl<const K_LEN: usize> ScanOutput<K_LEN> for [([u8; K_LEN], usize)] {
+    fn capacity(&self) -> usize {
+        self.len()
+    }
+
+    fn write(&mut self, i: usize, key: [u8; K_LEN], payload: usize) {
+        self[i] = (key, payload);
+    }
+}
+
+/// Converts the entries while writing them, so they land in the caller's buffer without a copy in between.
+pub(crate) struct Converted<'a, T, F> {
+    result: &'a mut [T],
+    convert: F,
+}
+
+impl<'a, T, F> Converted<'a, T, F> {
+    pub(crate) fn new(result: &'a mut [T], convert: F) -> Self {
+        Self { result, convert }
+    }
+}
+
+impl<T, F: FnMut([u8; K_LEN], usize) -> T, const K_LEN: usize> ScanOutput<K_LEN>
+    for Converted<'_, T, F>
+{
+    fn capacity(&self) -> usize {
+        self.result.len()
+    }
+
+    fn write(&mut self, i: usize, key: [u8; K_LEN], payload: usize) {
+        self.result[i] = (self.convert)(key, payload);
+    }
+}
+
+pub(crate) struct RangeScan<'a, O: ScanOutput<K_LEN> + ?Sized, const K_LEN: usize> {
     start: &'a [u8; K_LEN],
     end: &'a [u8; K_LEN],
-    result: &'a mut [([u8; K_LEN], usize)],
+    result: &'a mut O,
     root: NonNull<BaseNode>,
+    end_inclusive: bool,
+    reverse: bool,
+    count_only: bool,
+    prefix_len: Option<usize>,
     to_continue: bool,
     result_found: usize,
 }
 
-impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
+impl<'a, O: ScanOutput<K_LEN> + ?Sized, const K_LEN: usize> RangeScan<'a, O, K_LEN> {
     pub(crate) fn new(
         start: &'a [u8; K_LEN],
         end: &'a [u8; K_LEN],
-        result: &'a mut [([u8; K_LEN], usize)],
+        result: &'a mut O,
         root: NonNull<BaseNode>,
     ) -> Self {
         Self {
@@ -36,20 +82,75 @@ impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
             end,
             result,
             root,
+            end_inclusive: false,
+            reverse: false,
+            count_only: false,
+            prefix_len: None,
             to_continue: false,
             result_found: 0,
         }
     }
 
+    /// Makes the scan include keys equal to `end`.
+    pub(crate) fn with_inclusive_end(mut self) -> Self {
+        self.end_inclusive = true;
+        self
+    }
+
+    /// Makes the scan visit keys in descending order, so a full result buffer holds the largest keys in range.
+    pub(crate) fn with_reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    /// Makes the scan only count the keys in range, the result buffer is not used.
+    pub(crate) fn with_count_only(mut self) -> Self {
+        self.count_only = true;
+        self
+    }
+
+    /// Makes the scan visit the keys sharing the first `prefix_len` bytes of `start`,
+    /// by descending to the node covering the prefix and copying all of it.
+    /// `start` and `end` must be the prefix filled with zeros and ones.
+    pub(crate) fn with_prefix_len(mut self, prefix_len: usize) -> Self {
+        debug_assert!(self.start[..prefix_len] == self.end[..prefix_len]);
+        self.prefix_len = Some(prefix_len);
+        self
+    }
+
+    /// Children in the order of the scan.
+    fn ordered<'n>(&self, children: NodeIter<'n>) -> impl Iterator<Item = (u8, NodePtr)> + 'n {
+        let (forward, backward) = if self.reverse {
+            (None, Some(children.rev()))
+        } else {
+            (Some(children), None)
+        };
+        forward
+            .into_iter()
+            .flatten()
+            .chain(backward.into_iter().flatten())
+    }
+
     pub(crate) fn is_valid_key_pair(&self) -> bool {
-        self.start < self.end
+        if self.end_inclusive {
+            self.start <= self.end
+        } else {
+            self.start < self.end
+        }
     }
 
     fn key_in_range(&self, key: &LastLevelKey<K_LEN>) -> bool {
-        self.start <= key.key() && key.key() < self.end
+        if self.end_inclusive {
+            self.start <= key.key() && key.key() <= self.end
+        } else {
+            self.start <= key.key() && key.key() < self.end
+        }
     }
 
     pub(crate) fn scan(&mut self) -> Result<usize, ArtError> {
+        if let Some(prefix_len) = self.prefix_len {
+            return self.scan_prefix(prefix_len);
+        }
         let mut node = BaseNode::read_lock(self.root)?;
         let mut parent_node: Option<ReadGuard> = None;
         self.to_continue = false;
@@ -60,8 +161,8 @@ impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
         loop {
             let prefix_check_result = self.check_prefix_equals(node.as_ref(), &mut key_tracker);
 
-            if parent_node.is_some() {
-                parent_node.as_ref().unwrap().check_version()?;
+            if let Some(p) = &parent_node {
+                p.check_version()?;
             }
 
             node.check_version()?;
@@ -81,34 +182,35 @@ impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
                     };
 
                     if start_level != end_level {
-                        let children = node.as_ref().get_children(start_level, end_level);
+                        let children =
+                            self.ordered(node.as_ref().get_children(start_level, end_level));
 
                         for (k, n) in children {
                             node.check_version()?;
 
                             key_tracker.push(k);
 
-                            cast_ptr!(n => {
-                                Payload(payload) => {
+                            match n.downcast::<K_LEN>(key_tracker.len() - 1) {
+                                PtrType::Payload(payload) => {
                                     self.write_result(payload, &key_tracker);
-                                },
-                                SubNode(sub_node) => {
+                                }
+                                PtrType::SubNode(sub_node) => {
                                     if k == start_level {
                                         self.find_start(sub_node, &node, key_tracker.clone())?;
                                     } else if k > start_level && k < end_level {
-                                        let cur_key = KeyTracker::append_prefix(n, &key_tracker)?;
-                                        self.copy_node_recursive(n, &cur_key)?;
+                                        self.copy_node_recursive(n, &node, &key_tracker)?;
                                     } else if k == end_level {
                                         self.find_end(sub_node, &node, key_tracker.clone())?;
                                     }
                                 }
-                            });
+                            }
                             key_tracker.pop();
 
                             if self.to_continue {
                                 return Ok(self.result_found);
                             }
                         }
+                        node.check_version()?;
                     } else {
                         let next_node_tmp = if let Some(n) = node.as_ref().get_child(start_level) {
                             n
@@ -117,28 +219,28 @@ impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
                         };
                         node.check_version()?;
 
-                        if key_tracker.len() == (K_LEN - 1) {
-                            self.copy_node_recursive(next_node_tmp, &key_tracker)?;
+                        key_tracker.push(start_level);
+                        if key_tracker.len() == K_LEN {
+                            self.copy_node_recursive(next_node_tmp, &node, &key_tracker)?;
                             return Ok(self.result_found);
                         }
-                        key_tracker.push(start_level);
 
-                        cast_ptr!(next_node_tmp => {
-                            Payload(_payload) => {
+                        match next_node_tmp.downcast::<K_LEN>(key_tracker.len() - 1) {
+                            PtrType::Payload(_payload) => {
                                 unreachable!()
-                            },
-                            SubNode(sub_node) => {
+                            }
+                            PtrType::SubNode(sub_node) => {
                                 let next_node = BaseNode::read_lock(sub_node)?;
                                 parent_node = Some(node);
                                 node = next_node;
                                 continue;
                             }
-                        });
+                        }
                     }
                     return Ok(self.result_found);
                 }
                 PrefixCheckEqualsResult::AllIncluded => {
-                    self.copy_node_recursive(NodePtr::from_node_ref(node.as_ref()), &key_tracker)?;
+                    self.copy_children(&node, key_tracker.clone())?;
                     return Ok(self.result_found);
                 }
                 PrefixCheckEqualsResult::NotMatch => {
@@ -148,6 +250,56 @@ impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
         }
     }
 
+    /// Follows the prefix like a lookup until the key of a node covers it, then copies that node.
+    fn scan_prefix(&mut self, prefix_len: usize) -> Result<usize, ArtError> {
+        let mut node = BaseNode::read_lock(self.root)?;
+        let mut parent_node: Option<ReadGuard> = None;
+        self.to_continue = false;
+        self.result_found = 0;
+
+        let mut key_tracker = KeyTracker::empty();
+        loop {
+            let mut matches = true;
+            for v in node.as_ref().prefix() {
+                let level = key_tracker.len();
+                if level < prefix_len && *v != self.start[level] {
+                    matches = false;
+                    break;
+                }
+                key_tracker.push(*v);
+            }
+            if let Some(p) = &parent_node {
+                p.check_version()?;
+            }
+            node.check_version()?;
+            if !matches {
+                return Ok(0);
+            }
+
+            if key_tracker.len() >= prefix_len {
+                self.copy_children(&node, key_tracker)?;
+                return Ok(self.result_found);
+            }
+
+            let level = key_tracker.len();
+            let child = node.as_ref().get_child(self.start[level]);
+            node.check_version()?;
+            let Some(child) = child else {
+                return Ok(0);
+            };
+            key_tracker.push(self.start[level]);
+
+            if key_tracker.len() >= prefix_len {
+                self.copy_node_recursive(child, &node, &key_tracker)?;
+                return Ok(self.result_found);
+            }
+            // The prefix is shorter than the key, so the child can't be a payload.
+            let next_node = BaseNode::read_lock(unsafe { child.as_sub_node_unchecked() })?;
+            parent_node = Some(node);
+            node = next_node;
+        }
+    }
+
     fn find_end(
         &mut self,
         node: NonNull<BaseNode>,
@@ -171,36 +323,35 @@ impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
                     255
                 };
 
-                let children = node.as_ref().get_children(0, end_level);
+                let children = self.ordered(node.as_ref().get_children(0, end_level));
                 for (k, n) in children {
                     node.check_version()?;
 
                     key_tracker.push(k);
 
-                    cast_ptr!(n => {
-                        Payload(payload) => {
+                    match n.downcast::<K_LEN>(key_tracker.len() - 1) {
+                        PtrType::Payload(payload) => {
                             self.write_result(payload, &key_tracker);
-                        },
-                        SubNode(sub_node) => {
+                        }
+                        PtrType::SubNode(sub_node) => {
                             if k == end_level {
                                 self.find_end(sub_node, &node, key_tracker.clone())?;
                             } else if k < end_level {
-                                let cur_key = KeyTracker::append_prefix(n, &key_tracker)?;
-                                self.copy_node_recursive(n, &cur_key)?;
+                                self.copy_node_recursive(n, &node, &key_tracker)?;
                             }
                         }
-                    });
+                    }
 
                     key_tracker.pop();
                     if self.to_continue {
                         break;
                     }
                 }
+                // The iteration might have stopped early on a concurrent removal.
+                node.check_version()?;
                 Ok(())
             }
-            cmp::Ordering::Less => {
-                self.copy_node_recursive(NodePtr::from_node_ref(node.as_ref()), &key_tracker)
-            }
+            cmp::Ordering::Less => self.copy_children(&node, key_tracker),
         }
     }
 
@@ -218,9 +369,7 @@ impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
         node.check_version()?;
 
         match prefix_result {
-            cmp::Ordering::Greater => {
-                self.copy_node_recursive(NodePtr::from_node_ref(node.as_ref()), &key_tracker)
-            }
+            cmp::Ordering::Greater => self.copy_children(&node, key_tracker),
             cmp::Ordering::Equal => {
                 let start_level = if self.start.len() > key_tracker.len() {
                     self.start[key_tracker.len()]
@@ -228,32 +377,33 @@ impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
                     0
                 };
 
-                let children = node.as_ref().get_children(start_level, 255);
+                let children = self.ordered(node.as_ref().get_children(start_level, 255));
 
                 for (k, n) in children {
                     node.check_version()?;
 
                     key_tracker.push(k);
 
-                    cast_ptr!(n => {
-                        Payload(payload) => {
+                    match n.downcast::<K_LEN>(key_tracker.len() - 1) {
+                        PtrType::Payload(payload) => {
                             self.write_result(payload, &key_tracker);
-                        },
-                        SubNode(sub_node) => {
+                        }
+                        PtrType::SubNode(sub_node) => {
                             if k == start_level {
                                 self.find_start(sub_node, &node, key_tracker.clone())?;
                             } else if k > start_level {
-                                let cur_key = KeyTracker::append_prefix(n, &key_tracker)?;
-                                self.copy_node_recursive(n, &cur_key)?;
+                                self.copy_node_recursive(n, &node, &key_tracker)?;
                             }
                         }
-                    });
+                    }
 
                     key_tracker.pop();
                     if self.to_continue {
                         break;
                     }
                 }
+                // The iteration might have stopped early on a concurrent removal.
+                node.check_version()?;
                 Ok(())
             }
             cmp::Ordering::Less => Ok(()),
@@ -263,47 +413,64 @@ impl<'a, const K_LEN: usize> RangeScan<'a, K_LEN> {
     fn write_result(&mut self, payload: usize, key_tracker: &KeyTracker<K_LEN>) {
         let last_level_key = unsafe { key_tracker.as_last_level_unchecked() };
         if self.key_in_range(&last_level_key) {
-            if self.result_found == self.result.len() {
+            if self.count_only {
+                self.result_found += 1;
+                return;
+            }
+            if self.result_found == self.result.capacity() {
                 self.to_continue = true;
                 return;
             }
-            self.result[self.result_found] = (*last_level_key.key(), payload);
+            self.result
+                .write(self.result_found, *last_level_key.key(), payload);
             self.result_found += 1;
         }
     }
 
     /// Copy this node and all its children recursively.
+    /// `key_tracker` holds the key up to the byte leading to `node`, without the prefix of `node`.
     fn copy_node_recursive(
         &mut self,
         node: NodePtr,
+        parent_node: &ReadGuard,
         key_tracker: &KeyTracker<K_LEN>,
     ) -> Result<(), ArtError> {
-        cast_ptr!(node => {
-            Payload(payload) => {
-                self.write_result(payload, key_tracker);
-            },
-            SubNode(sub_node) => {
-                let node = BaseNode::read_lock(sub_node)?;
-                let mut key_tracker = key_tracker.clone();
-
-                let children = node.as_ref().get_children(0, 255);
+        if key_tracker.len() == K_LEN {
+            self.write_result(node.as_payload(), key_tracker);
+            return Ok(());
+        }
 
-                for (k, c) in children {
-                    node.check_version()?;
+        let node = BaseNode::read_lock(unsafe { node.as_sub_node_unchecked() })?;
+        parent_node.check_version()?;
+        let mut key_tracker = key_tracker.clone();
+        for v in node.as_ref().prefix() {
+            key_tracker.push(*v);
+        }
+        self.copy_children(&node, key_tracker)
+    }
 
-                    key_tracker.push(k);
+    /// Copy all children of a locked node, `key_tracker` includes the prefix of the node.
+    /// The version is checked before visiting each child, so the level of the children is consistent with the prefix.
+    fn copy_children(
+        &mut self,
+        node: &ReadGuard,
+        mut key_tracker: KeyTracker<K_LEN>,
+    ) -> Result<(), ArtError> {
+        let children = self.ordered(node.as_ref().get_children(0, 255));
 
-                    let cur_key = KeyTracker::append_prefix(c, &key_tracker)?;
-                    self.copy_node_recursive(c, &cur_key)?;
+        for (k, c) in children {
+            node.check_version()?;
 
-                    if self.to_continue {
-                        break;
-                    }
+            key_tracker.push(k);
+            self.copy_node_recursive(c, node, &key_tracker)?;
 
-                    key_tracker.pop();
-                }
+            if self.to_continue {
+                break;
             }
-        });
+
+            key_tracker.pop();
+        }
+        node.check_version()?;
 
         Ok(())
     }
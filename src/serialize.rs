@@ -0,0 +1,145 @@
+//! Serde support, enabled by the `serde` feature.
+//!
+//! Maps are serialized as a sequence of `(key, value)` tuples and sets as a sequence of keys, both in ascending key order.
+//! A tree is serialized from a snapshot without copying it: the snapshot is counted first, so formats that need
+//! the length up front (such as bincode) get it, then its entries are written. Writers are not stopped,
+//! and the output holds the entries the tree had when serialization started.
+//! Deserializing accepts entries in any order, later entries win over earlier ones with the same key,
+//! sorted input (such as the output of serialization) is bulk loaded without sorting.
+
+use std::{fmt, marker::PhantomData, sync::Arc};
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{Error as _, SeqAccess, Visitor},
+    ser::SerializeSeq,
+};
+
+use crate::{Allocator, Congee, CongeeRaw, CongeeSet, KeyEncoding};
+
+/// Sorts the entries by key and keeps the last entry of each key, as if they were inserted in order.
+fn sort_entries<K: KeyEncoding<K_LEN>, T, const K_LEN: usize>(entries: &mut Vec<(K, T)>) {
+    if entries.is_sorted_by(|a, b| a.0.to_key_bytes() < b.0.to_key_bytes()) {
+        return;
+    }
+    entries.reverse();
+    // The sort is stable, so the last entry of each key comes first and is the one `dedup_by` keeps.
+    entries.sort_by_key(|(k, _v)| k.to_key_bytes());
+    entries.dedup_by(|a, b| a.0.to_key_bytes() == b.0.to_key_bytes());
+}
+
+/// Collects a sequence of `T` into a vector.
+struct SeqVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for SeqVisitor<T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+        let mut entries = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(e) = seq.next_element()? {
+            entries.push(e);
+        }
+        Ok(entries)
+    }
+}
+
+fn deserialize_seq<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<T>, D::Error> {
+    deserializer.deserialize_seq(SeqVisitor(PhantomData))
+}
+
+impl<K, V, A, const K_LEN: usize> Serialize for CongeeRaw<K, V, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN> + Serialize,
+    V: Copy + From<usize> + Serialize,
+    A: Allocator + Clone + Send + 'static,
+    usize: From<V>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let guard = self.pin();
+        let snapshot = self.snapshot(&guard);
+        let mut seq = serializer.serialize_seq(Some(snapshot.iter().count()))?;
+        for entry in snapshot.iter() {
+            seq.serialize_element(&entry)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, K, V, A, const K_LEN: usize> Deserialize<'de> for CongeeRaw<K, V, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN> + Deserialize<'de>,
+    V: Copy + From<usize> + Deserialize<'de>,
+    A: Allocator + Clone + Send + Default + 'static,
+    usize: From<V>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut entries: Vec<(K, V)> = deserialize_seq(deserializer)?;
+        sort_entries(&mut entries);
+        CongeeRaw::from_sorted_iter(A::default(), entries).map_err(D::Error::custom)
+    }
+}
+
+impl<K, A, const K_LEN: usize> Serialize for CongeeSet<K, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN> + Serialize,
+    A: Allocator + Clone + Send + 'static,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let guard = self.pin();
+        let snapshot = self.snapshot(&guard);
+        let mut seq = serializer.serialize_seq(Some(snapshot.iter().count()))?;
+        for k in snapshot.iter() {
+            seq.serialize_element(&k)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, K, A, const K_LEN: usize> Deserialize<'de> for CongeeSet<K, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN> + Deserialize<'de>,
+    A: Allocator + Clone + Send + Default + 'static,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let keys: Vec<K> = deserialize_seq(deserializer)?;
+        let mut entries: Vec<(K, ())> = keys.into_iter().map(|k| (k, ())).collect();
+        sort_entries(&mut entries);
+        CongeeSet::from_sorted_iter(A::default(), entries.into_iter().map(|(k, _)| k))
+            .map_err(D::Error::custom)
+    }
+}
+
+impl<K, V, const K_LEN: usize> Serialize for Congee<K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN> + Serialize,
+    V: Sync + Send + Serialize + 'static,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let guard = self.pin();
+        let snapshot = self.snapshot(&guard);
+        let mut seq = serializer.serialize_seq(Some(snapshot.iter().count()))?;
+        for (k, v) in snapshot.iter() {
+            seq.serialize_element(&(k, v.as_ref()))?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, K, V, const K_LEN: usize> Deserialize<'de> for Congee<K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN> + Deserialize<'de>,
+    V: Sync + Send + Deserialize<'de> + 'static,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut entries: Vec<(K, V)> = deserialize_seq(deserializer)?;
+        sort_entries(&mut entries);
+        Congee::from_sorted_iter(entries.into_iter().map(|(k, v)| (k, Arc::new(v))))
+            .map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,449 @@
+//! Point-in-time snapshots, kept by recording the values that writes replace while a snapshot is alive.
+//!
+//! Every snapshot bumps the version of the tree and reads the tree as of the version it bumped.
+//! While a snapshot is alive, each write records the value it replaces in an undo log, stamped with the version
+//! it reads once it holds the locks of every node it changes and before anything changes.
+//! A snapshot reads the current tree, then looks up the records of the keys it read:
+//! the first record stamped with a newer version than the snapshot holds the value the key had when it was taken.
+//!
+//! - A write stamped with an older version locked its nodes before the snapshot bumped the version,
+//!   so reads of the snapshot either see the write or wait for it like any other read.
+//! - A write stamped with a newer version is recorded before it changes anything, so a read that sees the write
+//!   finds its record. Records of a key are appended under the lock of the node holding the key,
+//!   so they are in the order of the writes and their versions never decrease.
+//!
+//! Writers never wait for snapshots, snapshots never wait for writers other than through the node locks.
+//! Records are kept until the last snapshot is dropped.
+
+use std::{
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering, fence},
+    },
+};
+
+use crossbeam_epoch::Guard;
+
+use crate::{
+    Allocator, CongeeInner, CongeeIter, CongeeRawIter, CongeeSetIter, DefaultAllocator,
+    KeyEncoding,
+    congee::clone_value,
+    epoch,
+    iter::{RawIter, encode_bound},
+};
+
+/// The value a write replaced, `None` if the key was absent.
+struct UndoRecord {
+    /// The version the write was stamped with.
+    version: u64,
+    old: Option<usize>,
+    /// The record of the next write to the same key.
+    next: AtomicPtr<UndoRecord>,
+}
+
+/// Frees the records of a key, starting at `head`.
+///
+/// # Safety
+/// No thread can reach the records anymore.
+unsafe fn free_records(head: usize) {
+    let mut record = head as *mut UndoRecord;
+    while !record.is_null() {
+        let owned = unsafe { Box::from_raw(record) };
+        record = owned.next.load(Ordering::Relaxed);
+    }
+}
+
+/// The snapshots of a tree and the undo log they read.
+pub(crate) struct History<const K_LEN: usize> {
+    /// Bumped by every new snapshot.
+    version: AtomicU64,
+    /// The number of snapshots alive, writes are only recorded if there is any.
+    snapshots: AtomicUsize,
+    /// Maps each key to the records of the writes made to it, oldest first.
+    /// Created by the first snapshot, null before.
+    log: AtomicPtr<CongeeInner<K_LEN>>,
+    /// Orders taking the first snapshot after the last one is dropped against clearing the log.
+    lifecycle: Mutex<()>,
+}
+
+impl<const K_LEN: usize> Drop for History<K_LEN> {
+    fn drop(&mut self) {
+        let log = *self.log.get_mut();
+        if !log.is_null() {
+            drop(unsafe { Box::from_raw(log) });
+        }
+    }
+}
+
+impl<const K_LEN: usize> History<K_LEN> {
+    pub(crate) fn new() -> Self {
+        Self {
+            version: AtomicU64::new(0),
+            snapshots: AtomicUsize::new(0),
+            log: AtomicPtr::new(std::ptr::null_mut()),
+            lifecycle: Mutex::new(()),
+        }
+    }
+
+    fn log(&self) -> Option<&CongeeInner<K_LEN>> {
+        // The log is only freed with the history.
+        unsafe { self.log.load(Ordering::SeqCst).as_ref() }
+    }
+
+    /// Takes a snapshot, returns its version. Each call must be paired with a call to `close`.
+    pub(crate) fn open(&self) -> u64 {
+        let _lifecycle = self.lifecycle.lock().unwrap_or_else(|e| e.into_inner());
+        if self.log().is_none() {
+            let log = CongeeInner::new(
+                DefaultAllocator {},
+                Arc::new(|_k, head| unsafe { free_records(head) }),
+            );
+            self.log
+                .store(Box::into_raw(Box::new(log)), Ordering::SeqCst);
+        }
+        self.snapshots.fetch_add(1, Ordering::SeqCst);
+        let version = self.version.fetch_add(1, Ordering::SeqCst);
+        // Writers that saw no snapshot or an older version held their locks when they looked,
+        // the reads that follow see those locks.
+        fence(Ordering::SeqCst);
+        version
+    }
+
+    /// Drops a snapshot taken by `open`. The records are cleared once no snapshot is left.
+    pub(crate) fn close(&self) {
+        let _lifecycle = self.lifecycle.lock().unwrap_or_else(|e| e.into_inner());
+        if self.snapshots.fetch_sub(1, Ordering::SeqCst) == 1
+            && let Some(log) = self.log()
+        {
+            // Writers that saw the last snapshot may still append to the records, which are freed after them.
+            let guard = crossbeam_epoch::pin();
+            log.remove_range(Bound::Unbounded, Bound::Unbounded, &guard);
+        }
+    }
+
+    /// Records the values about to be replaced by a write, which `writes` passes with their keys.
+    /// Must be called once the write holds the locks of every node it changes and before it changes anything.
+    /// `writes` is not called if no snapshot is alive.
+    pub(crate) fn record(
+        &self,
+        writes: impl FnOnce(&mut dyn FnMut(&[u8; K_LEN], Option<usize>)),
+        guard: &Guard,
+    ) {
+        if self.snapshots.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let version = self.version.load(Ordering::SeqCst);
+        let Some(log) = self.log() else {
+            return;
+        };
+        writes(&mut |k, old| Self::append(log, k, version, old, guard));
+    }
+
+    /// Appends a record to the records of `k`.
+    /// The writes to a key hold the lock of the node the key is in, so no other thread appends to them meanwhile.
+    fn append(
+        log: &CongeeInner<K_LEN>,
+        k: &[u8; K_LEN],
+        version: u64,
+        old: Option<usize>,
+        guard: &Guard,
+    ) {
+        let new = |next: *mut UndoRecord| {
+            Box::into_raw(Box::new(UndoRecord {
+                version,
+                old,
+                next: AtomicPtr::new(next),
+            }))
+        };
+        let Some(head) = log.get(k, guard) else {
+            let record = new(std::ptr::null_mut());
+            log.insert(k, record as usize, guard)
+                .expect("Can't allocate memory for the undo log!");
+            return;
+        };
+
+        let mut tail = unsafe { &*(head as *const UndoRecord) };
+        while let Some(next) = unsafe { tail.next.load(Ordering::Acquire).as_ref() } {
+            tail = next;
+        }
+        // Snapshots older than the version find the record before this one first.
+        if tail.version != version {
+            tail.next
+                .store(new(std::ptr::null_mut()), Ordering::Release);
+        }
+    }
+
+    /// The value `k` had in the snapshot at `version` if it was written since,
+    /// `None` if it wasn't, `Some(None)` if the key was absent.
+    pub(crate) fn undo(
+        &self,
+        k: &[u8; K_LEN],
+        version: u64,
+        guard: &Guard,
+    ) -> Option<Option<usize>> {
+        let head = self.log()?.get(k, guard)?;
+        Self::undo_records(head, version)
+    }
+
+    /// Like `undo`, starting at the first record of a key.
+    pub(crate) fn undo_records(head: usize, version: u64) -> Option<Option<usize>> {
+        // Records are freed only after the guard of the reader is dropped.
+        let mut record = unsafe { (head as *const UndoRecord).as_ref() };
+        while let Some(r) = record {
+            if r.version > version {
+                return Some(r.old);
+            }
+            record = unsafe { r.next.load(Ordering::Acquire).as_ref() };
+        }
+        None
+    }
+
+    /// Scans the keys that have records within `[start, end]` like `CongeeInner::range_inclusive`,
+    /// or downwards like `CongeeInner::range_inclusive_rev` if `reverse`.
+    /// The payloads are the first records of the keys, to be passed to `undo_records`.
+    pub(crate) fn scan_log(
+        &self,
+        start: &[u8; K_LEN],
+        end: &[u8; K_LEN],
+        reverse: bool,
+        result: &mut [([u8; K_LEN], usize)],
+        guard: &Guard,
+    ) -> usize {
+        match self.log() {
+            Some(log) if reverse => log.range_inclusive_rev(start, end, result, guard),
+            Some(log) => log.range_inclusive(start, end, result, guard),
+            None => 0,
+        }
+    }
+}
+
+/// A read-only view of a [CongeeRaw](crate::CongeeRaw) as of a single point in time.
+///
+/// Created by [CongeeRaw::snapshot](crate::CongeeRaw::snapshot).
+/// Reads see the entries the tree had when the snapshot was taken, writers don't wait for the snapshot.
+/// The values replaced while the snapshot is alive are kept until it is dropped.
+pub struct CongeeRawSnapshot<
+    'a,
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static,
+    const K_LEN: usize,
+> {
+    tree: &'a CongeeInner<K_LEN, A>,
+    guard: &'a epoch::Guard,
+    version: u64,
+    pt: PhantomData<(K, V)>,
+}
+
+impl<K, V, A, const K_LEN: usize> Drop for CongeeRawSnapshot<'_, K, V, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        self.tree.release_snapshot();
+    }
+}
+
+impl<'a, K, V, A, const K_LEN: usize> CongeeRawSnapshot<'a, K, V, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static,
+{
+    pub(crate) fn new(tree: &'a CongeeInner<K_LEN, A>, guard: &'a epoch::Guard) -> Self {
+        Self {
+            tree,
+            guard,
+            version: tree.take_snapshot(),
+            pt: PhantomData,
+        }
+    }
+
+    /// Returns a copy of the value the key had when the snapshot was taken.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.tree
+            .get_at(&key.to_key_bytes(), self.version, self.guard)
+            .map(V::from)
+    }
+
+    /// Scans the entries within `range` in ascending key order, writes them to `result`.
+    /// Returns the number of entries scanned, at most the length of `result`.
+    pub fn range<R: RangeBounds<K>>(&self, range: R, result: &mut [(K, V)]) -> usize {
+        result
+            .iter_mut()
+            .zip(self.range_iter(range))
+            .map(|(slot, entry)| *slot = entry)
+            .count()
+    }
+
+    /// Iterates over all entries in ascending key order.
+    pub fn iter(&self) -> CongeeRawIter<'_, K, V, A, K_LEN> {
+        self.range_iter(..)
+    }
+
+    /// Iterates over the entries within `range` in ascending key order.
+    /// Call `rev` on the iterator to walk the entries from the largest key.
+    pub fn range_iter<R: RangeBounds<K>>(&self, range: R) -> CongeeRawIter<'_, K, V, A, K_LEN> {
+        CongeeRawIter::new(raw_iter(self.tree, range, self.version, self.guard))
+    }
+}
+
+/// A read-only view of a [CongeeSet](crate::CongeeSet) as of a single point in time.
+///
+/// Created by [CongeeSet::snapshot](crate::CongeeSet::snapshot).
+/// Reads see the keys the set had when the snapshot was taken, writers don't wait for the snapshot.
+pub struct CongeeSetSnapshot<
+    'a,
+    K: KeyEncoding<K_LEN>,
+    A: Allocator + Clone + Send + 'static,
+    const K_LEN: usize,
+> {
+    tree: &'a CongeeInner<K_LEN, A>,
+    guard: &'a epoch::Guard,
+    version: u64,
+    pt: PhantomData<K>,
+}
+
+impl<K, A, const K_LEN: usize> Drop for CongeeSetSnapshot<'_, K, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    A: Allocator + Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        self.tree.release_snapshot();
+    }
+}
+
+impl<'a, K, A, const K_LEN: usize> CongeeSetSnapshot<'a, K, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    A: Allocator + Clone + Send + 'static,
+{
+    pub(crate) fn new(tree: &'a CongeeInner<K_LEN, A>, guard: &'a epoch::Guard) -> Self {
+        Self {
+            tree,
+            guard,
+            version: tree.take_snapshot(),
+            pt: PhantomData,
+        }
+    }
+
+    /// Returns true if the set contained the key when the snapshot was taken.
+    pub fn contains(&self, key: &K) -> bool {
+        self.tree
+            .get_at(&key.to_key_bytes(), self.version, self.guard)
+            .is_some()
+    }
+
+    /// Scans the keys within `range` in ascending order, writes them to `result`.
+    /// Returns the number of keys scanned, at most the length of `result`.
+    pub fn range<R: RangeBounds<K>>(&self, range: R, result: &mut [K]) -> usize {
+        result
+            .iter_mut()
+            .zip(self.range_iter(range))
+            .map(|(slot, k)| *slot = k)
+            .count()
+    }
+
+    /// Iterates over all keys in ascending order.
+    pub fn iter(&self) -> CongeeSetIter<'_, K, A, K_LEN> {
+        self.range_iter(..)
+    }
+
+    /// Iterates over the keys within `range` in ascending order.
+    /// Call `rev` on the iterator to walk the keys from the largest one.
+    pub fn range_iter<R: RangeBounds<K>>(&self, range: R) -> CongeeSetIter<'_, K, A, K_LEN> {
+        CongeeSetIter::new(raw_iter(self.tree, range, self.version, self.guard))
+    }
+}
+
+/// A read-only view of a [Congee](crate::Congee) as of a single point in time.
+///
+/// Created by [Congee::snapshot](crate::Congee::snapshot).
+/// Reads see the entries the tree had when the snapshot was taken, writers don't wait for the snapshot.
+/// The values removed from the tree while the snapshot is alive are released once its guard is dropped.
+pub struct CongeeSnapshot<'a, K: KeyEncoding<K_LEN>, V: Sync + Send + 'static, const K_LEN: usize> {
+    tree: &'a CongeeInner<K_LEN, DefaultAllocator>,
+    guard: &'a epoch::Guard,
+    version: u64,
+    pt: PhantomData<(K, V)>,
+}
+
+impl<K, V, const K_LEN: usize> Drop for CongeeSnapshot<'_, K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Sync + Send + 'static,
+{
+    fn drop(&mut self) {
+        self.tree.release_snapshot();
+    }
+}
+
+impl<'a, K, V, const K_LEN: usize> CongeeSnapshot<'a, K, V, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Sync + Send + 'static,
+{
+    pub(crate) fn new(
+        tree: &'a CongeeInner<K_LEN, DefaultAllocator>,
+        guard: &'a epoch::Guard,
+    ) -> Self {
+        Self {
+            tree,
+            guard,
+            version: tree.take_snapshot(),
+            pt: PhantomData,
+        }
+    }
+
+    /// Returns the value the key had when the snapshot was taken.
+    pub fn get(&self, key: K) -> Option<Arc<V>> {
+        // Values removed after the snapshot was taken are released after the guard is dropped.
+        self.tree
+            .get_at(&key.to_key_bytes(), self.version, self.guard)
+            .map(clone_value)
+    }
+
+    /// Scans the entries within `range` in ascending key order, writes them to `result`.
+    /// Returns the number of entries scanned, at most the length of `result`.
+    pub fn range<R: RangeBounds<K>>(&self, range: R, result: &mut [(K, Option<Arc<V>>)]) -> usize {
+        result
+            .iter_mut()
+            .zip(self.range_iter(range))
+            .map(|(slot, (k, v))| *slot = (k, Some(v)))
+            .count()
+    }
+
+    /// Iterates over all entries in ascending key order.
+    pub fn iter(&self) -> CongeeIter<'_, K, V, K_LEN> {
+        self.range_iter(..)
+    }
+
+    /// Iterates over the entries within `range` in ascending key order.
+    /// Call `rev` on the iterator to walk the entries from the largest key.
+    pub fn range_iter<R: RangeBounds<K>>(&self, range: R) -> CongeeIter<'_, K, V, K_LEN> {
+        CongeeIter::new(raw_iter(self.tree, range, self.version, self.guard))
+    }
+}
+
+fn raw_iter<'a, K: KeyEncoding<K_LEN>, R: RangeBounds<K>, A, const K_LEN: usize>(
+    tree: &'a CongeeInner<K_LEN, A>,
+    range: R,
+    version: u64,
+    guard: &'a epoch::Guard,
+) -> RawIter<'a, K_LEN, A>
+where
+    A: Allocator + Clone + Send + 'static,
+{
+    RawIter::new(
+        tree,
+        encode_bound(range.start_bound()),
+        encode_bound(range.end_bound()),
+        guard,
+    )
+    .at_version(version)
+}
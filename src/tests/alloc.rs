@@ -7,6 +7,8 @@ use crate::{Allocator, CongeeRaw, error::OOMError, nodes::Node4};
 
 struct SmallAllocatorInner {
     max_size: AtomicUsize,
+    /// The number of allocations not freed yet.
+    live: AtomicUsize,
 }
 
 #[derive(Clone)]
@@ -16,6 +18,7 @@ impl SmallAllocator {
     fn new(max_size: usize) -> Self {
         Self(Arc::new(SmallAllocatorInner {
             max_size: AtomicUsize::new(max_size),
+            live: AtomicUsize::new(0),
         }))
     }
 }
@@ -27,6 +30,7 @@ impl Allocator for SmallAllocator {
             self.0
                 .max_size
                 .store(current_size - layout.size(), Ordering::Relaxed);
+            self.0.live.fetch_add(1, Ordering::Relaxed);
             let ptr = unsafe { std::alloc::alloc(layout) };
             let ptr_slice = std::ptr::slice_from_raw_parts_mut(ptr, layout.size());
             Ok(std::ptr::NonNull::new(ptr_slice).unwrap())
@@ -36,6 +40,7 @@ impl Allocator for SmallAllocator {
     }
 
     unsafe fn deallocate(&self, ptr: std::ptr::NonNull<u8>, layout: std::alloc::Layout) {
+        self.0.live.fetch_sub(1, Ordering::Relaxed);
         unsafe {
             std::alloc::dealloc(ptr.as_ptr(), layout);
         }
@@ -73,3 +78,84 @@ fn insert_but_only_once() {
     let rv = art.insert(usize::MAX, 100, &guard);
     assert!(rv.is_err());
 }
+
+#[test]
+fn bulk_load_out_of_memory() {
+    let allocator = SmallAllocator::new(std::mem::size_of::<Node4>() * 64);
+    let tree = CongeeRaw::<usize, usize, SmallAllocator>::from_sorted_iter(
+        allocator,
+        (0..100_000).map(|k| (k, k)),
+    );
+    assert!(tree.is_err());
+}
+
+#[test]
+fn bulk_load_out_of_memory_drains_consumed_entries() {
+    // 16 byte keys split long prefixes into chains, so the build can fail in the middle of a chain as well.
+    let keys: Vec<u128> = (0..2_000u128)
+        .map(|k| (k % 7) << 120 | (k / 7) << 40 | k)
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    for nodes in 1..64 {
+        let allocator = SmallAllocator::new(std::mem::size_of::<Node4>() * nodes * 3);
+        let drained = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let consumed = AtomicUsize::new(0);
+        let tree = {
+            let drained = drained.clone();
+            crate::CongeeInner::<16, SmallAllocator>::from_sorted_iter(
+                allocator.clone(),
+                Arc::new(move |k, v| drained.lock().unwrap().push((u128::from_be_bytes(k), v))),
+                keys.iter().map(|k| {
+                    consumed.fetch_add(1, Ordering::Relaxed);
+                    (k.to_be_bytes(), *k as usize)
+                }),
+            )
+        };
+        assert!(tree.is_err());
+
+        // Every entry taken from the iterator is handed back once, and no node is left behind.
+        let consumed = consumed.load(Ordering::Relaxed);
+        assert!(consumed < keys.len());
+        let mut drained = drained.lock().unwrap().clone();
+        drained.sort_unstable();
+        let expected: Vec<(u128, usize)> =
+            keys[..consumed].iter().map(|k| (*k, *k as usize)).collect();
+        assert_eq!(drained, expected);
+        assert_eq!(allocator.0.live.load(Ordering::Relaxed), 0);
+    }
+}
+
+#[test]
+fn transaction_out_of_memory() {
+    let allocator = SmallAllocator::new(std::mem::size_of::<Node4>() * 64);
+    let tree = CongeeRaw::<usize, usize, SmallAllocator>::new(allocator);
+    let guard = tree.pin();
+    for k in 0..16 {
+        tree.insert(k, k, &guard).unwrap();
+    }
+
+    let mut txn = tree.transaction(&guard);
+    txn.remove(&3);
+    for k in 0..10_000 {
+        txn.insert(k << 20, k);
+    }
+    assert_eq!(txn.commit(), Err(crate::TransactionError::Oom));
+    assert!(tree.iter(&guard).eq((0..16).map(|k| (k, k))));
+    assert_eq!(tree.len(&guard), 16);
+}
+
+#[test]
+fn compact_set_out_of_memory() {
+    let set = crate::CongeeSet::<usize>::default();
+    let guard = set.pin();
+    for k in 0..100_000 {
+        set.insert(k * 1_000, &guard).unwrap();
+    }
+    let data = set.to_compact_set();
+    let compact = crate::CongeeCompactSet::<usize>::new(&data);
+
+    let allocator = SmallAllocator::new(std::mem::size_of::<Node4>() * 64);
+    let loaded = crate::CongeeSet::<usize, SmallAllocator>::from_compact_set(allocator, &compact);
+    assert!(matches!(loaded, Err(crate::CompactSetError::Oom)));
+}
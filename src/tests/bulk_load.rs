@@ -0,0 +1,143 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::utils::leak_check::LeakCheckAllocator;
+use crate::{Congee, CongeeRaw, CongeeSet, DefaultAllocator};
+
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+
+fn assert_same_shape(keys: &BTreeSet<usize>) {
+    let loaded = CongeeRaw::<usize, usize>::from_sorted_iter(
+        DefaultAllocator {},
+        keys.iter().map(|k| (*k, *k)),
+    )
+    .unwrap();
+    let inserted = CongeeRaw::<usize, usize>::default();
+    let guard = inserted.pin();
+    for k in keys.iter() {
+        inserted.insert(*k, *k, &guard).unwrap();
+    }
+
+    let (loaded_stats, inserted_stats) = (loaded.stats(), inserted.stats());
+    assert_eq!(loaded_stats.total_nodes(), inserted_stats.total_nodes());
+    assert_eq!(
+        loaded_stats.memory_by_node_type(),
+        inserted_stats.memory_by_node_type()
+    );
+    assert_eq!(
+        loaded_stats.prefix_distribution(),
+        inserted_stats.prefix_distribution()
+    );
+    assert_eq!(loaded.keys(), keys.iter().copied().collect::<Vec<_>>());
+    for k in keys.iter() {
+        assert_eq!(loaded.get(k, &guard), Some(*k));
+    }
+}
+
+#[test]
+fn bulk_load_same_shape_as_inserts() {
+    assert_same_shape(&BTreeSet::new());
+    assert_same_shape(&BTreeSet::from([42]));
+    assert_same_shape(&(0..100_000).collect());
+    assert_same_shape(&(0..100_000).map(|k| k * 997).collect());
+
+    let mut r = StdRng::seed_from_u64(42);
+    for bits in [8, 12, 20, 40, 64] {
+        let keys: BTreeSet<usize> = (0..20_000)
+            .map(|_| r.r#gen::<usize>() >> (64 - bits))
+            .collect();
+        assert_same_shape(&keys);
+    }
+}
+
+#[test]
+fn bulk_load_long_keys() {
+    let mut r = StdRng::seed_from_u64(7);
+    let mut keys: BTreeSet<u128> = (0..5_000).map(|_| r.r#gen::<u128>()).collect();
+    keys.extend((0..1_000u128).map(|k| k << 100));
+    keys.extend((0..1_000u128).map(|k| 0xdead_beef_dead_beef_dead_beef_0000_0000 | (k << 20)));
+
+    let tree: CongeeRaw<u128, usize, LeakCheckAllocator, 16> = CongeeRaw::from_sorted_iter(
+        LeakCheckAllocator::new(),
+        keys.iter().map(|k| (*k, *k as usize)),
+    )
+    .unwrap();
+    let guard = tree.pin();
+    assert_eq!(tree.keys(), keys.iter().copied().collect::<Vec<_>>());
+    for k in keys.iter() {
+        assert_eq!(tree.get(k, &guard), Some(*k as usize));
+    }
+
+    // Long prefixes take as many chain nodes as inserts make, though inserts may split a chain
+    // at other bytes depending on the insertion order.
+    let inserted = CongeeRaw::<u128, usize, DefaultAllocator, 16>::default();
+    for k in keys.iter() {
+        inserted.insert(*k, *k as usize, &guard).unwrap();
+    }
+    let (loaded_stats, inserted_stats) = (tree.stats(), inserted.stats());
+    assert_eq!(loaded_stats.total_nodes(), inserted_stats.total_nodes());
+    assert_eq!(
+        loaded_stats.memory_by_node_type(),
+        inserted_stats.memory_by_node_type()
+    );
+
+    // The tree behaves like any other tree after being loaded.
+    for k in keys.iter().step_by(2) {
+        assert_eq!(tree.remove(k, &guard), Some(*k as usize));
+        tree.insert(k + 1, 1, &guard).unwrap();
+    }
+    for k in keys.iter().step_by(2) {
+        assert_eq!(tree.get(k, &guard), None);
+        assert!(tree.get(&(k + 1), &guard).is_some());
+    }
+}
+
+#[test]
+fn bulk_load_set_and_arc_values() {
+    let set = CongeeSet::<usize>::from_sorted_iter(DefaultAllocator {}, (0..10_000).map(|k| k * 3))
+        .unwrap();
+    let guard = set.pin();
+    assert_eq!(set.iter(&guard).count(), 10_000);
+    assert!(set.contains(&2997, &guard));
+    assert!(!set.contains(&2998, &guard));
+
+    let values: Vec<Arc<usize>> = (0..1_000).map(Arc::new).collect();
+    let tree: Congee<usize, usize> =
+        Congee::from_sorted_iter(values.iter().map(|v| (**v, v.clone()))).unwrap();
+    assert_eq!(*tree.get(500, &guard).unwrap(), 500);
+    drop(tree);
+    for v in values.iter() {
+        assert_eq!(Arc::strong_count(v), 1);
+    }
+}
+
+#[test]
+#[should_panic(expected = "strictly ascending")]
+fn bulk_load_rejects_unsorted_keys() {
+    let _tree =
+        CongeeRaw::<usize, usize>::from_sorted_iter(DefaultAllocator {}, [(1, 1), (3, 3), (2, 2)]);
+}
+
+#[test]
+#[should_panic(expected = "out of order key at index 2")]
+fn bulk_load_stops_at_the_first_unsorted_key() {
+    // The rest of the input is never read, so an endless iterator fails as well.
+    let _tree = CongeeRaw::<usize, usize>::from_sorted_iter(
+        DefaultAllocator {},
+        [(1, 1), (3, 3), (2, 2)]
+            .into_iter()
+            .chain((10..).map(|k| (k, k))),
+    );
+}
+
+#[test]
+fn bulk_load_rejects_duplicate_keys_without_leaking() {
+    let value = Arc::new(0usize);
+    let keys = [1, 2, 2];
+    let result = std::panic::catch_unwind(|| {
+        Congee::<usize, usize>::from_sorted_iter(keys.iter().map(|k| (*k, value.clone())))
+    });
+    assert!(result.is_err());
+    assert_eq!(Arc::strong_count(&value), 1);
+}
@@ -0,0 +1,186 @@
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{CheckpointError, CongeeRaw, CongeeSet, DefaultAllocator};
+
+fn write(tree: &CongeeRaw<u64, usize>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    tree.write_snapshot(&mut buf, &tree.pin()).unwrap();
+    buf
+}
+
+fn read(buf: &[u8]) -> Result<CongeeRaw<u64, usize>, CheckpointError> {
+    CongeeRaw::read_snapshot(DefaultAllocator {}, buf)
+}
+
+#[test]
+fn checkpoint_round_trip() {
+    let mut r = StdRng::seed_from_u64(42);
+    let tree = CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    for _ in 0..20_000 {
+        // Dense and sparse keys, so blocks range from one to 256 entries.
+        let k = if r.gen_bool(0.5) {
+            r.gen_range(0..5_000)
+        } else {
+            r.r#gen::<u64>()
+        };
+        tree.insert(k, r.r#gen::<usize>(), &guard).unwrap();
+    }
+
+    let loaded = read(&write(&tree)).unwrap();
+    assert!(loaded.iter(&guard).eq(tree.iter(&guard)));
+    assert_eq!(loaded.len(&guard), tree.len(&guard));
+    assert_eq!(loaded.stats().kv_pairs(), tree.len(&guard));
+
+    // The loaded tree is a regular tree.
+    loaded.insert(u64::MAX, 1, &guard).unwrap();
+    assert_eq!(loaded.get(&u64::MAX, &guard), Some(1));
+
+    let empty = CongeeRaw::<u64, usize>::default();
+    let loaded = read(&write(&empty)).unwrap();
+    assert!(loaded.is_empty(&guard));
+}
+
+#[test]
+fn checkpoint_set_round_trip() {
+    let set = CongeeSet::<u64>::default();
+    let guard = set.pin();
+    for k in (0..10_000u64).map(|k| k * 1_009) {
+        set.insert(k, &guard).unwrap();
+    }
+    let mut buf = Vec::new();
+    set.write_snapshot(&mut buf, &guard).unwrap();
+
+    let loaded = CongeeSet::<u64>::read_snapshot(DefaultAllocator {}, &buf[..]).unwrap();
+    assert!(loaded.iter(&guard).eq(set.iter(&guard)));
+    assert!(matches!(
+        read(&buf),
+        Err(CheckpointError::KindMismatch {
+            expected: "map",
+            found: "set"
+        })
+    ));
+}
+
+#[test]
+fn checkpoint_rejects_truncated() {
+    let tree = CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    for k in 0..600u64 {
+        tree.insert(k * 3, k as usize, &guard).unwrap();
+    }
+    let buf = write(&tree);
+    for len in 0..buf.len() {
+        assert!(
+            matches!(read(&buf[..len]), Err(CheckpointError::Truncated)),
+            "truncated at {len}"
+        );
+    }
+}
+
+#[test]
+fn checkpoint_rejects_corrupted() {
+    let tree = CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    for k in 0..300u64 {
+        tree.insert(k * 5, k as usize, &guard).unwrap();
+    }
+    let buf = write(&tree);
+
+    // Any single flipped bit is detected, though a flipped count may show up as a truncated input.
+    for i in 0..buf.len() {
+        for bit in 0..8 {
+            let mut corrupted = buf.clone();
+            corrupted[i] ^= 1 << bit;
+            assert!(read(&corrupted).is_err(), "flipped bit {bit} of byte {i}");
+        }
+    }
+
+    let mut corrupted = buf.clone();
+    corrupted[0] ^= 1;
+    assert!(matches!(read(&corrupted), Err(CheckpointError::BadMagic)));
+    let mut corrupted = buf.clone();
+    corrupted[8] = 2;
+    assert!(matches!(
+        read(&corrupted),
+        Err(CheckpointError::UnsupportedVersion(2))
+    ));
+    let mut corrupted = buf.clone();
+    corrupted[20] ^= 1;
+    assert!(matches!(
+        read(&corrupted),
+        Err(CheckpointError::CorruptedHeader)
+    ));
+    let mut corrupted = buf.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        read(&corrupted),
+        Err(CheckpointError::CorruptedTrailer)
+    ));
+    // The last block ends right before the 14 byte trailer.
+    let mut corrupted = buf.clone();
+    corrupted[buf.len() - 15] ^= 1;
+    assert!(matches!(
+        read(&corrupted),
+        Err(CheckpointError::CorruptedBlock { block: 5 })
+    ));
+}
+
+#[test]
+fn checkpoint_during_writes() {
+    let tree = std::sync::Arc::new(CongeeRaw::<u64, usize>::default());
+    {
+        let guard = tree.pin();
+        for k in 0..10_000u64 {
+            tree.insert(k * 2, 1, &guard).unwrap();
+        }
+    }
+
+    let writer = {
+        let tree = tree.clone();
+        std::thread::spawn(move || {
+            let guard = tree.pin();
+            for k in 0..10_000u64 {
+                tree.insert(k * 2 + 1, 2, &guard).unwrap();
+                tree.remove(&(k * 2), &guard);
+            }
+        })
+    };
+    let mut checkpoints = Vec::new();
+    while !writer.is_finished() {
+        checkpoints.push(write(&tree));
+    }
+    writer.join().unwrap();
+    checkpoints.push(write(&tree));
+
+    // Every checkpoint is valid, the writes it saw are a mix of before and after.
+    for buf in checkpoints {
+        let loaded = read(&buf).unwrap();
+        let guard = loaded.pin();
+        assert!(loaded.iter(&guard).all(|(k, v)| v == 1 + (k % 2) as usize));
+    }
+}
+
+#[test]
+fn checkpoint_rejects_other_key_length() {
+    let tree = CongeeRaw::<u64, usize>::default();
+    let buf = write(&tree);
+    let other = CongeeRaw::<[u8; 16], usize, DefaultAllocator, 16>::read_snapshot(
+        DefaultAllocator {},
+        &buf[..],
+    );
+    assert!(matches!(
+        other,
+        Err(CheckpointError::KeyLengthMismatch {
+            expected: 16,
+            found: 8
+        })
+    ));
+}
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crate::utils::crc32(b""), 0);
+    assert_eq!(crate::utils::crc32(b"123456789"), 0xcbf4_3926);
+}
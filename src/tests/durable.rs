@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{DefaultAllocator, DurableCongee, DurableError};
+
+/// An empty directory only used by the calling test.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("congee-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn open(dir: &Path, checkpoint_interval: u64) -> DurableCongee<u64, usize> {
+    DurableCongee::open_with_checkpoint_interval(dir, DefaultAllocator {}, checkpoint_interval)
+        .unwrap()
+}
+
+fn segments(dir: &Path) -> Vec<PathBuf> {
+    let mut segments: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.file_name().unwrap().to_str().unwrap().starts_with("wal-"))
+        .collect();
+    segments.sort();
+    segments
+}
+
+fn assert_same(tree: &DurableCongee<u64, usize>, expected: &BTreeMap<u64, usize>) {
+    let guard = tree.pin();
+    assert!(tree.iter(&guard).eq(expected.iter().map(|(k, v)| (*k, *v))));
+    assert_eq!(tree.len(&guard), expected.len());
+}
+
+/// Applies random writes to the tree and to `expected`.
+fn random_writes(
+    tree: &DurableCongee<u64, usize>,
+    expected: &mut BTreeMap<u64, usize>,
+    r: &mut StdRng,
+    n: usize,
+) {
+    let guard = tree.pin();
+    for _ in 0..n {
+        let k = r.gen_range(0..500);
+        match r.gen_range(0..4) {
+            0 => {
+                let v = r.gen_range(0..1_000);
+                assert_eq!(tree.insert(k, v, &guard).unwrap(), expected.insert(k, v));
+            }
+            1 => assert_eq!(tree.remove(&k, &guard).unwrap(), expected.remove(&k)),
+            2 => {
+                let old = r.gen_range(0..1_000);
+                let new = r.gen_bool(0.8).then(|| r.gen_range(0..1_000));
+                let r = tree.compare_exchange(&k, &old, new, &guard).unwrap();
+                if r.is_ok() {
+                    match new {
+                        Some(v) => expected.insert(k, v),
+                        None => expected.remove(&k),
+                    };
+                }
+            }
+            _ => {
+                let r = tree
+                    .compute_if_present(&k, |v| (v % 3 != 0).then_some(v + 1), &guard)
+                    .unwrap();
+                if let Some((old, new)) = r {
+                    assert_eq!(expected.get(&k), Some(&old));
+                    match new {
+                        Some(v) => expected.insert(k, v),
+                        None => expected.remove(&k),
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn replay_after_reopen() {
+    let dir = test_dir("replay");
+    let mut r = StdRng::seed_from_u64(1);
+    let mut expected = BTreeMap::new();
+    for _ in 0..3 {
+        let tree = open(&dir, 0);
+        assert_same(&tree, &expected);
+        random_writes(&tree, &mut expected, &mut r, 2_000);
+    }
+    assert_same(&open(&dir, 0), &expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn checkpoint_truncates_log() {
+    let dir = test_dir("checkpoint");
+    let mut r = StdRng::seed_from_u64(2);
+    let mut expected = BTreeMap::new();
+    {
+        let tree = open(&dir, 0);
+        random_writes(&tree, &mut expected, &mut r, 2_000);
+        tree.checkpoint(&tree.pin()).unwrap();
+        // Only the segment after the checkpoint is left, and it is empty.
+        let left = segments(&dir);
+        assert_eq!(left.len(), 1);
+        assert_eq!(fs::metadata(&left[0]).unwrap().len(), 0);
+        random_writes(&tree, &mut expected, &mut r, 2_000);
+    }
+    assert_same(&open(&dir, 0), &expected);
+
+    // Automatic checkpoints keep the log short.
+    {
+        let tree = open(&dir, 100);
+        random_writes(&tree, &mut expected, &mut r, 5_000);
+        assert!(segments(&dir).len() <= 2);
+    }
+    assert_same(&open(&dir, 100), &expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn torn_log_tail() {
+    let dir = test_dir("torn");
+    let mut r = StdRng::seed_from_u64(3);
+    let mut expected = BTreeMap::new();
+    let mut history = vec![expected.clone()];
+    {
+        let tree = open(&dir, 0);
+        for _ in 0..100 {
+            random_writes(&tree, &mut expected, &mut r, 1);
+            history.push(expected.clone());
+        }
+    }
+    let log = segments(&dir)[0].clone();
+    let data = fs::read(&log).unwrap();
+
+    // Cutting the log anywhere loses the torn record and nothing before it.
+    for len in (0..=data.len()).rev() {
+        fs::write(&log, &data[..len]).unwrap();
+        let tree = open(&dir, 0);
+        let guard = tree.pin();
+        let recovered: BTreeMap<u64, usize> = tree.iter(&guard).collect();
+        assert!(history.contains(&recovered), "cut at {len}");
+        assert!(fs::metadata(&log).unwrap().len() <= len as u64);
+    }
+
+    // New writes after a torn tail are recovered too.
+    fs::write(&log, &data[..data.len() - 3]).unwrap();
+    let mut expected = {
+        let tree = open(&dir, 0);
+        let guard = tree.pin();
+        let mut expected: BTreeMap<u64, usize> = tree.iter(&guard).collect();
+        random_writes(&tree, &mut expected, &mut r, 100);
+        expected
+    };
+    let tree = open(&dir, 0);
+    assert_same(&tree, &expected);
+    random_writes(&tree, &mut expected, &mut r, 100);
+    drop(tree);
+    assert_same(&open(&dir, 0), &expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupted_files() {
+    let dir = test_dir("corrupted");
+    {
+        let tree = open(&dir, 0);
+        let guard = tree.pin();
+        for k in 0..100 {
+            tree.insert(k, k as usize, &guard).unwrap();
+        }
+        tree.checkpoint(&guard).unwrap();
+        tree.insert(1_000, 1, &guard).unwrap();
+    }
+    // A later segment, left by a crash before the checkpoint deleted the damaged one.
+    let log = segments(&dir)[0].clone();
+    fs::write(dir.join("wal-00000000000000ff"), []).unwrap();
+    let mut data = fs::read(&log).unwrap();
+    data[10] ^= 1;
+    fs::write(&log, &data).unwrap();
+    assert!(matches!(
+        DurableCongee::<u64, usize>::open(&dir, DefaultAllocator {}),
+        Err(DurableError::CorruptedLog { offset: 0, .. })
+    ));
+    data[10] ^= 1;
+    fs::write(&log, &data).unwrap();
+
+    let checkpoint = dir.join("checkpoint");
+    let mut data = fs::read(&checkpoint).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    fs::write(&checkpoint, &data).unwrap();
+    assert!(matches!(
+        DurableCongee::<u64, usize>::open(&dir, DefaultAllocator {}),
+        Err(DurableError::Checkpoint(
+            crate::CheckpointError::CorruptedTrailer
+        ))
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn damaged_record_in_last_segment() {
+    let dir = test_dir("damaged");
+    {
+        let tree = open(&dir, 0);
+        let guard = tree.pin();
+        for k in 0..100 {
+            tree.insert(k, k as usize, &guard).unwrap();
+        }
+    }
+    let log = segments(&dir)[0].clone();
+    let mut data = fs::read(&log).unwrap();
+    let len = data.len();
+    // The third record, the records after it were acknowledged and must not be cut off.
+    let offset = 2 * len / 100;
+    data[offset + 10] ^= 1;
+    fs::write(&log, &data).unwrap();
+    assert!(matches!(
+        DurableCongee::<u64, usize>::open(&dir, DefaultAllocator {}),
+        Err(DurableError::CorruptedLog { offset: o, .. }) if o == offset as u64
+    ));
+    assert_eq!(fs::metadata(&log).unwrap().len(), len as u64);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn damaged_last_record() {
+    let dir = test_dir("damaged_last");
+    {
+        let tree = open(&dir, 0);
+        let guard = tree.pin();
+        for k in 0..100 {
+            tree.insert(k, k as usize, &guard).unwrap();
+        }
+    }
+    let log = segments(&dir)[0].clone();
+    let mut data = fs::read(&log).unwrap();
+    let len = data.len();
+    // A crash can leave the last record's bytes unwritten after the file grew, it was never acknowledged.
+    let offset = 99 * len / 100;
+    data[offset + 10] ^= 1;
+    fs::write(&log, &data).unwrap();
+    let mut expected: BTreeMap<u64, usize> = (0..99).map(|k| (k, k as usize)).collect();
+    let tree = open(&dir, 0);
+    assert_same(&tree, &expected);
+    assert_eq!(fs::metadata(&log).unwrap().len(), offset as u64);
+
+    // New writes follow the valid records.
+    let guard = tree.pin();
+    tree.insert(1_000, 1, &guard).unwrap();
+    expected.insert(1_000, 1);
+    drop(guard);
+    drop(tree);
+    assert_same(&open(&dir, 0), &expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn checkpoint_during_writes() {
+    let dir = test_dir("checkpoint-writes");
+    let tree = Arc::new(open(&dir, 0));
+    let mut handlers = Vec::new();
+    for t in 0..4u64 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let guard = tree.pin();
+            for k in 0..500 {
+                tree.insert(k * 4 + t, k as usize, &guard).unwrap();
+                if k % 2 == 0 {
+                    tree.remove(&(k * 4 + t), &guard).unwrap();
+                }
+            }
+        }));
+    }
+    while handlers.iter().any(|h| !h.is_finished()) {
+        tree.checkpoint(&tree.pin()).unwrap();
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+
+    let expected: BTreeMap<u64, usize> = (0..500u64)
+        .filter(|k| k % 2 == 1)
+        .flat_map(|k| (0..4).map(move |t| (k * 4 + t, k as usize)))
+        .collect();
+    assert_same(&tree, &expected);
+    drop(tree);
+    assert_same(&open(&dir, 0), &expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn concurrent_group_commit() {
+    let dir = test_dir("group-commit");
+    let tree = Arc::new(open(&dir, 1_000));
+    let mut handlers = Vec::new();
+    for t in 0..4u64 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let guard = tree.pin();
+            for k in 0..1_000 {
+                tree.insert(k * 4 + t, k as usize, &guard).unwrap();
+            }
+            for k in (0..1_000).step_by(2) {
+                tree.remove(&(k * 4 + t), &guard).unwrap();
+            }
+        }));
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+    drop(tree);
+
+    let expected: BTreeMap<u64, usize> = (0..4u64)
+        .flat_map(|t| (1..1_000).step_by(2).map(move |k| (k * 4 + t, k as usize)))
+        .collect();
+    assert_same(&open(&dir, 0), &expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
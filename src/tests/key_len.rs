@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use crate::utils::leak_check::LeakCheckAllocator;
+use crate::{CongeeRaw, CongeeSet, DefaultAllocator};
+
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[test]
+fn u32_keys() {
+    let tree: CongeeRaw<u32, usize, DefaultAllocator, 4> = CongeeRaw::default();
+    let guard = tree.pin();
+    for k in 0..10_000u32 {
+        tree.insert(k, k as usize, &guard).unwrap();
+    }
+    for k in 0..10_000u32 {
+        assert_eq!(tree.get(&k, &guard), Some(k as usize));
+    }
+    assert_eq!(tree.get(&10_000, &guard), None);
+
+    let mut result = [(0u32, 0usize); 8];
+    let n = tree.range(&100, &200, &mut result, &guard);
+    assert_eq!(n, 8);
+    for (i, (k, v)) in result.iter().enumerate() {
+        assert_eq!(*k, 100 + i as u32);
+        assert_eq!(*v, 100 + i);
+    }
+}
+
+#[test]
+fn u128_long_shared_prefix() {
+    // All keys share the first 12 bytes, longer than the prefix a single node can hold.
+    let base = 0xdead_beef_dead_beef_dead_beef_0000_0000u128;
+    let tree: CongeeRaw<u128, usize, LeakCheckAllocator, 16> =
+        CongeeRaw::new(LeakCheckAllocator::new());
+    let guard = tree.pin();
+
+    tree.insert(base | 7, 7, &guard).unwrap();
+    assert_eq!(tree.get(&(base | 7), &guard), Some(7));
+    assert_eq!(tree.get(&(base | 8), &guard), None);
+    assert_eq!(tree.get(&7, &guard), None);
+
+    for k in 0..1_000u128 {
+        tree.insert(base | (k << 20), k as usize, &guard).unwrap();
+    }
+    // splits inside the long prefix
+    tree.insert(1u128 << 60, 1, &guard).unwrap();
+    tree.insert(u128::MAX, 2, &guard).unwrap();
+
+    for k in 0..1_000u128 {
+        assert_eq!(tree.get(&(base | (k << 20)), &guard), Some(k as usize));
+    }
+    assert_eq!(tree.get(&(base | 7), &guard), Some(7));
+    assert_eq!(tree.get(&(1u128 << 60), &guard), Some(1));
+    assert_eq!(tree.get(&u128::MAX, &guard), Some(2));
+
+    let mut keys = tree.keys();
+    keys.sort();
+    assert_eq!(keys.len(), 1_003);
+    assert_eq!(keys[0], 1u128 << 60);
+    assert_eq!(keys[1_002], u128::MAX);
+
+    assert_eq!(tree.remove(&(base | 7), &guard), Some(7));
+    assert_eq!(tree.get(&(base | 7), &guard), None);
+}
+
+#[test]
+fn tuple_keys_match_btree() {
+    let tree: CongeeRaw<(u64, u64), usize, DefaultAllocator, 16> = CongeeRaw::default();
+    let guard = tree.pin();
+    let mut expected = BTreeMap::new();
+    let mut rng = StdRng::seed_from_u64(42);
+    for i in 0..10_000usize {
+        let key = (
+            rng.gen_range(0..16u64),
+            rng.r#gen::<u64>() >> rng.gen_range(0..64),
+        );
+        tree.insert(key, i, &guard).unwrap();
+        expected.insert(key, i);
+    }
+
+    for (k, v) in expected.iter() {
+        assert_eq!(tree.get(k, &guard), Some(*v));
+    }
+
+    let start = (3u64, 0u64);
+    let end = (5u64, u64::MAX);
+    let mut result = vec![((0u64, 0u64), 0usize); expected.len()];
+    let n = tree.range(&start, &end, &mut result, &guard);
+    let scanned: Vec<_> = result[..n].to_vec();
+    let want: Vec<_> = expected.range(start..=end).map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(scanned, want);
+}
+
+#[test]
+fn byte_array_set() {
+    let set: CongeeSet<[u8; 16], DefaultAllocator, 16> = CongeeSet::default();
+    let guard = set.pin();
+    let mut key = [0xabu8; 16];
+    for i in 0..=255u8 {
+        key[15] = i;
+        key[3] = i % 4;
+        assert!(set.insert(key, &guard).unwrap());
+    }
+    for i in 0..=255u8 {
+        key[15] = i;
+        key[3] = i % 4;
+        assert!(set.contains(&key, &guard));
+        key[3] = (i % 4) + 4;
+        assert!(!set.contains(&key, &guard));
+    }
+    assert_eq!(set.keys().len(), 256);
+}
+
+#[test]
+fn signed_keys_scan_in_order() {
+    let tree: CongeeRaw<i64, usize> = CongeeRaw::default();
+    let guard = tree.pin();
+    for k in -500i64..500 {
+        tree.insert(k * 1_000_003, (k + 500) as usize, &guard)
+            .unwrap();
+    }
+
+    let mut result = vec![(0i64, 0usize); 1_000];
+    let n = tree.range(&i64::MIN, &i64::MAX, &mut result, &guard);
+    assert_eq!(n, 1_000);
+    for (i, (k, v)) in result.iter().enumerate() {
+        assert_eq!(*k, (i as i64 - 500) * 1_000_003);
+        assert_eq!(*v, i);
+    }
+
+    let n = tree.range(&-2_000_006, &1_000_004, &mut result, &guard);
+    assert_eq!(n, 4);
+    assert_eq!(
+        result[..n].iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+        vec![-2_000_006, -1_000_003, 0, 1_000_003]
+    );
+}
+
+#[test]
+fn composite_keys() {
+    let set: CongeeSet<(i32, f64), DefaultAllocator, 12> = CongeeSet::default();
+    let guard = set.pin();
+    for tenant in -2i32..2 {
+        for score in [-1.5f64, 0.0, 2.25, f64::INFINITY] {
+            set.insert((tenant, score), &guard).unwrap();
+        }
+    }
+    assert!(set.contains(&(-1, 2.25), &guard));
+    assert!(!set.contains(&(-1, 2.5), &guard));
+
+    let mut result = [(0i32, 0f64); 16];
+    let n = set.range(&(-1, f64::NEG_INFINITY), &(0, 1.0), &mut result, &guard);
+    assert_eq!(
+        &result[..n],
+        &[
+            (-1, -1.5),
+            (-1, 0.0),
+            (-1, 2.25),
+            (-1, f64::INFINITY),
+            (0, -1.5),
+            (0, 0.0)
+        ]
+    );
+}
@@ -3,10 +3,40 @@ use std::sync::Arc;
 use crate::DefaultAllocator;
 
 mod alloc;
+mod bulk_load;
+mod checkpoint;
+mod durable;
+mod key_len;
 mod memory_stats;
+mod remove;
 mod scan;
+#[cfg(feature = "serde")]
+mod serialize;
+mod snapshot;
+mod transaction;
 mod tree;
 
+/// Key sets that shape compact sets and maps differently: empty, a single key, dense and sparse runs,
+/// keys that only differ in the first and last byte, the extremes of the key space, and random keys.
+pub(crate) fn compact_key_patterns() -> Vec<Vec<usize>> {
+    let mut seed = 12345usize;
+    let random = (0..2000)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            seed
+        })
+        .collect();
+    vec![
+        vec![],
+        vec![42],
+        (0..5000).collect(),
+        (0..3000).map(|i| i * 7).collect(),
+        (0..64).map(|i| i << 56 | i).collect(),
+        vec![0, 1 << 32, usize::MAX - 1, usize::MAX],
+        random,
+    ]
+}
+
 #[test]
 fn drop_with_drainer() {
     let deleted_key = Arc::new(std::sync::atomic::AtomicUsize::new(0));
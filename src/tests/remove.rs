@@ -0,0 +1,533 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+#[cfg(all(feature = "shuttle", test))]
+use shuttle::thread;
+
+#[cfg(not(all(feature = "shuttle", test)))]
+use std::thread;
+
+use crate::utils::leak_check::LeakCheckAllocator;
+use crate::{CongeeRaw, CongeeSet};
+
+use rand::prelude::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+/// A tree that had most of its keys removed should be as compact as one built from the remaining keys.
+#[test]
+fn remove_shrinks_to_fresh_shape() {
+    let tree: CongeeSet<usize, LeakCheckAllocator> = CongeeSet::new(LeakCheckAllocator::new());
+    let guard = tree.pin();
+
+    let mut r = StdRng::seed_from_u64(42);
+    let mut keys: Vec<usize> = (0..50_000)
+        .map(|i| if i % 2 == 0 { i } else { r.r#gen() })
+        .collect();
+    for k in keys.iter() {
+        tree.insert(*k, &guard).unwrap();
+    }
+    let full_nodes = tree.stats().total_nodes();
+
+    keys.shuffle(&mut r);
+    let (kept, removed) = keys.split_at(500);
+    for k in removed.iter() {
+        assert!(tree.remove(k, &guard));
+    }
+
+    let fresh = CongeeSet::<usize>::default();
+    for k in kept.iter() {
+        fresh.insert(*k, &guard).unwrap();
+    }
+
+    let stats = tree.stats();
+    let fresh_stats = fresh.stats();
+    assert!(stats.total_nodes() * 10 < full_nodes);
+    assert_eq!(stats.total_nodes(), fresh_stats.total_nodes());
+    assert!(stats.total_memory_bytes() <= fresh_stats.total_memory_bytes() * 2);
+
+    let expected: BTreeSet<usize> = kept.iter().copied().collect();
+    assert_eq!(
+        tree.iter(&guard).collect::<Vec<_>>(),
+        expected.into_iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn shrink_each_node_type() {
+    let tree = CongeeRaw::<usize, usize>::default();
+    let guard = tree.pin();
+
+    // All keys below one N256 at the last level.
+    for k in 0..256 {
+        tree.insert(k, k, &guard).unwrap();
+    }
+    let (_, _, _, n256_memory) = tree.stats().memory_by_node_type();
+    assert!(n256_memory > 0);
+
+    for k in 3..256 {
+        assert_eq!(tree.remove(&k, &guard), Some(k));
+        for probe in [0, 1, 2, k + 1] {
+            let expected = (probe <= 2 || probe > k) && probe < 256;
+            assert_eq!(tree.get(&probe, &guard).is_some(), expected);
+        }
+    }
+    let (_, n16_memory, n48_memory, n256_memory) = tree.stats().memory_by_node_type();
+    assert_eq!((n16_memory, n48_memory, n256_memory), (0, 0, 0));
+
+    // Growing again right after shrinking works as usual.
+    for k in 3..256 {
+        tree.insert(k, k, &guard).unwrap();
+    }
+    for k in 0..256 {
+        assert_eq!(tree.get(&k, &guard), Some(k));
+    }
+}
+
+#[test]
+fn remove_merges_single_child_nodes() {
+    let tree = CongeeRaw::<usize, usize>::default();
+    let guard = tree.pin();
+
+    let a = 0x0101_0101_0101_0101usize;
+    let b = 0x0101_0102_0101_0101usize;
+    let c = 0x0101_0102_0101_0202usize;
+    for k in [a, b, c] {
+        tree.insert(k, k, &guard).unwrap();
+    }
+    // root, the node splitting `a` from the others, the node splitting `b` from `c`, and a leaf node per key.
+    assert_eq!(tree.stats().total_nodes(), 6);
+
+    // The leaf node of `a` is freed, the node splitting `a` from the others is left with one child and merged into it.
+    tree.remove(&a, &guard).unwrap();
+    assert_eq!(tree.stats().total_nodes(), 4);
+    assert_eq!(tree.get(&b, &guard), Some(b));
+    assert_eq!(tree.get(&c, &guard), Some(c));
+
+    let mut result = [(0, 0); 4];
+    assert_eq!(tree.range_bounds(.., &mut result, &guard), 2);
+    assert_eq!(result[..2], [(b, b), (c, c)]);
+
+    // Splitting the merged prefix again.
+    tree.insert(a, a, &guard).unwrap();
+    assert_eq!(tree.get(&a, &guard), Some(a));
+    assert_eq!(tree.stats().total_nodes(), 6);
+
+    for k in [a, b, c] {
+        tree.remove(&k, &guard).unwrap();
+    }
+    assert!(tree.is_empty(&guard));
+    assert_eq!(tree.stats().total_nodes(), 1);
+}
+
+#[test]
+fn remove_long_keys_frees_chains() {
+    let base = 0xdead_beef_dead_beef_dead_beef_0000_0000u128;
+    let tree: CongeeRaw<u128, usize, LeakCheckAllocator, 16> =
+        CongeeRaw::new(LeakCheckAllocator::new());
+    let guard = tree.pin();
+
+    for k in 0..1_000u128 {
+        tree.insert(base | (k << 20), k as usize, &guard).unwrap();
+        tree.insert(k << 100, k as usize, &guard).unwrap();
+    }
+    for k in 0..1_000u128 {
+        assert_eq!(tree.remove(&(base | (k << 20)), &guard), Some(k as usize));
+    }
+    for k in 0..1_000u128 {
+        assert_eq!(tree.get(&(k << 100), &guard), Some(k as usize));
+        assert_eq!(tree.remove(&(k << 100), &guard), Some(k as usize));
+    }
+    assert!(tree.is_empty(&guard));
+    assert_eq!(tree.stats().total_nodes(), 1);
+}
+
+#[test]
+fn concurrent_remove_and_read() {
+    let key_cnt = 20_000usize;
+    let tree: Arc<CongeeRaw<usize, usize, LeakCheckAllocator>> =
+        Arc::new(CongeeRaw::new(LeakCheckAllocator::new()));
+    {
+        let guard = tree.pin();
+        for k in 0..key_cnt {
+            tree.insert(k * 3, k, &guard).unwrap();
+        }
+    }
+
+    let mut handlers = Vec::new();
+    for t in 0..2 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t);
+            let mut keys: Vec<usize> = (0..key_cnt).filter(|k| k % 4 == t as usize).collect();
+            keys.shuffle(&mut r);
+            let guard = tree.pin();
+            for k in keys {
+                assert_eq!(tree.remove(&(k * 3), &guard), Some(k));
+            }
+        }));
+    }
+    {
+        // Writer re-inserting keys in between the removed ones.
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let guard = tree.pin();
+            for k in 0..key_cnt {
+                tree.insert(k * 3 + 1, k, &guard).unwrap();
+            }
+        }));
+    }
+    {
+        // Keys not touched by the removers must stay visible.
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(7);
+            let guard = tree.pin();
+            for _ in 0..key_cnt * 2 {
+                let k = r.gen_range(0..key_cnt);
+                if k % 4 >= 2 {
+                    assert_eq!(tree.get(&(k * 3), &guard), Some(k));
+                }
+            }
+        }));
+    }
+    {
+        // Scans see the untouched keys in order while nodes shrink and merge underneath.
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let guard = tree.pin();
+            for _ in 0..4 {
+                let mut last = None;
+                let mut untouched = 0;
+                for (k, v) in tree.iter(&guard) {
+                    assert!(last < Some(k));
+                    last = Some(k);
+                    if k % 3 == 0 && v % 4 >= 2 {
+                        untouched += 1;
+                    }
+                }
+                assert_eq!(untouched, key_cnt / 2);
+            }
+        }));
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+
+    let guard = tree.pin();
+    for k in 0..key_cnt {
+        let expected = if k % 4 >= 2 { Some(k) } else { None };
+        assert_eq!(tree.get(&(k * 3), &guard), expected);
+        assert_eq!(tree.get(&(k * 3 + 1), &guard), Some(k));
+    }
+}
+
+#[test]
+fn remove_range_matches_btree() {
+    let mut r = StdRng::seed_from_u64(11);
+    for round in 0..20 {
+        let tree = CongeeRaw::<usize, usize>::default();
+        let guard = tree.pin();
+        let mut expected = BTreeSet::new();
+        let bits = if round % 2 == 0 { 16 } else { 64 };
+        for _ in 0..5_000 {
+            let k: usize = r.r#gen::<usize>() >> (64 - bits);
+            tree.insert(k, k, &guard).unwrap();
+            expected.insert(k);
+        }
+
+        for _ in 0..10 {
+            let a: usize = r.r#gen::<usize>() >> (64 - bits);
+            let b: usize = r.r#gen::<usize>() >> (64 - bits);
+            let (lo, hi) = (a.min(b), a.max(b));
+            let to_remove: Vec<usize> = expected.range(lo..hi).copied().collect();
+            assert_eq!(tree.remove_range(lo..hi, &guard), to_remove.len());
+            for k in to_remove {
+                expected.remove(&k);
+            }
+            assert_eq!(
+                tree.keys(),
+                expected.iter().copied().collect::<Vec<_>>(),
+                "after removing [{lo}, {hi})"
+            );
+        }
+        for k in expected.iter() {
+            assert_eq!(tree.get(k, &guard), Some(*k));
+        }
+    }
+}
+
+#[test]
+fn remove_range_frees_detached_nodes() {
+    let tree: CongeeRaw<usize, usize, LeakCheckAllocator> =
+        CongeeRaw::new(LeakCheckAllocator::new());
+    let guard = tree.pin();
+    for k in 0..20_000 {
+        tree.insert(k * 7, k, &guard).unwrap();
+    }
+
+    assert_eq!(tree.remove_range(..=0, &guard), 1);
+    assert_eq!(tree.remove_range(7..7, &guard), 0);
+    assert_eq!(tree.remove_range(7..=7, &guard), 1);
+    assert_eq!(tree.remove_range(1000 * 7..2000 * 7, &guard), 1000);
+    assert_eq!(tree.get(&(999 * 7), &guard), Some(999));
+    assert_eq!(tree.get(&(2000 * 7), &guard), Some(2000));
+
+    assert_eq!(tree.remove_range(.., &guard), 20_000 - 1002);
+    assert!(tree.is_empty(&guard));
+    assert_eq!(tree.stats().total_nodes(), 1);
+
+    // The tree is still usable after being emptied.
+    tree.insert(42, 42, &guard).unwrap();
+    assert_eq!(tree.get(&42, &guard), Some(42));
+}
+
+#[test]
+fn remove_range_merges_parent_of_emptied_node() {
+    let tree = CongeeRaw::<usize, usize>::default();
+    let guard = tree.pin();
+
+    let a = 0x0101_0101_0101_0101usize;
+    let b = 0x0101_0102_0101_0101usize;
+    let c = 0x0101_0102_0101_0202usize;
+    for k in [a, a + 1, b, c] {
+        tree.insert(k, k, &guard).unwrap();
+    }
+    assert_eq!(tree.stats().total_nodes(), 6);
+
+    // The leaf node of `a` is emptied and unlinked, its parent is left with one child and merged into it.
+    assert_eq!(tree.remove_range(a..=a + 1, &guard), 2);
+    assert_eq!(tree.stats().total_nodes(), 4);
+    assert_eq!(tree.get(&b, &guard), Some(b));
+    assert_eq!(tree.get(&c, &guard), Some(c));
+    assert_eq!(tree.len(&guard), 2);
+
+    assert_eq!(tree.remove_range(b..=c, &guard), 2);
+    assert!(tree.is_empty(&guard));
+    assert_eq!(tree.stats().total_nodes(), 1);
+}
+
+#[test]
+fn concurrent_remove_range_counts_inserts() {
+    // Each key added by an insert is counted by exactly one removal, or is still in the tree at the end.
+    let key_cnt = 1usize << 16;
+    let tree: Arc<CongeeRaw<usize, usize>> = Arc::new(CongeeRaw::default());
+
+    let mut handlers = Vec::new();
+    for t in 0..4u64 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t);
+            let mut added = 0;
+            for _ in 0..100_000 {
+                let guard = tree.pin();
+                let k = r.gen_range(0..key_cnt);
+                if tree.insert(k, k, &guard).unwrap().is_none() {
+                    added += 1;
+                }
+            }
+            added
+        }));
+    }
+    for t in 0..2u64 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t + 10);
+            let mut removed = 0;
+            for _ in 0..2_000 {
+                let guard = tree.pin();
+                let k = r.gen_range(0..key_cnt);
+                let len = 1usize << r.gen_range(0..14);
+                removed -= tree.remove_range(k..k + len, &guard) as isize;
+            }
+            removed
+        }));
+    }
+    let total: isize = handlers.into_iter().map(|h| h.join().unwrap()).sum();
+
+    let guard = tree.pin();
+    let remaining = tree.iter(&guard).count();
+    assert_eq!(total, remaining as isize);
+    assert_eq!(tree.len(&guard), remaining);
+}
+
+#[test]
+fn remove_range_and_retain_drain_entries() {
+    let drained = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let tree = {
+        let drained = drained.clone();
+        CongeeRaw::<usize, usize>::new_with_drainer(
+            crate::DefaultAllocator {},
+            move |k: usize, v: usize| {
+                drained.lock().unwrap().push((k, v));
+            },
+        )
+    };
+    {
+        let guard = tree.pin();
+        for k in 0..1_000 {
+            tree.insert(k, k + 1, &guard).unwrap();
+        }
+        assert_eq!(tree.remove_range(100..200, &guard), 100);
+        assert_eq!(tree.retain(|k, _v| k % 2 == 0, &guard), 450);
+    }
+    // Entries are drained once the guards that might still see them are gone.
+    for _ in 0..128 {
+        crossbeam_epoch::pin().flush();
+    }
+    let mut drained_keys: Vec<usize> = drained
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(k, v)| {
+            assert_eq!(k + 1, *v);
+            *k
+        })
+        .collect();
+    drained_keys.sort();
+    let expected: Vec<usize> = (0..1_000)
+        .filter(|k| (100..200).contains(k) || k % 2 == 1)
+        .collect();
+    assert_eq!(drained_keys, expected);
+    assert_eq!(tree.keys().len(), 450);
+}
+
+#[test]
+fn congee_remove_range_releases_values() {
+    let tree: crate::Congee<usize, usize> = crate::Congee::new();
+    let values: Vec<Arc<usize>> = (0..100).map(Arc::new).collect();
+    {
+        let guard = tree.pin();
+        for (k, v) in values.iter().enumerate() {
+            tree.insert(k, v.clone(), &guard).unwrap();
+        }
+        assert_eq!(tree.remove_range(..50, &guard), 50);
+        assert_eq!(tree.retain(|_k, v| *v % 10 != 0, &guard), 5);
+    }
+    for _ in 0..128 {
+        crossbeam_epoch::pin().flush();
+    }
+    for (k, v) in values.iter().enumerate() {
+        let in_tree = k >= 50 && k % 10 != 0;
+        assert_eq!(Arc::strong_count(v), if in_tree { 2 } else { 1 });
+    }
+}
+
+#[test]
+fn concurrent_remove_range() {
+    let key_cnt = 10_240usize;
+    let tree: Arc<CongeeSet<usize, LeakCheckAllocator>> =
+        Arc::new(CongeeSet::new(LeakCheckAllocator::new()));
+    {
+        let guard = tree.pin();
+        for k in 0..key_cnt {
+            tree.insert(k * 2, &guard).unwrap();
+        }
+    }
+
+    let mut handlers = Vec::new();
+    for t in 0..2usize {
+        // Each thread removes its own half of the even keys, one leaf node at a time.
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let guard = tree.pin();
+            let half = key_cnt / 2;
+            for chunk in (0..half).step_by(128) {
+                let lo = (t * half + chunk) * 2;
+                assert_eq!(tree.remove_range(lo..lo + 256, &guard), 128);
+            }
+        }));
+    }
+    {
+        // Odd keys inserted concurrently are never inside a removed range.
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let guard = tree.pin();
+            for k in 0..key_cnt {
+                tree.insert(k * 2 + 1 + key_cnt * 2, &guard).unwrap();
+            }
+        }));
+    }
+    {
+        // A removed chunk disappears at once, a scan never sees part of it.
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(3);
+            let guard = tree.pin();
+            let mut result = [0; 256];
+            for _ in 0..2_000 {
+                let lo = r.gen_range(0..key_cnt / 128) * 256;
+                let cnt = tree.range(&lo, &(lo + 256), &mut result, &guard);
+                assert!(cnt == 0 || cnt == 128, "saw {cnt} keys of chunk {lo}");
+            }
+        }));
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+
+    let guard = tree.pin();
+    let expected: Vec<usize> = (0..key_cnt).map(|k| k * 2 + 1 + key_cnt * 2).collect();
+    assert_eq!(tree.iter(&guard).collect::<Vec<_>>(), expected);
+}
+
+/// Counts the values alive, so a value released twice or never shows up in the count.
+struct Live(Arc<std::sync::atomic::AtomicIsize>);
+
+impl Live {
+    fn new(live: &Arc<std::sync::atomic::AtomicIsize>) -> Arc<Self> {
+        live.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Arc::new(Self(live.clone()))
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn concurrent_remove_range_with_writers() {
+    let live = Arc::new(std::sync::atomic::AtomicIsize::new(0));
+    let tree: Arc<crate::Congee<usize, Live>> = Arc::new(crate::Congee::new());
+
+    let mut handlers = Vec::new();
+    for t in 0..4u64 {
+        let tree = tree.clone();
+        let live = live.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t);
+            for _ in 0..2_000 {
+                let guard = tree.pin();
+                let k = r.gen_range(0..1usize << 16);
+                match r.gen_range(0..10) {
+                    // Overlapping ranges of all sizes, from single leaves to large subtrees.
+                    0 => {
+                        let len = 1usize << r.gen_range(0..14);
+                        tree.remove_range(k..k + len, &guard);
+                    }
+                    1..=2 => {
+                        tree.remove(k, &guard);
+                    }
+                    _ => {
+                        tree.insert(k, Live::new(&live), &guard).unwrap();
+                    }
+                }
+            }
+        }));
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+
+    {
+        let guard = tree.pin();
+        assert_eq!(tree.len(&guard), tree.iter(&guard).count());
+    }
+    drop(tree);
+    for _ in 0..128 {
+        crossbeam_epoch::pin().flush();
+    }
+    assert_eq!(live.load(std::sync::atomic::Ordering::Relaxed), 0);
+}
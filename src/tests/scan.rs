@@ -24,7 +24,7 @@ fn small_scan() {
     let high_key: [u8; 8] = (low_v + scan_cnt).to_be_bytes();
 
     let mut results = [([0; 8], 0); 20];
-    let scan_r = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scan_r = tree.range(&low_key, &high_key, &mut results[..], &guard);
 
     assert_eq!(scan_r, scan_cnt);
     for (i, r) in results.iter().enumerate().take(scan_r) {
@@ -62,7 +62,7 @@ fn large_scan() {
 
         let mut scan_results = vec![([0; 8], 0); *scan_cnt];
 
-        let r_found = tree.range(&low_key, &high_key, &mut scan_results, &guard);
+        let r_found = tree.range(&low_key, &high_key, &mut scan_results[..], &guard);
         assert_eq!(r_found, *scan_cnt);
 
         for (i, v) in scan_results.iter().enumerate() {
@@ -79,7 +79,7 @@ fn large_scan() {
         let high_key: [u8; 8] = (low_key_v + scan_cnt).to_be_bytes();
 
         let mut scan_results = vec![([0; 8], 0); *scan_cnt];
-        let r_found = tree.range(&low_key, &high_key, &mut scan_results, &guard);
+        let r_found = tree.range(&low_key, &high_key, &mut scan_results[..], &guard);
         assert_eq!(r_found, 0);
     }
 }
@@ -114,7 +114,7 @@ fn large_scan_small_buffer() {
 
         let mut scan_results = vec![([0; 8], 0); (*scan_cnt) / 2];
 
-        let r_found = tree.range(&low_key, &high_key, &mut scan_results, &guard);
+        let r_found = tree.range(&low_key, &high_key, &mut scan_results[..], &guard);
         assert_eq!(r_found, *scan_cnt / 2);
 
         for (i, v) in scan_results.iter().enumerate() {
@@ -128,7 +128,7 @@ fn large_scan_small_buffer() {
         let high_key: [u8; 8] = 0x6_ffff_usize.to_be_bytes();
         let mut scan_results = vec![([0; 8], 0); *scan_cnt];
 
-        let r_found = tree.range(&low_key, &high_key, &mut scan_results, &guard);
+        let r_found = tree.range(&low_key, &high_key, &mut scan_results[..], &guard);
         assert_eq!(r_found, *scan_cnt);
 
         for (i, v) in scan_results.iter().enumerate() {
@@ -137,6 +137,213 @@ fn large_scan_small_buffer() {
     }
 }
 
+#[test]
+fn large_scan_reverse() {
+    let tree = CongeeInner::default();
+    let key_cnt = 100_000;
+    let mut key_space: Vec<usize> = (0..key_cnt).map(|i| i * 3).collect();
+
+    let mut r = StdRng::seed_from_u64(42);
+    key_space.shuffle(&mut r);
+
+    let guard = crossbeam_epoch::pin();
+    for v in key_space.iter() {
+        let key: [u8; 8] = v.to_be_bytes();
+        tree.insert(&key, *v, &guard).unwrap();
+    }
+
+    let scan_counts = [3, 13, 65, 300];
+
+    for _r in 0..32 {
+        let scan_cnt = *scan_counts.choose(&mut r).unwrap();
+        let low_v = r.gen_range(0..key_cnt * 3);
+        let high_v = low_v + r.gen_range(0..1_000);
+
+        let low_key: [u8; 8] = low_v.to_be_bytes();
+        let high_key: [u8; 8] = high_v.to_be_bytes();
+
+        let mut scan_results = vec![([0; 8], 0); scan_cnt];
+        let r_found = tree.range_rev(&low_key, &high_key, &mut scan_results[..], &guard);
+
+        let expected: Vec<usize> = (low_v..high_v)
+            .rev()
+            .filter(|v| v % 3 == 0 && *v < key_cnt * 3)
+            .take(scan_cnt)
+            .collect();
+        assert_eq!(r_found, expected.len());
+        for (v, e) in scan_results.iter().zip(expected.iter()) {
+            assert_eq!(v.1, *e);
+            assert_eq!(v.0, e.to_be_bytes());
+        }
+
+        let mut scan_results = vec![([0; 8], 0); scan_cnt];
+        let r_found = tree.range_inclusive_rev(&low_key, &high_key, &mut scan_results[..], &guard);
+        let expected_first = (low_v..=high_v)
+            .rev()
+            .find(|v| v % 3 == 0 && *v < key_cnt * 3);
+        assert_eq!(r_found > 0, expected_first.is_some());
+        if let Some(first) = expected_first {
+            assert_eq!(scan_results[0].1, first);
+        }
+    }
+}
+
+#[test]
+fn ordered_lookups_match_btree() {
+    use std::collections::BTreeSet;
+    use std::ops::Bound;
+
+    let tree = CongeeInner::default();
+    let guard = crossbeam_epoch::pin();
+    assert!(tree.first(&guard).is_none());
+    assert!(tree.last(&guard).is_none());
+    assert!(tree.floor(&[u8::MAX; 8], &guard).is_none());
+
+    let mut r = StdRng::seed_from_u64(42);
+    let mut expected = BTreeSet::new();
+    for _ in 0..20_000 {
+        // Mix dense small keys with sparse large ones to get every node type.
+        let v: usize = if r.gen_bool(0.5) {
+            r.gen_range(0..50_000)
+        } else {
+            r.r#gen()
+        };
+        tree.insert(&v.to_be_bytes(), v, &guard).unwrap();
+        expected.insert(v);
+    }
+
+    let decode = |e: Option<([u8; 8], usize)>| {
+        e.map(|(k, v)| {
+            assert_eq!(usize::from_be_bytes(k), v);
+            v
+        })
+    };
+    assert_eq!(decode(tree.first(&guard)), expected.first().copied());
+    assert_eq!(decode(tree.last(&guard)), expected.last().copied());
+
+    for _ in 0..10_000 {
+        let probe: usize = if r.gen_bool(0.5) {
+            r.gen_range(0..50_000)
+        } else {
+            r.r#gen()
+        };
+        let key = probe.to_be_bytes();
+        assert_eq!(
+            decode(tree.ceiling(&key, &guard)),
+            expected.range(probe..).next().copied()
+        );
+        assert_eq!(
+            decode(tree.floor(&key, &guard)),
+            expected.range(..=probe).next_back().copied()
+        );
+        assert_eq!(
+            decode(tree.successor(&key, &guard)),
+            expected
+                .range((Bound::Excluded(probe), Bound::Unbounded))
+                .next()
+                .copied()
+        );
+        assert_eq!(
+            decode(tree.predecessor(&key, &guard)),
+            expected.range(..probe).next_back().copied()
+        );
+    }
+}
+
+#[test]
+fn ordered_lookups_across_prefixes() {
+    use std::collections::BTreeSet;
+
+    // Keys that share long compressed prefixes, so probes diverge from the tree inside a prefix on either side.
+    let tree = CongeeInner::default();
+    let guard = crossbeam_epoch::pin();
+    let mut expected = BTreeSet::new();
+    for hi in [0usize, 0x17, 0x80, 0xff] {
+        for lo in [0usize, 0x3c, 0x41, 0xff] {
+            let v = hi << 56 | 0x00_5a5a_5a00_0000 | lo;
+            tree.insert(&v.to_be_bytes(), v, &guard).unwrap();
+            expected.insert(v);
+        }
+    }
+
+    for hi in [0usize, 0x16, 0x17, 0x18, 0x80, 0xfe, 0xff] {
+        for mid in [
+            0usize,
+            0x00_5a5a_5a00_0000,
+            0x00_5a5b_0000_0000,
+            0x00_5a59_ffff_0000,
+        ] {
+            for lo in [0usize, 0x3c, 0x3d, 0x41, 0xfe, 0xff] {
+                let probe = hi << 56 | mid | lo;
+                let key = probe.to_be_bytes();
+                assert_eq!(
+                    tree.ceiling(&key, &guard).map(|(_, v)| v),
+                    expected.range(probe..).next().copied()
+                );
+                assert_eq!(
+                    tree.floor(&key, &guard).map(|(_, v)| v),
+                    expected.range(..=probe).next_back().copied()
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn ordered_lookups_with_concurrent_writes() {
+    // Multiples of 4 stay in the tree, the other keys come and go, so every lookup has a stable bound.
+    let stable_cnt: usize = 20_000;
+    let tree = Arc::new(CongeeInner::default());
+    let guard = crossbeam_epoch::pin();
+    for i in 0..stable_cnt {
+        let v = i * 4;
+        tree.insert(&v.to_be_bytes(), v, &guard).unwrap();
+    }
+    drop(guard);
+
+    let mut handlers = Vec::new();
+    for t in 0..2 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t);
+            for _ in 0..100_000 {
+                let v = r.gen_range(0..stable_cnt * 4) | (1 + t as usize);
+                let guard = crossbeam_epoch::pin();
+                if r.gen_bool(0.5) {
+                    tree.insert(&v.to_be_bytes(), v, &guard).unwrap();
+                } else {
+                    tree.compute_if_present(&v.to_be_bytes(), &mut |_v| None, &guard);
+                }
+            }
+        }));
+    }
+    for t in 0..4 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t + 100);
+            for _ in 0..50_000 {
+                let probe = r.gen_range(0..stable_cnt * 4 - 4);
+                let key = probe.to_be_bytes();
+                let guard = crossbeam_epoch::pin();
+
+                let (k, v) = tree.ceiling(&key, &guard).unwrap();
+                assert_eq!(usize::from_be_bytes(k), v);
+                assert!(v >= probe && v <= probe.next_multiple_of(4));
+
+                let (k, v) = tree.floor(&key, &guard).unwrap();
+                assert_eq!(usize::from_be_bytes(k), v);
+                assert!(v <= probe && v >= probe / 4 * 4);
+
+                assert_eq!(tree.first(&guard).unwrap().1, 0);
+                assert!(tree.last(&guard).unwrap().1 >= (stable_cnt - 1) * 4);
+            }
+        }));
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+}
+
 #[test]
 fn test_insert_and_scan() {
     let insert_thread = 2;
@@ -170,7 +377,7 @@ fn test_insert_and_scan() {
             let high_key: [u8; 8] = (low_key_v + scan_cnt).to_be_bytes();
 
             let mut scan_results = vec![([0; 8], 0); *scan_cnt];
-            let _v = tree.range(&low_key, &high_key, &mut scan_results, &guard);
+            let _v = tree.range(&low_key, &high_key, &mut scan_results[..], &guard);
         }));
     }
 
@@ -213,7 +420,7 @@ fn fuzz_0() {
     let high_key: [u8; 8] = 0usize.to_be_bytes();
 
     let mut results = vec![([0; 8], 0); 255];
-    let scanned = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scanned = tree.range(&low_key, &high_key, &mut results[..], &guard);
     assert_eq!(scanned, 0);
 }
 
@@ -231,12 +438,12 @@ fn fuzz_1() {
     let high_key: [u8; 8] = (scan_key + 255).to_be_bytes();
 
     let mut results = vec![([0; 8], 0); 256];
-    let scanned = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scanned = tree.range(&low_key, &high_key, &mut results[..], &guard);
     assert_eq!(scanned, 0);
 
     let low_key: [u8; 8] = value.to_be_bytes();
     let high_key: [u8; 8] = (value + 255).to_be_bytes();
-    let scanned = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scanned = tree.range(&low_key, &high_key, &mut results[..], &guard);
     assert_eq!(scanned, 1);
 }
 
@@ -258,7 +465,7 @@ fn fuzz_2() {
     let high_key: [u8; 8] = (scan_key + 253).to_be_bytes();
 
     let mut results = vec![([0; 8], 0); 256];
-    let scanned = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scanned = tree.range(&low_key, &high_key, &mut results[..], &guard);
     assert_eq!(scanned, 0);
 }
 
@@ -280,14 +487,14 @@ fn fuzz_3() {
     let high_key: [u8; 8] = (scan_key + 253).to_be_bytes();
 
     let mut results = vec![([0; 8], 0); 256];
-    let scanned = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scanned = tree.range(&low_key, &high_key, &mut results[..], &guard);
     assert_eq!(scanned, 2);
 
     let scan_key = 4294967000usize;
     let low_key: [u8; 8] = scan_key.to_be_bytes();
     let high_key: [u8; 8] = (scan_key + 253).to_be_bytes();
 
-    let scanned = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scanned = tree.range(&low_key, &high_key, &mut results[..], &guard);
     assert_eq!(scanned, 1);
 }
 
@@ -309,7 +516,7 @@ fn fuzz_4() {
     let high_key: [u8; 8] = (scan_key + 253).to_be_bytes();
 
     let mut results = vec![([0; 8], 0); 256];
-    let scanned = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scanned = tree.range(&low_key, &high_key, &mut results[..], &guard);
     assert_eq!(scanned, 0);
 }
 
@@ -331,7 +538,7 @@ fn fuzz_5() {
     let high_key: [u8; 8] = (scan_key + 255).to_be_bytes();
 
     let mut results = vec![([0; 8], 0); 256];
-    let scanned = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scanned = tree.range(&low_key, &high_key, &mut results[..], &guard);
     assert_eq!(scanned, 1);
 }
 
@@ -353,6 +560,266 @@ fn fuzz_6() {
     let high_key: [u8; 8] = (scan_key + 255).to_be_bytes();
 
     let mut results = vec![([0; 8], 0); 256];
-    let scanned = tree.range(&low_key, &high_key, &mut results, &guard);
+    let scanned = tree.range(&low_key, &high_key, &mut results[..], &guard);
     assert_eq!(scanned, 1);
 }
+
+#[test]
+fn range_bounds_edges() {
+    use crate::CongeeRaw;
+    use std::ops::Bound;
+
+    let tree = CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    for k in [0, 1, 2, u64::MAX - 1, u64::MAX] {
+        tree.insert(k, k as usize, &guard).unwrap();
+    }
+
+    let scan = |range: (Bound<u64>, Bound<u64>)| {
+        let mut result = [(0, 0); 8];
+        let n = tree.range_bounds(range, &mut result, &guard);
+        result[..n].iter().map(|(k, _)| *k).collect::<Vec<_>>()
+    };
+
+    use Bound::{Excluded, Included, Unbounded};
+    assert_eq!(scan((Included(1), Included(1))), vec![1]);
+    assert_eq!(scan((Included(1), Excluded(1))), Vec::<u64>::new());
+    assert_eq!(scan((Excluded(1), Included(1))), Vec::<u64>::new());
+    assert_eq!(scan((Excluded(0), Excluded(2))), vec![1]);
+    assert_eq!(scan((Unbounded, Excluded(2))), vec![0, 1]);
+    assert_eq!(scan((Unbounded, Excluded(0))), Vec::<u64>::new());
+    assert_eq!(
+        scan((Included(u64::MAX), Included(u64::MAX))),
+        vec![u64::MAX]
+    );
+    assert_eq!(scan((Excluded(u64::MAX), Unbounded)), Vec::<u64>::new());
+    assert_eq!(scan((Excluded(2), Unbounded)), vec![u64::MAX - 1, u64::MAX]);
+    assert_eq!(scan((Included(2), Included(1))), Vec::<u64>::new());
+    assert_eq!(scan((Unbounded, Unbounded)).len(), 5);
+
+    // The old exclusive-end range still cannot reach the largest key.
+    let mut result = [(0, 0); 8];
+    assert_eq!(tree.range(&2, &u64::MAX, &mut result, &guard), 2);
+}
+
+#[test]
+fn count_range_matches_btree() {
+    use std::ops::Bound;
+
+    let mut r = StdRng::seed_from_u64(13);
+    let tree = crate::CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    let mut expected = std::collections::BTreeSet::new();
+    for _ in 0..5_000 {
+        let k = r.gen_range(0..1u64 << 24) << (r.gen_range(0..3) * 16);
+        tree.insert(k, k as usize, &guard).unwrap();
+        expected.insert(k);
+    }
+    tree.insert(u64::MAX, 0, &guard).unwrap();
+    expected.insert(u64::MAX);
+
+    for _ in 0..100 {
+        let a = *expected.iter().nth(r.gen_range(0..expected.len())).unwrap();
+        let b = r.r#gen::<u64>() >> r.gen_range(0..40);
+        let (lo, hi) = (a.min(b), a.max(b));
+        for bounds in [
+            (Bound::Included(lo), Bound::Excluded(hi)),
+            (Bound::Excluded(lo), Bound::Included(hi)),
+            (Bound::Included(lo), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Included(hi)),
+        ] {
+            assert_eq!(
+                tree.count_range(bounds, &guard),
+                expected.range(bounds).count(),
+                "{bounds:?}"
+            );
+        }
+    }
+    assert_eq!(tree.count_range(.., &guard), expected.len());
+    assert_eq!(tree.count_range(5..5, &guard), 0);
+    assert_eq!(tree.count_range(u64::MAX.., &guard), 1);
+}
+
+#[test]
+fn prefix_ops_match_btree() {
+    // Keys are (tenant: u16, object: u48), tenant 0xffff sits at the top of the key space.
+    let mut r = StdRng::seed_from_u64(17);
+    let tenants = [0u64, 1, 2, 0x0100, 0xfffe, 0xffff];
+    let tree = crate::CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    let mut expected = std::collections::BTreeMap::new();
+    for _ in 0..4_000 {
+        let tenant = tenants[r.gen_range(0..tenants.len())];
+        let k = tenant << 48 | r.gen_range(0..1u64 << 20) << (r.gen_range(0..2) * 28);
+        tree.insert(k, k as usize, &guard).unwrap();
+        expected.insert(k, k as usize);
+    }
+    let in_prefix = |k: u64, p: u64, len: usize| len == 0 || (k ^ p) >> (64 - len * 8) == 0;
+
+    for &tenant in tenants.iter() {
+        let prefix = tenant << 48 | 0x1234;
+        let want: Vec<(u64, usize)> = expected
+            .iter()
+            .filter(|(k, _)| in_prefix(**k, prefix, 2))
+            .map(|(k, v)| (*k, *v))
+            .collect();
+
+        let mut result = vec![(0, 0); want.len() + 1];
+        let cnt = tree.scan_prefix(&prefix, 2, &mut result, &guard);
+        assert_eq!(&result[..cnt], &want[..]);
+        assert_eq!(tree.count_prefix(&prefix, 2, &guard), want.len());
+        let iterated: Vec<_> = tree.iter_prefix(&prefix, 2, &guard).collect();
+        assert_eq!(iterated, want);
+        let reversed: Vec<_> = tree.iter_prefix(&prefix, 2, &guard).rev().collect();
+        assert!(reversed.iter().eq(want.iter().rev()));
+    }
+
+    // Prefixes that end inside the object bytes.
+    for _ in 0..200 {
+        let k = *expected.keys().nth(r.gen_range(0..expected.len())).unwrap();
+        let len = r.gen_range(0..=8);
+        let want: Vec<(u64, usize)> = expected
+            .iter()
+            .filter(|(e, _)| in_prefix(**e, k, len))
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        assert_eq!(
+            tree.count_prefix(&k, len, &guard),
+            want.len(),
+            "{k:x} {len}"
+        );
+        let mut result = vec![(0, 0); 16];
+        let cnt = tree.scan_prefix(&k, len, &mut result, &guard);
+        assert_eq!(cnt, want.len().min(16));
+        assert_eq!(&result[..cnt], &want[..cnt], "{k:x} {len}");
+    }
+    assert_eq!(tree.count_prefix(&0, 0, &guard), expected.len());
+    let k = *expected.keys().next().unwrap();
+    assert_eq!(
+        tree.iter_prefix(&k, 8, &guard).collect::<Vec<_>>(),
+        vec![(k, k as usize)]
+    );
+
+    for &tenant in tenants.iter().rev() {
+        let want = expected.keys().filter(|k| *k >> 48 == tenant).count();
+        assert_eq!(tree.remove_prefix(&(tenant << 48), 2, &guard), want);
+        expected.retain(|k, _| *k >> 48 != tenant);
+        assert_eq!(tree.len(&guard), expected.len());
+        assert!(
+            tree.iter(&guard)
+                .map(|(k, _)| k)
+                .eq(expected.keys().copied())
+        );
+    }
+    assert!(tree.is_empty(&guard));
+}
+
+#[test]
+fn prefix_scan_through_long_node_prefixes() {
+    // Shared runs of more than 8 bytes make chains of nodes, a prefix can end anywhere inside them.
+    let tree = crate::CongeeSet::<u128, crate::DefaultAllocator, 16>::default();
+    let guard = tree.pin();
+    let mut expected = std::collections::BTreeSet::new();
+    for hi in [0u128, 0xab, 0xabcd] {
+        for lo in 0..300u128 {
+            let k = hi << 112 | 0x1111_2222_3333_4444 << 40 | lo << (lo % 3 * 8);
+            tree.insert(k, &guard).unwrap();
+            expected.insert(k);
+        }
+    }
+
+    let keys: Vec<u128> = expected.iter().copied().collect();
+    for k in keys.iter().step_by(37) {
+        for len in 0..=16 {
+            let want: Vec<u128> = keys
+                .iter()
+                .copied()
+                .filter(|e| len == 0 || (e ^ k) >> (128 - len * 8) == 0)
+                .collect();
+            let mut result = vec![0; want.len()];
+            assert_eq!(tree.scan_prefix(k, len, &mut result, &guard), want.len());
+            assert_eq!(result, want, "{k:x} {len}");
+            assert_eq!(tree.count_prefix(k, len, &guard), want.len());
+        }
+    }
+    // A prefix that leaves the tree inside a node prefix finds nothing.
+    let missing = 0xab << 112 | 0x1111_2222_3333_4445 << 40;
+    assert_eq!(tree.count_prefix(&missing, 11, &guard), 0);
+    assert_eq!(tree.count_prefix(&missing, 10, &guard), 300);
+}
+
+#[test]
+#[should_panic(expected = "prefix length")]
+fn prefix_longer_than_key() {
+    let tree = crate::CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    tree.count_prefix(&0, 9, &guard);
+}
+
+#[test]
+fn congee_scan_into_reused_buffer() {
+    let tree: crate::Congee<usize, usize> = crate::Congee::new();
+    let guard = tree.pin();
+    for k in 0..100 {
+        tree.insert(k, Arc::new(k), &guard).unwrap();
+    }
+
+    let old = Arc::new(usize::MAX);
+    let mut result = vec![(0, Some(old.clone())); 8];
+    for start in [96, 0, 50] {
+        let scanned = tree.range(&start, &100, &mut result, &guard);
+        assert_eq!(scanned, 8.min(100 - start));
+        for (i, (k, v)) in result.iter().take(scanned).enumerate() {
+            assert_eq!(*k, start + i);
+            assert_eq!(v.as_deref(), Some(&(start + i)));
+        }
+        if start == 96 {
+            // The values replaced by the scan were released, the rest is left alone.
+            assert_eq!(Arc::strong_count(&old), 1 + 4);
+            assert_eq!(result[4].1.as_deref(), Some(&usize::MAX));
+        }
+    }
+    assert_eq!(Arc::strong_count(&old), 1);
+}
+
+#[test]
+fn congee_scan_with_writers() {
+    let tree: Arc<crate::Congee<usize, usize>> = Arc::new(crate::Congee::new());
+    let key_cnt = 4_096;
+
+    let mut handlers = vec![];
+    for t in 0..2 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t);
+            for _ in 0..20_000 {
+                let guard = tree.pin();
+                let k = r.gen_range(0..key_cnt);
+                if r.gen_bool(0.3) {
+                    tree.remove(k, &guard);
+                } else {
+                    tree.insert(k, Arc::new(k), &guard).unwrap();
+                }
+            }
+        }));
+    }
+    for t in 0..2 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(42 + t);
+            let mut result = vec![(0, None); 64];
+            for _ in 0..2_000 {
+                let guard = tree.pin();
+                let start = r.gen_range(0..key_cnt);
+                let scanned = tree.range(&start, &key_cnt, &mut result, &guard);
+                // A value is cloned right after its key is validated, so it always belongs to its key.
+                for (k, v) in result.iter().take(scanned) {
+                    assert_eq!(v.as_deref(), Some(k));
+                }
+            }
+        }));
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+}
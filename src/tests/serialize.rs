@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use crate::{Congee, CongeeRaw, CongeeSet};
+
+#[test]
+fn raw_round_trip() {
+    let tree = CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    for k in 0..1_000u64 {
+        tree.insert(k * 7919 % 100_003, k as usize, &guard).unwrap();
+    }
+
+    let json = serde_json::to_string(&tree).unwrap();
+    let loaded: CongeeRaw<u64, usize> = serde_json::from_str(&json).unwrap();
+    assert!(loaded.iter(&guard).eq(tree.iter(&guard)));
+    assert_eq!(loaded.len(&guard), 1_000);
+
+    let empty: CongeeRaw<u64, usize> = serde_json::from_str("[]").unwrap();
+    assert!(empty.is_empty(&guard));
+    assert_eq!(serde_json::to_string(&empty).unwrap(), "[]");
+}
+
+#[test]
+fn unsorted_input() {
+    // Later entries win, like inserting them in order.
+    let tree: CongeeRaw<u64, usize> =
+        serde_json::from_str("[[3, 30], [1, 10], [3, 31], [2, 20], [1, 11]]").unwrap();
+    let guard = tree.pin();
+    assert_eq!(
+        tree.iter(&guard).collect::<Vec<_>>(),
+        vec![(1, 11), (2, 20), (3, 31)]
+    );
+    assert_eq!(
+        serde_json::to_string(&tree).unwrap(),
+        "[[1,11],[2,20],[3,31]]"
+    );
+
+    let set: CongeeSet<u64> = serde_json::from_str("[5, 1, 5, 3]").unwrap();
+    assert_eq!(set.iter(&guard).collect::<Vec<_>>(), vec![1, 3, 5]);
+
+    assert!(serde_json::from_str::<CongeeRaw<u64, usize>>("{}").is_err());
+}
+
+#[test]
+fn set_round_trip() {
+    let set = CongeeSet::<u64>::default();
+    let guard = set.pin();
+    for k in (0..2_000u64).step_by(3) {
+        set.insert(k << 20, &guard).unwrap();
+    }
+
+    let json = serde_json::to_string(&set).unwrap();
+    let loaded: CongeeSet<u64> = serde_json::from_str(&json).unwrap();
+    assert!(loaded.iter(&guard).eq(set.iter(&guard)));
+}
+
+#[test]
+fn congee_round_trip() {
+    let tree = Congee::<u64, String>::new();
+    let guard = tree.pin();
+    let mut expected = BTreeMap::new();
+    for k in 0..500u64 {
+        tree.insert(k * 31, Arc::new(format!("v{k}")), &guard)
+            .unwrap();
+        expected.insert(k * 31, format!("v{k}"));
+    }
+
+    let json = serde_json::to_string(&tree).unwrap();
+    assert_eq!(
+        json,
+        serde_json::to_string(&expected.iter().collect::<Vec<_>>()).unwrap()
+    );
+    let loaded: Congee<u64, String> = serde_json::from_str(&json).unwrap();
+    assert!(
+        loaded
+            .iter(&guard)
+            .map(|(k, v)| (k, v.as_ref().clone()))
+            .eq(expected.into_iter())
+    );
+}
+
+#[test]
+fn bincode_round_trip() {
+    // Bincode writes the length of a sequence before its elements.
+    let tree = CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    for k in 0..1_000u64 {
+        tree.insert(k * 7919 % 100_003, k as usize, &guard).unwrap();
+    }
+    let bytes = bincode::serialize(&tree).unwrap();
+    let loaded: CongeeRaw<u64, usize> = bincode::deserialize(&bytes).unwrap();
+    assert!(loaded.iter(&guard).eq(tree.iter(&guard)));
+
+    let set = CongeeSet::<u64>::default();
+    for k in (0..2_000u64).step_by(3) {
+        set.insert(k << 20, &guard).unwrap();
+    }
+    let bytes = bincode::serialize(&set).unwrap();
+    let loaded: CongeeSet<u64> = bincode::deserialize(&bytes).unwrap();
+    assert!(loaded.iter(&guard).eq(set.iter(&guard)));
+
+    let congee = Congee::<u64, String>::new();
+    for k in 0..500u64 {
+        congee
+            .insert(k * 31, Arc::new(format!("v{k}")), &guard)
+            .unwrap();
+    }
+    let bytes = bincode::serialize(&congee).unwrap();
+    let loaded: Congee<u64, String> = bincode::deserialize(&bytes).unwrap();
+    assert!(
+        loaded
+            .iter(&guard)
+            .map(|(k, v)| (k, v.as_ref().clone()))
+            .eq(congee.iter(&guard).map(|(k, v)| (k, v.as_ref().clone())))
+    );
+}
+
+#[test]
+fn bincode_while_writing() {
+    // The length written up front matches the entries that follow, however the tree changes meanwhile.
+    let tree = Arc::new(CongeeRaw::<u64, usize>::default());
+    let done = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let tree = tree.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut k = 0u64;
+            while !done.load(Ordering::Relaxed) {
+                let guard = tree.pin();
+                tree.insert(k % 5_000, k as usize, &guard).unwrap();
+                tree.remove(&((k * 7) % 5_000), &guard);
+                k += 1;
+            }
+        })
+    };
+
+    for _ in 0..50 {
+        let bytes = bincode::serialize(&*tree).unwrap();
+        let loaded: CongeeRaw<u64, usize> = bincode::deserialize(&bytes).unwrap();
+        let guard = loaded.pin();
+        assert!(loaded.iter(&guard).map(|(k, _v)| k).is_sorted());
+    }
+    done.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+}
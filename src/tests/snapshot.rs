@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{Congee, CongeeRaw, CongeeSet, TransactionError};
+
+#[test]
+fn snapshot_ignores_later_writes() {
+    let tree = CongeeRaw::<usize, usize>::default();
+    let guard = tree.pin();
+    for i in 0..10_000 {
+        tree.insert(i, i, &guard).unwrap();
+    }
+
+    let snapshot = tree.snapshot(&guard);
+    tree.remove_range(..5_000, &guard);
+    for i in 5_000..10_000 {
+        tree.insert(i, i + 1, &guard).unwrap();
+    }
+    tree.insert(20_000, 0, &guard).unwrap();
+    tree.insert_batch(&[(0, 7), (1, 7), (30_000, 7)], &guard)
+        .unwrap();
+    tree.compute_if_present(&9_999, |_v| None, &guard);
+
+    assert_eq!(snapshot.get(&1), Some(1));
+    assert_eq!(snapshot.get(&9_999), Some(9_999));
+    assert_eq!(snapshot.get(&20_000), None);
+    assert!(snapshot.iter().eq((0..10_000).map(|i| (i, i))));
+    assert!(snapshot.iter().rev().eq((0..10_000).rev().map(|i| (i, i))));
+
+    let mut result = [(0, 0); 4];
+    assert_eq!(snapshot.range(4_998.., &mut result), 4);
+    assert_eq!(
+        result,
+        [
+            (4_998, 4_998),
+            (4_999, 4_999),
+            (5_000, 5_000),
+            (5_001, 5_001)
+        ]
+    );
+    assert_eq!(snapshot.range_iter(..3).next_back(), Some((2, 2)));
+
+    assert_eq!(tree.len(&guard), 5_003);
+    drop(snapshot);
+    assert_eq!(tree.get(&5_000, &guard), Some(5_001));
+
+    let snapshot = tree.snapshot(&guard);
+    tree.insert(5_000, 0, &guard).unwrap();
+    assert_eq!(snapshot.get(&5_000), Some(5_001));
+    assert_eq!(snapshot.get(&0), Some(7));
+    assert_eq!(snapshot.iter().count(), 5_003);
+}
+
+#[test]
+fn snapshots_match_btree() {
+    let mut r = StdRng::seed_from_u64(17);
+    let tree = CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    let mut expected = BTreeMap::new();
+    let mut snapshots = Vec::new();
+
+    for round in 0..40 {
+        // Dense runs put many keys in the same nodes, sparse keys spread over the whole key space.
+        let base = r.gen_range(0..64u64) << (r.gen_range(0..6) * 8);
+        for _ in 0..r.gen_range(0..300) {
+            let k = if r.gen_bool(0.5) {
+                base + r.gen_range(0..512)
+            } else {
+                r.r#gen::<u64>() >> r.gen_range(0..64)
+            };
+            if r.gen_bool(0.3) {
+                tree.remove(&k, &guard);
+                expected.remove(&k);
+            } else {
+                tree.insert(k, round, &guard).unwrap();
+                expected.insert(k, round);
+            }
+        }
+        if r.gen_bool(0.2) {
+            tree.remove_range(base..base + 256, &guard);
+            expected.retain(|k, _| !(base..base + 256).contains(k));
+        }
+        if r.gen_bool(0.3) {
+            snapshots.push((tree.snapshot(&guard), expected.clone()));
+        }
+        if r.gen_bool(0.1) && !snapshots.is_empty() {
+            snapshots.swap_remove(r.gen_range(0..snapshots.len()));
+        }
+
+        for (snapshot, expected) in snapshots.iter() {
+            assert!(snapshot.iter().eq(expected.iter().map(|(k, v)| (*k, *v))));
+            assert!(
+                snapshot.range_iter(base..=base + 300).rev().eq(expected
+                    .range(base..=base + 300)
+                    .rev()
+                    .map(|(k, v)| (*k, *v)))
+            );
+            for _ in 0..20 {
+                let k = base + r.gen_range(0..512);
+                assert_eq!(snapshot.get(&k), expected.get(&k).copied());
+            }
+        }
+    }
+}
+
+#[test]
+fn set_snapshot() {
+    let set = CongeeSet::<usize>::default();
+    let guard = set.pin();
+    let empty = set.snapshot(&guard);
+    for i in 0..100 {
+        set.insert(i, &guard).unwrap();
+    }
+    let snapshot = set.snapshot(&guard);
+    set.retain(|k| k % 2 == 0, &guard);
+
+    assert_eq!(empty.iter().count(), 0);
+    assert_eq!(snapshot.iter().count(), 100);
+    assert!(snapshot.contains(&99));
+    assert!(!set.contains(&99, &guard));
+    let mut result = [0; 3];
+    assert_eq!(snapshot.range(10..13, &mut result), 3);
+    assert_eq!(result, [10, 11, 12]);
+}
+
+#[test]
+fn congee_snapshot_keeps_replaced_values() {
+    let tree: Congee<usize, String> = Congee::new();
+    let guard = tree.pin();
+    let value = Arc::new("v".to_string());
+    for i in 0..10 {
+        tree.insert(i, value.clone(), &guard).unwrap();
+    }
+
+    let snapshot = tree.snapshot(&guard);
+    tree.remove(3, &guard);
+    tree.insert(4, Arc::new("w".to_string()), &guard).unwrap();
+    tree.remove_range(8.., &guard);
+    assert!(Arc::ptr_eq(&snapshot.get(3).unwrap(), &value));
+    assert!(Arc::ptr_eq(&snapshot.get(4).unwrap(), &value));
+    assert_eq!(snapshot.iter().count(), 10);
+    let mut result = vec![(0, None); 3];
+    assert_eq!(snapshot.range(8.., &mut result), 2);
+    assert!(Arc::ptr_eq(result[1].1.as_ref().unwrap(), &value));
+    drop(result);
+    drop(snapshot);
+    assert_eq!(tree.get(4, &guard).unwrap().as_str(), "w");
+}
+
+#[test]
+fn concurrent_snapshot_is_consistent() {
+    // Each writer moves its only key downwards by inserting the next key before removing the current one,
+    // so every point in time has one or two keys per writer, next to each other.
+    // A read committed scan in ascending order can miss both.
+    const WRITERS: usize = 3;
+    const STEPS: usize = 20_000;
+    let tree = Arc::new(CongeeRaw::<usize, usize>::default());
+    let key = |t: usize, i: usize| (t << 32) | (STEPS - i);
+    {
+        let guard = tree.pin();
+        for t in 0..WRITERS {
+            tree.insert(key(t, 0), 0, &guard).unwrap();
+        }
+    }
+
+    let done = Arc::new(AtomicBool::new(false));
+    let mut handlers = Vec::new();
+    for t in 0..WRITERS {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            for i in 1..=STEPS {
+                let guard = tree.pin();
+                tree.insert(key(t, i), i, &guard).unwrap();
+                tree.remove(&key(t, i - 1), &guard).unwrap();
+            }
+        }));
+    }
+
+    let reader = {
+        let tree = tree.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut snapshots = 0;
+            while !done.load(Ordering::Relaxed) || snapshots == 0 {
+                let guard = tree.pin();
+                let snapshot = tree.snapshot(&guard);
+                for t in 0..WRITERS {
+                    let steps: Vec<usize> = snapshot
+                        .range_iter(t << 32..(t + 1) << 32)
+                        .map(|(_k, v)| v)
+                        .collect();
+                    match steps[..] {
+                        [_] => {}
+                        [new, old] => assert_eq!(new, old + 1),
+                        _ => panic!("writer {t} has keys {steps:?}"),
+                    }
+                    let newest = steps[0];
+                    assert_eq!(snapshot.get(&key(t, newest)), Some(newest));
+                }
+                snapshots += 1;
+            }
+        })
+    };
+
+    for h in handlers {
+        h.join().unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    reader.join().unwrap();
+
+    let guard = tree.pin();
+    let keys: Vec<usize> = tree.iter(&guard).map(|(k, _v)| k).collect();
+    assert_eq!(
+        keys,
+        (0..WRITERS).map(|t| key(t, STEPS)).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn concurrent_snapshot_sees_whole_transactions() {
+    // Transactions move amounts between accounts, so every snapshot must see the same total.
+    const ACCOUNTS: usize = 500;
+    const WRITERS: usize = 3;
+    let tree = Arc::new(CongeeRaw::<usize, usize>::default());
+    {
+        let guard = tree.pin();
+        for k in 0..ACCOUNTS {
+            tree.insert(k * 997, 100, &guard).unwrap();
+        }
+    }
+
+    let done = Arc::new(AtomicBool::new(false));
+    let mut handlers = Vec::new();
+    for t in 0..WRITERS {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t as u64);
+            for _ in 0..5_000 {
+                let from = r.gen_range(0..ACCOUNTS) * 997;
+                let to = r.gen_range(0..ACCOUNTS) * 997;
+                let amount = r.gen_range(0..10);
+                let guard = tree.pin();
+                loop {
+                    let mut txn = tree.transaction(&guard);
+                    let balance = txn.get(&from).unwrap();
+                    if from == to || balance < amount {
+                        break;
+                    }
+                    let target = txn.get(&to).unwrap();
+                    txn.insert(from, balance - amount);
+                    txn.insert(to, target + amount);
+                    match txn.commit() {
+                        Ok(()) => break,
+                        Err(TransactionError::Conflict) => continue,
+                        Err(e) => panic!("{e}"),
+                    }
+                }
+            }
+        }));
+    }
+
+    let reader = {
+        let tree = tree.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut snapshots = 0;
+            while !done.load(Ordering::Relaxed) || snapshots == 0 {
+                let guard = tree.pin();
+                let snapshot = tree.snapshot(&guard);
+                let total: usize = snapshot.iter().map(|(_k, v)| v).sum();
+                assert_eq!(total, ACCOUNTS * 100);
+                let total: usize = snapshot.iter().rev().map(|(_k, v)| v).sum();
+                assert_eq!(total, ACCOUNTS * 100);
+                let total: usize = (0..ACCOUNTS)
+                    .map(|k| snapshot.get(&(k * 997)).unwrap())
+                    .sum();
+                assert_eq!(total, ACCOUNTS * 100);
+                snapshots += 1;
+            }
+        })
+    };
+
+    for h in handlers {
+        h.join().unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    reader.join().unwrap();
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread;
+
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{CongeeRaw, TransactionError};
+
+fn assert_same(tree: &CongeeRaw<u64, usize>, expected: &BTreeMap<u64, usize>) {
+    let guard = tree.pin();
+    assert!(tree.iter(&guard).eq(expected.iter().map(|(k, v)| (*k, *v))));
+    assert_eq!(tree.len(&guard), expected.len());
+    assert_eq!(tree.stats().kv_pairs(), expected.len());
+}
+
+#[test]
+fn transaction_matches_btree() {
+    let mut r = StdRng::seed_from_u64(7);
+    let tree = CongeeRaw::<u64, usize>::default();
+    let mut expected = BTreeMap::new();
+
+    for round in 0..300 {
+        let guard = tree.pin();
+        let mut txn = tree.transaction(&guard);
+        let mut writes = BTreeMap::new();
+        // Dense keys fill last level nodes until they grow, sparse ones create new paths and split prefixes.
+        let base = r.gen_range(0..64u64) << (r.gen_range(0..6) * 8);
+        for _ in 0..r.gen_range(1..200) {
+            let k = if r.gen_bool(0.5) {
+                base + r.gen_range(0..256)
+            } else {
+                r.r#gen::<u64>() >> r.gen_range(0..64)
+            };
+            if r.gen_bool(0.3) {
+                txn.remove(&k);
+                writes.insert(k, None);
+            } else {
+                txn.insert(k, round);
+                writes.insert(k, Some(round));
+            }
+        }
+        // Removing whole ranges empties nodes.
+        if r.gen_bool(0.3) {
+            let keys: Vec<u64> = expected.range(base..base + 256).map(|(k, _)| *k).collect();
+            for k in keys {
+                txn.remove(&k);
+                writes.insert(k, None);
+            }
+        }
+        txn.commit().unwrap();
+
+        for (k, v) in writes {
+            match v {
+                Some(v) => expected.insert(k, v),
+                None => expected.remove(&k),
+            };
+        }
+        assert_same(&tree, &expected);
+    }
+
+    let guard = tree.pin();
+    let mut txn = tree.transaction(&guard);
+    for k in expected.keys() {
+        txn.remove(k);
+    }
+    txn.commit().unwrap();
+    assert!(tree.is_empty(&guard));
+    assert_eq!(tree.iter(&guard).count(), 0);
+}
+
+#[test]
+fn transaction_splits_prefixes() {
+    // The keys share their first seven bytes, so they sit in a node whose prefix spans levels 1 to 6.
+    let base = 0x0102_0304_0506_0700u64;
+    let diverging = [
+        0x01ff_0000_0000_0000,
+        0x0102_03ff_0000_0000,
+        0x0102_03fe_0000_0001,
+        0x0102_0304_05ff_0000,
+    ];
+    for (round, removes_all) in [(0, false), (1, false), (2, true)] {
+        let tree = CongeeRaw::<u64, usize>::default();
+        let guard = tree.pin();
+        let mut expected = BTreeMap::new();
+        for i in 0..4 {
+            tree.insert(base + i, i as usize, &guard).unwrap();
+            expected.insert(base + i, i as usize);
+        }
+
+        let mut txn = tree.transaction(&guard);
+        assert_eq!(txn.get(&(base + 1)), Some(1));
+        assert_eq!(txn.get(&0x0102_03ff_0000_0001), None);
+        for k in diverging {
+            txn.insert(k, 100);
+            expected.insert(k, 100);
+        }
+        if round == 1 {
+            // The node outgrows its type at the same time.
+            txn.insert(base + 4, 4);
+            expected.insert(base + 4, 4);
+        }
+        if removes_all {
+            for i in 0..4 {
+                txn.remove(&(base + i));
+                expected.remove(&(base + i));
+            }
+        }
+        txn.commit().unwrap();
+
+        assert_same(&tree, &expected);
+        for k in expected.keys() {
+            assert_eq!(tree.get(k, &guard), expected.get(k).copied());
+        }
+        assert_eq!(tree.get(&0x0102_03ff_0000_0001, &guard), None);
+    }
+}
+
+#[test]
+fn transaction_reads_own_writes() {
+    let tree = CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    tree.insert(1, 10, &guard).unwrap();
+
+    let mut txn = tree.transaction(&guard);
+    assert_eq!(txn.get(&1), Some(10));
+    txn.insert(1, 11);
+    assert_eq!(txn.get(&1), Some(11));
+    txn.remove(&1);
+    assert_eq!(txn.get(&1), None);
+    txn.insert(2, 20);
+    assert_eq!(txn.get(&2), Some(20));
+    assert_eq!(tree.get(&2, &guard), None);
+    txn.commit().unwrap();
+
+    assert_eq!(tree.get(&1, &guard), None);
+    assert_eq!(tree.get(&2, &guard), Some(20));
+}
+
+#[test]
+fn transaction_conflict() {
+    let tree = CongeeRaw::<u64, usize>::default();
+    let guard = tree.pin();
+    tree.insert(1, 10, &guard).unwrap();
+
+    // A changed value.
+    let mut txn = tree.transaction(&guard);
+    assert_eq!(txn.get(&1), Some(10));
+    txn.insert(2, 20);
+    tree.insert(1, 11, &guard).unwrap();
+    assert_eq!(txn.commit(), Err(TransactionError::Conflict));
+    assert_eq!(tree.get(&2, &guard), None);
+
+    // A key that was absent.
+    let mut txn = tree.transaction(&guard);
+    assert_eq!(txn.get(&3), None);
+    txn.insert(1, 12);
+    tree.insert(3, 30, &guard).unwrap();
+    assert_eq!(txn.commit(), Err(TransactionError::Conflict));
+    assert_eq!(tree.get(&1, &guard), Some(11));
+
+    // A value that changed and changed back is not a conflict.
+    let mut txn = tree.transaction(&guard);
+    assert_eq!(txn.get(&1), Some(11));
+    txn.insert(4, 40);
+    tree.insert(1, 0, &guard).unwrap();
+    tree.insert(1, 11, &guard).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(tree.get(&4, &guard), Some(40));
+}
+
+#[test]
+fn concurrent_transfers() {
+    const ACCOUNTS: u64 = 64;
+    const BALANCE: usize = 1_000;
+    let tree = Arc::new(CongeeRaw::<u64, usize>::default());
+    {
+        let guard = tree.pin();
+        for a in 0..ACCOUNTS {
+            // Spread the accounts over several last level nodes.
+            tree.insert(a << 12, BALANCE, &guard).unwrap();
+        }
+    }
+
+    let mut handlers = Vec::new();
+    for t in 0..4 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t);
+            let mut conflicts = 0;
+            for _ in 0..2_000 {
+                let from = r.gen_range(0..ACCOUNTS) << 12;
+                let to = r.gen_range(0..ACCOUNTS) << 12;
+                let amount = r.gen_range(0..10);
+                loop {
+                    let guard = tree.pin();
+                    let mut txn = tree.transaction(&guard);
+                    let a = txn.get(&from).unwrap();
+                    let b = txn.get(&to).unwrap();
+                    if from != to && a >= amount {
+                        txn.insert(from, a - amount);
+                        txn.insert(to, b + amount);
+                    }
+                    match txn.commit() {
+                        Ok(()) => break,
+                        Err(TransactionError::Conflict) => conflicts += 1,
+                        Err(e) => panic!("{e}"),
+                    }
+                }
+            }
+            conflicts
+        }));
+    }
+
+    let auditor = {
+        let tree = tree.clone();
+        thread::spawn(move || {
+            let mut audits = 0;
+            while audits < 100 {
+                // A read-only transaction that commits saw all balances at one point in time.
+                let guard = tree.pin();
+                let mut txn = tree.transaction(&guard);
+                let total: usize = (0..ACCOUNTS).map(|a| txn.get(&(a << 12)).unwrap()).sum();
+                if txn.commit().is_ok() {
+                    assert_eq!(total, ACCOUNTS as usize * BALANCE);
+                    audits += 1;
+                }
+            }
+        })
+    };
+
+    for h in handlers {
+        h.join().unwrap();
+    }
+    auditor.join().unwrap();
+
+    let guard = tree.pin();
+    let total: usize = tree.iter(&guard).map(|(_k, v)| v).sum();
+    assert_eq!(total, ACCOUNTS as usize * BALANCE);
+    assert_eq!(tree.len(&guard), ACCOUNTS as usize);
+}
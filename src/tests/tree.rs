@@ -57,7 +57,7 @@ fn test_sparse_keys() {
     let guard = crossbeam_epoch::pin();
     let mut rng = StdRng::seed_from_u64(12);
     for _i in 0..key_cnt {
-        let k = rng.r#gen::<usize>() & 0x7fff_ffff_ffff_ffff;
+        let k = rng.r#gen::<usize>();
         keys.push(k);
 
         let key: [u8; 8] = k.to_be_bytes();
@@ -87,6 +87,40 @@ fn test_sparse_keys() {
     println!("{}", tree.stats());
 }
 
+#[test]
+fn full_width_values() {
+    use crate::utils::leak_check::LeakCheckAllocator;
+    let tree = CongeeInner::new(LeakCheckAllocator::new(), Arc::new(|_k, _v| {}));
+    let guard = crossbeam_epoch::pin();
+
+    let values = [usize::MAX, 1 << 63, (1 << 63) | 0x1000, 0];
+    for (i, v) in values.iter().enumerate() {
+        let key: [u8; 8] = (i * 1_000_000).to_be_bytes();
+        tree.insert(&key, *v, &guard).unwrap();
+    }
+    for (i, v) in values.iter().enumerate() {
+        let key: [u8; 8] = (i * 1_000_000).to_be_bytes();
+        assert_eq!(tree.get(&key, &guard), Some(*v));
+    }
+
+    let mut results = [([0; 8], 0); 8];
+    let n = tree.range(&[0; 8], &[0xff; 8], &mut results[..], &guard);
+    assert_eq!(n, values.len());
+    for (r, v) in results.iter().zip(values.iter()) {
+        assert_eq!(r.1, *v);
+    }
+
+    let key: [u8; 8] = 1_000_000usize.to_be_bytes();
+    let (old, new) = tree
+        .compute_if_present(&key, &mut |v| Some(v | 0xff), &guard)
+        .unwrap();
+    assert_eq!((old, new), (1 << 63, Some((1 << 63) | 0xff)));
+    tree.compute_if_present(&key, &mut |_v| None, &guard)
+        .unwrap();
+    assert_eq!(tree.get(&key, &guard), None);
+    assert_eq!(tree.keys().len(), values.len() - 1);
+}
+
 use rand::prelude::StdRng;
 use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
@@ -261,3 +295,312 @@ fn shuttle_replay() {
 
     shuttle::check_random_with_seed(test_concurrent_insert_read, 324037473359401122, 1000);
 }
+
+#[test]
+fn get_many_matches_get() {
+    let mut r = StdRng::seed_from_u64(21);
+    let tree = crate::CongeeRaw::<usize, usize>::default();
+    let guard = tree.pin();
+    for _ in 0..20_000 {
+        let k: usize = r.gen_range(0..1 << 20);
+        tree.insert(k, k + 1, &guard).unwrap();
+    }
+    for k in [0, 1 << 40, usize::MAX] {
+        tree.insert(k, k.wrapping_add(1), &guard).unwrap();
+    }
+
+    let mut keys: Vec<usize> = (0..5_000).map(|_| r.gen_range(0..1 << 20)).collect();
+    keys.extend([0, 1 << 40, (1 << 40) + 1, usize::MAX, usize::MAX - 1, 0]);
+    let check = |keys: &[usize]| {
+        let mut values = vec![None; keys.len()];
+        tree.get_many(keys, &mut values, &guard);
+        for (k, v) in keys.iter().zip(values) {
+            assert_eq!(v, tree.get(k, &guard), "key {k}");
+        }
+    };
+    check(&keys);
+    keys.sort();
+    check(&keys);
+    keys.reverse();
+    check(&keys);
+    check(&[]);
+
+    let long_tree: crate::CongeeSet<u128, crate::DefaultAllocator, 16> =
+        crate::CongeeSet::default();
+    let long_keys: Vec<u128> = (0..1_000u128).map(|k| (k << 100) | (k << 20) | k).collect();
+    for k in long_keys.iter().step_by(2) {
+        long_tree.insert(*k, &guard).unwrap();
+    }
+    let mut result = vec![false; long_keys.len()];
+    long_tree.contains_many(&long_keys, &mut result, &guard);
+    for (i, found) in result.into_iter().enumerate() {
+        assert_eq!(found, i % 2 == 0);
+    }
+}
+
+#[test]
+fn concurrent_get_many() {
+    let key_cnt = 10_000usize;
+    let tree = Arc::new(crate::CongeeRaw::<usize, usize>::default());
+    {
+        let guard = tree.pin();
+        for k in 0..key_cnt {
+            tree.insert(k * 4, k, &guard).unwrap();
+        }
+    }
+
+    let mut handlers = Vec::new();
+    {
+        // Writer growing, splitting and shrinking the nodes around the stable keys.
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let guard = tree.pin();
+            for round in 0..4 {
+                for k in 0..key_cnt {
+                    tree.insert(k * 4 + 1 + round % 2, k, &guard).unwrap();
+                }
+                for k in 0..key_cnt {
+                    tree.remove(&(k * 4 + 1 + round % 2), &guard);
+                }
+            }
+        }));
+    }
+    for t in 0..2 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t);
+            let guard = tree.pin();
+            for _ in 0..200 {
+                let mut keys: Vec<usize> = (0..256).map(|_| r.gen_range(0..key_cnt)).collect();
+                keys.sort();
+                let encoded: Vec<usize> = keys.iter().map(|k| k * 4).collect();
+                let mut values = vec![None; keys.len()];
+                tree.get_many(&encoded, &mut values, &guard);
+                for (k, v) in keys.iter().zip(values) {
+                    assert_eq!(v, Some(*k));
+                }
+            }
+        }));
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn insert_batch_matches_insert() {
+    let mut r = StdRng::seed_from_u64(5);
+    let batched = crate::CongeeRaw::<usize, usize>::default();
+    let single = crate::CongeeRaw::<usize, usize>::default();
+    let guard = batched.pin();
+    let mut expected = std::collections::BTreeMap::new();
+
+    for round in 0..50 {
+        let len = r.gen_range(0..2_000);
+        let mut batch: Vec<(usize, usize)> = (0..len)
+            .map(|_| {
+                (
+                    r.gen_range(0..1 << 16) << (round % 3 * 8),
+                    r.r#gen::<u32>() as usize,
+                )
+            })
+            .collect();
+        if round % 2 == 0 {
+            batch.sort();
+        }
+
+        let old = batched.insert_batch(&batch, &guard).unwrap();
+        assert_eq!(old.len(), batch.len());
+        for ((k, v), old) in batch.iter().zip(old) {
+            assert_eq!(old, expected.insert(*k, *v));
+            single.insert(*k, *v, &guard).unwrap();
+        }
+    }
+
+    assert_eq!(batched.keys(), expected.keys().copied().collect::<Vec<_>>());
+    for (k, v) in expected.iter() {
+        assert_eq!(batched.get(k, &guard), Some(*v));
+    }
+    // Nodes grow straight to the size they need, which is the same size reached one insert at a time.
+    let (batched_stats, single_stats) = (batched.stats(), single.stats());
+    assert_eq!(batched_stats.total_nodes(), single_stats.total_nodes());
+    assert_eq!(
+        batched_stats.memory_by_node_type(),
+        single_stats.memory_by_node_type()
+    );
+}
+
+#[test]
+fn insert_batch_long_keys() {
+    let tree: crate::CongeeRaw<u128, usize, crate::utils::leak_check::LeakCheckAllocator, 16> =
+        crate::CongeeRaw::new(crate::utils::leak_check::LeakCheckAllocator::new());
+    let guard = tree.pin();
+    let batch: Vec<(u128, usize)> = (0..5_000u128)
+        .map(|k| ((k / 300) << 100 | (k % 300), k as usize))
+        .collect();
+    let old = tree.insert_batch(&batch, &guard).unwrap();
+    assert!(old.iter().all(|v| v.is_none()));
+    for (k, v) in batch.iter() {
+        assert_eq!(tree.get(k, &guard), Some(*v));
+    }
+
+    let updated: Vec<(u128, usize)> = batch.iter().map(|(k, v)| (*k, v + 1)).collect();
+    let old = tree.insert_batch(&updated, &guard).unwrap();
+    for ((_, v), old) in batch.iter().zip(old) {
+        assert_eq!(old, Some(*v));
+    }
+}
+
+#[test]
+fn concurrent_insert_batch() {
+    let key_cnt = 20_000usize;
+    let tree = Arc::new(crate::CongeeRaw::<usize, usize>::default());
+
+    let mut handlers = Vec::new();
+    for t in 0..4 {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(t);
+            let guard = tree.pin();
+            // Interleaved keys, so the threads contend for the same nodes.
+            let mut keys: Vec<usize> = (0..key_cnt).filter(|k| k % 4 == t as usize).collect();
+            keys.shuffle(&mut r);
+            for chunk in keys.chunks(500) {
+                let mut batch: Vec<(usize, usize)> = chunk.iter().map(|k| (*k, *k)).collect();
+                batch.sort();
+                let old = tree.insert_batch(&batch, &guard).unwrap();
+                assert!(old.iter().all(|v| v.is_none()));
+            }
+        }));
+    }
+    {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let mut r = StdRng::seed_from_u64(42);
+            let guard = tree.pin();
+            for _ in 0..key_cnt {
+                let k = r.gen_range(0..key_cnt);
+                if let Some(v) = tree.get(&k, &guard) {
+                    assert_eq!(v, k);
+                }
+            }
+        }));
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+
+    let guard = tree.pin();
+    for k in 0..key_cnt {
+        assert_eq!(tree.get(&k, &guard), Some(k));
+    }
+}
+
+#[test]
+fn len_tracks_every_update() {
+    let tree = crate::CongeeRaw::<usize, usize>::default();
+    let guard = tree.pin();
+    let check = |expected: usize| {
+        assert_eq!(tree.len(&guard), expected);
+        assert_eq!(tree.stats().kv_pairs(), expected);
+        assert_eq!(tree.is_empty(&guard), expected == 0);
+    };
+    check(0);
+
+    for k in 0..1_000 {
+        tree.insert(k * 3, k, &guard).unwrap();
+    }
+    // Overwrites and misses don't change the length.
+    tree.insert(0, 1, &guard).unwrap();
+    tree.compute_or_insert(3, |v| v.unwrap_or(0) + 1, &guard)
+        .unwrap();
+    tree.compute_if_present(&6, |v| Some(v + 1), &guard);
+    assert_eq!(tree.remove(&1, &guard), None);
+    check(1_000);
+
+    // A prefix split.
+    tree.insert(1 << 40, 0, &guard).unwrap();
+    tree.compute_or_insert(1, |_| 1, &guard).unwrap();
+    check(1_002);
+
+    tree.remove(&(1 << 40), &guard).unwrap();
+    tree.compute_if_present(&1, |_| None, &guard).unwrap();
+    check(1_000);
+
+    let batch: Vec<(usize, usize)> = (0..600).map(|k| (k * 2, k)).collect();
+    tree.insert_batch(&batch, &guard).unwrap();
+    // 0, 6, ..., 1194 were already there.
+    check(1_000 + 600 - 200);
+
+    let removed = tree.remove_range(100..1_000, &guard);
+    check(1_400 - removed);
+    let removed_by_retain = tree.retain(|k, _| k % 2 == 0, &guard);
+    check(1_400 - removed - removed_by_retain);
+
+    tree.remove_range(.., &guard);
+    check(0);
+
+    let loaded = crate::CongeeRaw::<usize, usize>::from_sorted_iter(
+        crate::DefaultAllocator {},
+        (0..500).map(|k| (k, k)),
+    )
+    .unwrap();
+    assert_eq!(loaded.len(&guard), 500);
+}
+
+#[test]
+fn concurrent_len() {
+    let tree = Arc::new(crate::CongeeRaw::<usize, usize>::default());
+    let mut handlers = Vec::new();
+    for t in 0..4usize {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let guard = tree.pin();
+            for k in 0..5_000 {
+                tree.insert(k * 4 + t, k, &guard).unwrap();
+            }
+            for k in (0..5_000).step_by(2) {
+                tree.remove(&(k * 4 + t), &guard).unwrap();
+            }
+            // Never negative or beyond what could have been inserted.
+            assert!(tree.len(&guard) <= 20_000);
+        }));
+    }
+    for h in handlers {
+        h.join().unwrap();
+    }
+    let guard = tree.pin();
+    assert_eq!(tree.len(&guard), 10_000);
+    assert_eq!(tree.stats().kv_pairs(), 10_000);
+}
+
+#[test]
+fn concurrent_insert_if_absent() {
+    let tree = Arc::new(crate::CongeeRaw::<usize, usize>::default());
+    let mut handlers = Vec::new();
+    for t in 1..=4usize {
+        let tree = tree.clone();
+        handlers.push(thread::spawn(move || {
+            let guard = tree.pin();
+            let mut won = Vec::new();
+            for k in 0..5_000 {
+                match tree.insert_if_absent(k, t, &guard).unwrap() {
+                    Ok(()) => won.push(k),
+                    Err(existing) => assert_ne!(existing, t),
+                }
+            }
+            won
+        }));
+    }
+    let won: Vec<Vec<usize>> = handlers.into_iter().map(|h| h.join().unwrap()).collect();
+
+    // Each key is won by exactly one thread, whose value is never overwritten.
+    let guard = tree.pin();
+    assert_eq!(won.iter().map(|w| w.len()).sum::<usize>(), 5_000);
+    for (t, keys) in won.iter().enumerate() {
+        for k in keys {
+            assert_eq!(tree.get(k, &guard), Some(t + 1));
+        }
+    }
+    assert_eq!(tree.len(&guard), 5_000);
+}
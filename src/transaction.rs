@@ -0,0 +1,612 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, hash_map::Entry};
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+
+use crossbeam_epoch::Guard;
+
+use crate::{
+    Allocator, CongeeInner, KeyEncoding,
+    bulk_load::BulkLoader,
+    epoch,
+    error::{ArtError, TransactionError},
+    lock::{ReadGuard, WriteGuard},
+    nodes::{BaseNode, Node, Node4, Node16, Node48, Node256, NodePtr, PtrType},
+    snapshot::History,
+};
+#[cfg(all(feature = "shuttle", test))]
+use shuttle::sync::atomic::{AtomicPtr, Ordering};
+#[cfg(not(all(feature = "shuttle", test)))]
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// Reads and writes on a [CongeeRaw](crate::CongeeRaw) that take effect atomically.
+///
+/// Created by [CongeeRaw::transaction](crate::CongeeRaw::transaction).
+/// Writes are buffered until [commit](Self::commit), reads see the tree plus the writes of the transaction.
+/// A key read twice returns the same value both times.
+///
+/// The commit fails with [TransactionError::Conflict] if a key read by the transaction has changed since,
+/// nothing is written then and the transaction can be retried from the start.
+pub struct Transaction<
+    'a,
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static,
+    const K_LEN: usize,
+> where
+    usize: From<V>,
+{
+    inner: &'a CongeeInner<K_LEN, A>,
+    guard: &'a epoch::Guard,
+    /// The value of each key read from the tree, `None` if the key was absent.
+    reads: BTreeMap<[u8; K_LEN], Option<usize>>,
+    /// The buffered writes, `None` removes the key.
+    writes: BTreeMap<[u8; K_LEN], Option<usize>>,
+    pt: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, A, const K_LEN: usize> Transaction<'a, K, V, A, K_LEN>
+where
+    K: KeyEncoding<K_LEN>,
+    V: Copy + From<usize>,
+    A: Allocator + Clone + Send + 'static,
+    usize: From<V>,
+{
+    pub(crate) fn new(inner: &'a CongeeInner<K_LEN, A>, guard: &'a epoch::Guard) -> Self {
+        Self {
+            inner,
+            guard,
+            reads: BTreeMap::new(),
+            writes: BTreeMap::new(),
+            pt: PhantomData,
+        }
+    }
+
+    /// Returns the value of the key, the commit checks that the key still has this value.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let k = key.to_key_bytes();
+        if let Some(v) = self.writes.get(&k) {
+            return v.map(V::from);
+        }
+        let inner = self.inner;
+        let guard = self.guard;
+        let v = *self.reads.entry(k).or_insert_with(|| inner.get(&k, guard));
+        v.map(V::from)
+    }
+
+    /// Sets the value of the key once the transaction commits.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.writes
+            .insert(key.to_key_bytes(), Some(usize::from(value)));
+    }
+
+    /// Removes the key once the transaction commits.
+    pub fn remove(&mut self, key: &K) {
+        self.writes.insert(key.to_key_bytes(), None);
+    }
+
+    /// Applies all writes at once, if every key read by the transaction still has the value it read.
+    /// Overwritten and removed values are not passed to the drainer.
+    pub fn commit(self) -> Result<(), TransactionError> {
+        self.inner.commit(&self.reads, &self.writes, self.guard)
+    }
+}
+
+/// Where the lookup of a key stopped.
+#[derive(Clone, Copy)]
+enum End {
+    /// The payload of the key.
+    Found(usize),
+    /// The node has no child for the key byte.
+    Missing,
+    /// The key differs from the node prefix at the given level.
+    Diverged(usize),
+}
+
+/// A node read while looking up the keys of the transaction.
+struct Visited<'a> {
+    /// Taken once the node is locked.
+    guard: Option<ReadGuard<'a>>,
+    depth: usize,
+    /// The parent and the key of the edge to this node, `None` for the root.
+    parent: Option<(NonNull<BaseNode>, u8)>,
+    locked: Option<usize>,
+}
+
+/// What happens to a locked node once the commit is applied.
+#[derive(Clone, Copy)]
+enum Fate {
+    Kept,
+    /// All children are removed, the node is unlinked from its parent.
+    Emptied,
+    /// The new children don't fit, the node must be replaced by a bigger one.
+    Grow,
+    Replaced(NodePtr),
+}
+
+/// The edge byte, the level of the edge and the sorted entries of a sub node to build.
+type NewSubNode<const K_LEN: usize> = (u8, usize, Vec<([u8; K_LEN], usize)>);
+
+/// An entry whose key differs from the prefix of the node, with the level the prefix starts at
+/// and the level the key differs at.
+type Diverged<const K_LEN: usize> = (usize, usize, ([u8; K_LEN], usize));
+
+struct Locked<'a, const K_LEN: usize> {
+    node: WriteGuard<'a>,
+    depth: usize,
+    parent: Option<(NonNull<BaseNode>, u8)>,
+    removes: Vec<u8>,
+    changes: Vec<(u8, NodePtr)>,
+    inserts: Vec<(u8, NodePtr)>,
+    /// Entries below missing children, each group becomes a new sub node at the edge byte and level.
+    new_sub_nodes: Vec<NewSubNode<K_LEN>>,
+    /// Entries whose keys differ from the prefix, in key order. They go into new nodes above this one.
+    diverged: Vec<Diverged<K_LEN>>,
+    /// The number of bytes cut from the front of the prefix to make room for the nodes above.
+    prefix_cut: usize,
+    fate: Fate,
+}
+
+/// Removes the first `cut` bytes of the prefix of `node`.
+fn cut_prefix(node: &mut BaseNode, cut: usize) {
+    if cut > 0 {
+        let prefix = node.prefix().to_vec();
+        node.set_prefix(&prefix[cut..]);
+    }
+}
+
+/// Commits the reads and writes of a transaction.
+///
+/// The node holding each written key, or the node where its lookup stops, is write-locked in key order,
+/// which keeps the keys from changing until all writes are applied. Once the locks are taken,
+/// every other node read on the way is checked to be unchanged, which keeps the keys that are only read.
+/// Parents are only locked when a node grows, becomes empty, or gets new nodes above it for keys that
+/// differ from its prefix, and the edge to the node has to change.
+/// Every new node is allocated before the first write, so the commit either applies all writes or none.
+pub(crate) struct TransactionCommit<'a, const K_LEN: usize, A: Allocator + Clone + Send + 'static> {
+    root: &'a AtomicPtr<BaseNode>,
+    allocator: &'a A,
+    history: &'a History<K_LEN>,
+    visited: HashMap<NonNull<BaseNode>, Visited<'a>>,
+    locked: Vec<Locked<'a, K_LEN>>,
+    /// Sub nodes built for new keys, with the level of their edge.
+    built: Vec<(u8, NodePtr, usize)>,
+    /// Nodes built above nodes whose prefix is split, their children are freed on their own.
+    middles: Vec<NodePtr>,
+}
+
+impl<'a, const K_LEN: usize, A: Allocator + Clone + Send + 'static>
+    TransactionCommit<'a, K_LEN, A>
+{
+    pub(crate) fn new(
+        root: &'a AtomicPtr<BaseNode>,
+        allocator: &'a A,
+        history: &'a History<K_LEN>,
+    ) -> Self {
+        Self {
+            root,
+            allocator,
+            history,
+            visited: HashMap::new(),
+            locked: Vec::new(),
+            built: Vec::new(),
+            middles: Vec::new(),
+        }
+    }
+
+    /// Tries to commit once, returns the change in the number of keys.
+    /// On `ArtError` nothing is written and the commit can be retried.
+    pub(crate) fn run(
+        mut self,
+        reads: &BTreeMap<[u8; K_LEN], Option<usize>>,
+        writes: &BTreeMap<[u8; K_LEN], Option<usize>>,
+        guard: &Guard,
+    ) -> Result<Result<isize, TransactionError>, ArtError> {
+        let keys: BTreeSet<&[u8; K_LEN]> = reads.keys().chain(writes.keys()).collect();
+        let mut targets = Vec::with_capacity(keys.len());
+        for k in keys {
+            let (path, node, level, end) = self.find(k)?;
+            let write = writes.get(k).copied();
+            if let Some(expected) = reads.get(k) {
+                let current = match end {
+                    End::Found(v) => Some(v),
+                    _ => None,
+                };
+                if *expected != current {
+                    return Ok(Err(TransactionError::Conflict));
+                }
+            }
+
+            let mut parent = None;
+            for (depth, (g, edge)) in path.into_iter().enumerate() {
+                let ptr = self.visit(g, depth, parent)?;
+                parent = Some((ptr, edge));
+            }
+            let depth = parent.map_or(0, |(p, _)| self.visited[&p].depth + 1);
+            let ptr = self.visit(node, depth, parent)?;
+            targets.push((k, ptr, level, end, write));
+        }
+
+        let mut added = 0;
+        // The keys that change, with the value they had.
+        let mut replaced = Vec::new();
+        for (k, ptr, level, end, write) in targets {
+            // Writes that change nothing only read the key.
+            let (write, old) = match (end, write) {
+                (End::Found(old), Some(Some(v))) if old != v => (Some(v), Some(old)),
+                (End::Found(old), Some(None)) => (None, Some(old)),
+                (End::Missing | End::Diverged(_), Some(Some(v))) => (Some(v), None),
+                _ => continue,
+            };
+            let idx = self.lock(ptr)?;
+            let n = &mut self.locked[idx];
+            replaced.push((k, old));
+            match (end, write) {
+                (End::Found(_), Some(v)) => {
+                    n.changes.push((k[level], NodePtr::from_payload(v)));
+                }
+                (End::Found(_), None) => {
+                    n.removes.push(k[level]);
+                    added -= 1;
+                }
+                (End::Missing, Some(v)) => {
+                    if level == K_LEN - 1 {
+                        n.inserts.push((k[level], NodePtr::from_payload(v)));
+                    } else {
+                        match n.new_sub_nodes.last_mut() {
+                            Some((b, _, entries)) if *b == k[level] => entries.push((*k, v)),
+                            _ => n.new_sub_nodes.push((k[level], level, vec![(*k, v)])),
+                        }
+                    }
+                    added += 1;
+                }
+                (End::Diverged(next_level), Some(v)) => {
+                    n.diverged.push((level, next_level, (*k, v)));
+                    added += 1;
+                }
+                (End::Missing | End::Diverged(_), None) => unreachable!("nothing to remove"),
+            }
+        }
+
+        self.lock_parents()?;
+        // The values read from the nodes that are not locked still hold if the nodes are unchanged.
+        for v in self.visited.values() {
+            if let Some(g) = &v.guard {
+                g.check_version()?;
+            }
+        }
+        self.history.record(
+            |w| {
+                for (k, old) in replaced {
+                    w(k, old);
+                }
+            },
+            guard,
+        );
+        if self.build().is_err() {
+            self.free_built();
+            return Ok(Err(TransactionError::Oom));
+        }
+        self.apply(guard);
+        Ok(Ok(added))
+    }
+
+    /// Looks up `k`, returns the ancestors with the key of the edge taken from each,
+    /// the node where the lookup stopped, the level of its edges and why the lookup stopped.
+    #[allow(clippy::type_complexity)]
+    fn find(
+        &self,
+        k: &[u8; K_LEN],
+    ) -> Result<(Vec<(ReadGuard<'a>, u8)>, ReadGuard<'a>, usize, End), ArtError> {
+        let mut path = Vec::new();
+        // SAFETY: The root pointer is always non-null after initialization.
+        let root = unsafe { NonNull::new_unchecked(self.root.load(Ordering::Relaxed)) };
+        let mut node = BaseNode::read_lock(root)?;
+        let mut level = 0;
+        loop {
+            let mut next_level = level;
+            if node
+                .as_ref()
+                .check_prefix_not_match(k, &mut next_level)
+                .is_some()
+            {
+                node.check_version()?;
+                return Ok((path, node, level, End::Diverged(next_level)));
+            }
+            level = next_level;
+
+            let child = node.as_ref().get_child(k[level]);
+            node.check_version()?;
+            let Some(child) = child else {
+                return Ok((path, node, level, End::Missing));
+            };
+            match child.downcast::<K_LEN>(level) {
+                PtrType::Payload(v) => return Ok((path, node, level, End::Found(v))),
+                PtrType::SubNode(sub_node) => {
+                    let next = BaseNode::read_lock(sub_node)?;
+                    path.push((node, k[level]));
+                    node = next;
+                    level += 1;
+                }
+            }
+        }
+    }
+
+    /// Records a node read by a lookup, the node must not change until it's locked.
+    fn visit(
+        &mut self,
+        g: ReadGuard<'a>,
+        depth: usize,
+        parent: Option<(NonNull<BaseNode>, u8)>,
+    ) -> Result<NonNull<BaseNode>, ArtError> {
+        let ptr = NonNull::from(g.as_ref());
+        match self.visited.entry(ptr) {
+            // Both guards have to match the current version, which the first one checks when it's locked.
+            Entry::Occupied(_) => {
+                g.check_version()?;
+            }
+            Entry::Vacant(e) => {
+                e.insert(Visited {
+                    guard: Some(g),
+                    depth,
+                    parent,
+                    locked: None,
+                });
+            }
+        }
+        Ok(ptr)
+    }
+
+    /// Write-locks a visited node, returns its index in `self.locked`.
+    fn lock(&mut self, ptr: NonNull<BaseNode>) -> Result<usize, ArtError> {
+        let v = self.visited.get_mut(&ptr).unwrap();
+        if let Some(idx) = v.locked {
+            return Ok(idx);
+        }
+        let node = v.guard.take().unwrap().upgrade().map_err(|(_n, e)| e)?;
+        let idx = self.locked.len();
+        v.locked = Some(idx);
+        self.locked.push(Locked {
+            node,
+            depth: v.depth,
+            parent: v.parent,
+            removes: Vec::new(),
+            changes: Vec::new(),
+            inserts: Vec::new(),
+            new_sub_nodes: Vec::new(),
+            diverged: Vec::new(),
+            prefix_cut: 0,
+            fate: Fate::Kept,
+        });
+        Ok(idx)
+    }
+
+    /// Locks the parents of nodes that become empty, outgrow their type or get new nodes above them,
+    /// from the bottom up, since unlinking an empty node can leave its parent empty as well.
+    fn lock_parents(&mut self) -> Result<(), ArtError> {
+        let max_depth = self.locked.iter().map(|n| n.depth).max().unwrap_or(0);
+        for depth in (0..=max_depth).rev() {
+            // Parents are appended while iterating, they are handled at their own depth.
+            let mut i = 0;
+            while i < self.locked.len() {
+                let n = &self.locked[i];
+                if n.depth == depth {
+                    let count =
+                        n.node.as_ref().value_count() + n.inserts.len() + n.new_sub_nodes.len()
+                            - n.removes.len();
+                    let capacity = n.node.as_ref().get_type().capacity();
+                    let diverged = !n.diverged.is_empty();
+                    match n.parent {
+                        Some((parent, edge)) if count == 0 => {
+                            let p = self.lock(parent)?;
+                            // The edge then leads to the entries that differ from the prefix instead.
+                            if !diverged {
+                                self.locked[p].removes.push(edge);
+                            }
+                            self.locked[i].fate = Fate::Emptied;
+                        }
+                        parent if count > capacity => {
+                            if let Some((parent, _)) = parent {
+                                self.lock(parent)?;
+                            }
+                            self.locked[i].fate = Fate::Grow;
+                        }
+                        _ => {}
+                    }
+                    if diverged {
+                        // The root has no prefix, so the node has a parent.
+                        let (parent, _) = self.locked[i].parent.unwrap();
+                        self.lock(parent)?;
+                    }
+                }
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocates the sub nodes for new keys, the nodes replacing the ones that grow and the nodes above
+    /// split prefixes, from the bottom up, so the edge to a replaced child is known before its parent is copied.
+    fn build(&mut self) -> Result<(), ArtError> {
+        let loader = BulkLoader::<K_LEN, A>::new(self.allocator);
+        let mut order: Vec<usize> = (0..self.locked.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.locked[i].depth));
+        for i in order {
+            for (b, level, entries) in std::mem::take(&mut self.locked[i].new_sub_nodes) {
+                let child = loader.build_sub_node(&entries, level + 1)?;
+                self.built.push((b, child, level));
+                self.locked[i].inserts.push((b, child));
+            }
+            let diverged = std::mem::take(&mut self.locked[i].diverged);
+            if let Some(&(level, _, _)) = diverged.first() {
+                let n = &mut self.locked[i];
+                let (parent, edge) = n.parent.unwrap();
+                let top = if matches!(n.fate, Fate::Emptied) {
+                    let entries: Vec<_> = diverged.iter().map(|d| d.2).collect();
+                    let child = loader.build_sub_node(&entries, level)?;
+                    self.built.push((edge, child, level - 1));
+                    child
+                } else {
+                    let last = diverged.iter().map(|d| d.1).max().unwrap();
+                    n.prefix_cut = last - level + 1;
+                    let child = match n.fate {
+                        Fate::Grow => self.grow(i)?,
+                        _ => NodePtr::from_node_ref(self.locked[i].node.as_ref()),
+                    };
+                    self.split(i, child, &diverged)?
+                };
+                let p = self.visited[&parent].locked.unwrap();
+                self.locked[p].changes.push((edge, top));
+            } else if matches!(self.locked[i].fate, Fate::Grow) {
+                let bigger = self.grow(i)?;
+                if let Some((parent, edge)) = self.locked[i].parent {
+                    let p = self.visited[&parent].locked.unwrap();
+                    self.locked[p].changes.push((edge, bigger));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocates the node replacing a node that grows, with the edits of the commit.
+    fn grow(&mut self, i: usize) -> Result<NodePtr, ArtError> {
+        let n = &self.locked[i];
+        let mut children: Vec<(u8, NodePtr)> = n
+            .node
+            .as_ref()
+            .get_children(0, u8::MAX)
+            .filter(|(k, _)| !n.removes.contains(k))
+            .map(|(k, child)| {
+                let changed = n.changes.iter().find(|(c, _)| *c == k);
+                (k, changed.map_or(child, |(_, c)| *c))
+            })
+            .collect();
+        children.extend(n.inserts.iter().copied());
+        let bigger = self.fill(&[], &children)?;
+        // Safety: the node was just allocated, no other thread can reach it.
+        let base = unsafe { &mut *bigger.as_sub_node_unchecked().as_ptr() };
+        base.copy_prefix_from(n.node.as_ref());
+        cut_prefix(base, n.prefix_cut);
+        self.locked[i].fate = Fate::Replaced(bigger);
+        Ok(bigger)
+    }
+
+    /// Allocates the nodes between the node at `i` and its parent that hold the keys differing from its prefix,
+    /// one per level a key differs at, with `child` at the bottom. Returns the top node.
+    fn split(
+        &mut self,
+        i: usize,
+        child: NodePtr,
+        diverged: &[Diverged<K_LEN>],
+    ) -> Result<NodePtr, ArtError> {
+        let loader = BulkLoader::<K_LEN, A>::new(self.allocator);
+        let prefix = self.locked[i].node.as_ref().prefix().to_vec();
+        let level = diverged[0].0;
+        let mut levels: Vec<usize> = diverged.iter().map(|d| d.1).collect();
+        levels.sort_unstable();
+        levels.dedup();
+
+        let mut below = child;
+        for (j, &at) in levels.iter().enumerate().rev() {
+            let start = if j == 0 { level } else { levels[j - 1] + 1 };
+            let mut children = vec![(prefix[at - level], below)];
+            // The keys are sorted, so the keys sharing the byte at the level are next to each other.
+            let entries: Vec<_> = diverged.iter().filter(|d| d.1 == at).map(|d| d.2).collect();
+            for group in entries.chunk_by(|a, b| a.0[at] == b.0[at]) {
+                let sub_node = loader.build_sub_node(group, at + 1)?;
+                self.built.push((group[0].0[at], sub_node, at));
+                children.push((group[0].0[at], sub_node));
+            }
+            below = self.fill(&prefix[start - level..at - level], &children)?;
+            self.middles.push(below);
+        }
+        Ok(below)
+    }
+
+    /// Allocates the smallest node that holds `children`.
+    fn fill(&self, prefix: &[u8], children: &[(u8, NodePtr)]) -> Result<NodePtr, ArtError> {
+        match children.len() {
+            0..=4 => self.fill_as::<Node4>(prefix, children),
+            5..=16 => self.fill_as::<Node16>(prefix, children),
+            17..=48 => self.fill_as::<Node48>(prefix, children),
+            _ => self.fill_as::<Node256>(prefix, children),
+        }
+    }
+
+    fn fill_as<N: Node>(
+        &self,
+        prefix: &[u8],
+        children: &[(u8, NodePtr)],
+    ) -> Result<NodePtr, ArtError> {
+        let mut new_node = BaseNode::make_node::<N, A>(prefix, self.allocator)?;
+        for (k, child) in children {
+            new_node.as_mut().insert(*k, *child);
+        }
+        Ok(new_node.into_note_ptr())
+    }
+
+    /// Frees the nodes allocated by a failed `build`, the tree is left untouched.
+    fn free_built(&mut self) {
+        let loader = BulkLoader::<K_LEN, A>::new(self.allocator);
+        for (b, child, level) in self.built.drain(..) {
+            loader.drop_children(vec![(b, child)], level);
+        }
+        for middle in self.middles.drain(..) {
+            unsafe { BaseNode::drop_node(middle.as_sub_node_unchecked(), self.allocator.clone()) };
+        }
+        for n in self.locked.iter() {
+            if let Fate::Replaced(bigger) = n.fate {
+                unsafe {
+                    BaseNode::drop_node(bigger.as_sub_node_unchecked(), self.allocator.clone())
+                };
+            }
+        }
+    }
+
+    /// Writes all edits, then unlocks the nodes that stay in the tree.
+    fn apply(self, guard: &Guard) {
+        let mut retired = Vec::new();
+        let mut kept = Vec::with_capacity(self.locked.len());
+        for mut n in self.locked {
+            match n.fate {
+                Fate::Kept => {
+                    let node = n.node.as_mut();
+                    for k in n.removes {
+                        node.remove(k);
+                    }
+                    for (k, child) in n.changes {
+                        node.change(k, child);
+                    }
+                    for (k, child) in n.inserts {
+                        node.insert(k, child);
+                    }
+                    cut_prefix(node, n.prefix_cut);
+                    kept.push(n.node);
+                }
+                Fate::Replaced(bigger) => {
+                    if n.parent.is_none() {
+                        self.root.store(
+                            unsafe { bigger.as_sub_node_unchecked() }.as_ptr(),
+                            Ordering::Release,
+                        );
+                    }
+                    retired.push(n.node);
+                }
+                Fate::Emptied => retired.push(n.node),
+                Fate::Grow => unreachable!("every growing node is replaced by build"),
+            }
+        }
+        // Unlock only after every edit is written.
+        drop(kept);
+
+        for mut write_n in retired {
+            write_n.mark_obsolete();
+            let delete_n = write_n.as_mut() as *mut BaseNode as usize;
+            std::mem::forget(write_n);
+            let allocator = self.allocator.clone();
+            guard.defer(move || unsafe {
+                let delete_n = NonNull::new(delete_n as *mut BaseNode).unwrap();
+                BaseNode::drop_node(delete_n, allocator);
+            });
+        }
+    }
+}
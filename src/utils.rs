@@ -1,10 +1,10 @@
 use crate::congee_raw::CongeeRaw;
-use crate::error::{ArtError, OOMError};
-use crate::nodes::{BaseNode, NodePtr};
-use crate::{CongeeSet, cast_ptr};
+use crate::error::OOMError;
+use crate::{CongeeSet, KeyEncoding};
 use core::cell::Cell;
 use core::fmt;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
 
 const SPIN_LIMIT: u32 = 6;
 const YIELD_LIMIT: u32 = 10;
@@ -125,25 +125,6 @@ impl<const K_LEN: usize> KeyTracker<K_LEN> {
         LastLevelKey { key: self }
     }
 
-    #[inline]
-    pub(crate) fn append_prefix(
-        node: NodePtr,
-        key_tracker: &KeyTracker<K_LEN>,
-    ) -> Result<KeyTracker<K_LEN>, ArtError> {
-        cast_ptr!(node => {
-            Payload(_payload) => Ok(key_tracker.clone()),
-            SubNode(sub_node) => {
-                let node_ref = BaseNode::read_lock(sub_node)?;
-                let n_prefix = node_ref.as_ref().prefix().iter();
-                let mut cur_key = key_tracker.clone();
-                for i in n_prefix {
-                    cur_key.push(*i);
-                }
-                Ok(cur_key)
-            }
-        })
-    }
-
     #[inline]
     pub(crate) fn len(&self) -> usize {
         self.len
@@ -156,7 +137,91 @@ impl<const K_LEN: usize> KeyTracker<K_LEN> {
     }
 }
 
-#[derive(Clone)]
+/// A counter split into cache line sized shards, threads update their own shard so concurrent writers don't contend.
+/// Reading sums up all shards, the result is exact once the updates it races with have finished.
+pub(crate) struct ShardedCounter {
+    shards: Box<[CounterShard]>,
+}
+
+#[repr(align(128))]
+#[derive(Default)]
+struct CounterShard(AtomicIsize);
+
+const COUNTER_SHARDS: usize = 32;
+
+static NEXT_COUNTER_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static COUNTER_SHARD: usize = NEXT_COUNTER_SHARD.fetch_add(1, Ordering::Relaxed) % COUNTER_SHARDS;
+}
+
+impl ShardedCounter {
+    pub(crate) fn new(value: usize) -> Self {
+        let shards: Box<[CounterShard]> = (0..COUNTER_SHARDS)
+            .map(|_| CounterShard::default())
+            .collect();
+        shards[0].0.store(value as isize, Ordering::Relaxed);
+        Self { shards }
+    }
+
+    #[inline]
+    pub(crate) fn add(&self, delta: isize) {
+        let shard = COUNTER_SHARD.with(|s| *s);
+        self.shards[shard].0.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// The sum of all shards, an update racing with the read may or may not be included.
+    pub(crate) fn get(&self) -> usize {
+        let sum: isize = self
+            .shards
+            .iter()
+            .map(|s| s.0.load(Ordering::Relaxed))
+            .sum();
+        // A decrement can be seen before the increment of the same key on another shard.
+        sum.max(0) as usize
+    }
+}
+
+/// Hints the CPU to load the cache line at `ptr`, a no-op on platforms without a stable prefetch intrinsic.
+#[inline]
+pub(crate) fn prefetch<T>(ptr: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        std::arch::x86_64::_mm_prefetch(ptr as *const i8, std::arch::x86_64::_MM_HINT_T0);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = ptr;
+}
+
+/// Lookup table of the CRC-32 (IEEE) polynomial, one entry per byte value.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The CRC-32 (IEEE) checksum of `data`, the same as zlib's `crc32`.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, b| {
+        (crc >> 8) ^ CRC32_TABLE[((crc ^ *b as u32) & 0xff) as usize]
+    })
+}
+
+#[derive(Clone, Default)]
 pub struct DefaultAllocator {}
 
 unsafe impl Send for DefaultAllocator {}
@@ -236,11 +301,11 @@ impl<A: Allocator + Clone + Send + 'static> Allocator for MemoryStatsAllocator<A
     }
 }
 
-impl<K, V, A: Allocator + Clone + Send + 'static> CongeeRaw<K, V, MemoryStatsAllocator<A>>
+impl<K, V, A: Allocator + Clone + Send + 'static, const K_LEN: usize>
+    CongeeRaw<K, V, MemoryStatsAllocator<A>, K_LEN>
 where
-    K: Copy + From<usize>,
+    K: KeyEncoding<K_LEN>,
     V: Copy + From<usize>,
-    usize: From<K>,
     usize: From<V>,
 {
     pub fn allocated_bytes(&self) -> usize {
@@ -258,10 +323,10 @@ where
     }
 }
 
-impl<K, A: Allocator + Clone + Send + 'static> CongeeSet<K, MemoryStatsAllocator<A>>
+impl<K, A: Allocator + Clone + Send + 'static, const K_LEN: usize>
+    CongeeSet<K, MemoryStatsAllocator<A>, K_LEN>
 where
-    K: Copy + From<usize>,
-    usize: From<K>,
+    K: KeyEncoding<K_LEN>,
 {
     pub fn allocated_bytes(&self) -> usize {
         self.allocator()
@@ -280,9 +345,8 @@ where
 
 #[cfg(test)]
 pub(crate) mod leak_check {
-    use super::*;
-
     use crate::error::OOMError;
+    use crate::nodes::BaseNode;
     use crate::{Allocator, DefaultAllocator};
     use std::collections::HashSet;
     use std::ptr::NonNull;